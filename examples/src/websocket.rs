@@ -1,30 +1,22 @@
-#[macro_use]
-extern crate cogo;
-extern crate native_tls;
-extern crate tungstenite;
-
-use cogo::net::TcpListener;
-use tungstenite::accept;
+use cogo::std::http::server::{HttpServer, Message, Router, WebSocket};
 
 fn main() {
-    let handler = go!(move || {
-        let listener = TcpListener::bind(("0.0.0.0", 8080)).unwrap();
-        for stream in listener.incoming() {
-            go!(move || -> () {
-                let mut websocket = accept(stream.unwrap()).unwrap();
-
-                loop {
-                    let msg = websocket.read_message().unwrap();
-
-                    // Just echo back everything that the client sent to us
-                    if msg.is_binary() || msg.is_text() {
-                        websocket.write_message(msg).unwrap();
+    let router = Router::new().get("/", |req, rsp| {
+        WebSocket::upgrade(&req, rsp, |mut ws| loop {
+            match ws.recv() {
+                // Just echo back everything that the client sent to us
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(msg) => {
+                    if ws.send(msg).is_err() {
+                        break;
                     }
                 }
-            });
-        }
+            }
+        });
+        Ok(())
     });
+    let server = HttpServer(router).start("0.0.0.0:8080").unwrap();
 
     println!("Websocket server running on ws://0.0.0.0:8080");
-    handler.join().unwrap();
+    server.join().unwrap();
 }
@@ -0,0 +1,60 @@
+//! helper threads that run the blocking calls
+//!
+//! the coroutine waits for the result on a channel, so the worker thread
+//! keeps running the other coroutines
+
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use once_cell::sync::OnceCell;
+
+use crate::std::sync::channel;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// a helper thread that is spawned on the first use
+pub(crate) struct Helper {
+    name: &'static str,
+    tx: OnceCell<Mutex<mpsc::Sender<Job>>>,
+}
+
+/// the helper of the file system calls
+pub(crate) static FS: Helper = Helper::new("cogo-fs");
+
+impl Helper {
+    pub const fn new(name: &'static str) -> Self {
+        Helper {
+            name,
+            tx: OnceCell::new(),
+        }
+    }
+
+    fn sender(&self) -> &Mutex<mpsc::Sender<Job>> {
+        self.tx.get_or_init(|| {
+            let (tx, rx) = mpsc::channel::<Job>();
+            thread::Builder::new()
+                .name(self.name.into())
+                .spawn(move || {
+                    for job in rx {
+                        job();
+                    }
+                })
+                .expect("failed to spawn the helper thread");
+            Mutex::new(tx)
+        })
+    }
+
+    /// run the blocking `f` on the helper thread and wait for the result
+    pub fn run<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> T {
+        let (tx, rx) = channel();
+        let job = Box::new(move || {
+            tx.send(f()).ok();
+        });
+        self.sender()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(job)
+            .expect("the helper thread exited");
+        rx.recv().expect("the helper thread exited")
+    }
+}
@@ -0,0 +1,101 @@
+//! coroutine aware io on the `bytes` buffers
+//!
+//! the data is read into the spare capacity of `BytesMut` directly by the
+//! syscall, so there is no need to create a slice on the uninitialized
+//! memory. the `Buf` is written with one `sendmsg(2)` for all its chunks
+
+use std::io::{self, IoSlice};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::zero_copy::retry;
+use super::{AsIoData, WaitIo};
+
+// the max number of chunks written by one syscall
+const MAX_IOVS: usize = 64;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SEND_FLAGS: libc::c_int = 0;
+
+// run the syscall once when `co` is false, or else until it's not blocked
+fn run<F, W>(co: bool, mut f: F, wait: W) -> io::Result<usize>
+where
+    F: FnMut() -> isize,
+    W: FnMut() -> io::Result<()>,
+{
+    if co {
+        return retry(f, wait);
+    }
+    match f() {
+        n if n < 0 => Err(io::Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+/// read into the spare capacity of `buf`, more space is reserved when it's full
+pub(crate) fn read_buf<T: AsRawFd + AsIoData>(
+    io: &T,
+    buf: &mut BytesMut,
+    timeout: Option<Duration>,
+    co: bool,
+) -> io::Result<usize> {
+    let fd = io.as_raw_fd();
+    // this would reserve more space if the buffer is full
+    let dst = buf.chunk_mut();
+    let (ptr, len) = (dst.as_mut_ptr() as *mut libc::c_void, dst.len());
+    let n = run(
+        co,
+        || unsafe { libc::read(fd, ptr, len) },
+        || io.wait_readable(timeout),
+    )?;
+    // the first `n` bytes are initialized by the kernel
+    unsafe { buf.advance_mut(n) };
+    Ok(n)
+}
+
+/// write the chunks of `buf` and advance it by the bytes written
+pub(crate) fn write_buf<T: AsRawFd + AsIoData, B: Buf>(
+    io: &T,
+    buf: &mut B,
+    timeout: Option<Duration>,
+    co: bool,
+) -> io::Result<usize> {
+    if !buf.has_remaining() {
+        return Ok(0);
+    }
+    let fd = io.as_raw_fd();
+    let mut iovs = [IoSlice::new(&[]); MAX_IOVS];
+    let cnt = buf.chunks_vectored(&mut iovs);
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    // IoSlice is guaranteed to be ABI compatible with iovec
+    hdr.msg_iov = iovs.as_mut_ptr() as *mut libc::iovec;
+    hdr.msg_iovlen = cnt as _;
+    let n = run(
+        co,
+        || unsafe { libc::sendmsg(fd, &hdr, SEND_FLAGS) },
+        || io.wait_writable(timeout),
+    )?;
+    buf.advance(n);
+    Ok(n)
+}
+
+/// receive data without removing it from the queue
+pub(crate) fn peek<T: AsRawFd + AsIoData>(
+    io: &T,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+    co: bool,
+) -> io::Result<usize> {
+    let fd = io.as_raw_fd();
+    let (ptr, len) = (buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+    run(
+        co,
+        || unsafe { libc::recv(fd, ptr, len, libc::MSG_PEEK) },
+        || io.wait_readable(timeout),
+    )
+}
@@ -0,0 +1,57 @@
+//! per operation deadline helpers
+//!
+//! a deadline is turned into the timeout of a single io request, so the
+//! one-shot timer is only registered for that call and the socket wide
+//! timeouts are left untouched
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// the time left before `deadline`
+///
+/// a `TimedOut` error is returned if the deadline has already passed
+pub(crate) fn remaining(deadline: Instant) -> io::Result<Duration> {
+    let now = Instant::now();
+    if deadline <= now {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "deadline has elapsed",
+        ));
+    }
+    Ok(deadline - now)
+}
+
+// adapt a read closure to `Read` so that `read_exact` could be reused
+pub(crate) struct ReadFn<F>(pub F);
+
+impl<F: FnMut(&mut [u8]) -> io::Result<usize>> Read for ReadFn<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (self.0)(buf)
+    }
+}
+
+// adapt a write closure to `Write` so that `write_all` could be reused
+pub(crate) struct WriteFn<F>(pub F);
+
+impl<F: FnMut(&[u8]) -> io::Result<usize>> Write for WriteFn<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elapsed_deadline() {
+        let err = remaining(Instant::now()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let dur = remaining(Instant::now() + Duration::from_secs(10)).unwrap();
+        assert!(dur <= Duration::from_secs(10));
+    }
+}
@@ -11,7 +11,15 @@ pub(crate) mod sys;
 // export the generic IO wrapper
 pub mod co_io_err;
 
+pub(crate) mod blocking;
+#[cfg(unix)]
+mod buf_io;
+pub(crate) mod deadline;
 mod event_loop;
+#[cfg(unix)]
+mod stdio;
+#[cfg(unix)]
+mod zero_copy;
 
 use std::io;
 use std::ops::Deref;
@@ -22,8 +30,24 @@ use crate::coroutine_impl::is_coroutine;
 pub(crate) use self::event_loop::EventLoop;
 pub use self::sys::co_io::CoIo;
 #[cfg(unix)]
+pub use self::stdio::{
+    stderr, stdin, stdout, Stderr, StderrLock, Stdin, StdinLock, Stdout, StdoutLock,
+};
+#[cfg(unix)]
 pub use self::sys::wait_io::WaitIo;
+#[cfg(unix)]
+pub use self::zero_copy::{copy_bidirectional, splice};
+#[cfg(unix)]
+pub(crate) use self::buf_io::{peek, read_buf, write_buf};
+#[cfg(unix)]
+pub(crate) use self::zero_copy::sendfile;
 pub(crate) use self::sys::{add_socket, cancel, net, IoData, Selector};
+#[cfg(unix)]
+pub(crate) use self::sys::add_socket_to;
+#[cfg(unix)]
+pub(crate) use self::sys::wait_io::poll_fd;
+#[cfg(unix)]
+pub(crate) use self::sys::Interest;
 
 pub trait AsIoData {
     fn as_io_data(&self) -> &IoData;
@@ -0,0 +1,402 @@
+//! coroutine aware standard io handles
+//!
+//! the stdio fds are registered to the selector when they are pollable
+//! (tty, pipe, socket), so a coroutine waiting for input doesn't block the
+//! worker thread. `O_NONBLOCK` is only set for the duration of a single
+//! syscall, because the file description is usually shared with the shell
+//! and other processes, and the original flags are restored on exit.
+//!
+//! when the fd is not pollable (e.g. a regular file), the io is done by a
+//! helper thread while the coroutine waits for the result
+
+use std::io::{self, BufRead, BufReader, LineWriter, Lines, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Once;
+
+use once_cell::sync::Lazy;
+
+use super::blocking::Helper;
+use super::{add_socket, AsIoData, IoData, WaitIo};
+use crate::coroutine_impl::is_coroutine;
+use crate::std::sync::{Mutex, MutexGuard};
+
+static STDIN: Lazy<Mutex<BufReader<Raw>>> = Lazy::new(|| Mutex::new(BufReader::new(Raw::new(0))));
+static STDOUT: Lazy<Mutex<LineWriter<Raw>>> =
+    Lazy::new(|| Mutex::new(LineWriter::new(Raw::new(1))));
+static STDERR: Lazy<Mutex<Raw>> = Lazy::new(|| Mutex::new(Raw::new(2)));
+
+// the original fd flags, `-1` means the fd is not touched
+static FLAGS: [AtomicI32; 3] = [AtomicI32::new(-1), AtomicI32::new(-1), AtomicI32::new(-1)];
+
+// the helper thread that runs the io of the fds that are not pollable,
+// a stdin read may block for long, so it's not shared with others
+static HELPER: Helper = Helper::new("cogo-stdio");
+
+// restore the fd flags and flush the stdout buffer when the process exits
+extern "C" fn at_exit() {
+    if let Some(stdout) = Lazy::get(&STDOUT) {
+        if let Ok(mut w) = stdout.try_lock() {
+            w.flush().ok();
+        }
+    }
+    for (fd, flags) in FLAGS.iter().enumerate() {
+        let flags = flags.load(Ordering::Relaxed);
+        if flags != -1 {
+            unsafe { libc::fcntl(fd as RawFd, libc::F_SETFL, flags) };
+        }
+    }
+}
+
+fn cvt(ret: libc::ssize_t) -> io::Result<usize> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let len = std::cmp::min(buf.len(), libc::ssize_t::MAX as usize);
+    cvt(unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, len) })
+}
+
+fn write_fd(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    let len = std::cmp::min(buf.len(), libc::ssize_t::MAX as usize);
+    cvt(unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, len) })
+}
+
+struct Fd(RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+// the raw stdio fd
+struct Raw {
+    fd: RawFd,
+    // the original fd flags
+    flags: libc::c_int,
+    // registered io data, `None` if the fd is not pollable
+    io: Option<IoData>,
+}
+
+// wait on the io data of the pollable fd
+struct Pollable<'a>(&'a IoData);
+
+impl<'a> AsIoData for Pollable<'a> {
+    fn as_io_data(&self) -> &IoData {
+        self.0
+    }
+}
+
+impl Raw {
+    fn new(fd: RawFd) -> Raw {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        // the selector refuses regular files and closed fds
+        let io = if flags == -1 {
+            None
+        } else {
+            add_socket(&Fd(fd)).ok()
+        };
+        if let (Some(saved), Some(_)) = (FLAGS.get(fd as usize), &io) {
+            saved.store(flags, Ordering::Relaxed);
+            static AT_EXIT: Once = Once::new();
+            AT_EXIT.call_once(|| unsafe {
+                libc::atexit(at_exit);
+            });
+        }
+        Raw { fd, flags, io }
+    }
+
+    // run `f` with `O_NONBLOCK` set and restore the flags afterwards
+    fn nonblocking<T>(&self, f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        if self.flags & libc::O_NONBLOCK != 0 {
+            return f();
+        }
+        unsafe { libc::fcntl(self.fd, libc::F_SETFL, self.flags | libc::O_NONBLOCK) };
+        let ret = f();
+        unsafe { libc::fcntl(self.fd, libc::F_SETFL, self.flags) };
+        ret
+    }
+}
+
+impl Read for Raw {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let fd = self.fd;
+        if !is_coroutine() {
+            return read_fd(fd, buf);
+        }
+
+        let io = match self.io {
+            Some(ref io) => io,
+            None => {
+                let len = buf.len();
+                let (data, ret) = HELPER.run(move || {
+                    let mut data = vec![0; len];
+                    let ret = read_fd(fd, &mut data);
+                    (data, ret)
+                });
+                let n = ret?;
+                buf[..n].copy_from_slice(&data[..n]);
+                return Ok(n);
+            }
+        };
+
+        loop {
+            io.reset_read();
+            match self.nonblocking(|| read_fd(fd, buf)) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                ret => return ret,
+            }
+            Pollable(io).wait_readable(None)?;
+        }
+    }
+}
+
+impl Write for Raw {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let fd = self.fd;
+        if !is_coroutine() {
+            return write_fd(fd, buf);
+        }
+
+        let io = match self.io {
+            Some(ref io) => io,
+            None => {
+                let data = buf.to_vec();
+                return HELPER.run(move || write_fd(fd, &data));
+            }
+        };
+
+        loop {
+            io.reset_write();
+            match self.nonblocking(|| write_fd(fd, buf)) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                ret => return ret,
+            }
+            Pollable(io).wait_writable(None)?;
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// a handle to the standard input of the process
+///
+/// the input is buffered, reading from the handle only blocks the
+/// current coroutine
+pub struct Stdin {
+    inner: &'static Mutex<BufReader<Raw>>,
+}
+
+/// a locked reference to the `Stdin` handle
+pub struct StdinLock<'a> {
+    inner: MutexGuard<'a, BufReader<Raw>>,
+}
+
+/// create a new handle to the standard input of the process
+pub fn stdin() -> Stdin {
+    Stdin { inner: &STDIN }
+}
+
+impl Stdin {
+    /// lock the handle for exclusive access
+    pub fn lock(&self) -> StdinLock<'static> {
+        StdinLock {
+            inner: self.inner.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    /// read a line of input and append it to `buf`
+    ///
+    /// the line feed is kept, `0` means the end of input is reached
+    pub fn read_line(&self, buf: &mut String) -> io::Result<usize> {
+        self.lock().read_line(buf)
+    }
+
+    /// consume the handle and return an iterator over the input lines
+    pub fn lines(self) -> Lines<StdinLock<'static>> {
+        self.lock().lines()
+    }
+}
+
+impl Read for Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl<'a> Read for StdinLock<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<'a> BufRead for StdinLock<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+/// a handle to the standard output of the process
+///
+/// the output is line buffered, writing to the handle only blocks the
+/// current coroutine
+pub struct Stdout {
+    inner: &'static Mutex<LineWriter<Raw>>,
+}
+
+/// a locked reference to the `Stdout` handle
+pub struct StdoutLock<'a> {
+    inner: MutexGuard<'a, LineWriter<Raw>>,
+}
+
+/// create a new handle to the standard output of the process
+pub fn stdout() -> Stdout {
+    Stdout { inner: &STDOUT }
+}
+
+impl Stdout {
+    /// lock the handle for exclusive access
+    pub fn lock(&self) -> StdoutLock<'static> {
+        StdoutLock {
+            inner: self.inner.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.lock().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl<'a> Write for StdoutLock<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// a handle to the standard error of the process
+///
+/// the output is not buffered, writing to the handle only blocks the
+/// current coroutine
+pub struct Stderr {
+    inner: &'static Mutex<Raw>,
+}
+
+/// a locked reference to the `Stderr` handle
+pub struct StderrLock<'a> {
+    inner: MutexGuard<'a, Raw>,
+}
+
+/// create a new handle to the standard error of the process
+pub fn stderr() -> Stderr {
+    Stderr { inner: &STDERR }
+}
+
+impl Stderr {
+    /// lock the handle for exclusive access
+    pub fn lock(&self) -> StderrLock<'static> {
+        StderrLock {
+            inner: self.inner.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+}
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.lock().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Write for StderrLock<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::os::unix::io::IntoRawFd;
+    use std::thread;
+
+    #[test]
+    fn pipe_is_pollable() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut r = Raw::new(fds[0]);
+        let mut w = Raw::new(fds[1]);
+        assert!(r.io.is_some());
+
+        let h = go!(move || {
+            let mut buf = [0; 5];
+            r.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+            // the fd is left in blocking mode between the calls
+            let flags = unsafe { libc::fcntl(r.fd, libc::F_GETFL) };
+            assert_eq!(flags & libc::O_NONBLOCK, 0);
+            r
+        });
+        thread::sleep(std::time::Duration::from_millis(50));
+        w.write_all(b"hello").unwrap();
+        let r = h.join().unwrap();
+        drop((r, w));
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[test]
+    fn file_uses_helper_thread() {
+        let dir = tempdir::TempDir::new("cogo_stdio").unwrap();
+        let path = dir.path().join("input");
+        std::fs::write(&path, b"line 1\nline 2\n").unwrap();
+        let fd = File::open(&path).unwrap().into_raw_fd();
+        let raw = Raw::new(fd);
+        assert!(raw.io.is_none());
+
+        let h = go!(move || {
+            let lines: Vec<String> = BufReader::new(raw).lines().map(|l| l.unwrap()).collect();
+            assert_eq!(lines, ["line 1", "line 2"]);
+        });
+        h.join().unwrap();
+        unsafe { libc::close(fd) };
+    }
+}
@@ -1,21 +1,23 @@
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use super::EventData;
+use super::{EventData, Interest};
 use crate::cancel::CancelIo;
 use crate::scheduler::get_scheduler;
 use crate::std::sync::AtomicOption;
 
-pub struct CancelIoImpl(AtomicOption<Arc<EventData>>);
+// the io data and whether the coroutine is blocked on the write direction
+pub struct CancelIoImpl(AtomicOption<Arc<EventData>>, AtomicBool);
 
 impl CancelIo for CancelIoImpl {
-    type Data = Arc<EventData>;
+    type Data = (Arc<EventData>, Interest);
 
     fn new() -> Self {
-        CancelIoImpl(AtomicOption::none())
+        CancelIoImpl(AtomicOption::none(), AtomicBool::new(false))
     }
 
-    fn set(&self, data: Arc<EventData>) {
+    fn set(&self, (data, interest): (Arc<EventData>, Interest)) {
+        self.1.store(interest == Interest::Write, Ordering::Relaxed);
         self.0.swap(data, Ordering::Release);
     }
 
@@ -25,7 +27,12 @@ impl CancelIo for CancelIoImpl {
 
     unsafe fn cancel(&self) {
         if let Some(e) = self.0.take(Ordering::Acquire) {
-            if let Some(co) = e.co.take(Ordering::Acquire) {
+            let waiter = if self.1.load(Ordering::Relaxed) {
+                &e.write
+            } else {
+                &e.read
+            };
+            if let Some(co) = waiter.co.take(Ordering::Acquire) {
                 get_scheduler().schedule(co);
             }
         }
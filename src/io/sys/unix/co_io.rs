@@ -3,13 +3,14 @@
 //! context with non blocking operations
 //!
 
-use std::io::{self, Read, Write};
+use std::io::{self, IoSliceMut, Read, Write};
 use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use self::io_impl::co_io_err::Error;
 use self::io_impl::net as net_impl;
 use crate::io as io_impl;
+use crate::io::deadline::{self, ReadFn, WriteFn};
 use crate::std::sync::atomic_dur::AtomicDuration;
 use crate::yield_now::yield_with;
 
@@ -96,9 +97,14 @@ impl<T: AsRawFd> CoIo<T> {
         }
     }
 
-    /// reset internal io data
-    pub(crate) fn io_reset(&self) {
-        self.io.reset()
+    /// reset internal io data for reading
+    pub(crate) fn io_reset_read(&self) {
+        self.io.reset_read()
+    }
+
+    /// reset internal io data for writing
+    pub(crate) fn io_reset_write(&self) {
+        self.io.reset_write()
     }
 
     /// check current ctx
@@ -151,14 +157,34 @@ impl<T: AsRawFd> CoIo<T> {
     }
 }
 
-impl<T: AsRawFd + Read> Read for CoIo<T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+impl<T: AsRawFd + Read> CoIo<T> {
+    /// read into `buf`, a `TimedOut` error is returned if nothing could
+    /// be read before `deadline`
+    ///
+    /// the deadline only applies to this call, the read timeout is not used
+    pub fn read_with_deadline(&mut self, buf: &mut [u8], deadline: Instant) -> io::Result<usize> {
+        let timeout = deadline::remaining(deadline)?;
         if !self.ctx_check()? {
-            // this can't be nonblocking!!
+            // block the thread until the deadline
+            io_impl::poll_fd(self.as_raw_fd(), io_impl::Interest::Read, Some(timeout))?;
             return self.inner.read(buf);
         }
 
-        self.io.reset();
+        self.co_read(buf, Some(timeout))
+    }
+
+    /// read the exact number of bytes to fill `buf` before `deadline`
+    pub fn read_exact_with_deadline(
+        &mut self,
+        buf: &mut [u8],
+        deadline: Instant,
+    ) -> io::Result<()> {
+        ReadFn(|buf: &mut [u8]| self.read_with_deadline(buf, deadline)).read_exact(buf)
+    }
+
+    // read in coroutine context with the given timeout
+    fn co_read(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<usize> {
+        self.io.reset_read();
         // this is an earlier return try for nonblocking read
         // it's useful for server but not necessary for client
         match self.inner.read(buf) {
@@ -174,20 +200,36 @@ impl<T: AsRawFd + Read> Read for CoIo<T> {
             }
         }
 
-        let mut reader = net_impl::SocketRead::new(self, buf, self.read_timeout.get());
+        let mut reader = net_impl::SocketRead::new(self, buf, timeout);
         yield_with(&reader);
         reader.done()
     }
 }
 
-impl<T: AsRawFd + Write> Write for CoIo<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+impl<T: AsRawFd + Write> CoIo<T> {
+    /// write from `buf`, a `TimedOut` error is returned if nothing could
+    /// be written before `deadline`
+    ///
+    /// the deadline only applies to this call, the write timeout is not used
+    pub fn write_with_deadline(&mut self, buf: &[u8], deadline: Instant) -> io::Result<usize> {
+        let timeout = deadline::remaining(deadline)?;
         if !self.ctx_check()? {
-            // this can't be nonblocking!!
+            // block the thread until the deadline
+            io_impl::poll_fd(self.as_raw_fd(), io_impl::Interest::Write, Some(timeout))?;
             return self.inner.write(buf);
         }
 
-        self.io.reset();
+        self.co_write(buf, Some(timeout))
+    }
+
+    /// write the entire `buf` before `deadline`
+    pub fn write_all_with_deadline(&mut self, buf: &[u8], deadline: Instant) -> io::Result<()> {
+        WriteFn(|buf: &[u8]| self.write_with_deadline(buf, deadline)).write_all(buf)
+    }
+
+    // write in coroutine context with the given timeout
+    fn co_write(&mut self, buf: &[u8], timeout: Option<Duration>) -> io::Result<usize> {
+        self.io.reset_write();
         // this is an earlier return try for nonblocking write
         match self.inner.write(buf) {
             Ok(n) => return Ok(n),
@@ -202,10 +244,58 @@ impl<T: AsRawFd + Write> Write for CoIo<T> {
             }
         }
 
-        let mut writer = net_impl::SocketWrite::new(self, buf, self.write_timeout.get());
+        let mut writer = net_impl::SocketWrite::new(self, buf, timeout);
         yield_with(&writer);
         writer.done()
     }
+}
+
+impl<T: AsRawFd + Read> Read for CoIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.ctx_check()? {
+            // this can't be nonblocking!!
+            return self.inner.read(buf);
+        }
+
+        self.co_read(buf, self.read_timeout.get())
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        if !self.ctx_check()? {
+            // this can't be nonblocking!!
+            return self.inner.read_vectored(bufs);
+        }
+
+        self.io.reset_read();
+        // this is an earlier return try for nonblocking read
+        match self.inner.read_vectored(bufs) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut reader = net_impl::SocketReadVectored::new(self, bufs, self.read_timeout.get());
+        yield_with(&reader);
+        reader.done()
+    }
+}
+
+impl<T: AsRawFd + Write> Write for CoIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.ctx_check()? {
+            // this can't be nonblocking!!
+            return self.inner.write(buf);
+        }
+
+        self.co_write(buf, self.write_timeout.get())
+    }
 
     fn flush(&mut self) -> io::Result<()> {
         self.inner.flush()
@@ -2,10 +2,9 @@ use std::os::unix::io::RawFd;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
-use std::{cmp, io, isize, ptr};
+use std::{cmp, io, isize};
 
-use super::{from_nix_error, timeout_handler, EventData, IoData, TimerList};
-use crate::coroutine_impl::run_coroutine;
+use super::{from_nix_error, timeout_handler, EventData, Interest, IoData, TimerList};
 use crate::scheduler::get_scheduler;
 use crate::timeout_list::{now, ns_to_ms};
 use crate::std::queue::seg_queue::SegQueue as mpsc;
@@ -24,6 +23,20 @@ fn create_eventfd() -> io::Result<RawFd> {
 
 pub type SysEvent = EpollEvent;
 
+// the events that would wake up the read waiter
+const READABLE: EpollFlags = EpollFlags::from_bits_truncate(
+    EpollFlags::EPOLLIN.bits()
+        | EpollFlags::EPOLLPRI.bits()
+        | EpollFlags::EPOLLRDHUP.bits()
+        | EpollFlags::EPOLLHUP.bits()
+        | EpollFlags::EPOLLERR.bits(),
+);
+
+// the events that would wake up the write waiter
+const WRITABLE: EpollFlags = EpollFlags::from_bits_truncate(
+    EpollFlags::EPOLLOUT.bits() | EpollFlags::EPOLLHUP.bits() | EpollFlags::EPOLLERR.bits(),
+);
+
 struct SingleSelector {
     epfd: RawFd,
     evfd: RawFd,
@@ -114,27 +127,13 @@ impl Selector {
             }
             let data = unsafe { &mut *(event.data() as *mut EventData) };
             // //info!("select got event, data={:p}", data);
-            data.io_flag.store(true, Ordering::Release);
-
-            // first check the atomic co, this may be grab by the worker first
-            let co = match data.co.take(Ordering::Acquire) {
-                None => continue,
-                Some(co) => co,
-            };
-            co.prefetch();
-
-            // it's safe to remove the timer since we are running the timer_list in the same thread
-            data.timer.borrow_mut().take().map(|h| {
-                unsafe {
-                    // tell the timer handler not to cancel the io
-                    // it's not always true that you can really remove the timer entry
-                    h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
-                }
-                h.remove()
-            });
-
-            // schedule the coroutine
-            run_coroutine(co);
+            let flags = event.events();
+            if flags.intersects(READABLE) {
+                data.readable();
+            }
+            if flags.intersects(WRITABLE) {
+                data.writable();
+            }
         }
 
         // run all the local tasks
@@ -170,7 +169,7 @@ impl Selector {
         );
 
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = io_data.selector % self.vec.len();
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         let epfd = single_selector.epfd;
         //info!("add fd to epoll select, fd={:?}", fd);
@@ -185,18 +184,11 @@ impl Selector {
 
         let mut info = EpollEvent::empty();
 
-        if let Some(h) = io_data.timer.borrow_mut().take() {
-            unsafe {
-                // mark the timer as removed if any, this only happened
-                // when cancel an IO. what if the timer expired at the same time?
-                // because we run this func in the user space, so the timer handler
-                // will not got the coroutine
-                h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
-            }
-        }
+        // mark the timers as removed if any
+        io_data.clear_timers();
 
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = io_data.selector % self.vec.len();
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         let epfd = single_selector.epfd;
         //info!("del fd from epoll select, fd={:?}", fd);
@@ -216,16 +208,17 @@ impl Selector {
 
     // register the io request to the timeout list
     #[inline]
-    pub fn add_io_timer(&self, io: &IoData, timeout: Duration) {
-        let id = io.fd as usize % self.vec.len();
+    pub fn add_io_timer(&self, io: &IoData, interest: Interest, timeout: Duration) {
+        let id = io.selector % self.vec.len();
+        let waiter = io.waiter(interest);
         // //info!("io timeout = {:?}", dur);
         let (h, b_new) = unsafe { self.vec.get_unchecked(id) }
             .timer_list
-            .add_timer(timeout, io.timer_data());
+            .add_timer(timeout, waiter.timer_data());
         if b_new {
             // wake up the event loop thread to recall the next wait timeout
             self.wakeup(id);
         }
-        io.timer.borrow_mut().replace(h);
+        waiter.timer.borrow_mut().replace(h);
     }
 }
@@ -4,13 +4,12 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::{io, ptr};
 
-use crate::coroutine_impl::run_coroutine;
 use crate::scheduler::get_scheduler;
 use crate::timeout_list::{now, ns_to_dur};
 use crate::std::queue::seg_queue::SegQueue as mpsc;
 use smallvec::SmallVec;
 
-use super::{timeout_handler, EventData, IoData, TimerList};
+use super::{timeout_handler, EventData, Interest, IoData, TimerList};
 
 pub type SysEvent = libc::kevent;
 
@@ -148,27 +147,10 @@ impl Selector {
             }
             let data = unsafe { &mut *(event.udata as *mut EventData) };
             // //info!("select got event, data={:p}", data);
-            data.io_flag.store(true, Ordering::Release);
-
-            // first check the atomic co, this may be grab by the worker first
-            let co = match data.co.take(Ordering::Acquire) {
-                None => continue,
-                Some(co) => co,
-            };
-            co.prefetch();
-
-            // it's safe to remove the timer since we are running the timer_list in the same thread
-            data.timer.borrow_mut().take().map(|h| {
-                unsafe {
-                    // tell the timer handler not to cancel the io
-                    // it's not always true that you can really remove the timer entry
-                    h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
-                }
-                h.remove()
-            });
-
-            // schedule the coroutine
-            run_coroutine(co);
+            match event.filter {
+                libc::EVFILT_WRITE => data.writable(),
+                _ => data.readable(),
+            }
         }
 
         // run all the local tasks
@@ -206,7 +188,7 @@ impl Selector {
     #[inline]
     pub fn add_fd(&self, io_data: IoData) -> io::Result<IoData> {
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = io_data.selector % self.vec.len();
         let kqfd = unsafe { self.vec.get_unchecked(id) }.kqfd;
         //info!("add fd to kqueue select, fd={:?}", fd);
 
@@ -238,18 +220,11 @@ impl Selector {
     pub fn del_fd(&self, io_data: &IoData) {
         use std::ops::Deref;
 
-        io_data.timer.borrow_mut().take().map(|h| {
-            unsafe {
-                // mark the timer as removed if any, this only happened
-                // when cancel an IO. what if the timer expired at the same time?
-                // because we run this func in the user space, so the timer handler
-                // will not got the coroutine
-                h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
-            }
-        });
+        // mark the timers as removed if any
+        io_data.clear_timers();
 
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = io_data.selector % self.vec.len();
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         let kqfd = single_selector.kqfd;
         //info!("del fd from kqueue select, fd={:?}", fd);
@@ -285,16 +260,17 @@ impl Selector {
 
     // register the io request to the timeout list
     #[inline]
-    pub fn add_io_timer(&self, io: &IoData, timeout: Duration) {
-        let id = io.fd as usize % self.vec.len();
+    pub fn add_io_timer(&self, io: &IoData, interest: Interest, timeout: Duration) {
+        let id = io.selector % self.vec.len();
+        let waiter = io.waiter(interest);
         // //info!("io timeout = {:?}", dur);
         let (h, b_new) = unsafe { self.vec.get_unchecked(id) }
             .timer_list
-            .add_timer(timeout, io.timer_data());
+            .add_timer(timeout, waiter.timer_data());
         if b_new {
             // wakeup the event loop thread to recall the next wait timeout
             self.wakeup(id);
         }
-        io.timer.borrow_mut().replace(h);
+        waiter.timer.borrow_mut().replace(h);
     }
 }
@@ -39,6 +39,14 @@ pub fn add_socket<T: AsRawFd + ?Sized>(t: &T) -> io::Result<IoData> {
     get_scheduler().get_selector().add_fd(IoData::new(t))
 }
 
+// register the socket to the selector that is run by the given worker
+#[inline]
+pub fn add_socket_to<T: AsRawFd + ?Sized>(t: &T, id: usize) -> io::Result<IoData> {
+    get_scheduler()
+        .get_selector()
+        .add_fd(IoData::with_selector(t, id))
+}
+
 #[inline]
 fn del_socket(io: &IoData) {
     // transfer the io to the selector
@@ -65,16 +73,16 @@ fn from_nix_error(err: nix::Error) -> ::std::io::Error {
 }
 
 fn timeout_handler(data: TimerData) {
-    if data.event_data.is_null() {
+    if data.waiter.is_null() {
         return;
     }
 
-    let event_data = unsafe { &mut *data.event_data };
+    let waiter = unsafe { &mut *data.waiter };
     // remove the event timer
-    event_data.timer.borrow_mut().take();
+    waiter.timer.borrow_mut().take();
 
     // get and check the coroutine
-    let mut co = match event_data.co.take(Ordering::Relaxed) {
+    let mut co = match waiter.co.take(Ordering::Relaxed) {
         Some(co) => co,
         None => return,
     };
@@ -88,28 +96,31 @@ fn timeout_handler(data: TimerData) {
 
 // the timeout data
 pub struct TimerData {
-    event_data: *mut EventData,
+    waiter: *mut IoWaiter,
 }
 
 pub type TimerList = TimeOutList<TimerData>;
 pub type TimerHandle = TimeoutHandle<TimerData>;
 
-// event associated io data, must be construct in
-// each file handle, the epoll event.data would point to it
-pub struct EventData {
-    pub fd: RawFd,
+/// the io readiness that an io request is waiting for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    Read,
+    Write,
+}
+
+// the wait data for one io direction, each direction has its own
+// waiting coroutine so that reading and writing could be blocked
+// on the same io object at the same time
+pub struct IoWaiter {
     pub io_flag: AtomicBool,
     pub timer: RefCell<Option<TimerHandle>>,
     pub co: AtomicOption<CoroutineImpl>,
 }
 
-unsafe impl Send for EventData {}
-unsafe impl Sync for EventData {}
-
-impl EventData {
-    pub fn new(fd: RawFd) -> EventData {
-        EventData {
-            fd,
+impl IoWaiter {
+    fn new() -> Self {
+        IoWaiter {
             io_flag: AtomicBool::new(false),
             timer: RefCell::new(None),
             co: AtomicOption::none(),
@@ -118,7 +129,7 @@ impl EventData {
 
     pub fn timer_data(&self) -> TimerData {
         TimerData {
-            event_data: self as *const _ as *mut _,
+            waiter: self as *const _ as *mut _,
         }
     }
 
@@ -129,13 +140,14 @@ impl EventData {
             None => return, // it's already take by selector
             Some(co) => co,
         };
+        co.prefetch();
 
         // it's safe to remove the timer since we are running the timer_list in the same thread
         self.timer.borrow_mut().take().map(|h| {
             unsafe {
                 // tell the timer function not to cancel the io
                 // it's not always true that you can really remove the timer entry
-                h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
+                h.with_mut_data(|value| value.data.waiter = ptr::null_mut());
             }
             h.remove()
         });
@@ -143,6 +155,79 @@ impl EventData {
         // schedule the coroutine
         run_coroutine(co);
     }
+
+    // mark the timer as removed if any
+    #[inline]
+    fn clear_timer(&self) {
+        if let Some(h) = self.timer.borrow_mut().take() {
+            unsafe {
+                // mark the timer as removed if any, this only happened
+                // when cancel an IO. what if the timer expired at the same time?
+                // because we run this func in the user space, so the timer handler
+                // will not got the coroutine
+                h.with_mut_data(|value| value.data.waiter = ptr::null_mut());
+            }
+        }
+    }
+}
+
+// event associated io data, must be construct in
+// each file handle, the epoll event.data would point to it
+pub struct EventData {
+    pub fd: RawFd,
+    // the selector index that the fd is registered to, modulo the selector number
+    pub selector: usize,
+    pub read: IoWaiter,
+    pub write: IoWaiter,
+    // the read waiter would also be waken up by write events, used by `wait_io`
+    pub wait_any: AtomicBool,
+}
+
+unsafe impl Send for EventData {}
+unsafe impl Sync for EventData {}
+
+impl EventData {
+    pub fn new(fd: RawFd, selector: usize) -> EventData {
+        EventData {
+            fd,
+            selector,
+            read: IoWaiter::new(),
+            write: IoWaiter::new(),
+            wait_any: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    pub fn waiter(&self, interest: Interest) -> &IoWaiter {
+        match interest {
+            Interest::Read => &self.read,
+            Interest::Write => &self.write,
+        }
+    }
+
+    // called by the selector when the io is readable
+    #[inline]
+    pub fn readable(&self) {
+        self.read.io_flag.store(true, Ordering::Release);
+        self.read.schedule();
+    }
+
+    // called by the selector when the io is writable
+    #[inline]
+    pub fn writable(&self) {
+        self.write.io_flag.store(true, Ordering::Release);
+        self.write.schedule();
+        if self.wait_any.load(Ordering::Acquire) {
+            self.read.schedule();
+        }
+    }
+
+    // remove all the pending timers
+    #[inline]
+    pub fn clear_timers(&self) {
+        self.read.clear_timer();
+        self.write.clear_timer();
+    }
 }
 
 // each file associated data
@@ -151,14 +236,33 @@ pub struct IoData(Arc<EventData>);
 impl IoData {
     pub fn new<T: AsRawFd + ?Sized>(t: &T) -> Self {
         let fd = t.as_raw_fd();
-        let event_data = Arc::new(EventData::new(fd));
+        Self::with_selector(t, fd as usize)
+    }
+
+    // the io data that would be registered to the given selector
+    pub fn with_selector<T: AsRawFd + ?Sized>(t: &T, selector: usize) -> Self {
+        let fd = t.as_raw_fd();
+        let event_data = Arc::new(EventData::new(fd, selector));
         IoData(event_data)
     }
 
-    // clear the io flag
+    // clear the io flags
     #[inline]
     pub fn reset(&self) {
-        self.io_flag.store(false, Ordering::Relaxed);
+        self.read.io_flag.store(false, Ordering::Relaxed);
+        self.write.io_flag.store(false, Ordering::Relaxed);
+    }
+
+    // clear the read io flag
+    #[inline]
+    pub fn reset_read(&self) {
+        self.read.io_flag.store(false, Ordering::Relaxed);
+    }
+
+    // clear the write io flag
+    #[inline]
+    pub fn reset_write(&self) {
+        self.write.io_flag.store(false, Ordering::Relaxed);
     }
 }
 
@@ -1,23 +1,37 @@
 mod socket_read;
+mod socket_read_vectored;
 mod socket_write;
 mod socket_write_vectored;
 mod tcp_listener_accpet;
 mod tcp_stream_connect;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod udp_recv_batch;
 mod udp_recv_from;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod udp_send_batch;
 mod udp_send_to;
 mod unix_listener_accpet;
+mod unix_recv_fds;
 mod unix_recv_from;
+mod unix_send_fds;
 mod unix_send_to;
 mod unix_stream_connect;
 
 pub use self::socket_read::SocketRead;
+pub use self::socket_read_vectored::SocketReadVectored;
 pub use self::socket_write::SocketWrite;
 pub use self::socket_write_vectored::SocketWriteVectored;
 pub use self::tcp_listener_accpet::TcpListenerAccept;
 pub use self::tcp_stream_connect::TcpStreamConnect;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use self::udp_recv_batch::UdpRecvBatch;
 pub use self::udp_recv_from::UdpRecvFrom;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use self::udp_send_batch::UdpSendBatch;
 pub use self::udp_send_to::UdpSendTo;
-pub use self::unix_listener_accpet::UnixListenerAccept;
+pub use self::unix_listener_accpet::{UnixAccept, UnixListenerAccept};
+pub use self::unix_recv_fds::{recv_with_fds, UnixRecvFds};
 pub use self::unix_recv_from::UnixRecvFrom;
+pub use self::unix_send_fds::{send_with_fds, UnixSendFds};
 pub use self::unix_send_to::UnixSendTo;
 pub use self::unix_stream_connect::UnixStreamConnect;
@@ -2,7 +2,7 @@ use std::io;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
-use super::super::{co_io_result, from_nix_error, IoData};
+use super::super::{co_io_result, from_nix_error, Interest, IoData};
 use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
 use crate::io::AsIoData;
 use crate::scheduler::get_scheduler;
@@ -29,7 +29,7 @@ impl<'a> SocketRead<'a> {
             co_io_result()?;
 
             // clear the io_flag
-            self.io_data.io_flag.store(false, Ordering::Relaxed);
+            self.io_data.read.io_flag.store(false, Ordering::Relaxed);
 
             // finish the read operation
             match read(self.io_data.fd, self.buf) {
@@ -43,7 +43,7 @@ impl<'a> SocketRead<'a> {
                 }
             }
 
-            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+            if self.io_data.read.io_flag.swap(false, Ordering::Relaxed) {
                 continue;
             }
 
@@ -62,22 +62,22 @@ impl<'a> EventSource for SocketRead<'a> {
         if let Some(dur) = self.timeout {
             get_scheduler()
                 .get_selector()
-                .add_io_timer(self.io_data, dur);
+                .add_io_timer(self.io_data, Interest::Read, dur);
         }
 
         // after register the coroutine, it's possible that other thread run it immediately
         // and cause the process after it invalid, this is kind of user and kernel competition
         // so we need to delay the drop of the EventSource, that's why _g is here
-        self.io_data.co.swap(co, Ordering::Release);
+        self.io_data.read.co.swap(co, Ordering::Release);
         // till here the io may be done in other thread
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
-            return io_data.schedule();
+        if io_data.read.io_flag.load(Ordering::Acquire) {
+            return io_data.read.schedule();
         }
 
         // register the cancel io data
-        cancel.set_io(io_data);
+        cancel.set_io((io_data, Interest::Read));
         // re-check the cancel status
         if cancel.is_canceled() {
             unsafe { cancel.cancel() };
@@ -0,0 +1,96 @@
+use std::io::{self, IoSliceMut};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use super::super::{co_io_result, Interest, IoData};
+use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
+use crate::io::AsIoData;
+use crate::scheduler::get_scheduler;
+use crate::yield_now::yield_with;
+
+// read into the buffers with `readv(2)`
+fn readv(fd: libc::c_int, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+    // IoSliceMut is guaranteed to be ABI compatible with iovec
+    let len = ::std::cmp::min(bufs.len(), libc::c_int::MAX as usize) as libc::c_int;
+    match unsafe { libc::readv(fd, bufs.as_ptr() as *const libc::iovec, len) } {
+        -1 => Err(io::Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+pub struct SocketReadVectored<'a, 'b> {
+    io_data: &'a IoData,
+    bufs: &'a mut [IoSliceMut<'b>],
+    timeout: Option<Duration>,
+}
+
+impl<'a, 'b> SocketReadVectored<'a, 'b> {
+    pub fn new<T: AsIoData>(
+        s: &'a T,
+        bufs: &'a mut [IoSliceMut<'b>],
+        timeout: Option<Duration>,
+    ) -> Self {
+        SocketReadVectored {
+            io_data: s.as_io_data(),
+            bufs,
+            timeout,
+        }
+    }
+
+    pub fn done(&mut self) -> io::Result<usize> {
+        loop {
+            co_io_result()?;
+
+            // clear the io_flag
+            self.io_data.read.io_flag.store(false, Ordering::Relaxed);
+
+            // finish the read operation
+            match readv(self.io_data.fd, self.bufs) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+
+            if self.io_data.read.io_flag.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            // the result is still WouldBlock, need to try again
+            yield_with(self);
+        }
+    }
+}
+
+impl<'a, 'b> EventSource for SocketReadVectored<'a, 'b> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let handle = co_get_handle(&co);
+        let cancel = handle.get_cancel();
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, Interest::Read, dur);
+        }
+        self.io_data.read.co.swap(co, Ordering::Release);
+
+        // there is event, re-run the coroutine
+        if io_data.read.io_flag.load(Ordering::Acquire) {
+            return io_data.read.schedule();
+        }
+
+        // register the cancel io data
+        cancel.set_io((io_data, Interest::Read));
+        // re-check the cancel status
+        if cancel.is_canceled() {
+            unsafe { cancel.cancel() };
+        }
+    }
+}
@@ -2,7 +2,7 @@ use std::io::{self, IoSlice};
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
-use super::super::{co_io_result, IoData};
+use super::super::{co_io_result, Interest, IoData};
 use crate::coroutine_impl::{CoroutineImpl, EventSource};
 use crate::io::AsIoData;
 use crate::scheduler::get_scheduler;
@@ -37,7 +37,7 @@ impl<'a> SocketWriteVectored<'a> {
             co_io_result()?;
 
             // clear the io_flag
-            self.io_data.io_flag.store(false, Ordering::Relaxed);
+            self.io_data.write.io_flag.store(false, Ordering::Relaxed);
 
             match self.socket.write_vectored(self.bufs) {
                 Ok(n) => return Ok(n),
@@ -51,7 +51,7 @@ impl<'a> SocketWriteVectored<'a> {
                 }
             }
 
-            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+            if self.io_data.write.io_flag.swap(false, Ordering::Relaxed) {
                 continue;
             }
 
@@ -68,13 +68,13 @@ impl<'a> EventSource for SocketWriteVectored<'a> {
         if let Some(dur) = self.timeout {
             get_scheduler()
                 .get_selector()
-                .add_io_timer(self.io_data, dur);
+                .add_io_timer(self.io_data, Interest::Write, dur);
         }
-        self.io_data.co.swap(co, Ordering::Release);
+        self.io_data.write.co.swap(co, Ordering::Release);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
-            io_data.schedule();
+        if io_data.write.io_flag.load(Ordering::Acquire) {
+            io_data.write.schedule();
         }
     }
 }
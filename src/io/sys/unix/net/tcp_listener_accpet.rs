@@ -2,7 +2,7 @@ use std::net::SocketAddr;
 use std::sync::atomic::Ordering;
 use std::{self, io};
 
-use super::super::{add_socket, co_io_result, IoData};
+use super::super::{add_socket, co_io_result, Interest, IoData};
 use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
 use crate::io::AsIoData;
 use crate::net::{TcpListener, TcpStream};
@@ -26,7 +26,7 @@ impl<'a> TcpListenerAccept<'a> {
             co_io_result()?;
 
             // clear the io_flag
-            self.io_data.io_flag.store(false, Ordering::Relaxed);
+            self.io_data.read.io_flag.store(false, Ordering::Relaxed);
 
             match self.socket.accept() {
                 Ok((s, a)) => {
@@ -44,7 +44,7 @@ impl<'a> TcpListenerAccept<'a> {
                 }
             }
 
-            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+            if self.io_data.read.io_flag.swap(false, Ordering::Relaxed) {
                 continue;
             }
 
@@ -60,15 +60,15 @@ impl<'a> EventSource for TcpListenerAccept<'a> {
         let cancel = handle.get_cancel();
         let io_data = (*self.io_data).clone();
         // if there is no timer we don't need to call add_io_timer
-        self.io_data.co.swap(co, Ordering::Release);
+        self.io_data.read.co.swap(co, Ordering::Release);
 
         // there is event happened
-        if io_data.io_flag.load(Ordering::Acquire) {
-            return io_data.schedule();
+        if io_data.read.io_flag.load(Ordering::Acquire) {
+            return io_data.read.schedule();
         }
 
         // register the cancel io data
-        cancel.set_io(io_data);
+        cancel.set_io((io_data, Interest::Read));
         // re-check the cancel status
         if cancel.is_canceled() {
             unsafe { cancel.cancel() };
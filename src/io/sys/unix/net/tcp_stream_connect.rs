@@ -3,7 +3,7 @@ use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
-use super::super::{add_socket, co_io_result, IoData};
+use super::super::{add_socket, co_io_result, Interest, IoData};
 use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
 use crate::io::OptionCell;
 use crate::net::TcpStream;
@@ -34,18 +34,25 @@ impl TcpStreamConnect {
                     Ok((stream, addr))
                 })
             })
-            .and_then(|(stream, addr)| {
-                // before yield we must set the socket to nonblocking mode and registe to selector
-                stream.set_nonblocking(true)?;
-
-                add_socket(&stream).map(|io| TcpStreamConnect {
-                    io_data: OptionCell::new(io),
-                    stream: OptionCell::new(stream),
-                    timeout,
-                    addr,
-                    is_connected: false,
-                })
-            })
+            .and_then(|(stream, addr)| Self::from_socket(stream, addr, timeout))
+    }
+
+    // create the connect request from an already configured socket
+    pub fn from_socket(
+        stream: Socket,
+        addr: SocketAddr,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        // before yield we must set the socket to nonblocking mode and registe to selector
+        stream.set_nonblocking(true)?;
+
+        add_socket(&stream).map(|io| TcpStreamConnect {
+            io_data: OptionCell::new(io),
+            stream: OptionCell::new(stream),
+            timeout,
+            addr,
+            is_connected: false,
+        })
     }
 
     #[inline]
@@ -78,7 +85,7 @@ impl TcpStreamConnect {
             co_io_result()?;
 
             // clear the io_flag
-            self.io_data.io_flag.store(false, Ordering::Relaxed);
+            self.io_data.write.io_flag.store(false, Ordering::Relaxed);
 
             match self.stream.connect(&self.addr.into()) {
                 Ok(_) => return Ok(convert_to_stream(self)),
@@ -90,7 +97,7 @@ impl TcpStreamConnect {
                 Err(e) => return Err(e),
             }
 
-            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+            if self.io_data.write.io_flag.swap(false, Ordering::Relaxed) {
                 continue;
             }
 
@@ -109,17 +116,17 @@ impl EventSource for TcpStreamConnect {
         if let Some(dur) = self.timeout {
             get_scheduler()
                 .get_selector()
-                .add_io_timer(&self.io_data, dur);
+                .add_io_timer(&self.io_data, Interest::Write, dur);
         }
-        io_data.co.swap(co, Ordering::Release);
+        io_data.write.co.swap(co, Ordering::Release);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
-            return io_data.schedule();
+        if io_data.write.io_flag.load(Ordering::Acquire) {
+            return io_data.write.schedule();
         }
 
         // register the cancel io data
-        cancel.set_io(io_data);
+        cancel.set_io((io_data, Interest::Write));
         // re-check the cancel status
         if cancel.is_canceled() {
             unsafe { cancel.cancel() };
@@ -0,0 +1,85 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use super::super::{co_io_result, Interest, IoData};
+use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
+use crate::io::AsIoData;
+use crate::net::{udp_batch, RecvMsg, UdpSocket};
+use crate::scheduler::get_scheduler;
+use crate::yield_now::yield_with;
+
+pub struct UdpRecvBatch<'a, 'b> {
+    io_data: &'a IoData,
+    msgs: &'a mut [RecvMsg<'b>],
+    socket: &'a UdpSocket,
+    timeout: Option<Duration>,
+}
+
+impl<'a, 'b> UdpRecvBatch<'a, 'b> {
+    pub fn new(socket: &'a UdpSocket, msgs: &'a mut [RecvMsg<'b>]) -> Self {
+        UdpRecvBatch {
+            io_data: socket.as_io_data(),
+            msgs,
+            socket,
+            timeout: socket.read_timeout().unwrap(),
+        }
+    }
+
+    pub fn done(&mut self) -> io::Result<usize> {
+        loop {
+            co_io_result()?;
+
+            // clear the io_flag
+            self.io_data.read.io_flag.store(false, Ordering::Relaxed);
+
+            match udp_batch::recv(self.socket.as_raw_fd(), self.msgs) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+
+            if self.io_data.read.io_flag.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            // the result is still WouldBlock, need to try again
+            yield_with(self);
+        }
+    }
+}
+
+impl<'a, 'b> EventSource for UdpRecvBatch<'a, 'b> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let handle = co_get_handle(&co);
+        let cancel = handle.get_cancel();
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, Interest::Read, dur);
+        }
+        self.io_data.read.co.swap(co, Ordering::Release);
+
+        // there is event, re-run the coroutine
+        if io_data.read.io_flag.load(Ordering::Acquire) {
+            return io_data.read.schedule();
+        }
+
+        // register the cancel io data
+        cancel.set_io((io_data, Interest::Read));
+        // re-check the cancel status
+        if cancel.is_canceled() {
+            unsafe { cancel.cancel() };
+        }
+    }
+}
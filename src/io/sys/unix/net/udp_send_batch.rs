@@ -0,0 +1,76 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use super::super::{co_io_result, Interest, IoData};
+use crate::coroutine_impl::{CoroutineImpl, EventSource};
+use crate::io::AsIoData;
+use crate::net::{udp_batch, SendMsg, UdpSocket};
+use crate::scheduler::get_scheduler;
+use crate::yield_now::yield_with;
+
+pub struct UdpSendBatch<'a, 'b> {
+    io_data: &'a IoData,
+    msgs: &'a [SendMsg<'b>],
+    socket: &'a UdpSocket,
+    timeout: Option<Duration>,
+}
+
+impl<'a, 'b> UdpSendBatch<'a, 'b> {
+    pub fn new(socket: &'a UdpSocket, msgs: &'a [SendMsg<'b>]) -> Self {
+        UdpSendBatch {
+            io_data: socket.as_io_data(),
+            msgs,
+            socket,
+            timeout: socket.write_timeout().unwrap(),
+        }
+    }
+
+    pub fn done(&mut self) -> io::Result<usize> {
+        loop {
+            co_io_result()?;
+
+            // clear the io_flag
+            self.io_data.write.io_flag.store(false, Ordering::Relaxed);
+
+            match udp_batch::send(self.socket.as_raw_fd(), self.msgs) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+
+            if self.io_data.write.io_flag.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            // the result is still WouldBlock, need to try again
+            yield_with(self);
+        }
+    }
+}
+
+impl<'a, 'b> EventSource for UdpSendBatch<'a, 'b> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, Interest::Write, dur);
+        }
+        self.io_data.write.co.swap(co, Ordering::Release);
+
+        // there is event, re-run the coroutine
+        if io_data.write.io_flag.load(Ordering::Acquire) {
+            io_data.write.schedule();
+        }
+    }
+}
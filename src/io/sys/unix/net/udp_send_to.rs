@@ -3,7 +3,7 @@ use std::sync::atomic::Ordering;
 use std::time::Duration;
 use std::{self, io};
 
-use super::super::{co_io_result, IoData};
+use super::super::{co_io_result, Interest, IoData};
 use crate::coroutine_impl::{CoroutineImpl, EventSource};
 use crate::io::AsIoData;
 use crate::net::UdpSocket;
@@ -34,7 +34,7 @@ impl<'a, A: ToSocketAddrs> UdpSendTo<'a, A> {
             co_io_result()?;
 
             // clear the io_flag
-            self.io_data.io_flag.store(false, Ordering::Relaxed);
+            self.io_data.write.io_flag.store(false, Ordering::Relaxed);
 
             match self.socket.send_to(self.buf, &self.addr) {
                 Ok(n) => return Ok(n),
@@ -49,7 +49,7 @@ impl<'a, A: ToSocketAddrs> UdpSendTo<'a, A> {
                 }
             }
 
-            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+            if self.io_data.write.io_flag.swap(false, Ordering::Relaxed) {
                 continue;
             }
 
@@ -66,13 +66,13 @@ impl<'a, A: ToSocketAddrs> EventSource for UdpSendTo<'a, A> {
         if let Some(dur) = self.timeout {
             get_scheduler()
                 .get_selector()
-                .add_io_timer(self.io_data, dur);
+                .add_io_timer(self.io_data, Interest::Write, dur);
         }
-        self.io_data.co.swap(co, Ordering::Release);
+        self.io_data.write.co.swap(co, Ordering::Release);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
-            io_data.schedule();
+        if io_data.write.io_flag.load(Ordering::Acquire) {
+            io_data.write.schedule();
         }
     }
 }
@@ -1,38 +1,43 @@
 use std::io;
-use std::os::unix::net::{self, SocketAddr};
 use std::sync::atomic::Ordering;
 
 use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
-use crate::io::sys::{co_io_result, IoData};
-use crate::io::{AsIoData, CoIo};
-use crate::os::unix::net::{UnixListener, UnixStream};
+use crate::io::sys::{co_io_result, Interest, IoData};
+use crate::io::AsIoData;
 use crate::yield_now::yield_with;
 
-pub struct UnixListenerAccept<'a> {
+/// the unix listeners that could be accepted in coroutine context
+pub trait UnixAccept: AsIoData {
+    /// the accepted connection
+    type Conn;
+    /// the peer address
+    type Addr;
+    /// accept a connection without blocking
+    fn try_accept(&self) -> io::Result<(Self::Conn, Self::Addr)>;
+}
+
+pub struct UnixListenerAccept<'a, L: UnixAccept> {
     io_data: &'a IoData,
-    socket: &'a net::UnixListener,
+    listener: &'a L,
 }
 
-impl<'a> UnixListenerAccept<'a> {
-    pub fn new(socket: &'a UnixListener) -> io::Result<Self> {
+impl<'a, L: UnixAccept> UnixListenerAccept<'a, L> {
+    pub fn new(listener: &'a L) -> io::Result<Self> {
         Ok(UnixListenerAccept {
-            io_data: socket.0.as_io_data(),
-            socket: socket.0.inner(),
+            io_data: listener.as_io_data(),
+            listener,
         })
     }
 
-    pub fn done(&mut self) -> io::Result<(UnixStream, SocketAddr)> {
+    pub fn done(&mut self) -> io::Result<(L::Conn, L::Addr)> {
         loop {
             co_io_result()?;
 
             // clear the io_flag
-            self.io_data.io_flag.store(false, Ordering::Relaxed);
+            self.io_data.read.io_flag.store(false, Ordering::Relaxed);
 
-            match self.socket.accept() {
-                Ok((s, a)) => {
-                    let s = UnixStream::from_coio(CoIo::new(s)?);
-                    return Ok((s, a));
-                }
+            match self.listener.try_accept() {
+                Ok(ret) => return Ok(ret),
                 Err(e) => {
                     // raw_os_error is faster than kind
                     let raw_err = e.raw_os_error();
@@ -44,7 +49,7 @@ impl<'a> UnixListenerAccept<'a> {
                 }
             }
 
-            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+            if self.io_data.read.io_flag.swap(false, Ordering::Relaxed) {
                 continue;
             }
 
@@ -54,22 +59,22 @@ impl<'a> UnixListenerAccept<'a> {
     }
 }
 
-impl<'a> EventSource for UnixListenerAccept<'a> {
+impl<'a, L: UnixAccept> EventSource for UnixListenerAccept<'a, L> {
     fn subscribe(&mut self, co: CoroutineImpl) {
         let handle = co_get_handle(&co);
         let cancel = handle.get_cancel();
         let io_data = (*self.io_data).clone();
 
         // if there is no timer we don't need to call add_io_timer
-        self.io_data.co.swap(co, Ordering::Release);
+        self.io_data.read.co.swap(co, Ordering::Release);
 
         // there is event happened
-        if io_data.io_flag.load(Ordering::Acquire) {
-            return io_data.schedule();
+        if io_data.read.io_flag.load(Ordering::Acquire) {
+            return io_data.read.schedule();
         }
 
         // register the cancel io data
-        cancel.set_io(io_data);
+        cancel.set_io((io_data, Interest::Read));
         // re-check the cancel status
         if cancel.is_canceled() {
             unsafe { cancel.cancel() };
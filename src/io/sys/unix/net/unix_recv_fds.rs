@@ -0,0 +1,158 @@
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::{cmp, io, mem, ptr};
+
+use super::super::{co_io_result, Interest, IoData};
+use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
+use crate::io::{AsIoData, CoIo};
+use crate::scheduler::get_scheduler;
+use crate::yield_now::yield_with;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const RECV_FLAGS: libc::c_int = 0;
+
+/// receive the data together with the fds passed by `SCM_RIGHTS`
+///
+/// return the bytes received and the number of fds stored in `fds`,
+/// the fds that exceed the capacity of `fds` are closed. if the control
+/// message is truncated, all the received fds are closed and an error
+/// is returned
+pub fn recv_with_fds(fd: RawFd, buf: &mut [u8], fds: &mut [RawFd]) -> io::Result<(usize, usize)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of_val(fds) as libc::c_uint) } as usize;
+    // use u64 to make the control buffer aligned
+    let mut cmsg_buf = vec![0u64; (space + 7) / 8];
+
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    if !fds.is_empty() {
+        hdr.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        hdr.msg_controllen = space as _;
+    }
+
+    let ret = unsafe { libc::recvmsg(fd, &mut hdr, RECV_FLAGS) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut num = 0;
+    unsafe {
+        // the cmsg_len may exceed the truncated buffer on some platforms
+        let end = hdr.msg_control as usize + hdr.msg_controllen as usize;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let len = cmp::min(len, end.saturating_sub(data as usize));
+                // the control buffer is rounded up, it may hold more than `fds`
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    let fd = ptr::read_unaligned(data.add(i));
+                    if num == fds.len() {
+                        libc::close(fd);
+                        continue;
+                    }
+                    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                    libc::ioctl(fd, libc::FIOCLEX);
+                    fds[num] = fd;
+                    num += 1;
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&hdr, cmsg);
+        }
+
+        if hdr.msg_flags & libc::MSG_CTRUNC != 0 {
+            for &fd in &fds[..num] {
+                libc::close(fd);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many fds received, the control message is truncated",
+            ));
+        }
+    }
+    Ok((ret as usize, num))
+}
+
+pub struct UnixRecvFds<'a> {
+    io_data: &'a IoData,
+    fd: RawFd,
+    buf: &'a mut [u8],
+    fds: &'a mut [RawFd],
+    timeout: Option<Duration>,
+}
+
+impl<'a> UnixRecvFds<'a> {
+    pub fn new<T: AsRawFd>(socket: &'a CoIo<T>, buf: &'a mut [u8], fds: &'a mut [RawFd]) -> Self {
+        UnixRecvFds {
+            io_data: socket.as_io_data(),
+            fd: socket.as_raw_fd(),
+            buf,
+            fds,
+            timeout: socket.read_timeout().unwrap(),
+        }
+    }
+
+    pub fn done(&mut self) -> io::Result<(usize, usize)> {
+        loop {
+            co_io_result()?;
+
+            // clear the io_flag
+            self.io_data.read.io_flag.store(false, Ordering::Relaxed);
+
+            match recv_with_fds(self.fd, self.buf, self.fds) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+
+            if self.io_data.read.io_flag.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            // the result is still WouldBlock, need to try again
+            yield_with(self);
+        }
+    }
+}
+
+impl<'a> EventSource for UnixRecvFds<'a> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let handle = co_get_handle(&co);
+        let cancel = handle.get_cancel();
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, Interest::Read, dur);
+        }
+        self.io_data.read.co.swap(co, Ordering::Release);
+
+        // there is event, re-run the coroutine
+        if io_data.read.io_flag.load(Ordering::Acquire) {
+            return io_data.read.schedule();
+        }
+
+        // register the cancel io data
+        cancel.set_io((io_data, Interest::Read));
+        // re-check the cancel status
+        if cancel.is_canceled() {
+            unsafe { cancel.cancel() };
+        }
+    }
+}
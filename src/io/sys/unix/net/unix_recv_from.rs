@@ -3,7 +3,7 @@ use std::sync::atomic::Ordering;
 use std::time::Duration;
 use std::{self, io};
 
-use super::super::{co_io_result, IoData};
+use super::super::{co_io_result, Interest, IoData};
 use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
 use crate::io::AsIoData;
 use crate::os::unix::net::UnixDatagram;
@@ -32,7 +32,7 @@ impl<'a> UnixRecvFrom<'a> {
             co_io_result()?;
 
             // clear the io_flag
-            self.io_data.io_flag.store(false, Ordering::Relaxed);
+            self.io_data.read.io_flag.store(false, Ordering::Relaxed);
 
             match self.socket.recv_from(self.buf) {
                 Ok(n) => return Ok(n),
@@ -47,7 +47,7 @@ impl<'a> UnixRecvFrom<'a> {
                 }
             }
 
-            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+            if self.io_data.read.io_flag.swap(false, Ordering::Relaxed) {
                 continue;
             }
 
@@ -66,17 +66,17 @@ impl<'a> EventSource for UnixRecvFrom<'a> {
         if let Some(dur) = self.timeout {
             get_scheduler()
                 .get_selector()
-                .add_io_timer(self.io_data, dur);
+                .add_io_timer(self.io_data, Interest::Read, dur);
         }
-        self.io_data.co.swap(co, Ordering::Release);
+        self.io_data.read.co.swap(co, Ordering::Release);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
-            return io_data.schedule();
+        if io_data.read.io_flag.load(Ordering::Acquire) {
+            return io_data.read.schedule();
         }
 
         // register the cancel io data
-        cancel.set_io(io_data);
+        cancel.set_io((io_data, Interest::Read));
         // re-check the cancel status
         if cancel.is_canceled() {
             unsafe { cancel.cancel() };
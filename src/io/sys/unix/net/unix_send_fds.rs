@@ -0,0 +1,115 @@
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::{io, mem, ptr};
+
+use super::super::{co_io_result, Interest, IoData};
+use crate::coroutine_impl::{CoroutineImpl, EventSource};
+use crate::io::{AsIoData, CoIo};
+use crate::scheduler::get_scheduler;
+use crate::yield_now::yield_with;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SEND_FLAGS: libc::c_int = 0;
+
+/// send the data together with the fds by `SCM_RIGHTS`
+pub fn send_with_fds(fd: RawFd, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let fds_len = mem::size_of_val(fds) as libc::c_uint;
+    let space = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+    // use u64 to make the control buffer aligned
+    let mut cmsg_buf = vec![0u64; (space + 7) / 8];
+
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    if !fds.is_empty() {
+        hdr.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        hdr.msg_controllen = space as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&hdr);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        }
+    }
+
+    let ret = unsafe { libc::sendmsg(fd, &hdr, SEND_FLAGS) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+pub struct UnixSendFds<'a> {
+    io_data: &'a IoData,
+    fd: RawFd,
+    buf: &'a [u8],
+    fds: &'a [RawFd],
+    timeout: Option<Duration>,
+}
+
+impl<'a> UnixSendFds<'a> {
+    pub fn new<T: AsRawFd>(socket: &'a CoIo<T>, buf: &'a [u8], fds: &'a [RawFd]) -> Self {
+        UnixSendFds {
+            io_data: socket.as_io_data(),
+            fd: socket.as_raw_fd(),
+            buf,
+            fds,
+            timeout: socket.write_timeout().unwrap(),
+        }
+    }
+
+    pub fn done(&mut self) -> io::Result<usize> {
+        loop {
+            co_io_result()?;
+
+            // clear the io_flag
+            self.io_data.write.io_flag.store(false, Ordering::Relaxed);
+
+            match send_with_fds(self.fd, self.buf, self.fds) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+
+            if self.io_data.write.io_flag.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            // the result is still WouldBlock, need to try again
+            yield_with(self);
+        }
+    }
+}
+
+impl<'a> EventSource for UnixSendFds<'a> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, Interest::Write, dur);
+        }
+        self.io_data.write.co.swap(co, Ordering::Release);
+
+        // there is event, re-run the coroutine
+        if io_data.write.io_flag.load(Ordering::Acquire) {
+            io_data.write.schedule();
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::path::Path;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
-use super::super::{add_socket, co_io_result, IoData};
+use super::super::{add_socket, co_io_result, Interest, IoData};
 use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
 use crate::io::{CoIo, OptionCell};
 use crate::os::unix::net::UnixStream;
@@ -20,8 +20,16 @@ pub struct UnixStreamConnect {
 
 impl UnixStreamConnect {
     pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let path = SockAddr::unix(path)?;
-        let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+        Self::with_addr(SockAddr::unix(path)?)
+    }
+
+    pub fn with_addr(path: SockAddr) -> io::Result<Self> {
+        Self::with_type(path, Type::STREAM)
+    }
+
+    /// connect a unix socket of type `ty`, e.g. `SOCK_SEQPACKET`
+    pub fn with_type(path: SockAddr, ty: Type) -> io::Result<Self> {
+        let socket = Socket::new(Domain::UNIX, ty, None)?;
         // before yield we must set the socket to nonblocking mode and registe to selector
         socket.set_nonblocking(true)?;
         add_socket(&socket).map(|io| UnixStreamConnect {
@@ -48,33 +56,36 @@ impl UnixStreamConnect {
     }
 
     pub fn done(&mut self) -> io::Result<UnixStream> {
-        fn convert_to_stream(s: &mut UnixStreamConnect) -> UnixStream {
-            let stream = s.stream.take().into();
-            UnixStream::from_coio(CoIo::from_raw(stream, s.io_data.take()))
-        }
+        self.done_with(|socket, io| UnixStream::from_coio(CoIo::from_raw(socket.into(), io)))
+    }
 
+    /// finish the connect and convert the registered socket with `f`
+    pub fn done_with<T, F>(&mut self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(Socket, IoData) -> T,
+    {
         // first check if it's already connected
         if self.is_connected {
-            return Ok(convert_to_stream(self));
+            return Ok(f(self.stream.take(), self.io_data.take()));
         }
 
         loop {
             co_io_result()?;
 
             // clear the io_flag
-            self.io_data.io_flag.store(false, Ordering::Relaxed);
+            self.io_data.write.io_flag.store(false, Ordering::Relaxed);
 
             match self.stream.connect(&self.path) {
-                Ok(_) => return Ok(convert_to_stream(self)),
+                Ok(_) => return Ok(f(self.stream.take(), self.io_data.take())),
                 Err(ref e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
                 Err(ref e) if e.raw_os_error() == Some(libc::EALREADY) => {}
                 Err(ref e) if e.raw_os_error() == Some(libc::EISCONN) => {
-                    return Ok(convert_to_stream(self));
+                    return Ok(f(self.stream.take(), self.io_data.take()));
                 }
                 Err(e) => return Err(e),
             }
 
-            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+            if self.io_data.write.io_flag.swap(false, Ordering::Relaxed) {
                 continue;
             }
 
@@ -92,16 +103,16 @@ impl EventSource for UnixStreamConnect {
 
         get_scheduler()
             .get_selector()
-            .add_io_timer(&self.io_data, Duration::from_secs(2));
-        io_data.co.swap(co, Ordering::Release);
+            .add_io_timer(&self.io_data, Interest::Write, Duration::from_secs(2));
+        io_data.write.co.swap(co, Ordering::Release);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
-            return io_data.schedule();
+        if io_data.write.io_flag.load(Ordering::Acquire) {
+            return io_data.write.schedule();
         }
 
         // register the cancel io data
-        cancel.set_io(io_data);
+        cancel.set_io((io_data, Interest::Write));
         // re-check the cancel status
         if cancel.is_canceled() {
             unsafe { cancel.cancel() };
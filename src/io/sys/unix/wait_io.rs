@@ -2,11 +2,16 @@
 //! `wait_io` is a function that can be used in coroutine
 //! context to wait on the io events
 //!
+use std::io;
+use std::os::unix::io::RawFd;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
+use super::{co_io_result, Interest};
 use crate::cancel::Cancel;
-use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
+use crate::coroutine_impl::{co_get_handle, is_coroutine, CoroutineImpl, EventSource};
 use crate::io as io_impl;
+use crate::scheduler::get_scheduler;
 use crate::yield_now::yield_with;
 
 pub struct RawIoBlock<'a> {
@@ -23,15 +28,116 @@ impl<'a> EventSource for RawIoBlock<'a> {
     fn subscribe(&mut self, co: CoroutineImpl) {
         let handle = co_get_handle(&co);
         let io_data = (*self.io_data).clone();
-        self.io_data.co.swap(co, Ordering::Release);
+        // wait on the read waiter but also waken up by write events
+        io_data.wait_any.store(true, Ordering::Release);
+        self.io_data.read.co.swap(co, Ordering::Release);
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
-            return io_data.schedule();
+        if io_data.read.io_flag.load(Ordering::Acquire)
+            || io_data.write.io_flag.load(Ordering::Acquire)
+        {
+            return io_data.read.schedule();
         }
 
         let cancel = handle.get_cancel();
         // register the cancel io data
-        cancel.set_io(io_data);
+        cancel.set_io((io_data, Interest::Read));
+        // re-check the cancel status
+        if cancel.is_canceled() {
+            unsafe { cancel.cancel() };
+        }
+    }
+
+    /// after yield back process
+    fn yield_back(&self, cancel: &'static Cancel) {
+        self.io_data.wait_any.store(false, Ordering::Release);
+        cancel.clear_cancel_bit();
+    }
+}
+
+// block on one io direction with an optional timeout
+struct IoReady<'a> {
+    io_data: &'a io_impl::IoData,
+    interest: Interest,
+    timeout: Option<Duration>,
+}
+
+impl<'a> IoReady<'a> {
+    fn wait(&self) -> io::Result<()> {
+        let waiter = self.io_data.waiter(self.interest);
+        // consume the event that already happened
+        if waiter.io_flag.swap(false, Ordering::Acquire) {
+            return Ok(());
+        }
+
+        // block the thread with poll in thread context
+        if !is_coroutine() {
+            return self.poll();
+        }
+
+        yield_with(self);
+        co_io_result()?;
+        waiter.io_flag.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn poll(&self) -> io::Result<()> {
+        poll_fd(self.io_data.fd, self.interest, self.timeout)
+    }
+}
+
+// block the thread until the fd is ready for the interest or the timeout expires
+pub(crate) fn poll_fd(fd: RawFd, interest: Interest, timeout: Option<Duration>) -> io::Result<()> {
+    let events = match interest {
+        Interest::Read => libc::POLLIN,
+        Interest::Write => libc::POLLOUT,
+    };
+    let mut fds = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    let timeout = timeout
+        .map(|dur| {
+            // round up so that a sub millisecond timeout doesn't become a busy poll
+            let ms = (dur.as_nanos() + 999_999) / 1_000_000;
+            ::std::cmp::min(ms, libc::c_int::MAX as u128) as libc::c_int
+        })
+        .unwrap_or(-1);
+    loop {
+        match unsafe { libc::poll(&mut fds, 1, timeout) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            0 => return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout")),
+            _ => return Ok(()),
+        }
+    }
+}
+
+impl<'a> EventSource for IoReady<'a> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let handle = co_get_handle(&co);
+        let io_data = (*self.io_data).clone();
+        let waiter = io_data.waiter(self.interest);
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, self.interest, dur);
+        }
+        waiter.co.swap(co, Ordering::Release);
+
+        // there is event, re-run the coroutine
+        if waiter.io_flag.load(Ordering::Acquire) {
+            return waiter.schedule();
+        }
+
+        let cancel = handle.get_cancel();
+        // register the cancel io data
+        cancel.set_io((io_data, self.interest));
         // re-check the cancel status
         if cancel.is_canceled() {
             unsafe { cancel.cancel() };
@@ -45,11 +151,25 @@ impl<'a> EventSource for RawIoBlock<'a> {
 }
 
 /// This is trait that can block on io events but doing nothong about io
+///
+/// the io objects are registered in edge triggered mode, after the
+/// `wait_*` returns you should do the io until it returns `WouldBlock`
+/// before waiting again
 pub trait WaitIo {
     /// reset the io before io operation
     fn reset_io(&self);
     /// block on read/write event
     fn wait_io(&self);
+    /// block until the io is readable or the timeout expires
+    ///
+    /// a `TimedOut` error is returned when the timeout expires. this
+    /// only waits on the read direction, so another coroutine could
+    /// wait on the write direction of the same io object at the same time
+    fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<()>;
+    /// block until the io is writable or the timeout expires
+    ///
+    /// a `TimedOut` error is returned when the timeout expires
+    fn wait_writable(&self, timeout: Option<Duration>) -> io::Result<()>;
 }
 
 impl<T: io_impl::AsIoData> WaitIo for T {
@@ -61,10 +181,59 @@ impl<T: io_impl::AsIoData> WaitIo for T {
     fn wait_io(&self) {
         let io_data = self.as_io_data();
         // when io flag is set we do nothing
-        if io_data.io_flag.load(Ordering::Relaxed) {
+        if io_data.read.io_flag.load(Ordering::Relaxed)
+            || io_data.write.io_flag.load(Ordering::Relaxed)
+        {
             return;
         }
         let blocker = RawIoBlock::new(self.as_io_data());
         yield_with(&blocker);
     }
+
+    fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<()> {
+        IoReady {
+            io_data: self.as_io_data(),
+            interest: Interest::Read,
+            timeout,
+        }
+        .wait()
+    }
+
+    fn wait_writable(&self, timeout: Option<Duration>) -> io::Result<()> {
+        IoReady {
+            io_data: self.as_io_data(),
+            interest: Interest::Write,
+            timeout,
+        }
+        .wait()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::TcpListener;
+    use std::io::Write;
+
+    #[test]
+    fn wait_ready_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let j = go!(move || {
+            let (s, _) = listener.accept().unwrap();
+            s.wait_writable(Some(Duration::from_secs(1))).unwrap();
+            let e = s
+                .wait_readable(Some(Duration::from_millis(50)))
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            // the peer would write after we get here
+            s.wait_readable(Some(Duration::from_secs(5))).unwrap();
+        });
+
+        let mut s = ::std::net::TcpStream::connect(addr).unwrap();
+        ::std::thread::sleep(Duration::from_millis(200));
+        s.write_all(b"hello").unwrap();
+        j.join().unwrap();
+    }
 }
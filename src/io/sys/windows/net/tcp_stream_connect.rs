@@ -39,34 +39,42 @@ impl TcpStreamConnect {
                     Ok((socket, addr))
                 })
             })
-            .and_then(|(socket, addr)| {
-                // windows need to bind first when call ConnectEx API
-                let any = match addr {
-                    SocketAddr::V4(..) => {
-                        let any = Ipv4Addr::new(0, 0, 0, 0);
-                        let addr = SocketAddrV4::new(any, 0);
-                        SocketAddr::V4(addr)
-                    }
-                    SocketAddr::V6(..) => {
-                        let any = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0);
-                        let addr = SocketAddrV6::new(any, 0, 0, 0);
-                        SocketAddr::V6(addr)
-                    }
-                };
+            .and_then(|(socket, addr)| Self::from_socket(socket, addr, timeout))
+    }
+
+    // create the connect request from an already configured socket
+    pub fn from_socket(
+        socket: socket2::Socket,
+        addr: SocketAddr,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        // windows need to bind first when call ConnectEx API
+        let any = match addr {
+            SocketAddr::V4(..) => {
+                let any = Ipv4Addr::new(0, 0, 0, 0);
+                let addr = SocketAddrV4::new(any, 0);
+                SocketAddr::V4(addr)
+            }
+            SocketAddr::V6(..) => {
+                let any = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0);
+                let addr = SocketAddrV6::new(any, 0, 0, 0);
+                SocketAddr::V6(addr)
+            }
+        };
 
-                socket.bind(&any.into()).map(|_| socket.into()).and_then(
-                    |s: std::net::TcpStream| {
-                        // must register io first
-                        s.set_nonblocking(true)?;
-                        add_socket(&s).map(|_io| TcpStreamConnect {
-                            io_data: EventData::new(s.as_raw_socket() as HANDLE),
-                            addr,
-                            stream: OptionCell::new(s),
-                            timeout,
-                            can_drop: DelayDrop::new(),
-                        })
-                    },
-                )
+        socket
+            .bind(&any.into())
+            .map(|_| socket.into())
+            .and_then(|s: std::net::TcpStream| {
+                // must register io first
+                s.set_nonblocking(true)?;
+                add_socket(&s).map(|_io| TcpStreamConnect {
+                    io_data: EventData::new(s.as_raw_socket() as HANDLE),
+                    addr,
+                    stream: OptionCell::new(s),
+                    timeout,
+                    can_drop: DelayDrop::new(),
+                })
             })
     }
 
@@ -0,0 +1,370 @@
+//! zero copy data transfer between io objects
+//!
+//! on linux the data is moved by `sendfile(2)` and `splice(2)` without
+//! being copied through the user space, the other platforms fall back
+//! to plain read/write. the coroutine would park on the io readiness
+//! when the syscall returns `EAGAIN`
+
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use super::{AsIoData, WaitIo};
+use crate::coroutine::scope;
+
+// the max bytes transferred by one round
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[inline]
+fn would_block(e: &io::Error) -> bool {
+    // raw_os_error is faster than kind
+    let raw_err = e.raw_os_error();
+    raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK)
+}
+
+// run the syscall until it's done, wait for the io readiness on EAGAIN
+pub(super) fn retry<F, W>(mut f: F, mut wait: W) -> io::Result<usize>
+where
+    F: FnMut() -> isize,
+    W: FnMut() -> io::Result<()>,
+{
+    loop {
+        let ret = f();
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+        let e = io::Error::last_os_error();
+        if would_block(&e) {
+            wait()?;
+        } else if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn read<T: AsRawFd + AsIoData>(
+    io: &T,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let fd = io.as_raw_fd();
+    retry(
+        || unsafe { libc::read(fd, buf.as_mut_ptr() as *mut _, buf.len()) },
+        || io.wait_readable(timeout),
+    )
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn write_all<T: AsRawFd + AsIoData>(
+    io: &T,
+    mut buf: &[u8],
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    let fd = io.as_raw_fd();
+    while !buf.is_empty() {
+        let n = retry(
+            || unsafe { libc::write(fd, buf.as_ptr() as *const _, buf.len()) },
+            || io.wait_writable(timeout),
+        )?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "failed to write whole buffer",
+            ));
+        }
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+/// send `len` bytes of the file starting at `offset` to the io object
+///
+/// return the bytes sent, which is less than `len` only when the end
+/// of the file is reached
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn sendfile<T: AsRawFd + AsIoData>(
+    io: &T,
+    file: &File,
+    offset: u64,
+    len: usize,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let (out_fd, in_fd) = (io.as_raw_fd(), file.as_raw_fd());
+    let mut sent = 0;
+    while sent < len {
+        let mut off = (offset + sent as u64) as libc::off_t;
+        let n = retry(
+            || unsafe { libc::sendfile(out_fd, in_fd, &mut off, len - sent) },
+            || io.wait_writable(timeout),
+        )?;
+        if n == 0 {
+            // reach the end of the file
+            break;
+        }
+        sent += n;
+    }
+    Ok(sent)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn sendfile<T: AsRawFd + AsIoData>(
+    io: &T,
+    file: &File,
+    offset: u64,
+    len: usize,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+
+    let mut buf = vec![0; cmp::min(len, CHUNK_SIZE)];
+    let mut sent = 0;
+    while sent < len {
+        let size = cmp::min(len - sent, buf.len());
+        let n = file.read_at(&mut buf[..size], offset + sent as u64)?;
+        if n == 0 {
+            // reach the end of the file
+            break;
+        }
+        write_all(io, &buf[..n], timeout)?;
+        sent += n;
+    }
+    Ok(sent)
+}
+
+// move the data through a pipe with splice
+#[cfg(any(target_os = "linux", target_os = "android"))]
+struct Transfer {
+    rd: libc::c_int,
+    wr: libc::c_int,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Transfer {
+    fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Transfer {
+            rd: fds[0],
+            wr: fds[1],
+        })
+    }
+
+    fn transfer<R, W>(&mut self, from: &R, to: &W, len: usize) -> io::Result<usize>
+    where
+        R: AsRawFd + AsIoData,
+        W: AsRawFd + AsIoData,
+    {
+        use std::ptr;
+        const FLAGS: libc::c_uint = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+
+        // the pipe buffer is 64k by default, don't move more than that
+        let len = cmp::min(len, CHUNK_SIZE);
+        let (in_fd, out_fd) = (from.as_raw_fd(), to.as_raw_fd());
+        let (rd, wr) = (self.rd, self.wr);
+        let n = retry(
+            || unsafe { libc::splice(in_fd, ptr::null_mut(), wr, ptr::null_mut(), len, FLAGS) },
+            || from.wait_readable(None),
+        )?;
+
+        // drain the pipe, all the data must be moved out
+        let mut left = n;
+        while left > 0 {
+            let m = retry(
+                || unsafe {
+                    libc::splice(rd, ptr::null_mut(), out_fd, ptr::null_mut(), left, FLAGS)
+                },
+                || to.wait_writable(None),
+            )?;
+            if m == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ));
+            }
+            left -= m;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.rd);
+            libc::close(self.wr);
+        }
+    }
+}
+
+// move the data through a user space buffer
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+struct Transfer {
+    buf: Vec<u8>,
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+impl Transfer {
+    fn new() -> io::Result<Self> {
+        Ok(Transfer {
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+
+    fn transfer<R, W>(&mut self, from: &R, to: &W, len: usize) -> io::Result<usize>
+    where
+        R: AsRawFd + AsIoData,
+        W: AsRawFd + AsIoData,
+    {
+        let len = cmp::min(len, self.buf.len());
+        let n = read(from, &mut self.buf[..len], None)?;
+        write_all(to, &self.buf[..n], None)?;
+        Ok(n)
+    }
+}
+
+/// move at most `len` bytes from `from` to `to`
+///
+/// return the bytes moved, `0` means `from` reaches EOF. on linux the
+/// data is moved by `splice(2)` through a pipe so it's never copied
+/// into the user space, the other platforms fall back to read/write
+pub fn splice<R, W>(from: &R, to: &W, len: usize) -> io::Result<usize>
+where
+    R: AsRawFd + AsIoData,
+    W: AsRawFd + AsIoData,
+{
+    if len == 0 {
+        return Ok(0);
+    }
+    Transfer::new()?.transfer(from, to, len)
+}
+
+#[inline]
+fn shutdown<T: AsRawFd>(io: &T, how: libc::c_int) {
+    // the io may be not a socket, just ignore the error
+    unsafe { libc::shutdown(io.as_raw_fd(), how) };
+}
+
+// copy data in one direction until EOF
+fn copy<R, W>(from: &R, to: &W) -> io::Result<u64>
+where
+    R: AsRawFd + AsIoData,
+    W: AsRawFd + AsIoData,
+{
+    let mut transfer = Transfer::new()?;
+    let mut total = 0;
+    loop {
+        match transfer.transfer(from, to, CHUNK_SIZE) {
+            Ok(0) => {
+                // pass the EOF to the peer
+                shutdown(to, libc::SHUT_WR);
+                return Ok(total);
+            }
+            Ok(n) => total += n as u64,
+            Err(e) => {
+                // wake up the other direction
+                shutdown(from, libc::SHUT_RDWR);
+                shutdown(to, libc::SHUT_RDWR);
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// copy data between `a` and `b` in both directions until both reach EOF
+///
+/// the EOF of one side is passed to the other side by shutting down
+/// its write direction, this is useful for proxies. return the bytes
+/// copied from `a` to `b` and from `b` to `a`
+pub fn copy_bidirectional<A, B>(a: &A, b: &B) -> io::Result<(u64, u64)>
+where
+    A: AsRawFd + AsIoData + Sync,
+    B: AsRawFd + AsIoData + Sync,
+{
+    scope(|s| {
+        let h = go!(s, move || copy(b, a));
+        let a_to_b = copy(a, b);
+        let b_to_a = h.join();
+        Ok((a_to_b?, b_to_a?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{TcpListener, TcpStream};
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    fn pair(listener: &TcpListener) -> (TcpStream, TcpStream) {
+        let addr = listener.local_addr().unwrap();
+        let j = go!(move || TcpStream::connect(addr).unwrap());
+        let (s, _) = listener.accept().unwrap();
+        (j.join().unwrap(), s)
+    }
+
+    #[test]
+    fn sendfile_to_stream() {
+        let mut file = tempfile().unwrap();
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        file.write_all(&data).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (a, mut b) = pair(&listener);
+        let j = go!(move || {
+            let n = a.sendfile(&file, 100, 150_000).unwrap();
+            assert_eq!(n, 150_000);
+            // only the remaining bytes would be sent at EOF
+            let n = a.sendfile(&file, 190_000, 20_000).unwrap();
+            assert_eq!(n, 10_000);
+        });
+
+        let mut buf = vec![0; 160_000];
+        b.read_exact(&mut buf).unwrap();
+        j.join().unwrap();
+        assert_eq!(&buf[..150_000], &data[100..150_100]);
+        assert_eq!(&buf[150_000..], &data[190_000..]);
+    }
+
+    #[test]
+    fn copy_between_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (mut client, a) = pair(&listener);
+        let (b, mut server) = pair(&listener);
+
+        let proxy = go!(move || copy_bidirectional(&a, &b).unwrap());
+        let echo = go!(move || {
+            let mut buf = Vec::new();
+            server.read_to_end(&mut buf).unwrap();
+            server.write_all(&buf).unwrap();
+        });
+
+        let data = vec![7u8; 300_000];
+        client.write_all(&data).unwrap();
+        client.shutdown(::std::net::Shutdown::Write).unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        echo.join().unwrap();
+        assert_eq!(proxy.join().unwrap(), (300_000, 300_000));
+    }
+
+    // an anonymous file that would be removed on drop
+    fn tempfile() -> io::Result<File> {
+        let path = ::std::env::temp_dir().join(format!("cogo_sendfile_{}", ::std::process::id()));
+        let file = ::std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        ::std::fs::remove_file(&path)?;
+        Ok(file)
+    }
+}
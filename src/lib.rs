@@ -50,6 +50,8 @@ pub mod cqueue;
 pub mod io;
 pub mod net;
 pub mod os;
+#[cfg(unix)]
+pub mod pty;
 #[macro_use]
 pub mod std;
 
@@ -0,0 +1,480 @@
+//! coroutine aware wrapper of `socket2::Socket` for arbitrary protocols
+//!
+//! the socket is registered to the selector like the other io objects, the
+//! operations first try the nonblocking syscall and park the coroutine on
+//! the io readiness when it returns `EAGAIN`. in thread context they are
+//! just the blocking syscalls.
+
+use std::fmt;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::mem::{self, MaybeUninit};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::io::{AsIoData, CoIo, IoData, WaitIo};
+
+#[inline]
+fn would_block(e: &io::Error) -> bool {
+    // raw_os_error is faster than kind
+    let raw_err = e.raw_os_error();
+    raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK)
+}
+
+#[inline]
+fn as_uninit(buf: &mut [u8]) -> &mut [MaybeUninit<u8>] {
+    // the socket only writes initialized bytes into the buffer
+    unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) }
+}
+
+/// The metadata of a message received by [`CoSocket::recvmsg`]
+///
+/// [`CoSocket::recvmsg`]: struct.CoSocket.html#method.recvmsg
+#[derive(Debug)]
+pub struct RecvMeta {
+    /// the number of bytes received
+    pub len: usize,
+    /// the source address, `None` for the connected sockets
+    pub addr: Option<SockAddr>,
+    /// the number of bytes stored in the control buffer
+    pub control_len: usize,
+    /// the flags of the received message, e.g. `MSG_TRUNC` and `MSG_CTRUNC`
+    pub flags: libc::c_int,
+}
+
+/// A coroutine aware socket of any domain, type and protocol.
+///
+/// This is useful for the protocols that have no dedicated type, e.g. raw
+/// ICMP, packet sockets, SCTP or netlink. The socket options are accessed
+/// through [`socket`].
+///
+/// [`socket`]: #method.socket
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::net::CoSocket;
+/// use socket2::{Domain, Protocol, Type};
+///
+/// let socket = CoSocket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)).unwrap();
+/// let addr = "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap().into();
+/// // an ICMP echo request with the checksum filled
+/// socket.send_to(&[8, 0, 0xf7, 0xff, 0, 0, 0, 0], &addr).unwrap();
+/// let mut buf = [0; 1024];
+/// let (n, from) = socket.recv_from(&mut buf).unwrap();
+/// ```
+pub struct CoSocket(CoIo<Socket>);
+
+impl fmt::Debug for CoSocket {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut builder = fmt.debug_struct("CoSocket");
+        builder.field("fd", &self.as_raw_fd());
+        if let Ok(addr) = self.local_addr() {
+            builder.field("local", &addr);
+        }
+        builder.finish()
+    }
+}
+
+impl CoSocket {
+    /// Creates a new socket and registers it to the selector.
+    pub fn new(domain: Domain, ty: Type, protocol: Option<Protocol>) -> io::Result<CoSocket> {
+        CoSocket::from_socket(Socket::new(domain, ty, protocol)?)
+    }
+
+    /// Registers an existing socket to the selector.
+    pub fn from_socket(socket: Socket) -> io::Result<CoSocket> {
+        Ok(CoSocket(CoIo::new(socket)?))
+    }
+
+    /// Returns the underlying socket, used to get and set the socket options.
+    ///
+    /// Don't do io on it directly, the io would not block the coroutine.
+    pub fn socket(&self) -> &Socket {
+        self.0.inner()
+    }
+
+    /// Binds the socket to the given address.
+    pub fn bind(&self, addr: &SockAddr) -> io::Result<()> {
+        self.0.inner().bind(addr)
+    }
+
+    /// Marks the socket as ready to accept incoming connections.
+    pub fn listen(&self, backlog: i32) -> io::Result<()> {
+        self.0.inner().listen(backlog)
+    }
+
+    // run a read operation until it's not blocked
+    fn read_op<T, F>(&self, mut f: F) -> io::Result<T>
+    where
+        F: FnMut(&Socket) -> io::Result<T>,
+    {
+        if !self.0.ctx_check()? {
+            // this can't be nonblocking!!
+            return f(self.0.inner());
+        }
+
+        let timeout = self.0.read_timeout()?;
+        loop {
+            self.0.io_reset_read();
+            match f(self.0.inner()) {
+                Err(ref e) if would_block(e) => {}
+                ret => return ret,
+            }
+            self.wait_readable(timeout)?;
+        }
+    }
+
+    // run a write operation until it's not blocked
+    fn write_op<T, F>(&self, mut f: F) -> io::Result<T>
+    where
+        F: FnMut(&Socket) -> io::Result<T>,
+    {
+        if !self.0.ctx_check()? {
+            // this can't be nonblocking!!
+            return f(self.0.inner());
+        }
+
+        let timeout = self.0.write_timeout()?;
+        loop {
+            self.0.io_reset_write();
+            match f(self.0.inner()) {
+                Err(ref e) if would_block(e) => {}
+                ret => return ret,
+            }
+            self.wait_writable(timeout)?;
+        }
+    }
+
+    /// Connects the socket to the address, the write timeout applies.
+    pub fn connect(&self, addr: &SockAddr) -> io::Result<()> {
+        if !self.0.ctx_check()? {
+            return self.0.inner().connect(addr);
+        }
+
+        let timeout = self.0.write_timeout()?;
+        loop {
+            self.0.io_reset_write();
+            match self.0.inner().connect(addr) {
+                Ok(()) => return Ok(()),
+                Err(e) => match e.raw_os_error() {
+                    Some(libc::EISCONN) => return Ok(()),
+                    // the unix sockets return EAGAIN when the backlog is full
+                    Some(libc::EINPROGRESS) | Some(libc::EALREADY) | Some(libc::EAGAIN) => {}
+                    _ => return Err(e),
+                },
+            }
+            self.wait_writable(timeout)?;
+            if let Some(e) = self.0.inner().take_error()? {
+                return Err(e);
+            }
+        }
+    }
+
+    /// Accepts a new incoming connection, the read timeout applies.
+    pub fn accept(&self) -> io::Result<(CoSocket, SockAddr)> {
+        let (s, addr) = self.read_op(|s| s.accept())?;
+        Ok((CoSocket::from_socket(s)?, addr))
+    }
+
+    /// Receives data on the connected socket.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_op(|s| s.recv(as_uninit(buf)))
+    }
+
+    /// Receives data on the socket and returns the source address.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SockAddr)> {
+        self.read_op(|s| s.recv_from(as_uninit(buf)))
+    }
+
+    /// Sends data on the connected socket.
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.write_op(|s| s.send(buf))
+    }
+
+    /// Sends data on the socket to the given address.
+    pub fn send_to(&self, buf: &[u8], addr: &SockAddr) -> io::Result<usize> {
+        self.write_op(|s| s.send_to(buf, addr))
+    }
+
+    /// Sends a message with `sendmsg(2)`.
+    ///
+    /// The `control` buffer holds the ancillary data that is built with the
+    /// `CMSG_*` macros, it should be aligned as `cmsghdr`.
+    pub fn sendmsg(
+        &self,
+        bufs: &[IoSlice],
+        addr: Option<&SockAddr>,
+        control: &[u8],
+        flags: libc::c_int,
+    ) -> io::Result<usize> {
+        let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+        if let Some(addr) = addr {
+            hdr.msg_name = addr.as_ptr() as *mut libc::c_void;
+            hdr.msg_namelen = addr.len();
+        }
+        // IoSlice is guaranteed to be ABI compatible with iovec
+        hdr.msg_iov = bufs.as_ptr() as *mut libc::iovec;
+        hdr.msg_iovlen = bufs.len() as _;
+        if !control.is_empty() {
+            hdr.msg_control = control.as_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = control.len() as _;
+        }
+        let fd = self.as_raw_fd();
+        self.write_op(|_| match unsafe { libc::sendmsg(fd, &hdr, flags) } {
+            -1 => Err(io::Error::last_os_error()),
+            n => Ok(n as usize),
+        })
+    }
+
+    /// Receives a message with `recvmsg(2)`.
+    ///
+    /// The ancillary data is stored in `control`, it should be aligned as
+    /// `cmsghdr` so that it could be parsed with the `CMSG_*` macros.
+    pub fn recvmsg(
+        &self,
+        bufs: &mut [IoSliceMut],
+        control: &mut [u8],
+        flags: libc::c_int,
+    ) -> io::Result<RecvMeta> {
+        let fd = self.as_raw_fd();
+        let iov = bufs.as_mut_ptr() as *mut libc::iovec;
+        let iovlen = bufs.len();
+        let (control_ptr, control_cap) = (control.as_mut_ptr(), control.len());
+        self.read_op(|_| {
+            let (ret, addr) = unsafe {
+                SockAddr::init(|storage, len| {
+                    let mut hdr: libc::msghdr = mem::zeroed();
+                    hdr.msg_name = storage as *mut libc::c_void;
+                    hdr.msg_namelen = *len;
+                    hdr.msg_iov = iov;
+                    hdr.msg_iovlen = iovlen as _;
+                    if control_cap > 0 {
+                        hdr.msg_control = control_ptr as *mut libc::c_void;
+                        hdr.msg_controllen = control_cap as _;
+                    }
+                    match libc::recvmsg(fd, &mut hdr, flags) {
+                        -1 => Err(io::Error::last_os_error()),
+                        n => {
+                            *len = hdr.msg_namelen;
+                            Ok((n as usize, hdr.msg_controllen as usize, hdr.msg_flags))
+                        }
+                    }
+                })?
+            };
+            let (len, control_len, flags) = ret;
+            Ok(RecvMeta {
+                len,
+                addr: if addr.len() > 0 { Some(addr) } else { None },
+                control_len,
+                flags,
+            })
+        })
+    }
+
+    /// Returns the local address of the socket.
+    pub fn local_addr(&self) -> io::Result<SockAddr> {
+        self.0.inner().local_addr()
+    }
+
+    /// Returns the address of the peer.
+    pub fn peer_addr(&self) -> io::Result<SockAddr> {
+        self.0.inner().peer_addr()
+    }
+
+    /// Shuts down the read, write, or both halves of this connection.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.0.inner().shutdown(how)
+    }
+
+    /// Sets the read timeout, `None` means blocking forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.inner().set_read_timeout(timeout)?;
+        self.0.set_read_timeout(timeout)
+    }
+
+    /// Sets the write timeout, `None` means blocking forever.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.inner().set_write_timeout(timeout)?;
+        self.0.set_write_timeout(timeout)
+    }
+
+    /// Returns the read timeout of this socket.
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        self.0.read_timeout()
+    }
+
+    /// Returns the write timeout of this socket.
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        self.0.write_timeout()
+    }
+
+    /// Moves the socket into or out of nonblocking mode.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.0.set_nonblocking(nonblocking)
+    }
+
+    /// Returns the value of the `SO_ERROR` option.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.0.inner().take_error()
+    }
+
+    /// Creates a new independently owned handle to the underlying socket.
+    pub fn try_clone(&self) -> io::Result<CoSocket> {
+        CoSocket::from_socket(self.0.inner().try_clone()?)
+    }
+}
+
+impl AsRawFd for CoSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsIoData for CoSocket {
+    fn as_io_data(&self) -> &IoData {
+        self.0.as_io_data()
+    }
+}
+
+impl FromRawFd for CoSocket {
+    unsafe fn from_raw_fd(fd: RawFd) -> CoSocket {
+        CoSocket::from_socket(Socket::from_raw_fd(fd))
+            .unwrap_or_else(|e| panic!("from_raw_fd for CoSocket, err = {:?}", e))
+    }
+}
+
+impl IntoRawFd for CoSocket {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn addr(s: &str) -> SockAddr {
+        s.parse::<SocketAddr>().unwrap().into()
+    }
+
+    #[test]
+    fn tcp_connect_accept() {
+        let listener = CoSocket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        listener.bind(&addr("127.0.0.1:0")).unwrap();
+        listener.listen(16).unwrap();
+        let local = listener.local_addr().unwrap();
+
+        let j = go!(move || {
+            let (s, _) = listener.accept().unwrap();
+            let mut buf = [0; 16];
+            let n = s.recv(&mut buf).unwrap();
+            s.send(&buf[..n]).unwrap();
+        });
+
+        let j2 = go!(move || {
+            let s = CoSocket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+            s.connect(&local).unwrap();
+            s.send(b"hello").unwrap();
+            let mut buf = [0; 16];
+            let n = s.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"hello");
+        });
+        j.join().unwrap();
+        j2.join().unwrap();
+    }
+
+    #[test]
+    fn udp_recv_timeout() {
+        let s = CoSocket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        s.bind(&addr("127.0.0.1:0")).unwrap();
+        s.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        let local = s.local_addr().unwrap();
+
+        let j = go!(move || {
+            let mut buf = [0; 16];
+            let e = s.recv_from(&mut buf).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            s.set_read_timeout(None).unwrap();
+            let (n, from) = s.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"ping");
+            from
+        });
+
+        crate::coroutine::sleep(Duration::from_millis(200));
+        let c = CoSocket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        c.bind(&addr("127.0.0.1:0")).unwrap();
+        c.send_to(b"ping", &local).unwrap();
+        let from = j.join().unwrap();
+        assert_eq!(from.as_socket(), c.local_addr().unwrap().as_socket());
+    }
+
+    // list the network interfaces with a netlink RTM_GETLINK dump
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn netlink_dump() {
+        const NLM_F_REQUEST: u16 = 1;
+        const NLM_F_DUMP: u16 = 0x300;
+        const NLMSG_DONE: u16 = 3;
+        const RTM_NEWLINK: u16 = 16;
+        const RTM_GETLINK: u16 = 18;
+
+        let s = CoSocket::new(
+            Domain::from(libc::AF_NETLINK),
+            Type::RAW,
+            Some(Protocol::from(libc::NETLINK_ROUTE)),
+        )
+        .unwrap();
+        let (_, kernel) = unsafe {
+            SockAddr::init(|storage, len| {
+                let nl = &mut *(storage as *mut libc::sockaddr_nl);
+                nl.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+                *len = mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+                Ok(())
+            })
+        }
+        .unwrap();
+
+        // nlmsghdr followed by ifinfomsg
+        let mut req = Vec::with_capacity(32);
+        req.extend_from_slice(&32u32.to_ne_bytes());
+        req.extend_from_slice(&RTM_GETLINK.to_ne_bytes());
+        req.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+        req.extend_from_slice(&1u32.to_ne_bytes());
+        req.extend_from_slice(&0u32.to_ne_bytes());
+        req.resize(32, 0);
+
+        let j = go!(move || {
+            s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            s.sendmsg(&[IoSlice::new(&req)], Some(&kernel), &[], 0)
+                .unwrap();
+
+            let mut links = 0;
+            let mut buf = vec![0u8; 32 * 1024];
+            loop {
+                let meta = s
+                    .recvmsg(&mut [IoSliceMut::new(&mut buf)], &mut [], 0)
+                    .unwrap();
+                assert_eq!(meta.flags & libc::MSG_TRUNC, 0);
+                let mut msg = &buf[..meta.len];
+                while msg.len() >= 16 {
+                    let len = u32::from_ne_bytes([msg[0], msg[1], msg[2], msg[3]]) as usize;
+                    let ty = u16::from_ne_bytes([msg[4], msg[5]]);
+                    match ty {
+                        NLMSG_DONE => return links,
+                        RTM_NEWLINK => links += 1,
+                        _ => panic!("unexpected netlink message {}", ty),
+                    }
+                    // the messages are aligned to 4 bytes
+                    let len = (len + 3) & !3;
+                    msg = &msg[::std::cmp::min(len, msg.len())..];
+                }
+            }
+        });
+        // there is at least the loopback interface
+        assert!(j.join().unwrap() >= 1);
+    }
+}
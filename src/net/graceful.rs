@@ -0,0 +1,590 @@
+//! zero downtime restart with listener inheritance and connection draining
+//!
+//! a new process could inherit the listening sockets in two ways
+//!
+//! * from the environment with [`listen_fds`], the way systemd socket
+//!   activation passes the sockets (`LISTEN_PID` and `LISTEN_FDS`)
+//! * from the old process over a unix socket, the old process calls
+//!   [`handoff`] and the new process calls [`take_over`]
+//!
+//! after the listeners are handed off the old process calls
+//! [`Graceful::shutdown`] to stop accepting and close the idle connections,
+//! then [`Graceful::wait`] drains the in-flight connections up to a deadline
+//! before the process exits
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use cogo::net::graceful::{self, Graceful, Listener};
+//! use cogo::net::TcpListener;
+//!
+//! const HANDOFF: &str = "/run/app/handoff.sock";
+//!
+//! // the new process take over the listener of the old one if any
+//! let listener = match graceful::take_over(HANDOFF) {
+//!     Ok(mut listeners) => match listeners.pop() {
+//!         Some(Listener::Tcp(l)) => l,
+//!         _ => panic!("unexpected listener"),
+//!     },
+//!     Err(_) => TcpListener::bind("0.0.0.0:8080").unwrap(),
+//! };
+//!
+//! let graceful = Graceful::new();
+//! // serve the connections with `graceful.spawn_acceptor` and `graceful.track`
+//!
+//! // pass the listener to the next process when it's started
+//! use std::os::unix::io::AsRawFd;
+//! graceful::handoff(HANDOFF, &[listener.as_raw_fd()]).unwrap();
+//! graceful.shutdown();
+//! graceful.wait(Duration::from_secs(30));
+//! std::process::exit(0);
+//! ```
+//!
+//! [`listen_fds`]: fn.listen_fds.html
+//! [`handoff`]: fn.handoff.html
+//! [`take_over`]: fn.take_over.html
+//! [`Graceful::shutdown`]: struct.Graceful.html#method.shutdown
+//! [`Graceful::wait`]: struct.Graceful.html#method.wait
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use socket2::{SockRef, Socket, Type};
+
+use super::TcpListener;
+use crate::coroutine::{self, Coroutine, JoinHandle};
+use crate::os::unix::net::{UnixListener, UnixStream};
+use crate::std::sync::{Condvar, Mutex};
+
+// the first fd passed by socket activation, `SD_LISTEN_FDS_START`
+const LISTEN_FDS_START: RawFd = 3;
+// the message that comes with the handed off fds
+const HANDOFF_MAGIC: &[u8] = b"cogo-listeners";
+const HANDOFF_ACK: &[u8] = b"ok";
+// the max fds that could be handed off at once
+const MAX_HANDOFF_FDS: usize = 64;
+
+/// an inherited listening socket
+#[derive(Debug)]
+pub enum Listener {
+    /// a tcp listener, bound to an ipv4 or ipv6 address
+    Tcp(TcpListener),
+    /// a unix domain stream listener
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// wrap an inherited listening socket according to its address family
+    ///
+    /// the fd is owned by the returned listener, it's closed when the socket
+    /// is neither a tcp nor a unix stream socket
+    ///
+    /// # Safety
+    ///
+    /// the fd must be an open socket that is not owned by anything else
+    pub unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Listener> {
+        Listener::from_socket(Socket::from_raw_fd(fd))
+    }
+
+    fn from_socket(socket: Socket) -> io::Result<Listener> {
+        // the inherited fds may have no close-on-exec flag
+        socket.set_cloexec(true)?;
+        if socket.r#type()? != Type::STREAM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a stream socket",
+            ));
+        }
+        match socket.local_addr()?.family() as libc::c_int {
+            libc::AF_INET | libc::AF_INET6 => TcpListener::new(socket.into()).map(Listener::Tcp),
+            libc::AF_UNIX => Ok(Listener::Unix(unsafe {
+                UnixListener::from_raw_fd(socket.into_raw_fd())
+            })),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported socket family",
+            )),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(l) => l.as_raw_fd(),
+            Listener::Unix(l) => l.as_raw_fd(),
+        }
+    }
+}
+
+impl IntoRawFd for Listener {
+    fn into_raw_fd(self) -> RawFd {
+        match self {
+            Listener::Tcp(l) => l.into_raw_fd(),
+            Listener::Unix(l) => l.into_raw_fd(),
+        }
+    }
+}
+
+// get the number of fds passed to the process with `pid`
+fn parse_listen_fds(pid: Option<&str>, fds: Option<&str>, self_pid: u32) -> io::Result<usize> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let fds = match fds {
+        Some(fds) => fds,
+        None => return Ok(0),
+    };
+    if let Some(pid) = pid {
+        let pid: u32 = pid
+            .trim()
+            .parse()
+            .map_err(|_| invalid("invalid LISTEN_PID"))?;
+        // the fds are passed to another process
+        if pid != self_pid {
+            return Ok(0);
+        }
+    }
+    fds.trim()
+        .parse()
+        .map_err(|_| invalid("invalid LISTEN_FDS"))
+}
+
+/// take the listeners passed by the socket activation protocol of systemd
+///
+/// the listeners are in the same order as the sockets are configured.
+/// the environment variables are removed so that they are not passed to
+/// the child processes, the following calls would return nothing
+pub fn listen_fds() -> io::Result<Vec<Listener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let n = parse_listen_fds(pid.as_deref(), fds.as_deref(), process::id())?;
+    // own all the fds first, they are closed if any of them is invalid
+    let sockets: Vec<Socket> = (0..n as RawFd)
+        .map(|i| unsafe { Socket::from_raw_fd(LISTEN_FDS_START + i) })
+        .collect();
+    sockets.into_iter().map(Listener::from_socket).collect()
+}
+
+/// send the listening fds to the peer and wait for its acknowledgement
+///
+/// this is the low level part of [`handoff`], the listeners are still
+/// open in this process after the call
+///
+/// [`handoff`]: fn.handoff.html
+pub fn send_listeners(stream: &mut UnixStream, fds: &[RawFd]) -> io::Result<()> {
+    if fds.is_empty() || fds.len() > MAX_HANDOFF_FDS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid number of listeners",
+        ));
+    }
+    stream.send_with_fds(HANDOFF_MAGIC, fds)?;
+    let mut ack = [0; 2];
+    stream.read_exact(&mut ack)?;
+    if ack != HANDOFF_ACK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid handoff acknowledgement",
+        ));
+    }
+    Ok(())
+}
+
+/// receive the listening fds sent by [`send_listeners`] and acknowledge them
+///
+/// [`send_listeners`]: fn.send_listeners.html
+pub fn recv_listeners(stream: &mut UnixStream) -> io::Result<Vec<Listener>> {
+    let mut buf = [0; HANDOFF_MAGIC.len()];
+    let mut fds = [-1; MAX_HANDOFF_FDS];
+    let (n, nfds) = stream.recv_with_fds(&mut buf, &mut fds)?;
+    let sockets: Vec<Socket> = fds[..nfds]
+        .iter()
+        .map(|&fd| unsafe { Socket::from_raw_fd(fd) })
+        .collect();
+    if &buf[..n] != HANDOFF_MAGIC || sockets.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid handoff message",
+        ));
+    }
+    let listeners = sockets
+        .into_iter()
+        .map(Listener::from_socket)
+        .collect::<io::Result<Vec<_>>>()?;
+    stream.write_all(HANDOFF_ACK)?;
+    Ok(listeners)
+}
+
+/// hand off the listening fds to the new process that connects to `path`
+///
+/// block until the new process took the fds over with [`take_over`]. only
+/// the processes of the same user are accepted. the socket file is removed
+/// when the call returns, after that the old process should stop accepting
+/// with [`Graceful::shutdown`]
+///
+/// [`take_over`]: fn.take_over.html
+/// [`Graceful::shutdown`]: struct.Graceful.html#method.shutdown
+pub fn handoff<P: AsRef<Path>>(path: P, fds: &[RawFd]) -> io::Result<()> {
+    let path = path.as_ref();
+    // remove the stale socket file left by a crashed process
+    fs::remove_file(path).ok();
+    let listener = UnixListener::bind(path)?;
+    let uid = unsafe { libc::geteuid() };
+    let ret = loop {
+        let (mut stream, _) = match listener.accept() {
+            Ok(s) => s,
+            Err(e) => break Err(e),
+        };
+        match stream.peer_cred() {
+            Ok(cred) if cred.uid == uid => break send_listeners(&mut stream, fds),
+            Ok(_) => warn!("graceful handoff: reject the peer of another user"),
+            Err(e) => break Err(e),
+        }
+    };
+    fs::remove_file(path).ok();
+    ret
+}
+
+/// take over the listeners from the old process that is waiting in [`handoff`]
+///
+/// [`handoff`]: fn.handoff.html
+pub fn take_over<P: AsRef<Path>>(path: P) -> io::Result<Vec<Listener>> {
+    let mut stream = UnixStream::connect(path)?;
+    recv_listeners(&mut stream)
+}
+
+// the tracked connection
+struct ConnState {
+    // a dup of the connection, shutdown it would wake up the connection
+    socket: Socket,
+    // there is no request in progress
+    idle: AtomicBool,
+}
+
+impl ConnState {
+    fn close(&self) {
+        self.socket.shutdown(Shutdown::Both).ok();
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    shutdown: AtomicBool,
+    next_id: AtomicUsize,
+    acceptors: Mutex<Vec<(Coroutine, Arc<AtomicBool>)>>,
+    conns: Mutex<HashMap<usize, Arc<ConnState>>>,
+    drained: Condvar,
+}
+
+// mark the acceptor done when it exits
+struct Done(Arc<AtomicBool>);
+
+impl Drop for Done {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// coordinate the graceful shutdown of the accept loops and the connections
+///
+/// the accept loops are spawned by [`spawn_acceptor`] and each accepted
+/// connection is registered by [`track`]. the clones share the same state
+///
+/// [`spawn_acceptor`]: #method.spawn_acceptor
+/// [`track`]: #method.track
+#[derive(Clone, Default)]
+pub struct Graceful {
+    inner: Arc<Inner>,
+}
+
+impl Graceful {
+    /// create a new `Graceful` that is not shut down
+    pub fn new() -> Self {
+        Graceful::default()
+    }
+
+    /// whether `shutdown` is called
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// the number of the connections that are not closed yet
+    pub fn active(&self) -> usize {
+        self.inner.conns.lock().unwrap().len()
+    }
+
+    /// spawn an accept loop that is canceled by `shutdown`
+    ///
+    /// the listener should be moved into the closure so that it's closed
+    /// when the accept loop is canceled
+    pub fn spawn_acceptor<F>(&self, f: F) -> io::Result<JoinHandle<()>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let done = Arc::new(AtomicBool::new(false));
+        let flag = done.clone();
+        let h = go!(coroutine::Builder::new(), move || {
+            let _done = Done(flag);
+            f()
+        })?;
+        let mut acceptors = self.inner.acceptors.lock().unwrap();
+        if self.is_shutdown() {
+            unsafe { h.coroutine().cancel() };
+        } else {
+            acceptors.push((h.coroutine().clone(), done));
+        }
+        Ok(h)
+    }
+
+    /// register an accepted connection, it's tracked until the returned
+    /// `Conn` is dropped
+    pub fn track<T: AsRawFd>(&self, io: &T) -> io::Result<Conn> {
+        let state = Arc::new(ConnState {
+            socket: SockRef::from(io).try_clone()?,
+            idle: AtomicBool::new(false),
+        });
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner.conns.lock().unwrap().insert(id, state.clone());
+        Ok(Conn {
+            id,
+            state,
+            inner: self.inner.clone(),
+        })
+    }
+
+    /// stop all the accept loops and close the idle connections
+    ///
+    /// the connections that are serving requests are not interrupted, they
+    /// should exit when they become idle. the listeners are left open in the
+    /// other processes that took them over
+    pub fn shutdown(&self) {
+        if self.inner.shutdown.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for (co, done) in self.inner.acceptors.lock().unwrap().drain(..) {
+            if !done.load(Ordering::Acquire) {
+                unsafe { co.cancel() };
+            }
+        }
+        for conn in self.inner.conns.lock().unwrap().values() {
+            if conn.idle.load(Ordering::SeqCst) {
+                conn.close();
+            }
+        }
+    }
+
+    /// wait until all the tracked connections are closed
+    ///
+    /// when the timeout expires the remaining connections are shut down
+    /// forcibly and `false` is returned
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut conns = self.inner.conns.lock().unwrap();
+        while !conns.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                for conn in conns.values() {
+                    conn.close();
+                }
+                return false;
+            }
+            conns = self
+                .inner
+                .drained
+                .wait_timeout(conns, deadline - now)
+                .unwrap()
+                .0;
+        }
+        true
+    }
+}
+
+/// a tracked connection returned by [`Graceful::track`]
+///
+/// [`Graceful::track`]: struct.Graceful.html#method.track
+pub struct Conn {
+    id: usize,
+    state: Arc<ConnState>,
+    inner: Arc<Inner>,
+}
+
+impl Conn {
+    /// whether the connection should exit once it's idle
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// mark whether the connection has no request in progress
+    ///
+    /// the idle connections are closed by `shutdown`, the connection should
+    /// check `is_shutdown` after marking itself idle
+    pub fn set_idle(&self, idle: bool) {
+        self.state.idle.store(idle, Ordering::SeqCst);
+    }
+}
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        let mut conns = self.inner.conns.lock().unwrap();
+        conns.remove(&self.id);
+        if conns.is_empty() {
+            self.inner.drained.notify_all().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::TcpStream;
+
+    #[test]
+    fn parse_env() {
+        assert_eq!(parse_listen_fds(None, None, 10).unwrap(), 0);
+        assert_eq!(parse_listen_fds(Some("10"), Some("2"), 10).unwrap(), 2);
+        assert_eq!(parse_listen_fds(None, Some("1"), 10).unwrap(), 1);
+        // passed to another process
+        assert_eq!(parse_listen_fds(Some("11"), Some("2"), 10).unwrap(), 0);
+        assert!(parse_listen_fds(Some("10"), Some("x"), 10).is_err());
+    }
+
+    #[test]
+    fn listener_from_fd() {
+        let tcp = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let l = unsafe { Listener::from_raw_fd(tcp.into_raw_fd()) }.unwrap();
+        let l = match l {
+            Listener::Tcp(l) => l,
+            _ => panic!("not a tcp listener"),
+        };
+        let j = go!(move || TcpStream::connect(addr).unwrap());
+        l.accept().unwrap();
+        j.join().unwrap();
+
+        let dir = tempdir::TempDir::new("graceful").unwrap();
+        let unix = ::std::os::unix::net::UnixListener::bind(dir.path().join("sock")).unwrap();
+        let l = unsafe { Listener::from_raw_fd(unix.into_raw_fd()) }.unwrap();
+        assert!(matches!(l, Listener::Unix(_)));
+
+        let udp = ::std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(unsafe { Listener::from_raw_fd(udp.into_raw_fd()) }.is_err());
+    }
+
+    #[test]
+    fn handoff_listener() {
+        let dir = tempdir::TempDir::new("graceful").unwrap();
+        let path = dir.path().join("handoff.sock");
+        let old = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = old.local_addr().unwrap();
+
+        let p = path.clone();
+        let j = go!(move || {
+            handoff(&p, &[old.as_raw_fd()]).unwrap();
+            // the listener is still usable in the new process
+            drop(old);
+        });
+        let new = loop {
+            match take_over(&path) {
+                Ok(listeners) => break listeners,
+                Err(_) => coroutine::sleep(Duration::from_millis(10)),
+            }
+        };
+        j.join().unwrap();
+        assert!(!path.exists());
+
+        let new = match new.into_iter().next() {
+            Some(Listener::Tcp(l)) => l,
+            _ => panic!("not a tcp listener"),
+        };
+        assert_eq!(new.local_addr().unwrap(), addr);
+        let j = go!(move || TcpStream::connect(addr).unwrap());
+        new.accept().unwrap();
+        j.join().unwrap();
+    }
+
+    #[test]
+    fn drain_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let graceful = Graceful::new();
+
+        let g = graceful.clone();
+        let acceptor = graceful
+            .spawn_acceptor(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let conn = g.track(&stream).unwrap();
+                    go!(move || {
+                        let mut buf = [0; 4];
+                        // the first connection is idle, the second is busy
+                        stream.read_exact(&mut buf[..1]).unwrap();
+                        if buf[0] == b'i' {
+                            conn.set_idle(true);
+                            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+                        } else {
+                            coroutine::sleep(Duration::from_millis(200));
+                            stream.write_all(b"done").unwrap();
+                        }
+                    });
+                }
+            })
+            .unwrap();
+
+        let mut idle = TcpStream::connect(addr).unwrap();
+        idle.write_all(b"i").unwrap();
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"b").unwrap();
+        while graceful.active() < 2 {
+            coroutine::sleep(Duration::from_millis(10));
+        }
+        coroutine::sleep(Duration::from_millis(50));
+
+        graceful.shutdown();
+        assert!(graceful.wait(Duration::from_secs(5)));
+        // the accept loop is canceled
+        assert!(acceptor.join().is_err());
+        let mut buf = Vec::new();
+        busy.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"done");
+        idle.read_to_end(&mut buf).unwrap();
+        // the listener is closed with the canceled accept loop
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn drain_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let graceful = Graceful::new();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let conn = graceful.track(&stream).unwrap();
+        let j = go!(move || {
+            let _conn = conn;
+            // never finish by itself
+            let mut buf = [0; 1];
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        });
+
+        graceful.shutdown();
+        assert!(!graceful.wait(Duration::from_millis(100)));
+        j.join().unwrap();
+        assert_eq!(graceful.active(), 0);
+        let mut buf = [0; 1];
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+    }
+}
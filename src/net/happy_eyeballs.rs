@@ -0,0 +1,246 @@
+//! Happy Eyeballs (RFC 8305) connection racing for `TcpStream`
+//!
+//! the resolved addresses are interleaved by address family and each
+//! connect attempt runs in its own coroutine on top of the non blocking
+//! `TcpStreamConnect`. a new attempt is started every `attempt_delay`
+//! or as soon as the previous one failed, the first attempt that
+//! succeeds wins and all the other attempts are canceled.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use super::TcpStream;
+use crate::join::JoinHandle;
+use crate::std::sync::channel::{channel, Receiver, Sender};
+
+/// Options for [`TcpStream::connect_happy_eyeballs`]
+///
+/// [`TcpStream::connect_happy_eyeballs`]: struct.TcpStream.html#method.connect_happy_eyeballs
+#[derive(Debug, Clone, Copy)]
+pub struct HappyEyeballs {
+    attempt_delay: Duration,
+    timeout: Option<Duration>,
+}
+
+impl Default for HappyEyeballs {
+    fn default() -> Self {
+        HappyEyeballs::new()
+    }
+}
+
+impl HappyEyeballs {
+    /// the recommended "Connection Attempt Delay" of RFC 8305
+    pub const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+    /// the lower limit of the attempt delay, RFC 8305 section 5
+    pub const MIN_ATTEMPT_DELAY: Duration = Duration::from_millis(100);
+
+    /// create the default options, 250ms attempt delay and no overall deadline
+    pub fn new() -> Self {
+        HappyEyeballs {
+            attempt_delay: Self::DEFAULT_ATTEMPT_DELAY,
+            timeout: None,
+        }
+    }
+
+    /// set the delay before starting the next connect attempt
+    /// the value is clamped to at least `MIN_ATTEMPT_DELAY`
+    pub fn attempt_delay(mut self, delay: Duration) -> Self {
+        self.attempt_delay = ::std::cmp::max(delay, Self::MIN_ATTEMPT_DELAY);
+        self
+    }
+
+    /// set the overall deadline for the whole connect procedure
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// get the attempt delay
+    pub fn get_attempt_delay(&self) -> Duration {
+        self.attempt_delay
+    }
+
+    /// get the overall deadline
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+// sort the addresses as RFC 8305 section 4 describes, the family of the
+// first resolved address is preferred and then the families alternate
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let prefer_v6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let (mut first, mut second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == prefer_v6);
+
+    let mut ret = Vec::with_capacity(first.len() + second.len());
+    first.reverse();
+    second.reverse();
+    loop {
+        match (first.pop(), second.pop()) {
+            (None, None) => return ret,
+            (a, b) => {
+                ret.extend(a);
+                ret.extend(b);
+            }
+        }
+    }
+}
+
+type Attempt = (usize, io::Result<TcpStream>);
+
+struct Race {
+    attempts: Vec<JoinHandle<()>>,
+    winner: Option<usize>,
+}
+
+impl Race {
+    fn start(&mut self, addr: SocketAddr, deadline: Option<Instant>, tx: &Sender<Attempt>) {
+        let id = self.attempts.len();
+        let tx = tx.clone();
+        let h = go!(move || {
+            let ret = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(dur) if dur > Duration::from_millis(0) => {
+                        TcpStream::connect_timeout(&addr, dur)
+                    }
+                    _ => Err(io::Error::new(io::ErrorKind::TimedOut, "timeout")),
+                },
+                None => TcpStream::connect(addr),
+            };
+            // the receiver may be gone if another attempt already won
+            // in that case the stream would be dropped with the message
+            tx.send((id, ret)).ok();
+        });
+        self.attempts.push(h);
+    }
+}
+
+impl Drop for Race {
+    // cancel all the attempts that are still in progress
+    fn drop(&mut self) {
+        for (id, h) in self.attempts.iter().enumerate() {
+            if Some(id) == self.winner || h.is_done() {
+                continue;
+            }
+            unsafe { h.coroutine().cancel() };
+        }
+    }
+}
+
+fn wait(rx: &Receiver<Attempt>, dur: Option<Duration>) -> Result<Attempt, RecvTimeoutError> {
+    match dur {
+        Some(dur) => rx.recv_timeout(dur),
+        None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
+}
+
+pub(crate) fn connect<A: ToSocketAddrs>(addr: A, opts: HappyEyeballs) -> io::Result<TcpStream> {
+    let deadline = opts.timeout.map(|dur| Instant::now() + dur);
+    let addrs = interleave(addr.to_socket_addrs()?.collect());
+
+    let (tx, rx) = channel();
+    let mut race = Race {
+        attempts: Vec::with_capacity(addrs.len()),
+        winner: None,
+    };
+    let mut pending = 0;
+    let mut last_err = None;
+    let mut addrs = addrs.into_iter().peekable();
+
+    loop {
+        if let Some(addr) = addrs.next() {
+            race.start(addr, deadline, &tx);
+            pending += 1;
+        }
+
+        if pending == 0 {
+            break;
+        }
+
+        // wait for the attempt delay if there are more addresses to try
+        // or else wait for any pending attempt to finish
+        let remain = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let dur = match (addrs.peek().map(|_| opts.attempt_delay), remain) {
+            (Some(delay), Some(remain)) => Some(::std::cmp::min(delay, remain)),
+            (delay, remain) => delay.or(remain),
+        };
+
+        match wait(&rx, dur) {
+            Ok((id, Ok(s))) => {
+                race.winner = Some(id);
+                return Ok(s);
+            }
+            Ok((_, Err(e))) => {
+                pending -= 1;
+                last_err = Some(e);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => unreachable!("happy eyeballs recv"),
+        }
+    }
+
+    Err(last_err
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "no socket addresses resolved")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn v4(port: u16) -> SocketAddr {
+        (Ipv4Addr::LOCALHOST, port).into()
+    }
+
+    fn v6(port: u16) -> SocketAddr {
+        (Ipv6Addr::LOCALHOST, port).into()
+    }
+
+    #[test]
+    fn interleave_families() {
+        let addrs = vec![v6(1), v6(2), v6(3), v4(4), v4(5)];
+        assert_eq!(interleave(addrs), vec![v6(1), v4(4), v6(2), v4(5), v6(3)]);
+
+        let addrs = vec![v4(1), v6(2), v4(3)];
+        assert_eq!(interleave(addrs), vec![v4(1), v6(2), v4(3)]);
+
+        assert_eq!(interleave(vec![]), vec![]);
+    }
+
+    #[test]
+    fn connect_skip_refused() {
+        use crate::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // a port that nobody listens on
+        let refused = {
+            let l = TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap()
+        };
+
+        let opts = HappyEyeballs::new().timeout(Some(Duration::from_secs(5)));
+        let j = go!(move || connect(&[refused, addr][..], opts));
+        let (_s, peer) = listener.accept().unwrap();
+        let s = j.join().unwrap().unwrap();
+        assert_eq!(s.local_addr().unwrap(), peer);
+        assert_eq!(s.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn connect_no_addrs() {
+        let e = connect(&[][..] as &[SocketAddr], HappyEyeballs::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Other);
+    }
+}
@@ -1,8 +1,25 @@
 //! Networking primitives
 //!
 
+#[cfg(unix)]
+mod co_socket;
+#[cfg(unix)]
+pub mod graceful;
+mod happy_eyeballs;
 mod tcp;
+mod tcp_builder;
 mod udp;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) mod udp_batch;
+mod udp_listener;
 
+#[cfg(unix)]
+pub use self::co_socket::{CoSocket, RecvMeta};
+pub use self::happy_eyeballs::HappyEyeballs;
 pub use self::tcp::{TcpListener, TcpStream};
+pub use self::tcp_builder::TcpSocketBuilder;
+pub use socket2::TcpKeepalive;
 pub use self::udp::UdpSocket;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use self::udp_batch::{RecvMsg, SendMsg};
+pub use self::udp_listener::{UdpConn, UdpListener};
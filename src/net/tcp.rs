@@ -1,12 +1,20 @@
-use std::io::{self, ErrorKind, Read, Write};
+#[cfg(unix)]
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::net::{self, Shutdown, SocketAddr, ToSocketAddrs};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::coroutine_impl::is_coroutine;
 use crate::io as io_impl;
+use crate::io::deadline::{self, ReadFn, WriteFn};
 use crate::io::net as net_impl;
 use crate::std::sync::atomic_dur::AtomicDuration;
 use crate::yield_now::yield_with;
+use bytes::{Buf, BytesMut};
+use socket2::{SockRef, TcpKeepalive};
+
+use super::happy_eyeballs::{self, HappyEyeballs};
+use super::TcpSocketBuilder;
 
 // ===== TcpStream =====
 //
@@ -83,6 +91,20 @@ impl TcpStream {
         c.done()
     }
 
+    /// connect to `host:port` by racing all the resolved addresses
+    ///
+    /// the attempts are interleaved by address family and started one
+    /// `attempt_delay` after another as RFC 8305 describes, the first
+    /// established connection is returned and all the others are canceled.
+    /// this avoids hanging on hosts with broken IPv6 (or IPv4) connectivity
+    pub fn connect_happy_eyeballs(
+        host: &str,
+        port: u16,
+        opts: HappyEyeballs,
+    ) -> io::Result<TcpStream> {
+        happy_eyeballs::connect((host, port), opts)
+    }
+
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
         self.sys.peer_addr()
     }
@@ -124,10 +146,294 @@ impl TcpStream {
         self.sys.set_nodelay(nodelay)
     }
 
+    pub fn nodelay(&self) -> io::Result<bool> {
+        self.sys.nodelay()
+    }
+
+    /// set `SO_KEEPALIVE` with the idle/interval/count parameters
+    /// `None` would disable the keepalive
+    pub fn set_keepalive(&self, keepalive: Option<&TcpKeepalive>) -> io::Result<()> {
+        let s = SockRef::from(&self.sys);
+        match keepalive {
+            Some(keepalive) => s.set_tcp_keepalive(keepalive),
+            None => s.set_keepalive(false),
+        }
+    }
+
+    pub fn keepalive(&self) -> io::Result<bool> {
+        SockRef::from(&self.sys).keepalive()
+    }
+
+    /// set `SO_LINGER`
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        SockRef::from(&self.sys).set_linger(linger)
+    }
+
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        SockRef::from(&self.sys).linger()
+    }
+
+    /// set `SO_RCVBUF`
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        SockRef::from(&self.sys).set_recv_buffer_size(size)
+    }
+
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        SockRef::from(&self.sys).recv_buffer_size()
+    }
+
+    /// set `SO_SNDBUF`
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        SockRef::from(&self.sys).set_send_buffer_size(size)
+    }
+
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        SockRef::from(&self.sys).send_buffer_size()
+    }
+
+    /// set `TCP_QUICKACK`, note that the kernel may reset it after each ack
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_quickack(&self, quickack: bool) -> io::Result<()> {
+        SockRef::from(&self.sys).set_quickack(quickack)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn quickack(&self) -> io::Result<bool> {
+        SockRef::from(&self.sys).quickack()
+    }
+
+    /// set `TCP_USER_TIMEOUT`, `None` would use the system default
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_tcp_user_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        SockRef::from(&self.sys).set_tcp_user_timeout(timeout)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn tcp_user_timeout(&self) -> io::Result<Option<Duration>> {
+        SockRef::from(&self.sys).tcp_user_timeout()
+    }
+
     pub fn take_error(&self) -> io::Result<Option<io::Error>> {
         self.sys.take_error()
     }
 
+    /// send `len` bytes of the file starting at `offset` to the stream
+    ///
+    /// on linux this is done by `sendfile(2)` without copying the data
+    /// into the user space. return the bytes sent, which is less than
+    /// `len` only when the end of the file is reached
+    #[cfg(unix)]
+    pub fn sendfile(&self, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+        io_impl::sendfile(self, file, offset, len, self.write_timeout.get())
+    }
+
+    /// read into the spare capacity of `buf` and advance its length
+    ///
+    /// more space is reserved if `buf` is full. return the bytes read,
+    /// `0` means the peer has shut down
+    pub fn read_buf(&mut self, buf: &mut BytesMut) -> io::Result<usize> {
+        #[cfg(unix)]
+        {
+            let co = self.co_ctx()?;
+            io_impl::read_buf(self, buf, self.read_timeout.get(), co)
+        }
+
+        #[cfg(not(unix))]
+        {
+            let len = buf.len();
+            let spare = std::cmp::max(buf.capacity() - len, 64);
+            buf.resize(len + spare, 0);
+            let ret = self.read(&mut buf[len..]);
+            buf.truncate(len + *ret.as_ref().unwrap_or(&0));
+            ret
+        }
+    }
+
+    /// write the data of `buf` and advance it by the bytes written
+    ///
+    /// all the chunks of `buf` are written by one syscall if possible
+    pub fn write_buf<B: Buf>(&mut self, buf: &mut B) -> io::Result<usize> {
+        #[cfg(unix)]
+        {
+            let co = self.co_ctx()?;
+            io_impl::write_buf(self, buf, self.write_timeout.get(), co)
+        }
+
+        #[cfg(not(unix))]
+        {
+            let n = self.write(buf.chunk())?;
+            buf.advance(n);
+            Ok(n)
+        }
+    }
+
+    /// receive data without removing it from the queue
+    #[cfg(unix)]
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let co = self.co_ctx()?;
+        io_impl::peek(self, buf, self.read_timeout.get(), co)
+    }
+
+    // whether the io should block the coroutine instead of the thread
+    #[cfg(unix)]
+    fn co_ctx(&self) -> io::Result<bool> {
+        let nonblocking = self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?;
+        Ok(!nonblocking && self.ctx.check_context(|b| self.sys.set_nonblocking(b))?)
+    }
+
+    /// read into `buf`, a `TimedOut` error is returned if nothing could
+    /// be read before `deadline`
+    ///
+    /// the deadline only applies to this call, the read timeout of the
+    /// stream is not used and not changed
+    pub fn read_with_deadline(&mut self, buf: &mut [u8], deadline: Instant) -> io::Result<usize> {
+        let timeout = deadline::remaining(deadline)?;
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+        {
+            return self.sys.read(buf);
+        }
+
+        if !self.ctx.check_context(|b| self.sys.set_nonblocking(b))? {
+            // block the thread until the deadline
+            #[cfg(unix)]
+            {
+                let fd = self.sys.as_raw_fd();
+                io_impl::poll_fd(fd, io_impl::Interest::Read, Some(timeout))?;
+                return self.sys.read(buf);
+            }
+            #[cfg(windows)]
+            return self.sys_with_timeout(true, timeout, |s| s.read(buf));
+        }
+
+        self.co_read(buf, Some(timeout))
+    }
+
+    /// write from `buf`, a `TimedOut` error is returned if nothing could
+    /// be written before `deadline`
+    ///
+    /// the deadline only applies to this call, the write timeout of the
+    /// stream is not used and not changed
+    pub fn write_with_deadline(&mut self, buf: &[u8], deadline: Instant) -> io::Result<usize> {
+        let timeout = deadline::remaining(deadline)?;
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+        {
+            return self.sys.write(buf);
+        }
+
+        if !self.ctx.check_context(|b| self.sys.set_nonblocking(b))? {
+            // block the thread until the deadline
+            #[cfg(unix)]
+            {
+                let fd = self.sys.as_raw_fd();
+                io_impl::poll_fd(fd, io_impl::Interest::Write, Some(timeout))?;
+                return self.sys.write(buf);
+            }
+            #[cfg(windows)]
+            return self.sys_with_timeout(false, timeout, |s| s.write(buf));
+        }
+
+        self.co_write(buf, Some(timeout))
+    }
+
+    /// read the exact number of bytes to fill `buf` before `deadline`
+    pub fn read_exact_with_deadline(
+        &mut self,
+        buf: &mut [u8],
+        deadline: Instant,
+    ) -> io::Result<()> {
+        ReadFn(|buf: &mut [u8]| self.read_with_deadline(buf, deadline)).read_exact(buf)
+    }
+
+    /// write the entire `buf` before `deadline`
+    pub fn write_all_with_deadline(&mut self, buf: &[u8], deadline: Instant) -> io::Result<()> {
+        WriteFn(|buf: &[u8]| self.write_with_deadline(buf, deadline)).write_all(buf)
+    }
+
+    // the blocking socket honors the timeout set by the system call,
+    // so apply the deadline to it just for the duration of `f`
+    #[cfg(windows)]
+    fn sys_with_timeout<R>(
+        &mut self,
+        read: bool,
+        timeout: Duration,
+        f: impl FnOnce(&mut net::TcpStream) -> io::Result<R>,
+    ) -> io::Result<R> {
+        let ret = if read {
+            self.sys.set_read_timeout(Some(timeout))?;
+            let ret = f(&mut self.sys);
+            self.sys.set_read_timeout(self.read_timeout.get())?;
+            ret
+        } else {
+            self.sys.set_write_timeout(Some(timeout))?;
+            let ret = f(&mut self.sys);
+            self.sys.set_write_timeout(self.write_timeout.get())?;
+            ret
+        };
+        ret.map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock => {
+                io::Error::new(io::ErrorKind::TimedOut, "deadline has elapsed")
+            }
+            _ => e,
+        })
+    }
+
+    // read in coroutine context with the given timeout
+    fn co_read(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<usize> {
+        #[cfg(unix)]
+        {
+            self.io.reset_read();
+            // this is an earlier return try for nonblocking read
+            // it's useful for server but not necessary for client
+            match self.sys.read(buf) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        let mut reader = net_impl::SocketRead::new(self, buf, timeout);
+        yield_with(&reader);
+        reader.done()
+    }
+
+    // write in coroutine context with the given timeout
+    fn co_write(&mut self, buf: &[u8], timeout: Option<Duration>) -> io::Result<usize> {
+        #[cfg(unix)]
+        {
+            self.io.reset_write();
+            // this is an earlier return try for nonblocking write
+            match self.sys.write(buf) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        let mut writer = net_impl::SocketWrite::new(self, buf, timeout);
+        yield_with(&writer);
+        writer.done()
+    }
+
     pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
         self.sys.set_read_timeout(dur)?;
         self.read_timeout.swap(dur);
@@ -183,26 +489,35 @@ impl Read for TcpStream {
             return self.sys.read(buf);
         }
 
-        #[cfg(unix)]
+        self.co_read(buf, self.read_timeout.get())
+    }
+
+    #[cfg(unix)]
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+            || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
         {
-            self.io.reset();
-            // this is an earlier return try for nonblocking read
-            // it's useful for server but not necessary for client
-            match self.sys.read(buf) {
-                Ok(n) => return Ok(n),
-                Err(e) => {
-                    // raw_os_error is faster than kind
-                    let raw_err = e.raw_os_error();
-                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
-                        // do nothing here
-                    } else {
-                        return Err(e);
-                    }
+            return self.sys.read_vectored(bufs);
+        }
+
+        self.io.reset_read();
+        // this is an earlier return try for nonblocking read
+        match self.sys.read_vectored(bufs) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
                 }
             }
         }
 
-        let mut reader = net_impl::SocketRead::new(self, buf, self.read_timeout.get());
+        let mut reader = net_impl::SocketReadVectored::new(self, bufs, self.read_timeout.get());
         yield_with(&reader);
         reader.done()
     }
@@ -218,27 +533,7 @@ impl Write for TcpStream {
             return self.sys.write(buf);
         }
 
-        #[cfg(unix)]
-        {
-            self.io.reset();
-            // this is an earlier return try for nonblocking write
-            match self.sys.write(buf) {
-                Ok(n) => return Ok(n),
-                Err(e) => {
-                    // raw_os_error is faster than kind
-                    let raw_err = e.raw_os_error();
-                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
-                        // do nothing here
-                    } else {
-                        return Err(e);
-                    }
-                }
-            }
-        }
-
-        let mut writer = net_impl::SocketWrite::new(self, buf, self.write_timeout.get());
-        yield_with(&writer);
-        writer.done()
+        self.co_write(buf, self.write_timeout.get())
     }
 
     #[cfg(unix)]
@@ -253,7 +548,7 @@ impl Write for TcpStream {
 
         #[cfg(unix)]
         {
-            self.io.reset();
+            self.io.reset_write();
             // this is an earlier return try for nonblocking write
             match self.sys.write_vectored(bufs) {
                 Ok(n) => return Ok(n),
@@ -319,7 +614,7 @@ pub struct TcpListener {
 }
 
 impl TcpListener {
-    fn new(s: net::TcpListener) -> io::Result<TcpListener> {
+    pub(crate) fn new(s: net::TcpListener) -> io::Result<TcpListener> {
         // only set non blocking in coroutine context
         // we would first call nonblocking io in the coroutine
         // to avoid unnecessary context switch
@@ -337,32 +632,16 @@ impl TcpListener {
     }
 
     pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
-        use socket2::{Domain, Socket, Type};
-        let mut addrs = addr.to_socket_addrs()?;
-        let next = addrs.next();
-        if next.is_none(){
-            return io::Result::Err(io::Error::new(ErrorKind::Other, "addrs.next() is none!"));
-        }
-        let addr = next.unwrap();
-        let listener = match &addr {
-            SocketAddr::V4(_) => Socket::new(Domain::IPV4, Type::STREAM, None)?,
-            SocketAddr::V6(_) => Socket::new(Domain::IPV6, Type::STREAM, None)?,
-        };
-
-        // windows not have reuset port but reuse address is not safe
-        listener.set_reuse_address(true)?;
-
-        #[cfg(unix)]
-        listener.set_reuse_port(true)?;
-
-        listener.bind(&addr.into())?;
-        for addr in addrs {
-            listener.bind(&addr.into())?;
-        }
-        listener.listen(256)?;
+        TcpSocketBuilder::new().listen(addr)
+    }
 
-        let s = listener.into();
-        TcpListener::new(s)
+    /// bind `n` listeners on the same address with `SO_REUSEPORT`
+    ///
+    /// each listener is registered to a different worker's selector, run one
+    /// accept loop per listener to spread the accept rate on all the workers
+    #[cfg(unix)]
+    pub fn bind_sharded<A: ToSocketAddrs>(addr: A, n: usize) -> io::Result<Vec<TcpListener>> {
+        TcpSocketBuilder::new().listen_sharded(addr, n)
     }
 
     pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
@@ -379,7 +658,7 @@ impl TcpListener {
 
         #[cfg(unix)]
         {
-            self.io.reset();
+            self.io.reset_read();
             match self.sys.accept() {
                 Ok((s, a)) => return TcpStream::new(s).map(|s| (s, a)),
                 Err(e) => {
@@ -399,6 +678,16 @@ impl TcpListener {
         a.done()
     }
 
+    // convert std::net::TcpListener to Self without add_socket
+    #[cfg(unix)]
+    pub(crate) fn from_listener(s: net::TcpListener, io: io_impl::IoData) -> Self {
+        TcpListener {
+            io,
+            ctx: io_impl::IoContext::new(),
+            sys: s,
+        }
+    }
+
     pub fn incoming(&self) -> Incoming {
         Incoming { listener: self }
     }
@@ -566,3 +855,36 @@ impl FromRawSocket for TcpListener {
             .unwrap_or_else(|e| panic!("from_raw_socket for TcpListener, err = {:?}", e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let h = go!(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            let mut buf = [0; 5];
+            let deadline = Instant::now() + Duration::from_secs(1);
+            s.read_exact_with_deadline(&mut buf, deadline).unwrap();
+            assert_eq!(&buf, b"hello");
+            let deadline = Instant::now() + Duration::from_millis(50);
+            let e = s.read_with_deadline(&mut buf, deadline).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            // the stream is still usable after the deadline
+            s.write_all_with_deadline(b"bye", Instant::now() + Duration::from_secs(1))
+                .unwrap();
+        });
+
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.write_all_with_deadline(b"hello", Instant::now() + Duration::from_secs(1))
+            .unwrap();
+        let mut buf = [0; 3];
+        s.read_exact_with_deadline(&mut buf, Instant::now() + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(&buf, b"bye");
+        h.join().unwrap();
+    }
+}
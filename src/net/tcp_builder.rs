@@ -0,0 +1,399 @@
+//! Builder for TCP sockets with full socket options
+//!
+//! `TcpListener::bind` and `TcpStream::connect` only use the default
+//! socket options, `TcpSocketBuilder` let you config the socket before
+//! it's bound or connected and then register it to the selector
+
+use std::io;
+use std::net::{self, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use socket2::{Domain, Socket, TcpKeepalive, Type};
+
+use super::{TcpListener, TcpStream};
+use crate::coroutine_impl::is_coroutine;
+use crate::io as io_impl;
+use crate::io::net as net_impl;
+use crate::yield_now::yield_with;
+
+/// A builder that configures the TCP socket options before the socket is
+/// bound for a `TcpListener` or connected for a `TcpStream`
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::net::TcpSocketBuilder;
+///
+/// let listener = TcpSocketBuilder::new()
+///     .backlog(1024)
+///     .only_v6(false)
+///     .recv_buffer_size(256 * 1024)
+///     .listen("[::]:8080")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct TcpSocketBuilder {
+    backlog: i32,
+    reuse_address: Option<bool>,
+    #[cfg(unix)]
+    reuse_port: Option<bool>,
+    only_v6: Option<bool>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fastopen: Option<u32>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    defer_accept: Option<Duration>,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+    nodelay: Option<bool>,
+    keepalive: Option<TcpKeepalive>,
+    linger: Option<Option<Duration>>,
+}
+
+impl Default for TcpSocketBuilder {
+    fn default() -> Self {
+        TcpSocketBuilder::new()
+    }
+}
+
+impl TcpSocketBuilder {
+    /// create a builder with the same defaults that `TcpListener::bind` uses
+    /// a backlog of 256, `SO_REUSEADDR` and `SO_REUSEPORT`(unix only) are set
+    pub fn new() -> Self {
+        TcpSocketBuilder {
+            backlog: 256,
+            reuse_address: Some(true),
+            #[cfg(unix)]
+            reuse_port: Some(true),
+            only_v6: None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            fastopen: None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            defer_accept: None,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            nodelay: None,
+            keepalive: None,
+            linger: None,
+        }
+    }
+
+    /// set the listen backlog, only used by `listen`
+    pub fn backlog(mut self, backlog: i32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// set `SO_REUSEADDR`
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.reuse_address = Some(reuse);
+        self
+    }
+
+    /// set `SO_REUSEPORT`
+    #[cfg(unix)]
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.reuse_port = Some(reuse);
+        self
+    }
+
+    /// set `IPV6_V6ONLY`, only used for IPv6 addresses
+    pub fn only_v6(mut self, only_v6: bool) -> Self {
+        self.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// set `TCP_FASTOPEN` with the max length of pending SYNs queue
+    /// only used by `listen`
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn fastopen(mut self, queue_len: u32) -> Self {
+        self.fastopen = Some(queue_len);
+        self
+    }
+
+    /// set `TCP_DEFER_ACCEPT`, the listener would only wake up the accept
+    /// when data arrives within the duration, only used by `listen`
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn defer_accept(mut self, dur: Duration) -> Self {
+        self.defer_accept = Some(dur);
+        self
+    }
+
+    /// set `SO_RCVBUF`
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// set `SO_SNDBUF`
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// set `TCP_NODELAY`, the accepted streams on linux would inherit it
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// set `SO_KEEPALIVE` with the keepalive idle/interval/count parameters
+    pub fn keepalive(mut self, keepalive: TcpKeepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// set `SO_LINGER`
+    pub fn linger(mut self, linger: Option<Duration>) -> Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    // create the socket for the address and apply all the options
+    fn socket(&self, addr: &SocketAddr, listen: bool) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+
+        if let Some(reuse) = self.reuse_address {
+            socket.set_reuse_address(reuse)?;
+        }
+        #[cfg(unix)]
+        {
+            if let Some(reuse) = self.reuse_port {
+                socket.set_reuse_port(reuse)?;
+            }
+        }
+        if let (Some(only_v6), true) = (self.only_v6, addr.is_ipv6()) {
+            socket.set_only_v6(only_v6)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if let Some(ref keepalive) = self.keepalive {
+            socket.set_tcp_keepalive(keepalive)?;
+        }
+        if let Some(linger) = self.linger {
+            socket.set_linger(linger)?;
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            if let (Some(qlen), true) = (self.fastopen, listen) {
+                setsockopt(socket.as_raw_fd(), libc::TCP_FASTOPEN, qlen as libc::c_int)?;
+            }
+            if let (Some(dur), true) = (self.defer_accept, listen) {
+                let secs = ::std::cmp::max(dur.as_secs(), 1) as libc::c_int;
+                setsockopt(socket.as_raw_fd(), libc::TCP_DEFER_ACCEPT, secs)?;
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let _ = listen;
+
+        Ok(socket)
+    }
+
+    /// create a `TcpListener` that is bound to the first address
+    /// that could be bound successfully
+    pub fn listen<A: ToSocketAddrs>(&self, addr: A) -> io::Result<TcpListener> {
+        each_addr(addr, |addr| {
+            let listener = self.socket(addr, true)?;
+            listener.bind(&(*addr).into())?;
+            listener.listen(self.backlog)?;
+            let s: net::TcpListener = listener.into();
+            TcpListener::new(s)
+        })
+    }
+
+    /// create `n` listeners that are bound to the same address with `SO_REUSEPORT`
+    ///
+    /// each listener is registered to a different worker's selector, so the
+    /// accept coroutine of listener `i` would always be waken up on worker
+    /// `i % workers` and the kernel would load balance the connections
+    #[cfg(unix)]
+    pub fn listen_sharded<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+        n: usize,
+    ) -> io::Result<Vec<TcpListener>> {
+        let builder = self.clone().reuse_port(true);
+        let mut listeners: Vec<TcpListener> = Vec::with_capacity(n);
+        each_addr(addr, |addr| {
+            listeners.clear();
+            // the port may be zero, the others must bind to the real one
+            let mut addr = *addr;
+            for id in 0..n {
+                let listener = builder.socket(&addr, true)?;
+                listener.bind(&addr.into())?;
+                listener.listen(builder.backlog)?;
+                let s: net::TcpListener = listener.into();
+                s.set_nonblocking(true)?;
+                let io = io_impl::add_socket_to(&s, id)?;
+                addr = s.local_addr()?;
+                listeners.push(TcpListener::from_listener(s, io));
+            }
+            Ok(())
+        })?;
+        Ok(listeners)
+    }
+
+    /// create a `TcpStream` that is connected to the remote address
+    ///
+    /// the listener only options like `backlog` are ignored
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<TcpStream> {
+        each_addr(addr, |addr| self.connect_impl(addr, None))
+    }
+
+    /// create a `TcpStream` that is connected to the remote address with a timeout
+    pub fn connect_timeout(&self, addr: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        self.connect_impl(addr, Some(timeout))
+    }
+
+    fn connect_impl(&self, addr: &SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+        let socket = self.socket(addr, false)?;
+
+        if !is_coroutine() {
+            match timeout {
+                Some(dur) => socket.connect_timeout(&(*addr).into(), dur)?,
+                None => socket.connect(&(*addr).into())?,
+            }
+            let s: net::TcpStream = socket.into();
+            s.set_nonblocking(true)?;
+            let io = io_impl::add_socket(&s)?;
+            return Ok(TcpStream::from_stream(s, io));
+        }
+
+        let mut c = net_impl::TcpStreamConnect::from_socket(socket, *addr, timeout)?;
+
+        #[cfg(unix)]
+        {
+            if c.check_connected()? {
+                return c.done();
+            }
+        }
+
+        yield_with(&c);
+        c.done()
+    }
+}
+
+// try the function on each address until one success
+fn each_addr<A, F, T>(addr: A, mut f: F) -> io::Result<T>
+where
+    A: ToSocketAddrs,
+    F: FnMut(&SocketAddr) -> io::Result<T>,
+{
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match f(&addr) {
+            Ok(l) => return Ok(l),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn setsockopt(fd: libc::c_int, opt: libc::c_int, val: libc::c_int) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            opt,
+            &val as *const libc::c_int as *const libc::c_void,
+            ::std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_listen_connect() {
+        let listener = TcpSocketBuilder::new()
+            .backlog(16)
+            .recv_buffer_size(64 * 1024)
+            .listen("127.0.0.1:0")
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(30));
+        let builder = TcpSocketBuilder::new()
+            .nodelay(true)
+            .keepalive(keepalive)
+            .linger(Some(Duration::from_secs(1)));
+        let j = go!(move || builder.connect(addr).unwrap());
+
+        let (_s, peer) = listener.accept().unwrap();
+        let s = j.join().unwrap();
+        assert_eq!(s.local_addr().unwrap(), peer);
+        assert!(s.nodelay().unwrap());
+        assert!(s.keepalive().unwrap());
+        assert_eq!(s.linger().unwrap(), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn listen_sharded() {
+        let listeners = TcpListener::bind_sharded("127.0.0.1:0", 4).unwrap();
+        assert_eq!(listeners.len(), 4);
+        let addr = listeners[0].local_addr().unwrap();
+        for l in listeners.iter() {
+            assert_eq!(l.local_addr().unwrap(), addr);
+        }
+
+        let (tx, rx) = crate::std::sync::channel::channel();
+        let accepts: Vec<_> = listeners
+            .into_iter()
+            .map(|l| {
+                let tx = tx.clone();
+                go!(move || {
+                    let (s, _) = l.accept().unwrap();
+                    tx.send(s).unwrap();
+                })
+            })
+            .collect();
+
+        // keep connecting until every listener has got a connection
+        let mut clients = Vec::new();
+        while accepts.iter().any(|h| !h.is_done()) {
+            clients.push(TcpStream::connect(addr).unwrap());
+            rx.recv_timeout(Duration::from_millis(10)).ok();
+        }
+        assert!(clients.len() >= 4);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn stream_linux_options() {
+        let listener = TcpSocketBuilder::new()
+            .defer_accept(Duration::from_secs(1))
+            .fastopen(16)
+            .listen("127.0.0.1:0")
+            .unwrap();
+        let s = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        s.set_tcp_user_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        assert_eq!(s.tcp_user_timeout().unwrap(), Some(Duration::from_secs(10)));
+        s.set_quickack(true).unwrap();
+        s.set_keepalive(None).unwrap();
+        assert!(!s.keepalive().unwrap());
+    }
+}
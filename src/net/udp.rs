@@ -7,6 +7,9 @@ use crate::io::net as net_impl;
 use crate::std::sync::atomic_dur::AtomicDuration;
 use crate::yield_now::yield_with;
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use super::udp_batch::{self, RecvMsg, SendMsg};
+
 #[derive(Debug)]
 pub struct UdpSocket {
     io: io_impl::IoData,
@@ -85,7 +88,7 @@ impl UdpSocket {
 
         #[cfg(unix)]
         {
-            self.io.reset();
+            self.io.reset_write();
             // this is an earlier return try for nonblocking read
             match self.sys.send_to(buf, &addr) {
                 Ok(n) => return Ok(n),
@@ -118,7 +121,7 @@ impl UdpSocket {
 
         #[cfg(unix)]
         {
-            self.io.reset();
+            self.io.reset_read();
             // this is an earlier return try for nonblocking read
             match self.sys.recv_from(buf) {
                 Ok(n) => return Ok(n),
@@ -151,7 +154,7 @@ impl UdpSocket {
 
         #[cfg(unix)]
         {
-            self.io.reset();
+            self.io.reset_write();
             // this is an earlier return try for nonblocking write
             match self.sys.send(buf) {
                 Ok(n) => return Ok(n),
@@ -184,7 +187,7 @@ impl UdpSocket {
 
         #[cfg(unix)]
         {
-            self.io.reset();
+            self.io.reset_read();
             // this is an earlier return try for nonblocking read
             match self.sys.recv(buf) {
                 Ok(n) => return Ok(n),
@@ -288,6 +291,106 @@ impl UdpSocket {
         self.sys.leave_multicast_v6(multiaddr, interface)
     }
 
+    /// receive multiple datagrams with one `recvmmsg(2)` call
+    ///
+    /// return the number of messages received, it would block until
+    /// at least one message is received
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn recv_batch(&self, msgs: &mut [RecvMsg]) -> io::Result<usize> {
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+            || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
+        {
+            return udp_batch::recv(self.as_raw_fd(), msgs);
+        }
+
+        self.io.reset_read();
+        // this is an earlier return try for nonblocking io
+        match udp_batch::recv(self.as_raw_fd(), msgs) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut reader = net_impl::UdpRecvBatch::new(self, msgs);
+        yield_with(&reader);
+        reader.done()
+    }
+
+    /// send multiple datagrams with one `sendmmsg(2)` call
+    ///
+    /// return the number of messages sent, it would block until
+    /// at least one message is sent
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn send_batch(&self, msgs: &[SendMsg]) -> io::Result<usize> {
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+            || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
+        {
+            return udp_batch::send(self.as_raw_fd(), msgs);
+        }
+
+        self.io.reset_write();
+        // this is an earlier return try for nonblocking io
+        match udp_batch::send(self.as_raw_fd(), msgs) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut writer = net_impl::UdpSendBatch::new(self, msgs);
+        yield_with(&writer);
+        writer.done()
+    }
+
+    /// enable `UDP_GRO`, the received datagrams may be coalesced into
+    /// one message, see `RecvMsg::segments`
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_gro(&self, on: bool) -> io::Result<()> {
+        udp_batch::setsockopt(self.as_raw_fd(), libc::SOL_UDP, libc::UDP_GRO, on)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn gro(&self) -> io::Result<bool> {
+        udp_batch::getsockopt(self.as_raw_fd(), libc::SOL_UDP, libc::UDP_GRO)
+    }
+
+    /// report the destination address of the received messages,
+    /// see `RecvMsg::dst_addr`
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_recv_pktinfo(&self, on: bool) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        match self.local_addr()? {
+            SocketAddr::V4(_) => udp_batch::setsockopt(fd, libc::IPPROTO_IP, libc::IP_PKTINFO, on),
+            SocketAddr::V6(_) => {
+                udp_batch::setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, on)
+            }
+        }
+    }
+
+    /// report the receive timestamp of the received messages,
+    /// see `RecvMsg::timestamp`
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_recv_timestamp(&self, on: bool) -> io::Result<()> {
+        udp_batch::setsockopt(self.as_raw_fd(), libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, on)
+    }
+
     pub fn take_error(&self) -> io::Result<Option<io::Error>> {
         self.sys.take_error()
     }
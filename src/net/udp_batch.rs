@@ -0,0 +1,380 @@
+//! batch UDP io with `recvmmsg(2)`/`sendmmsg(2)`
+//!
+//! each message could carry the ancillary data, the destination address
+//! from `IP_PKTINFO`, the receive timestamp and the GSO/GRO segment size
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::RawFd;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{cmp, io, mem, ptr};
+
+use socket2::SockAddr;
+
+// the control message buffer size of one message, large enough for
+// the pktinfo, the timestamp and the segment size
+const CMSG_BUF_SIZE: usize = 128;
+
+#[repr(align(8))]
+#[derive(Clone, Copy)]
+struct CmsgBuf([u8; CMSG_BUF_SIZE]);
+
+/// a message buffer for [`UdpSocket::recv_batch`]
+///
+/// [`UdpSocket::recv_batch`]: struct.UdpSocket.html#method.recv_batch
+#[derive(Debug)]
+pub struct RecvMsg<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    addr: Option<SocketAddr>,
+    dst_addr: Option<IpAddr>,
+    timestamp: Option<SystemTime>,
+    segment_size: Option<usize>,
+    truncated: bool,
+}
+
+impl<'a> RecvMsg<'a> {
+    /// create a message that would receive the data into `buf`
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        RecvMsg {
+            buf,
+            len: 0,
+            addr: None,
+            dst_addr: None,
+            timestamp: None,
+            segment_size: None,
+            truncated: false,
+        }
+    }
+
+    /// the received data
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// the received data length
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// the source address of the message
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// the destination address of the message, only available
+    /// after `UdpSocket::set_recv_pktinfo(true)`
+    pub fn dst_addr(&self) -> Option<IpAddr> {
+        self.dst_addr
+    }
+
+    /// the receive timestamp of the message, only available
+    /// after `UdpSocket::set_recv_timestamp(true)`
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.timestamp
+    }
+
+    /// the size of each coalesced datagram, only available
+    /// after `UdpSocket::set_gro(true)`
+    pub fn segment_size(&self) -> Option<usize> {
+        self.segment_size
+    }
+
+    /// the datagram is truncated because the buffer is too small
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// split the data into the original datagrams if it's coalesced by GRO
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> {
+        let size = self.segment_size.unwrap_or(self.len);
+        self.data().chunks(cmp::max(size, 1))
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.addr = None;
+        self.dst_addr = None;
+        self.timestamp = None;
+        self.segment_size = None;
+        self.truncated = false;
+    }
+}
+
+/// a message for [`UdpSocket::send_batch`]
+///
+/// [`UdpSocket::send_batch`]: struct.UdpSocket.html#method.send_batch
+#[derive(Debug, Clone, Copy)]
+pub struct SendMsg<'a> {
+    buf: &'a [u8],
+    addr: Option<SocketAddr>,
+    segment_size: Option<u16>,
+}
+
+impl<'a> SendMsg<'a> {
+    /// create a message that send `buf` to the connected address
+    pub fn new(buf: &'a [u8]) -> Self {
+        SendMsg {
+            buf,
+            addr: None,
+            segment_size: None,
+        }
+    }
+
+    /// set the destination address of the message
+    pub fn to(mut self, addr: SocketAddr) -> Self {
+        self.addr = Some(addr);
+        self
+    }
+
+    /// let the kernel split the data into datagrams of `size` bytes (GSO)
+    pub fn segment_size(mut self, size: u16) -> Self {
+        self.segment_size = Some(size);
+        self
+    }
+}
+
+// receive the messages, return the number of messages received
+pub(crate) fn recv(fd: RawFd, msgs: &mut [RecvMsg]) -> io::Result<usize> {
+    let n = msgs.len();
+    if n == 0 {
+        return Ok(0);
+    }
+
+    let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; n];
+    let mut cmsgs = vec![CmsgBuf([0; CMSG_BUF_SIZE]); n];
+    let mut iovs: Vec<libc::iovec> = msgs
+        .iter_mut()
+        .map(|msg| libc::iovec {
+            iov_base: msg.buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: msg.buf.len(),
+        })
+        .collect();
+    let mut hdrs: Vec<libc::mmsghdr> = (0..n)
+        .map(|i| {
+            let mut hdr: libc::mmsghdr = unsafe { mem::zeroed() };
+            hdr.msg_hdr.msg_name = &mut addrs[i] as *mut _ as *mut libc::c_void;
+            hdr.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+            hdr.msg_hdr.msg_iov = &mut iovs[i];
+            hdr.msg_hdr.msg_iovlen = 1;
+            hdr.msg_hdr.msg_control = cmsgs[i].0.as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_hdr.msg_controllen = CMSG_BUF_SIZE as _;
+            hdr
+        })
+        .collect();
+
+    // don't wait for more messages after the first one is received
+    let ret = unsafe {
+        libc::recvmmsg(
+            fd,
+            hdrs.as_mut_ptr(),
+            n as _,
+            libc::MSG_WAITFORONE as _,
+            ptr::null_mut(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let ret = ret as usize;
+    for (i, (msg, hdr)) in msgs.iter_mut().zip(hdrs.iter()).take(ret).enumerate() {
+        msg.reset();
+        msg.len = hdr.msg_len as usize;
+        msg.truncated = hdr.msg_hdr.msg_flags & libc::MSG_TRUNC != 0;
+        msg.addr = unsafe { SockAddr::new(addrs[i], hdr.msg_hdr.msg_namelen) }.as_socket();
+        unsafe { parse_cmsgs(&hdr.msg_hdr, msg) };
+    }
+    Ok(ret)
+}
+
+unsafe fn parse_cmsgs(hdr: &libc::msghdr, msg: &mut RecvMsg) {
+    let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+    while !cmsg.is_null() {
+        let data = libc::CMSG_DATA(cmsg);
+        match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                let info = ptr::read_unaligned(data as *const libc::in_pktinfo);
+                let addr = u32::from_be(info.ipi_addr.s_addr);
+                msg.dst_addr = Some(IpAddr::V4(Ipv4Addr::from(addr)));
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                let info = ptr::read_unaligned(data as *const libc::in6_pktinfo);
+                msg.dst_addr = Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)));
+            }
+            (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                let ts = ptr::read_unaligned(data as *const libc::timespec);
+                let dur = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+                msg.timestamp = Some(UNIX_EPOCH + dur);
+            }
+            (libc::SOL_UDP, libc::UDP_GRO) => {
+                let size = ptr::read_unaligned(data as *const libc::c_int);
+                msg.segment_size = Some(size as usize);
+            }
+            _ => {}
+        }
+        cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+    }
+}
+
+// send the messages, return the number of messages sent
+pub(crate) fn send(fd: RawFd, msgs: &[SendMsg]) -> io::Result<usize> {
+    let n = msgs.len();
+    if n == 0 {
+        return Ok(0);
+    }
+
+    let addrs: Vec<Option<SockAddr>> = msgs
+        .iter()
+        .map(|msg| msg.addr.map(SockAddr::from))
+        .collect();
+    let mut cmsgs = vec![CmsgBuf([0; CMSG_BUF_SIZE]); n];
+    let mut iovs: Vec<libc::iovec> = msgs
+        .iter()
+        .map(|msg| libc::iovec {
+            iov_base: msg.buf.as_ptr() as *mut libc::c_void,
+            iov_len: msg.buf.len(),
+        })
+        .collect();
+    let mut hdrs: Vec<libc::mmsghdr> = (0..n)
+        .map(|i| {
+            let mut hdr: libc::mmsghdr = unsafe { mem::zeroed() };
+            if let Some(addr) = addrs[i].as_ref() {
+                hdr.msg_hdr.msg_name = addr.as_ptr() as *mut libc::c_void;
+                hdr.msg_hdr.msg_namelen = addr.len();
+            }
+            hdr.msg_hdr.msg_iov = &mut iovs[i];
+            hdr.msg_hdr.msg_iovlen = 1;
+            if let Some(size) = msgs[i].segment_size {
+                unsafe { set_segment_size(&mut hdr.msg_hdr, &mut cmsgs[i], size) };
+            }
+            hdr
+        })
+        .collect();
+
+    let ret = unsafe { libc::sendmmsg(fd, hdrs.as_mut_ptr(), n as _, 0) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+unsafe fn set_segment_size(hdr: &mut libc::msghdr, buf: &mut CmsgBuf, size: u16) {
+    let space = libc::CMSG_SPACE(mem::size_of::<u16>() as _);
+    hdr.msg_control = buf.0.as_mut_ptr() as *mut libc::c_void;
+    hdr.msg_controllen = space as _;
+    let cmsg = libc::CMSG_FIRSTHDR(hdr);
+    (*cmsg).cmsg_level = libc::SOL_UDP;
+    (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as _) as _;
+    ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, size);
+}
+
+pub(crate) fn setsockopt(
+    fd: RawFd,
+    level: libc::c_int,
+    opt: libc::c_int,
+    on: bool,
+) -> io::Result<()> {
+    let val = on as libc::c_int;
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            opt,
+            &val as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn getsockopt(fd: RawFd, level: libc::c_int, opt: libc::c_int) -> io::Result<bool> {
+    let mut val: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            opt,
+            &mut val as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(val != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::{RecvMsg, SendMsg, UdpSocket};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn batch_send_recv() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx.set_recv_pktinfo(true).unwrap();
+        rx.set_recv_timestamp(true).unwrap();
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = rx.local_addr().unwrap();
+
+        let j = go!(move || {
+            let data = [b"a".as_ref(), b"bb", b"ccc"];
+            let msgs: Vec<_> = data.iter().map(|d| SendMsg::new(d).to(addr)).collect();
+            assert_eq!(tx.send_batch(&msgs).unwrap(), 3);
+            tx.local_addr().unwrap()
+        });
+
+        let mut bufs = vec![[0u8; 16]; 4];
+        let mut msgs: Vec<_> = bufs.iter_mut().map(|b| RecvMsg::new(b)).collect();
+        let mut got = 0;
+        while got < 3 {
+            got += rx.recv_batch(&mut msgs[got..]).unwrap();
+        }
+        let src = j.join().unwrap();
+
+        assert_eq!(msgs[0].data(), b"a");
+        assert_eq!(msgs[1].data(), b"bb");
+        assert_eq!(msgs[2].data(), b"ccc");
+        for msg in &msgs[..3] {
+            assert_eq!(msg.addr(), Some(src));
+            assert_eq!(msg.dst_addr(), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+            assert!(msg.timestamp().is_some());
+            assert!(!msg.is_truncated());
+        }
+    }
+
+    #[test]
+    fn gso_segments() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx.connect(rx.local_addr().unwrap()).unwrap();
+
+        // the kernel would split the data into 4 datagrams
+        let j = go!(move || {
+            let data = vec![1u8; 1000];
+            let msg = SendMsg::new(&data).segment_size(250);
+            tx.send_batch(&[msg]).unwrap()
+        });
+
+        let mut bufs = vec![[0u8; 1000]; 4];
+        let mut msgs: Vec<_> = bufs.iter_mut().map(|b| RecvMsg::new(b)).collect();
+        let mut got = 0;
+        while got < 4 {
+            got += rx.recv_batch(&mut msgs[got..]).unwrap();
+        }
+        assert_eq!(j.join().unwrap(), 1);
+        for msg in &msgs {
+            assert_eq!(msg.len(), 250);
+            assert_eq!(msg.segments().count(), 1);
+        }
+    }
+}
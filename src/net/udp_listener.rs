@@ -0,0 +1,517 @@
+//! connection like datagram sockets over one `UdpSocket`
+//!
+//! a demux coroutine receives all the datagrams of the listener socket
+//! and dispatches them to the `UdpConn` of the remote address, a new
+//! `UdpConn` is created and queued for `accept` when an unknown peer
+//! sends the first datagram. the `UdpConn` is removed after it's dropped
+//! or when it's idle for too long
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::UdpSocket;
+use crate::join::JoinHandle;
+use crate::std::sync::atomic_dur::AtomicDuration;
+use crate::std::sync::channel::{bounded, Receiver, Sender};
+use crate::std::sync::Mutex;
+use crate::timeout_list::now;
+
+// the max datagram size
+const MAX_DATAGRAM_SIZE: usize = 65536;
+// the max connections that are waiting for accept
+const ACCEPT_BACKLOG: usize = 128;
+
+// the state shared between the demux coroutine and the connection
+struct ConnState {
+    // the last active time in ns
+    last_active: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl ConnState {
+    fn new() -> Self {
+        ConnState {
+            last_active: AtomicU64::new(now()),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    fn touch(&self) {
+        self.last_active.store(now(), Ordering::Relaxed);
+    }
+
+    fn is_expired(&self, idle_timeout: Option<Duration>) -> bool {
+        if self.closed.load(Ordering::Acquire) {
+            return true;
+        }
+        match idle_timeout {
+            Some(dur) => {
+                let idle = now().saturating_sub(self.last_active.load(Ordering::Relaxed));
+                idle >= dur.as_nanos() as u64
+            }
+            None => false,
+        }
+    }
+}
+
+struct Peer {
+    tx: Sender<Vec<u8>>,
+    // the queue size when it's accepted, `set_queue_size` doesn't change it
+    capacity: usize,
+    state: Arc<ConnState>,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    conns: Mutex<HashMap<SocketAddr, Peer>>,
+    // only one coroutine could wait on the socket write readiness
+    send_lock: Mutex<()>,
+    queue_size: AtomicUsize,
+    idle_timeout: AtomicDuration,
+}
+
+impl Shared {
+    // remove the expired connections, the receivers would get disconnected
+    fn sweep(&self) {
+        let idle_timeout = self.idle_timeout.get();
+        let mut conns = self.conns.lock().unwrap();
+        conns.retain(|_, peer| {
+            let expired = peer.state.is_expired(idle_timeout);
+            if expired {
+                peer.state.closed.store(true, Ordering::Release);
+            }
+            !expired
+        });
+    }
+
+    fn dispatch(
+        self: &Arc<Self>,
+        data: &[u8],
+        addr: SocketAddr,
+        accept_tx: &Sender<(UdpConn, SocketAddr)>,
+    ) {
+        let mut conns = self.conns.lock().unwrap();
+        if let Some(peer) = conns.get(&addr) {
+            if !peer.state.closed.load(Ordering::Acquire) {
+                // drop the datagram if the queue is full, just like the socket
+                // buffer, so that the send never blocks the demux coroutine
+                if peer.tx.remain() < peer.capacity {
+                    peer.state.touch();
+                    peer.tx.send(data.to_vec()).ok();
+                }
+                return;
+            }
+            // the connection is dropped, treat it as a new peer
+            conns.remove(&addr);
+        }
+
+        if accept_tx.remain() >= ACCEPT_BACKLOG {
+            return;
+        }
+        let queue_size = self.queue_size.load(Ordering::Relaxed);
+        let (tx, rx) = bounded(queue_size);
+        let state = Arc::new(ConnState::new());
+        tx.send(data.to_vec()).ok();
+        conns.insert(
+            addr,
+            Peer {
+                tx,
+                capacity: queue_size,
+                state: state.clone(),
+            },
+        );
+        let conn = UdpConn {
+            shared: self.clone(),
+            peer: addr,
+            rx,
+            state,
+            read_timeout: AtomicDuration::new(None),
+            write_timeout: AtomicDuration::new(None),
+        };
+        accept_tx.send((conn, addr)).ok();
+    }
+}
+
+// the read timeout of the listener socket, which is the sweep interval
+fn sweep_interval(idle_timeout: Option<Duration>) -> Duration {
+    let max = Duration::from_secs(1);
+    match idle_timeout {
+        Some(dur) => cmp::max(cmp::min(dur / 2, max), Duration::from_millis(10)),
+        None => max,
+    }
+}
+
+fn demux(shared: Arc<Shared>, accept_tx: Sender<(UdpConn, SocketAddr)>) {
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let mut last_sweep = now();
+    loop {
+        match shared.socket.recv_from(&mut buf) {
+            Ok((n, addr)) => shared.dispatch(&buf[..n], addr, &accept_tx),
+            Err(e) => match e.kind() {
+                io::ErrorKind::TimedOut
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset => {}
+                // the accept would get an error after the sender is dropped
+                _ => return,
+            },
+        }
+
+        let interval = sweep_interval(shared.idle_timeout.get()).as_nanos() as u64;
+        if now().saturating_sub(last_sweep) >= interval {
+            shared.sweep();
+            last_sweep = now();
+        }
+    }
+}
+
+/// A UDP listener that demultiplexes the datagrams by remote address
+///
+/// each remote address is seen as a connection, which is returned by
+/// `accept` when its first datagram arrives. dropping the listener
+/// would close all the connections
+pub struct UdpListener {
+    shared: Arc<Shared>,
+    accept_rx: Receiver<(UdpConn, SocketAddr)>,
+    demux: JoinHandle<()>,
+}
+
+impl UdpListener {
+    /// the default receive queue size of each connection
+    pub const DEFAULT_QUEUE_SIZE: usize = 64;
+    /// the default idle timeout of each connection
+    pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpListener> {
+        UdpSocket::bind(addr).and_then(UdpListener::from_socket)
+    }
+
+    /// create the listener from a bound socket
+    pub fn from_socket(socket: UdpSocket) -> io::Result<UdpListener> {
+        let idle_timeout = Some(Self::DEFAULT_IDLE_TIMEOUT);
+        socket.set_read_timeout(Some(sweep_interval(idle_timeout)))?;
+        let shared = Arc::new(Shared {
+            socket,
+            conns: Mutex::new(HashMap::new()),
+            send_lock: Mutex::new(()),
+            queue_size: AtomicUsize::new(Self::DEFAULT_QUEUE_SIZE),
+            idle_timeout: AtomicDuration::new(idle_timeout),
+        });
+
+        let (accept_tx, accept_rx) = bounded(usize::MAX);
+        let demux = {
+            let shared = shared.clone();
+            go!(move || demux(shared, accept_tx))
+        };
+        Ok(UdpListener {
+            shared,
+            accept_rx,
+            demux,
+        })
+    }
+
+    /// wait for a datagram from a new remote address
+    pub fn accept(&self) -> io::Result<(UdpConn, SocketAddr)> {
+        self.accept_rx
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "udp listener demux exited"))
+    }
+
+    pub fn incoming(&self) -> Incoming {
+        Incoming { listener: self }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+
+    /// set the receive queue size of the new connections, the datagrams
+    /// would be dropped when the queue is full. the accepted connections
+    /// keep their queue size
+    pub fn set_queue_size(&self, size: usize) {
+        self.shared
+            .queue_size
+            .store(cmp::max(size, 1), Ordering::Relaxed);
+    }
+
+    pub fn queue_size(&self) -> usize {
+        self.shared.queue_size.load(Ordering::Relaxed)
+    }
+
+    /// set the idle timeout of the connections, a connection is closed
+    /// if no datagram is sent or received within the timeout.
+    /// `None` means the connections would never expire
+    pub fn set_idle_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.shared
+            .socket
+            .set_read_timeout(Some(sweep_interval(dur)))?;
+        self.shared.idle_timeout.swap(dur);
+        Ok(())
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.shared.idle_timeout.get()
+    }
+}
+
+impl Drop for UdpListener {
+    fn drop(&mut self) {
+        if !self.demux.is_done() {
+            unsafe { self.demux.coroutine().cancel() };
+        }
+        // close all the connections
+        let mut conns = self.shared.conns.lock().unwrap();
+        for (_, peer) in conns.drain() {
+            peer.state.closed.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// An iterator that infinitely accepts connections on a `UdpListener`
+pub struct Incoming<'a> {
+    listener: &'a UdpListener,
+}
+
+impl<'a> Iterator for Incoming<'a> {
+    type Item = io::Result<UdpConn>;
+    fn next(&mut self) -> Option<io::Result<UdpConn>> {
+        Some(self.listener.accept().map(|p| p.0))
+    }
+}
+
+/// A virtual connected UDP socket for one remote address of a `UdpListener`
+pub struct UdpConn {
+    shared: Arc<Shared>,
+    peer: SocketAddr,
+    rx: Receiver<Vec<u8>>,
+    state: Arc<ConnState>,
+    read_timeout: AtomicDuration,
+    write_timeout: AtomicDuration,
+}
+
+impl UdpConn {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.peer)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+
+    fn check_closed(&self) -> io::Result<()> {
+        if self.state.closed.load(Ordering::Acquire) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "udp connection closed",
+            ));
+        }
+        Ok(())
+    }
+
+    /// send a datagram to the remote address
+    ///
+    /// the write timeout limits the wait for the socket to be writable,
+    /// not the wait for the sends of the other connections
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.check_closed()?;
+        self.state.touch();
+        let _guard = self.shared.send_lock.lock().unwrap();
+        // the socket is shared, apply the timeout of this connection
+        let timeout = self.write_timeout.get();
+        if self.shared.socket.write_timeout()? != timeout {
+            self.shared.socket.set_write_timeout(timeout)?;
+        }
+        self.shared.socket.send_to(buf, self.peer)
+    }
+
+    /// receive a datagram from the remote address
+    ///
+    /// the exceeding data is discarded if the buffer is too small. a
+    /// `ConnectionAborted` error is returned after the connection is
+    /// closed because of idle timeout or the listener is dropped
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let ret = match self.read_timeout.get() {
+            Some(dur) => self.rx.recv_timeout(dur),
+            None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match ret {
+            Ok(data) => {
+                let n = cmp::min(buf.len(), data.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                Ok(n)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                self.state.closed.store(true, Ordering::Release);
+                self.check_closed().map(|_| 0)
+            }
+        }
+    }
+
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.swap(dur);
+        Ok(())
+    }
+
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.read_timeout.get())
+    }
+
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.write_timeout.swap(dur);
+        Ok(())
+    }
+
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.write_timeout.get())
+    }
+}
+
+impl Drop for UdpConn {
+    // let the demux coroutine remove the connection
+    fn drop(&mut self) {
+        self.state.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_per_peer() {
+        let listener = UdpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let j = go!(move || {
+            let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+            a.send_to(b"a1", addr).unwrap();
+            b.send_to(b"b1", addr).unwrap();
+            a.send_to(b"a2", addr).unwrap();
+
+            let mut buf = [0; 16];
+            let (n, from) = a.recv_from(&mut buf).unwrap();
+            assert_eq!(from, addr);
+            assert_eq!(&buf[..n], b"echo a");
+        });
+
+        let mut buf = [0; 16];
+        let (a, a_addr) = listener.accept().unwrap();
+        let (b, b_addr) = listener.accept().unwrap();
+        assert_eq!(a.peer_addr().unwrap(), a_addr);
+        assert_eq!(b.peer_addr().unwrap(), b_addr);
+        assert_ne!(a_addr, b_addr);
+
+        let n = a.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"a1");
+        let n = a.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"a2");
+        let n = b.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"b1");
+
+        a.send(b"echo a").unwrap();
+        j.join().unwrap();
+
+        b.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        let e = b.recv(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn idle_expiry() {
+        let listener = UdpListener::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_idle_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let s = UdpSocket::bind("127.0.0.1:0").unwrap();
+        s.send_to(b"hello", addr).unwrap();
+        let (conn, _) = listener.accept().unwrap();
+
+        let mut buf = [0; 16];
+        assert_eq!(conn.recv(&mut buf).unwrap(), 5);
+        let e = conn.recv(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(conn.send(b"bye").is_err());
+
+        // the peer would be accepted again as a new connection
+        s.send_to(b"again", addr).unwrap();
+        let (conn, _) = listener.accept().unwrap();
+        assert_eq!(conn.recv(&mut buf).unwrap(), 5);
+    }
+
+    #[test]
+    fn write_timeout() {
+        let listener = UdpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let s = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let t = UdpSocket::bind("127.0.0.1:0").unwrap();
+        s.send_to(b"s", addr).unwrap();
+        let (a, _) = listener.accept().unwrap();
+        t.send_to(b"t", addr).unwrap();
+        let (b, _) = listener.accept().unwrap();
+
+        let dur = Some(Duration::from_millis(100));
+        assert_eq!(a.write_timeout().unwrap(), None);
+        a.set_write_timeout(dur).unwrap();
+        assert_eq!(a.write_timeout().unwrap(), dur);
+
+        // each connection sends with its own timeout
+        a.send(b"a").unwrap();
+        assert_eq!(listener.shared.socket.write_timeout().unwrap(), dur);
+        b.send(b"b").unwrap();
+        assert_eq!(listener.shared.socket.write_timeout().unwrap(), None);
+
+        let mut buf = [0; 16];
+        let (n, _) = s.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"a");
+        let (n, _) = t.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"b");
+    }
+
+    #[test]
+    fn queue_full_after_resize() {
+        let listener = UdpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_queue_size(2);
+        let addr = listener.local_addr().unwrap();
+
+        let s = UdpSocket::bind("127.0.0.1:0").unwrap();
+        s.send_to(b"1", addr).unwrap();
+        let (conn, _) = listener.accept().unwrap();
+        // the accepted connection still drops what exceeds its own queue
+        listener.set_queue_size(8);
+        for data in [b"2", b"3", b"4"].iter() {
+            s.send_to(*data, addr).unwrap();
+        }
+        crate::coroutine::sleep(Duration::from_millis(100));
+
+        let mut buf = [0; 16];
+        conn.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let n = conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"1");
+        let n = conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"2");
+        let e = conn.recv(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+        // the other peers are not blocked
+        let t = UdpSocket::bind("127.0.0.1:0").unwrap();
+        t.send_to(b"other", addr).unwrap();
+        let (other, _) = listener.accept().unwrap();
+        assert_eq!(other.recv(&mut buf).unwrap(), 5);
+    }
+}
@@ -6,12 +6,16 @@ use std::net::Shutdown;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::unix::net::{self, SocketAddr};
 use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::coroutine_impl::is_coroutine;
 use crate::io::sys::net as net_impl;
-use crate::io::CoIo;
+use crate::io::{AsIoData, CoIo, IoData};
 use crate::yield_now::yield_with;
+use bytes::{Buf, BytesMut};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use socket2::SockRef;
+use socket2::{Domain, SockAddr, Socket, Type};
 
 /// A Unix stream socket.
 ///
@@ -80,6 +84,36 @@ impl UnixStream {
         c.done()
     }
 
+    /// Connects to the socket in the linux abstract namespace named by `name`.
+    ///
+    /// The `name` doesn't contain the leading nul byte.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixStream;
+    ///
+    /// let socket = UnixStream::connect_abstract(b"my_socket").unwrap();
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn connect_abstract(name: &[u8]) -> io::Result<UnixStream> {
+        let addr = abstract_addr(name)?;
+        if !is_coroutine() {
+            let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+            socket.connect(&addr)?;
+            return Ok(UnixStream(CoIo::new(socket.into())?));
+        }
+
+        let mut c = net_impl::UnixStreamConnect::with_addr(addr)?;
+
+        if c.check_connected()? {
+            return c.done();
+        }
+
+        yield_with(&c);
+        c.done()
+    }
+
     /// Creates an unnamed pair of connected sockets.
     ///
     /// Returns two `UnixStream`s which are connected to each other.
@@ -272,12 +306,210 @@ impl UnixStream {
     pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
         self.0.inner().shutdown(how)
     }
+
+    /// Sends data on the socket together with the file descriptors.
+    ///
+    /// The file descriptors are passed to the peer by `SCM_RIGHTS`, the peer
+    /// would get its own duplicates of them. On success, returns the number
+    /// of bytes written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixStream;
+    /// use std::fs::File;
+    /// use std::os::unix::io::AsRawFd;
+    ///
+    /// let socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let file = File::open("/etc/hosts").unwrap();
+    /// socket.send_with_fds(b"file", &[file.as_raw_fd()]).unwrap();
+    /// ```
+    pub fn send_with_fds(&self, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+        send_with_fds(&self.0, buf, fds)
+    }
+
+    /// Receives data from the socket together with the file descriptors.
+    ///
+    /// Returns the number of bytes read and the number of file descriptors
+    /// stored in `fds`. The received file descriptors are owned by the caller,
+    /// and those exceed the capacity of `fds` are closed.
+    ///
+    /// If so many file descriptors are sent that the control message is
+    /// truncated, all the received ones are closed and an error is returned.
+    /// The data of the message is consumed in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixStream;
+    /// use std::fs::File;
+    /// use std::os::unix::io::FromRawFd;
+    ///
+    /// let socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let mut buf = [0; 16];
+    /// let mut fds = [0; 4];
+    /// let (n, num) = socket.recv_with_fds(&mut buf, &mut fds).unwrap();
+    /// let files: Vec<File> = fds[..num]
+    ///     .iter()
+    ///     .map(|fd| unsafe { File::from_raw_fd(*fd) })
+    ///     .collect();
+    /// ```
+    pub fn recv_with_fds(&self, buf: &mut [u8], fds: &mut [RawFd]) -> io::Result<(usize, usize)> {
+        recv_with_fds(&self.0, buf, fds)
+    }
+
+    /// Returns the credentials of the process on the other end of the socket.
+    ///
+    /// The `pid` is only available on linux and android.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixStream;
+    ///
+    /// let socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let cred = socket.peer_cred().unwrap();
+    /// println!("peer uid = {}", cred.uid);
+    /// ```
+    pub fn peer_cred(&self) -> io::Result<UCred> {
+        peer_cred(self.as_raw_fd())
+    }
+
+    /// Reads into the spare capacity of `buf` and advances its length.
+    ///
+    /// More space is reserved if `buf` is full. Returns the number of bytes
+    /// read, `0` means the peer has shut down.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bytes::BytesMut;
+    /// use cogo::os::unix::net::UnixStream;
+    ///
+    /// let mut socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let mut buf = BytesMut::with_capacity(4096);
+    /// let n = socket.read_buf(&mut buf).unwrap();
+    /// assert_eq!(buf.len(), n);
+    /// ```
+    pub fn read_buf(&mut self, buf: &mut BytesMut) -> io::Result<usize> {
+        let co = self.0.ctx_check()?;
+        crate::io::read_buf(self, buf, self.0.read_timeout()?, co)
+    }
+
+    /// Writes the data of `buf` and advances it by the number of bytes written.
+    ///
+    /// All the chunks of `buf` are written by one syscall if possible.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bytes::Buf;
+    /// use cogo::os::unix::net::UnixStream;
+    ///
+    /// let mut socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let mut buf = (&b"hello "[..]).chain(&b"world"[..]);
+    /// while buf.has_remaining() {
+    ///     socket.write_buf(&mut buf).unwrap();
+    /// }
+    /// ```
+    pub fn write_buf<B: Buf>(&mut self, buf: &mut B) -> io::Result<usize> {
+        let co = self.0.ctx_check()?;
+        crate::io::write_buf(self, buf, self.0.write_timeout()?, co)
+    }
+
+    /// Receives data on the socket without removing it from the queue.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixStream;
+    ///
+    /// let socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let mut buf = [0; 10];
+    /// let len = socket.peek(&mut buf).expect("peek failed");
+    /// ```
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let co = self.0.ctx_check()?;
+        crate::io::peek(self, buf, self.0.read_timeout()?, co)
+    }
+
+    /// Reads into `buf`, failing with a `TimedOut` error if no data arrives
+    /// before `deadline`.
+    ///
+    /// The deadline only applies to this call, the read timeout of the
+    /// socket is neither used nor changed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::{Duration, Instant};
+    /// use cogo::os::unix::net::UnixStream;
+    ///
+    /// let mut socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let mut buf = [0; 10];
+    /// let deadline = Instant::now() + Duration::from_secs(2);
+    /// let n = socket.read_with_deadline(&mut buf, deadline).unwrap();
+    /// ```
+    pub fn read_with_deadline(&mut self, buf: &mut [u8], deadline: Instant) -> io::Result<usize> {
+        self.0.read_with_deadline(buf, deadline)
+    }
+
+    /// Reads the exact number of bytes required to fill `buf` before
+    /// `deadline`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::{Duration, Instant};
+    /// use cogo::os::unix::net::UnixStream;
+    ///
+    /// let mut socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let mut header = [0; 4];
+    /// let deadline = Instant::now() + Duration::from_secs(2);
+    /// socket.read_exact_with_deadline(&mut header, deadline).unwrap();
+    /// ```
+    pub fn read_exact_with_deadline(
+        &mut self,
+        buf: &mut [u8],
+        deadline: Instant,
+    ) -> io::Result<()> {
+        self.0.read_exact_with_deadline(buf, deadline)
+    }
+
+    /// Writes from `buf`, failing with a `TimedOut` error if nothing could be
+    /// written before `deadline`.
+    ///
+    /// The deadline only applies to this call, the write timeout of the
+    /// socket is neither used nor changed.
+    pub fn write_with_deadline(&mut self, buf: &[u8], deadline: Instant) -> io::Result<usize> {
+        self.0.write_with_deadline(buf, deadline)
+    }
+
+    /// Writes the entire `buf` before `deadline`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::{Duration, Instant};
+    /// use cogo::os::unix::net::UnixStream;
+    ///
+    /// let mut socket = UnixStream::connect("/tmp/sock").unwrap();
+    /// let deadline = Instant::now() + Duration::from_secs(2);
+    /// socket.write_all_with_deadline(b"hello world", deadline).unwrap();
+    /// ```
+    pub fn write_all_with_deadline(&mut self, buf: &[u8], deadline: Instant) -> io::Result<()> {
+        self.0.write_all_with_deadline(buf, deadline)
+    }
 }
 
 impl io::Read for UnixStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.0.read(buf)
     }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        self.0.read_vectored(bufs)
+    }
 }
 
 // impl<'a> io::Read for &'a UnixStream {
@@ -312,6 +544,12 @@ impl AsRawFd for UnixStream {
     }
 }
 
+impl AsIoData for UnixStream {
+    fn as_io_data(&self) -> &IoData {
+        self.0.as_io_data()
+    }
+}
+
 impl FromRawFd for UnixStream {
     unsafe fn from_raw_fd(fd: RawFd) -> UnixStream {
         let stream = FromRawFd::from_raw_fd(fd);
@@ -387,6 +625,25 @@ impl UnixListener {
         Ok(UnixListener(CoIo::new(listener)?))
     }
 
+    /// Creates a new `UnixListener` bound to the linux abstract namespace `name`.
+    ///
+    /// The `name` doesn't contain the leading nul byte.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixListener;
+    ///
+    /// let listener = UnixListener::bind_abstract(b"my_socket").unwrap();
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn bind_abstract(name: &[u8]) -> io::Result<UnixListener> {
+        let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+        socket.bind(&abstract_addr(name)?)?;
+        socket.listen(128)?;
+        Ok(UnixListener(CoIo::new(socket.into())?))
+    }
+
     /// Accepts a new incoming connection to this listener.
     ///
     /// This function will block the calling thread until a new Unix connection
@@ -411,7 +668,7 @@ impl UnixListener {
             return Ok((UnixStream(CoIo::new(s)?), a));
         }
 
-        self.0.io_reset();
+        self.0.io_reset_read();
         match self.0.inner().accept() {
             Ok((s, a)) => return Ok((UnixStream(CoIo::new(s)?), a)),
             Err(e) => {
@@ -530,12 +787,28 @@ impl UnixListener {
     }
 }
 
+impl net_impl::UnixAccept for UnixListener {
+    type Conn = UnixStream;
+    type Addr = SocketAddr;
+
+    fn try_accept(&self) -> io::Result<(UnixStream, SocketAddr)> {
+        let (s, a) = self.0.inner().accept()?;
+        Ok((UnixStream(CoIo::new(s)?), a))
+    }
+}
+
 impl AsRawFd for UnixListener {
     fn as_raw_fd(&self) -> RawFd {
         self.0.as_raw_fd()
     }
 }
 
+impl AsIoData for UnixListener {
+    fn as_io_data(&self) -> &IoData {
+        self.0.as_io_data()
+    }
+}
+
 impl FromRawFd for UnixListener {
     unsafe fn from_raw_fd(fd: RawFd) -> UnixListener {
         let listener = FromRawFd::from_raw_fd(fd);
@@ -652,6 +925,24 @@ impl UnixDatagram {
         Ok(UnixDatagram(CoIo::new(datagram)?))
     }
 
+    /// Creates a Unix datagram socket bound to the linux abstract namespace `name`.
+    ///
+    /// The `name` doesn't contain the leading nul byte.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixDatagram;
+    ///
+    /// let sock = UnixDatagram::bind_abstract(b"my_socket").unwrap();
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn bind_abstract(name: &[u8]) -> io::Result<UnixDatagram> {
+        let socket = Socket::new(Domain::UNIX, Type::DGRAM, None)?;
+        socket.bind(&abstract_addr(name)?)?;
+        Ok(UnixDatagram(CoIo::new(socket.into())?))
+    }
+
     /// Creates a Unix Datagram socket which is not bound to any address.
     ///
     /// # Examples
@@ -725,6 +1016,23 @@ impl UnixDatagram {
         self.0.inner().connect(path)
     }
 
+    /// Connects the socket to the linux abstract namespace `name`.
+    ///
+    /// The `name` doesn't contain the leading nul byte.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixDatagram;
+    ///
+    /// let sock = UnixDatagram::unbound().unwrap();
+    /// sock.connect_abstract(b"my_socket").expect("Couldn't connect");
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn connect_abstract(&self, name: &[u8]) -> io::Result<()> {
+        SockRef::from(self.0.inner()).connect(&abstract_addr(name)?)
+    }
+
     /// Creates a new independently owned handle to the underlying socket.
     ///
     /// The returned `UnixDatagram` is a reference to the same socket that this
@@ -803,7 +1111,7 @@ impl UnixDatagram {
             return self.0.inner().recv_from(buf);
         }
 
-        self.0.io_reset();
+        self.0.io_reset_read();
         // this is an earlier return try for nonblocking read
         match self.0.inner().recv_from(buf) {
             Ok(n) => return Ok(n),
@@ -842,7 +1150,7 @@ impl UnixDatagram {
             return self.0.inner().recv(buf);
         }
 
-        self.0.io_reset();
+        self.0.io_reset_read();
         // this is an earlier return try for nonblocking read
         match self.0.inner().recv(buf) {
             Ok(n) => return Ok(n),
@@ -880,7 +1188,7 @@ impl UnixDatagram {
             return self.0.inner().send_to(buf, path);
         }
 
-        self.0.io_reset();
+        self.0.io_reset_write();
         // this is an earlier return try for nonblocking read
         match self.0.inner().send_to(buf, path.as_ref()) {
             Ok(n) => return Ok(n),
@@ -922,7 +1230,7 @@ impl UnixDatagram {
             return self.0.inner().send(buf);
         }
 
-        self.0.io_reset();
+        self.0.io_reset_write();
         // this is an earlier return try for nonblocking write
         match self.0.inner().send(buf) {
             Ok(n) => return Ok(n),
@@ -1068,6 +1376,77 @@ impl UnixDatagram {
     pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
         self.0.inner().shutdown(how)
     }
+
+    /// Sends data on the socket together with the file descriptors.
+    ///
+    /// The file descriptors are passed to the peer by `SCM_RIGHTS`, the peer
+    /// would get its own duplicates of them. On success, returns the number
+    /// of bytes written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixDatagram;
+    /// use std::fs::File;
+    /// use std::os::unix::io::AsRawFd;
+    ///
+    /// let socket = UnixDatagram::unbound().unwrap();
+    /// socket.connect("/tmp/sock").unwrap();
+    /// let file = File::open("/etc/hosts").unwrap();
+    /// socket.send_with_fds(b"file", &[file.as_raw_fd()]).unwrap();
+    /// ```
+    pub fn send_with_fds(&self, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+        send_with_fds(&self.0, buf, fds)
+    }
+
+    /// Receives data from the socket together with the file descriptors.
+    ///
+    /// Returns the number of bytes read and the number of file descriptors
+    /// stored in `fds`. The received file descriptors are owned by the caller,
+    /// and those exceed the capacity of `fds` are closed.
+    ///
+    /// If so many file descriptors are sent that the control message is
+    /// truncated, all the received ones are closed and an error is returned.
+    /// The data of the message is consumed in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixDatagram;
+    /// use std::fs::File;
+    /// use std::os::unix::io::FromRawFd;
+    ///
+    /// let socket = UnixDatagram::unbound().unwrap();
+    /// socket.connect("/tmp/sock").unwrap();
+    /// let mut buf = [0; 16];
+    /// let mut fds = [0; 4];
+    /// let (n, num) = socket.recv_with_fds(&mut buf, &mut fds).unwrap();
+    /// let files: Vec<File> = fds[..num]
+    ///     .iter()
+    ///     .map(|fd| unsafe { File::from_raw_fd(*fd) })
+    ///     .collect();
+    /// ```
+    pub fn recv_with_fds(&self, buf: &mut [u8], fds: &mut [RawFd]) -> io::Result<(usize, usize)> {
+        recv_with_fds(&self.0, buf, fds)
+    }
+
+    /// Returns the credentials of the process on the other end of the socket.
+    ///
+    /// The `pid` is only available on linux and android.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixDatagram;
+    ///
+    /// let socket = UnixDatagram::unbound().unwrap();
+    /// socket.connect("/tmp/sock").unwrap();
+    /// let cred = socket.peer_cred().unwrap();
+    /// println!("peer uid = {}", cred.uid);
+    /// ```
+    pub fn peer_cred(&self) -> io::Result<UCred> {
+        peer_cred(self.as_raw_fd())
+    }
 }
 
 impl AsRawFd for UnixDatagram {
@@ -1076,6 +1455,12 @@ impl AsRawFd for UnixDatagram {
     }
 }
 
+impl AsIoData for UnixDatagram {
+    fn as_io_data(&self) -> &IoData {
+        self.0.as_io_data()
+    }
+}
+
 impl FromRawFd for UnixDatagram {
     unsafe fn from_raw_fd(fd: RawFd) -> UnixDatagram {
         let datagram = FromRawFd::from_raw_fd(fd);
@@ -1089,46 +1474,634 @@ impl IntoRawFd for UnixDatagram {
     }
 }
 
-#[cfg(all(test, not(target_os = "emscripten")))]
-mod test {
-    use std::io;
-    use std::io::prelude::*;
-    use std::time::Duration;
-    use tempdir::TempDir;
-
-    use super::*;
+/// A Unix sequenced-packet listener.
+///
+/// The sequenced-packet sockets are connection oriented like `UnixStream`
+/// but preserve the message boundaries like `UnixDatagram`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::os::unix::net::UnixSeqpacketListener;
+///
+/// let listener = UnixSeqpacketListener::bind("/path/to/the/socket").unwrap();
+///
+/// loop {
+///     let (conn, _) = listener.accept().unwrap();
+///     cogo::go!(move || {
+///         let mut buf = [0; 1024];
+///         let n = conn.recv(&mut buf).unwrap();
+///         conn.send(&buf[..n]).unwrap();
+///     });
+/// }
+/// ```
+pub struct UnixSeqpacketListener(CoIo<Socket>);
 
-    fn tmpdir() -> TempDir {
-        TempDir::new("test").expect("failed to create TempDir")
+impl fmt::Debug for UnixSeqpacketListener {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut builder = fmt.debug_struct("UnixSeqpacketListener");
+        builder.field("fd", &self.as_raw_fd());
+        if let Ok(addr) = self.local_addr() {
+            builder.field("local", &addr);
+        }
+        builder.finish()
     }
+}
 
-    macro_rules! or_panic {
-        ($e:expr) => {
-            match $e {
-                Ok(e) => e,
-                Err(e) => panic!("{}", e),
-            }
-        };
+impl UnixSeqpacketListener {
+    /// Creates a new `UnixSeqpacketListener` bound to the specified socket.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacketListener;
+    ///
+    /// let listener = UnixSeqpacketListener::bind("/path/to/the/socket").unwrap();
+    /// ```
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixSeqpacketListener> {
+        Self::bind_addr(&SockAddr::unix(path)?)
     }
 
-    #[test]
-    fn basic() {
-        let dir = tmpdir();
-        let socket_path = dir.path().join("sock");
-        let msg1 = b"hello";
-        let msg2 = b"world!";
+    /// Creates a new `UnixSeqpacketListener` bound to the linux abstract namespace `name`.
+    ///
+    /// The `name` doesn't contain the leading nul byte.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacketListener;
+    ///
+    /// let listener = UnixSeqpacketListener::bind_abstract(b"my_socket").unwrap();
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn bind_abstract(name: &[u8]) -> io::Result<UnixSeqpacketListener> {
+        Self::bind_addr(&abstract_addr(name)?)
+    }
 
-        let listener = or_panic!(UnixListener::bind(&socket_path));
-        let thread = go!(move || {
-            let mut stream = or_panic!(listener.accept()).0;
-            let mut buf = [0; 5];
-            or_panic!(stream.read(&mut buf));
-            assert_eq!(&msg1[..], &buf[..]);
-            or_panic!(stream.write_all(msg2));
-        });
+    fn bind_addr(addr: &SockAddr) -> io::Result<UnixSeqpacketListener> {
+        let socket = Socket::new(Domain::UNIX, Type::SEQPACKET, None)?;
+        socket.bind(addr)?;
+        socket.listen(128)?;
+        Ok(UnixSeqpacketListener(CoIo::new(socket)?))
+    }
 
-        let mut stream = or_panic!(UnixStream::connect(&socket_path));
-        assert_eq!(
+    /// Accepts a new incoming connection to this listener.
+    ///
+    /// This function will block the calling coroutine until a new connection
+    /// is established. When established, the corresponding `UnixSeqpacket`
+    /// and the remote peer's address will be returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacketListener;
+    ///
+    /// let listener = UnixSeqpacketListener::bind("/path/to/the/socket").unwrap();
+    ///
+    /// match listener.accept() {
+    ///     Ok((conn, addr)) => println!("Got a client: {:?}", addr),
+    ///     Err(e) => println!("accept function failed: {:?}", e),
+    /// }
+    /// ```
+    pub fn accept(&self) -> io::Result<(UnixSeqpacket, SockAddr)> {
+        use crate::io::sys::net::UnixAccept;
+
+        if !self.0.ctx_check()? {
+            return self.try_accept();
+        }
+
+        self.0.io_reset_read();
+        match self.try_accept() {
+            Ok(ret) => return Ok(ret),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut a = net_impl::UnixListenerAccept::new(self)?;
+        yield_with(&a);
+        a.done()
+    }
+
+    /// Creates a new independently owned handle to the underlying socket.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacketListener;
+    ///
+    /// let listener = UnixSeqpacketListener::bind("/path/to/the/socket").unwrap();
+    ///
+    /// let listener_copy = listener.try_clone().expect("try_clone failed");
+    /// ```
+    pub fn try_clone(&self) -> io::Result<UnixSeqpacketListener> {
+        let socket = self.0.inner().try_clone()?;
+        Ok(UnixSeqpacketListener(CoIo::new(socket)?))
+    }
+
+    /// Returns the local socket address of this listener.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacketListener;
+    ///
+    /// let listener = UnixSeqpacketListener::bind("/path/to/the/socket").unwrap();
+    ///
+    /// let addr = listener.local_addr().expect("Couldn't get local address");
+    /// ```
+    pub fn local_addr(&self) -> io::Result<SockAddr> {
+        self.0.inner().local_addr()
+    }
+
+    /// Moves the socket into or out of nonblocking mode.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.0.set_nonblocking(nonblocking)
+    }
+
+    /// Returns the value of the `SO_ERROR` option.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.0.inner().take_error()
+    }
+}
+
+impl net_impl::UnixAccept for UnixSeqpacketListener {
+    type Conn = UnixSeqpacket;
+    type Addr = SockAddr;
+
+    fn try_accept(&self) -> io::Result<(UnixSeqpacket, SockAddr)> {
+        let (s, a) = self.0.inner().accept()?;
+        Ok((UnixSeqpacket(CoIo::new(s)?), a))
+    }
+}
+
+impl AsRawFd for UnixSeqpacketListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsIoData for UnixSeqpacketListener {
+    fn as_io_data(&self) -> &IoData {
+        self.0.as_io_data()
+    }
+}
+
+impl FromRawFd for UnixSeqpacketListener {
+    unsafe fn from_raw_fd(fd: RawFd) -> UnixSeqpacketListener {
+        let socket = FromRawFd::from_raw_fd(fd);
+        UnixSeqpacketListener(CoIo::new(socket).expect("can't convert to UnixSeqpacketListener"))
+    }
+}
+
+impl IntoRawFd for UnixSeqpacketListener {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+/// A Unix sequenced-packet socket.
+///
+/// Each `send` is delivered as one message to the peer and each `recv`
+/// returns exactly one message.
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::os::unix::net::UnixSeqpacket;
+///
+/// let conn = UnixSeqpacket::connect("/path/to/my/socket").unwrap();
+/// conn.send(b"hello").unwrap();
+/// conn.send(b"world").unwrap();
+/// let mut buf = [0; 1024];
+/// let n = conn.recv(&mut buf).unwrap();
+/// println!("{:?}", &buf[..n]);
+/// ```
+pub struct UnixSeqpacket(CoIo<Socket>);
+
+impl fmt::Debug for UnixSeqpacket {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut builder = fmt.debug_struct("UnixSeqpacket");
+        builder.field("fd", &self.as_raw_fd());
+        if let Ok(addr) = self.local_addr() {
+            builder.field("local", &addr);
+        }
+        if let Ok(addr) = self.peer_addr() {
+            builder.field("peer", &addr);
+        }
+        builder.finish()
+    }
+}
+
+impl UnixSeqpacket {
+    /// Connects to the socket named by `path`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacket;
+    ///
+    /// let conn = UnixSeqpacket::connect("/tmp/sock").unwrap();
+    /// ```
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<UnixSeqpacket> {
+        Self::connect_addr(SockAddr::unix(path)?)
+    }
+
+    /// Connects to the socket in the linux abstract namespace named by `name`.
+    ///
+    /// The `name` doesn't contain the leading nul byte.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacket;
+    ///
+    /// let conn = UnixSeqpacket::connect_abstract(b"my_socket").unwrap();
+    /// ```
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn connect_abstract(name: &[u8]) -> io::Result<UnixSeqpacket> {
+        Self::connect_addr(abstract_addr(name)?)
+    }
+
+    fn connect_addr(addr: SockAddr) -> io::Result<UnixSeqpacket> {
+        if !is_coroutine() {
+            let socket = Socket::new(Domain::UNIX, Type::SEQPACKET, None)?;
+            socket.connect(&addr)?;
+            return Ok(UnixSeqpacket(CoIo::new(socket)?));
+        }
+
+        let convert = |socket, io| UnixSeqpacket(CoIo::from_raw(socket, io));
+        let mut c = net_impl::UnixStreamConnect::with_type(addr, Type::SEQPACKET)?;
+
+        if c.check_connected()? {
+            return c.done_with(convert);
+        }
+
+        yield_with(&c);
+        c.done_with(convert)
+    }
+
+    /// Creates an unnamed pair of connected sockets.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacket;
+    ///
+    /// let (conn1, conn2) = UnixSeqpacket::pair().unwrap();
+    /// ```
+    pub fn pair() -> io::Result<(UnixSeqpacket, UnixSeqpacket)> {
+        let (s1, s2) = Socket::pair(Domain::UNIX, Type::SEQPACKET, None)?;
+        Ok((UnixSeqpacket(CoIo::new(s1)?), UnixSeqpacket(CoIo::new(s2)?)))
+    }
+
+    /// Creates a new independently owned handle to the underlying socket.
+    pub fn try_clone(&self) -> io::Result<UnixSeqpacket> {
+        let socket = self.0.inner().try_clone()?;
+        Ok(UnixSeqpacket(CoIo::new(socket)?))
+    }
+
+    /// Returns the socket address of the local half of this connection.
+    pub fn local_addr(&self) -> io::Result<SockAddr> {
+        self.0.inner().local_addr()
+    }
+
+    /// Returns the socket address of the remote half of this connection.
+    pub fn peer_addr(&self) -> io::Result<SockAddr> {
+        self.0.inner().peer_addr()
+    }
+
+    /// Receives one message from the socket.
+    ///
+    /// On success, returns the number of bytes read, `0` means the peer
+    /// has shut down. If the message is too long to fit in the buffer,
+    /// the excess bytes are discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacket;
+    ///
+    /// let conn = UnixSeqpacket::connect("/tmp/sock").unwrap();
+    /// let mut buf = vec![0; 1024];
+    /// let n = conn.recv(&mut buf).expect("recv function failed");
+    /// ```
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.0.ctx_check()? {
+            // this can't be nonblocking!!
+            return io::Read::read(&mut self.0.inner(), buf);
+        }
+
+        self.0.io_reset_read();
+        // this is an earlier return try for nonblocking read
+        match io::Read::read(&mut self.0.inner(), buf) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut reader = net_impl::SocketRead::new(&self.0, buf, self.0.read_timeout()?);
+        yield_with(&reader);
+        reader.done()
+    }
+
+    /// Sends the data as one message on the socket.
+    ///
+    /// On success, returns the number of bytes written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::os::unix::net::UnixSeqpacket;
+    ///
+    /// let conn = UnixSeqpacket::connect("/tmp/sock").unwrap();
+    /// conn.send(b"omelette au fromage").expect("send function failed");
+    /// ```
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        if !self.0.ctx_check()? {
+            // this can't be nonblocking!!
+            return self.0.inner().send(buf);
+        }
+
+        self.0.io_reset_write();
+        // this is an earlier return try for nonblocking write
+        match self.0.inner().send(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut writer = net_impl::SocketWrite::new(&self.0, buf, self.0.write_timeout()?);
+        yield_with(&writer);
+        writer.done()
+    }
+
+    /// Sends one message together with the file descriptors.
+    ///
+    /// See [`UnixStream::send_with_fds`](struct.UnixStream.html#method.send_with_fds).
+    pub fn send_with_fds(&self, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+        send_with_fds(&self.0, buf, fds)
+    }
+
+    /// Receives one message together with the file descriptors.
+    ///
+    /// See [`UnixStream::recv_with_fds`](struct.UnixStream.html#method.recv_with_fds).
+    pub fn recv_with_fds(&self, buf: &mut [u8], fds: &mut [RawFd]) -> io::Result<(usize, usize)> {
+        recv_with_fds(&self.0, buf, fds)
+    }
+
+    /// Returns the credentials of the process on the other end of the socket.
+    pub fn peer_cred(&self) -> io::Result<UCred> {
+        peer_cred(self.as_raw_fd())
+    }
+
+    /// Sets the read timeout for the socket.
+    ///
+    /// If the provided value is `None`, then `recv` calls will block
+    /// indefinitely. It is an error to pass the zero `Duration` to this
+    /// method.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.inner().set_read_timeout(timeout)?;
+        self.0.set_read_timeout(timeout)
+    }
+
+    /// Sets the write timeout for the socket.
+    ///
+    /// If the provided value is `None`, then `send` calls will block
+    /// indefinitely. It is an error to pass the zero `Duration` to this
+    /// method.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.inner().set_write_timeout(timeout)?;
+        self.0.set_write_timeout(timeout)
+    }
+
+    /// Returns the read timeout of this socket.
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        self.0.read_timeout()
+    }
+
+    /// Returns the write timeout of this socket.
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        self.0.write_timeout()
+    }
+
+    /// Moves the socket into or out of nonblocking mode.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.0.set_nonblocking(nonblocking)
+    }
+
+    /// Returns the value of the `SO_ERROR` option.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.0.inner().take_error()
+    }
+
+    /// Shuts down the read, write, or both halves of this connection.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.0.inner().shutdown(how)
+    }
+}
+
+impl AsRawFd for UnixSeqpacket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsIoData for UnixSeqpacket {
+    fn as_io_data(&self) -> &IoData {
+        self.0.as_io_data()
+    }
+}
+
+impl FromRawFd for UnixSeqpacket {
+    unsafe fn from_raw_fd(fd: RawFd) -> UnixSeqpacket {
+        let socket = FromRawFd::from_raw_fd(fd);
+        UnixSeqpacket(CoIo::new(socket).expect("can't convert to UnixSeqpacket"))
+    }
+}
+
+impl IntoRawFd for UnixSeqpacket {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+/// Credentials of the process on the other end of a Unix socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UCred {
+    /// the process id, only available on linux and android
+    pub pid: Option<libc::pid_t>,
+    /// the effective user id
+    pub uid: libc::uid_t,
+    /// the effective group id
+    pub gid: libc::gid_t,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_cred(fd: RawFd) -> io::Result<UCred> {
+    use std::mem;
+
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(UCred {
+        pid: Some(cred.pid),
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_cred(fd: RawFd) -> io::Result<UCred> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(UCred {
+        pid: None,
+        uid,
+        gid,
+    })
+}
+
+// the linux abstract socket address starts with a nul byte
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn abstract_addr(name: &[u8]) -> io::Result<SockAddr> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut path = Vec::with_capacity(name.len() + 1);
+    path.push(0);
+    path.extend_from_slice(name);
+    SockAddr::unix(OsStr::from_bytes(&path))
+}
+
+fn send_with_fds<T: AsRawFd>(io: &CoIo<T>, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+    if !io.ctx_check()? {
+        return net_impl::send_with_fds(io.as_raw_fd(), buf, fds);
+    }
+
+    io.io_reset_write();
+    // this is an earlier return try for nonblocking write
+    match net_impl::send_with_fds(io.as_raw_fd(), buf, fds) {
+        Ok(n) => return Ok(n),
+        Err(e) => {
+            // raw_os_error is faster than kind
+            let raw_err = e.raw_os_error();
+            if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                // do nothing here
+            } else {
+                return Err(e);
+            }
+        }
+    }
+
+    let mut writer = net_impl::UnixSendFds::new(io, buf, fds);
+    yield_with(&writer);
+    writer.done()
+}
+
+fn recv_with_fds<T: AsRawFd>(
+    io: &CoIo<T>,
+    buf: &mut [u8],
+    fds: &mut [RawFd],
+) -> io::Result<(usize, usize)> {
+    if !io.ctx_check()? {
+        return net_impl::recv_with_fds(io.as_raw_fd(), buf, fds);
+    }
+
+    io.io_reset_read();
+    // this is an earlier return try for nonblocking read
+    match net_impl::recv_with_fds(io.as_raw_fd(), buf, fds) {
+        Ok(n) => return Ok(n),
+        Err(e) => {
+            // raw_os_error is faster than kind
+            let raw_err = e.raw_os_error();
+            if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                // do nothing here
+            } else {
+                return Err(e);
+            }
+        }
+    }
+
+    let mut reader = net_impl::UnixRecvFds::new(io, buf, fds);
+    yield_with(&reader);
+    reader.done()
+}
+
+#[cfg(all(test, not(target_os = "emscripten")))]
+mod test {
+    use std::io;
+    use std::io::prelude::*;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn tmpdir() -> TempDir {
+        TempDir::new("test").expect("failed to create TempDir")
+    }
+
+    macro_rules! or_panic {
+        ($e:expr) => {
+            match $e {
+                Ok(e) => e,
+                Err(e) => panic!("{}", e),
+            }
+        };
+    }
+
+    #[test]
+    fn basic() {
+        let dir = tmpdir();
+        let socket_path = dir.path().join("sock");
+        let msg1 = b"hello";
+        let msg2 = b"world!";
+
+        let listener = or_panic!(UnixListener::bind(&socket_path));
+        let thread = go!(move || {
+            let mut stream = or_panic!(listener.accept()).0;
+            let mut buf = [0; 5];
+            or_panic!(stream.read(&mut buf));
+            assert_eq!(&msg1[..], &buf[..]);
+            or_panic!(stream.write_all(msg2));
+        });
+
+        let mut stream = or_panic!(UnixStream::connect(&socket_path));
+        assert_eq!(
             Some(&*socket_path),
             stream.peer_addr().unwrap().as_pathname()
         );
@@ -1402,4 +2375,247 @@ mod test {
     fn abstract_namespace_not_allowed() {
         assert!(UnixStream::connect("\0asdf").is_err());
     }
+
+    #[test]
+    fn pass_fds() {
+        let (s1, s2) = or_panic!(UnixStream::pair());
+        let (mut a, b) = or_panic!(UnixStream::pair());
+
+        let thread = go!(move || {
+            let fds = [b.as_raw_fd()];
+            assert_eq!(or_panic!(s1.send_with_fds(b"fd", &fds)), 2);
+        });
+
+        let mut buf = [0; 8];
+        let mut fds = [-1; 2];
+        let (n, num) = or_panic!(s2.recv_with_fds(&mut buf, &mut fds));
+        thread.join().unwrap();
+        assert_eq!(&buf[..n], b"fd");
+        assert_eq!(num, 1);
+
+        // the received fd is another handle of `b`
+        let mut c = unsafe { net::UnixStream::from_raw_fd(fds[0]) };
+        or_panic!(a.write_all(b"hello"));
+        let mut buf = [0; 5];
+        or_panic!(c.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn datagram_pass_fds() {
+        let (s1, s2) = or_panic!(UnixDatagram::pair());
+        let (a, _b) = or_panic!(UnixDatagram::pair());
+        or_panic!(s1.send_with_fds(b"x", &[a.as_raw_fd(), a.as_raw_fd()]));
+
+        // only one fd could be stored, the other is closed
+        let mut buf = [0; 8];
+        let mut fds = [-1; 1];
+        let (n, num) = or_panic!(s2.recv_with_fds(&mut buf, &mut fds));
+        assert_eq!((n, num), (1, 1));
+        unsafe { libc::close(fds[0]) };
+    }
+
+    #[test]
+    fn extra_fds_closed() {
+        for &sent in [2, 8].iter() {
+            let (s1, s2) = or_panic!(UnixDatagram::pair());
+            let (a, mut b) = or_panic!(net::UnixStream::pair());
+            let sent_fds = vec![a.as_raw_fd(); sent];
+            or_panic!(s1.send_with_fds(b"x", &sent_fds));
+            drop(a);
+
+            let mut buf = [0; 8];
+            let mut fds = [-1; 1];
+            match s2.recv_with_fds(&mut buf, &mut fds) {
+                Ok((n, num)) => {
+                    assert_eq!((n, num), (1, 1));
+                    unsafe { libc::close(fds[0]) };
+                }
+                // the truncated control message is reported
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    assert_eq!(sent, 8);
+                }
+            }
+
+            // all the handles of `a` are closed, so `b` sees the eof
+            or_panic!(b.set_nonblocking(true));
+            assert_eq!(or_panic!(b.read(&mut buf)), 0);
+        }
+    }
+
+    #[test]
+    fn peer_cred() {
+        let (s1, _s2) = or_panic!(UnixStream::pair());
+        let cred = or_panic!(s1.peer_cred());
+        assert_eq!(cred.uid, unsafe { libc::getuid() });
+        assert_eq!(cred.gid, unsafe { libc::getgid() });
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        assert_eq!(cred.pid, Some(unsafe { libc::getpid() }));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn abstract_namespace() {
+        let name = format!("cogo_test_{}", unsafe { libc::getpid() });
+        let listener = or_panic!(UnixListener::bind_abstract(name.as_bytes()));
+        let thread = go!(move || {
+            let mut stream = or_panic!(listener.accept()).0;
+            or_panic!(stream.write_all(b"abstract"));
+        });
+
+        let mut stream = or_panic!(UnixStream::connect_abstract(name.as_bytes()));
+        let mut buf = vec![];
+        or_panic!(stream.read_to_end(&mut buf));
+        assert_eq!(&buf[..], b"abstract");
+        thread.join().unwrap();
+
+        let dgram_name = format!("{}_dgram", name);
+        let server = or_panic!(UnixDatagram::bind_abstract(dgram_name.as_bytes()));
+        let client = or_panic!(UnixDatagram::unbound());
+        or_panic!(client.connect_abstract(dgram_name.as_bytes()));
+        or_panic!(client.send(b"hi"));
+        let mut buf = [0; 4];
+        assert_eq!(or_panic!(server.recv(&mut buf)), 2);
+    }
+
+    #[test]
+    fn seqpacket() {
+        let dir = tmpdir();
+        let socket_path = dir.path().join("seqpacket");
+
+        let listener = or_panic!(UnixSeqpacketListener::bind(&socket_path));
+        let thread = go!(move || {
+            let (conn, _) = or_panic!(listener.accept());
+            let mut buf = [0; 16];
+            // the message boundaries are preserved
+            loop {
+                let n = or_panic!(conn.recv(&mut buf));
+                if n == 0 {
+                    break;
+                }
+                or_panic!(conn.send(&buf[..n]));
+            }
+        });
+
+        let conn = or_panic!(UnixSeqpacket::connect(&socket_path));
+        or_panic!(conn.send(b"hello"));
+        or_panic!(conn.send(b"world!"));
+        let mut buf = [0; 16];
+        assert_eq!(or_panic!(conn.recv(&mut buf)), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(or_panic!(conn.recv(&mut buf)), 6);
+        assert_eq!(&buf[..6], b"world!");
+        or_panic!(conn.shutdown(Shutdown::Write));
+        assert_eq!(or_panic!(conn.recv(&mut buf)), 0);
+
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn seqpacket_in_coroutine() {
+        let (s1, s2) = or_panic!(UnixSeqpacket::pair());
+        let thread = go!(move || {
+            let mut buf = [0; 4];
+            // the excess bytes are discarded
+            assert_eq!(or_panic!(s2.recv(&mut buf)), 4);
+            assert_eq!(&buf, b"trun");
+            or_panic!(s2.set_read_timeout(Some(Duration::from_millis(50))));
+            let e = s2.recv(&mut buf).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        });
+        or_panic!(s1.send(b"truncated"));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn seqpacket_abstract() {
+        let name = format!("cogo_seqpacket_{}", unsafe { libc::getpid() });
+        let listener = or_panic!(UnixSeqpacketListener::bind_abstract(name.as_bytes()));
+        let thread = go!(move || {
+            let conn = or_panic!(UnixSeqpacket::connect_abstract(name.as_bytes()));
+            or_panic!(conn.send(b"abstract"));
+        });
+
+        let (conn, _) = or_panic!(listener.accept());
+        let mut buf = [0; 16];
+        assert_eq!(or_panic!(conn.recv(&mut buf)), 8);
+        assert_eq!(&buf[..8], b"abstract");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn buf_io() {
+        use bytes::Buf;
+        use std::io::IoSliceMut;
+
+        let (mut s1, mut s2) = or_panic!(UnixStream::pair());
+        let thread = go!(move || {
+            let mut buf = [0; 5];
+            // peek doesn't consume the data
+            assert_eq!(or_panic!(s2.peek(&mut buf)), 5);
+            assert_eq!(&buf, b"hello");
+
+            let (mut a, mut b) = ([0; 5], [0; 6]);
+            let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+            assert_eq!(or_panic!(s2.read_vectored(&mut bufs)), 11);
+            assert_eq!(&a, b"hello");
+            assert_eq!(&b, b" world");
+
+            let mut buf = BytesMut::with_capacity(4);
+            while buf.len() < 8 {
+                assert!(or_panic!(s2.read_buf(&mut buf)) > 0);
+            }
+            assert_eq!(&buf[..], b"12345678");
+        });
+
+        let mut data = Buf::chain(&b"hello"[..], &b" world"[..]);
+        while data.has_remaining() {
+            or_panic!(s1.write_buf(&mut data));
+        }
+        // wait the reader consumes the first message
+        std::thread::sleep(Duration::from_millis(50));
+        let mut data = &b"12345678"[..];
+        while data.has_remaining() {
+            or_panic!(s1.write_buf(&mut data));
+        }
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn deadline() {
+        use std::time::Instant;
+
+        let (mut s1, mut s2) = or_panic!(UnixStream::pair());
+        // the socket wide timeout is not used by the deadline calls
+        or_panic!(s2.set_read_timeout(Some(Duration::from_millis(10))));
+        let thread = go!(move || {
+            let mut buf = [0; 4];
+            let deadline = Instant::now() + Duration::from_millis(500);
+            or_panic!(s2.read_exact_with_deadline(&mut buf, deadline));
+            assert_eq!(&buf, b"ping");
+
+            let deadline = Instant::now() + Duration::from_millis(50);
+            let e = s2.read_with_deadline(&mut buf, deadline).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            assert!(Instant::now() >= deadline);
+
+            // a passed deadline fails at once
+            let e = s2.read_with_deadline(&mut buf, deadline).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            s2
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+        let deadline = Instant::now() + Duration::from_millis(500);
+        or_panic!(s1.write_all_with_deadline(b"ping", deadline));
+        let _s2 = thread.join().unwrap();
+
+        // the deadline also works in thread context
+        let mut buf = [0; 4];
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let e = s1.read_with_deadline(&mut buf, deadline).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
 }
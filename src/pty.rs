@@ -0,0 +1,349 @@
+//! pseudo terminal support
+//!
+//! `open` creates a pty pair, the master side is driven by coroutines
+//! without blocking the worker threads and the slave side is handed to a
+//! child process with `spawn`. `Expect` is a small helper to script the
+//! interactive programs in the classic expect way.
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use crate::io::{AsIoData, CoIo, IoData};
+
+/// the size of the terminal window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WindowSize {
+    pub rows: u16,
+    pub cols: u16,
+    pub x_pixels: u16,
+    pub y_pixels: u16,
+}
+
+impl WindowSize {
+    /// create a window size of `rows` x `cols` characters
+    pub fn new(rows: u16, cols: u16) -> Self {
+        WindowSize {
+            rows,
+            cols,
+            x_pixels: 0,
+            y_pixels: 0,
+        }
+    }
+}
+
+/// the master side of a pty
+///
+/// reading and writing only block the current coroutine. when all the
+/// slave fds are closed, e.g. the child exits, read returns `Ok(0)`
+/// instead of the `EIO` error that linux reports
+#[derive(Debug)]
+pub struct Master(CoIo<File>);
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+// the slave side hung up
+fn eof<T: Default>(ret: io::Result<T>) -> io::Result<T> {
+    match ret {
+        Err(ref e) if e.raw_os_error() == Some(libc::EIO) => Ok(T::default()),
+        ret => ret,
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn ptsname(fd: RawFd) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut buf = [0 as libc::c_char; 128];
+    let ret = unsafe { libc::ptsname_r(fd, buf.as_mut_ptr(), buf.len()) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(name.to_bytes())))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn ptsname(fd: RawFd) -> io::Result<PathBuf> {
+    use once_cell::sync::Lazy;
+    use std::os::unix::ffi::OsStrExt;
+    use std::sync::Mutex;
+
+    // `ptsname` returns a static buffer
+    static LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let name = unsafe { libc::ptsname(fd) };
+    if name.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let name = unsafe { CStr::from_ptr(name) };
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(name.to_bytes())))
+}
+
+/// open a new pty pair, return the master side and the path of the slave
+///
+/// the slave side is not opened, pass the path to `spawn` to run a child
+/// process on it
+pub fn open() -> io::Result<(Master, PathBuf)> {
+    let fd = cvt(unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) })?;
+    // close the fd on error
+    let file = unsafe { File::from_raw_fd(fd) };
+    cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    cvt(unsafe { libc::grantpt(fd) })?;
+    cvt(unsafe { libc::unlockpt(fd) })?;
+    let slave = ptsname(fd)?;
+    Ok((Master(CoIo::new(file)?), slave))
+}
+
+/// spawn `cmd` with its stdio attached to the pty `slave`
+///
+/// the child runs in a new session with the pty as its controlling terminal
+pub fn spawn(cmd: &mut Command, slave: &Path) -> io::Result<Child> {
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(slave)?;
+    cmd.stdin(tty.try_clone()?)
+        .stdout(tty.try_clone()?)
+        .stderr(tty);
+    unsafe {
+        cmd.pre_exec(|| {
+            cvt(libc::setsid())?;
+            // the stdin is the slave at this point
+            cvt(libc::ioctl(0, libc::TIOCSCTTY as _, 0))?;
+            Ok(())
+        });
+    }
+    cmd.spawn()
+}
+
+impl Master {
+    /// set the window size, the foreground process group gets a `SIGWINCH`
+    pub fn set_window_size(&self, size: WindowSize) -> io::Result<()> {
+        let ws = libc::winsize {
+            ws_row: size.rows,
+            ws_col: size.cols,
+            ws_xpixel: size.x_pixels,
+            ws_ypixel: size.y_pixels,
+        };
+        cvt(unsafe { libc::ioctl(self.as_raw_fd(), libc::TIOCSWINSZ as _, &ws) })?;
+        Ok(())
+    }
+
+    /// get the window size
+    pub fn window_size(&self) -> io::Result<WindowSize> {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        cvt(unsafe { libc::ioctl(self.as_raw_fd(), libc::TIOCGWINSZ as _, &mut ws) })?;
+        Ok(WindowSize {
+            rows: ws.ws_row,
+            cols: ws.ws_col,
+            x_pixels: ws.ws_xpixel,
+            y_pixels: ws.ws_ypixel,
+        })
+    }
+
+    /// read into `buf`, a `TimedOut` error is returned if nothing could
+    /// be read before `deadline`
+    pub fn read_with_deadline(&mut self, buf: &mut [u8], deadline: Instant) -> io::Result<usize> {
+        eof(self.0.read_with_deadline(buf, deadline))
+    }
+
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(dur)
+    }
+
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.0.set_write_timeout(dur)
+    }
+
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        self.0.read_timeout()
+    }
+
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        self.0.write_timeout()
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.0.set_nonblocking(nonblocking)
+    }
+}
+
+impl Read for Master {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        eof(self.0.read(buf))
+    }
+}
+
+impl Write for Master {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl AsRawFd for Master {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Master {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl AsIoData for Master {
+    fn as_io_data(&self) -> &IoData {
+        self.0.as_io_data()
+    }
+}
+
+/// an expect style driver of the interactive program on the master side
+#[derive(Debug)]
+pub struct Expect {
+    master: Master,
+    // the received data that is not consumed yet
+    buf: Vec<u8>,
+}
+
+impl Expect {
+    pub fn new(master: Master) -> Self {
+        Expect {
+            master,
+            buf: Vec::new(),
+        }
+    }
+
+    /// wait until `pattern` shows up in the output within `timeout`
+    ///
+    /// return the output up to and including the pattern, the rest is kept
+    /// for the next call. a `TimedOut` error is returned if the pattern is
+    /// not found in time, and an `UnexpectedEof` error if the program exits
+    pub fn expect(&mut self, pattern: &str, timeout: Duration) -> io::Result<String> {
+        let pattern = pattern.as_bytes();
+        let deadline = Instant::now() + timeout;
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = find(&self.buf[searched..], pattern) {
+                let end = searched + pos + pattern.len();
+                let rest = self.buf.split_off(end);
+                let out = std::mem::replace(&mut self.buf, rest);
+                return Ok(String::from_utf8_lossy(&out).into_owned());
+            }
+            // the pattern may span the old and the new data
+            searched = (self.buf.len() + 1).saturating_sub(pattern.len());
+
+            let mut chunk = [0; 4096];
+            let n = self.master.read_with_deadline(&mut chunk, deadline)?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the program exited before the pattern shows up",
+                ));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// send `data` to the program
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.master.write_all(data)
+    }
+
+    /// send `line` followed by a line feed to the program
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.send(line.as_bytes())?;
+        self.send(b"\n")
+    }
+
+    /// the output that is received but not consumed by `expect`
+    pub fn pending(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// get the master side of the pty
+    pub fn master(&mut self) -> &mut Master {
+        &mut self.master
+    }
+
+    /// convert back to the master side, the pending output is dropped
+    pub fn into_inner(self) -> Master {
+        self.master
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_size() {
+        let (master, _slave) = open().unwrap();
+        master.set_window_size(WindowSize::new(40, 100)).unwrap();
+        assert_eq!(master.window_size().unwrap(), WindowSize::new(40, 100));
+    }
+
+    #[test]
+    fn spawn_on_slave() {
+        let (master, slave) = open().unwrap();
+        master.set_window_size(WindowSize::new(33, 77)).unwrap();
+        let h = go!(move || {
+            let mut child = spawn(
+                Command::new("sh").args(&["-c", "stty size; read x; echo got $x"]),
+                &slave,
+            )
+            .unwrap();
+            let mut exp = Expect::new(master);
+            exp.expect("33 77", Duration::from_secs(5)).unwrap();
+            exp.send_line("ping").unwrap();
+            exp.expect("got ping", Duration::from_secs(5)).unwrap();
+            // the output is drained until the child exits
+            let e = exp.expect("never", Duration::from_secs(5)).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+            assert!(child.wait().unwrap().success());
+        });
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn expect_timeout() {
+        let (master, slave) = open().unwrap();
+        let h = go!(move || {
+            let mut child = spawn(&mut Command::new("cat"), &slave).unwrap();
+            let mut exp = Expect::new(master);
+            exp.send_line("hello").unwrap();
+            let out = exp.expect("hello", Duration::from_secs(5)).unwrap();
+            assert!(out.ends_with("hello"));
+            let e = exp.expect("world", Duration::from_millis(100)).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            child.kill().unwrap();
+            child.wait().unwrap();
+        });
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// the http client configuration
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    max_header_bytes: usize,
+    max_idle_per_host: usize,
+    idle_timeout: Option<Duration>,
+    max_connections_per_host: Option<usize>,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            connect_timeout: Some(Duration::from_secs(30)),
+            read_timeout: Some(Duration::from_secs(30)),
+            timeout: None,
+            max_redirects: 10,
+            max_header_bytes: 64 * 1024,
+            max_idle_per_host: 8,
+            idle_timeout: Some(Duration::from_secs(90)),
+            max_connections_per_host: None,
+            #[cfg(unix)]
+            unix_socket: None,
+        }
+    }
+}
+
+impl HttpClientConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// set the time allowed to establish a connection
+    ///
+    /// `None` means no limit
+    pub fn set_connect_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// set the time allowed for each read from the connection
+    ///
+    /// `None` means no limit
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// set the time allowed for a whole request
+    ///
+    /// it covers connecting, the redirects and reading the response body.
+    /// `None` means no limit
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// set the max number of redirects that are followed
+    ///
+    /// `0` returns the redirect responses as they are
+    pub fn set_max_redirects(&mut self, n: usize) -> &mut Self {
+        self.max_redirects = n;
+        self
+    }
+
+    pub fn max_redirects(&self) -> usize {
+        self.max_redirects
+    }
+
+    /// set the max bytes of the response status line and headers
+    pub fn set_max_header_bytes(&mut self, n: usize) -> &mut Self {
+        self.max_header_bytes = n;
+        self
+    }
+
+    pub fn max_header_bytes(&self) -> usize {
+        self.max_header_bytes
+    }
+
+    /// set the max number of idle keep-alive connections kept for each host
+    pub fn set_max_idle_per_host(&mut self, n: usize) -> &mut Self {
+        self.max_idle_per_host = n;
+        self
+    }
+
+    pub fn max_idle_per_host(&self) -> usize {
+        self.max_idle_per_host
+    }
+
+    /// set how long an idle connection is kept in the pool
+    ///
+    /// `None` means no limit
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// set the max number of concurrent requests to each host
+    ///
+    /// more requests wait for a connection to be released. `None` means no limit
+    pub fn set_max_connections_per_host(&mut self, n: Option<usize>) -> &mut Self {
+        self.max_connections_per_host = n;
+        self
+    }
+
+    pub fn max_connections_per_host(&self) -> Option<usize> {
+        self.max_connections_per_host
+    }
+
+    /// send all the requests through the unix socket at `path`
+    ///
+    /// the host of the url is only used for the `Host` header
+    #[cfg(unix)]
+    pub fn set_unix_socket<P: AsRef<Path>>(&mut self, path: Option<P>) -> &mut Self {
+        self.unix_socket = path.map(|p| p.as_ref().to_owned());
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn unix_socket(&self) -> Option<&Path> {
+        self.unix_socket.as_deref()
+    }
+}
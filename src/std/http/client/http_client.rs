@@ -0,0 +1,620 @@
+//! http client implementation on top of `MAY`
+
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+
+use super::pool::{Pool, PooledConn, Transport};
+use super::response::{self, Response, Timeouts};
+use super::url::Url;
+use super::HttpClientConfig;
+use crate::net::{HappyEyeballs, TcpStream};
+#[cfg(unix)]
+use crate::os::unix::net::UnixStream;
+
+fn valid_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_graphic() && !b"\"(),/:;<=>?@[\\]{}".contains(&b))
+}
+
+// the methods that could be sent again when the connection fails
+fn idempotent(method: &str) -> bool {
+    matches!(
+        method,
+        "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE"
+    )
+}
+
+struct Inner {
+    config: HttpClientConfig,
+    pool: Arc<Pool>,
+}
+
+/// a coroutine blocking HTTP/1.1 client
+///
+/// the keep-alive connections are pooled for each host, the clones of a
+/// client share the same pool
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::std::http::client::Client;
+///
+/// let client = Client::new();
+/// let rsp = client.get("http://127.0.0.1:8080/hello").send().unwrap();
+/// assert_eq!(rsp.status(), 200);
+/// println!("{}", rsp.text().unwrap());
+/// ```
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Client::with_config(HttpClientConfig::default())
+    }
+
+    pub fn with_config(config: HttpClientConfig) -> Self {
+        let pool = Pool::new(
+            config.max_idle_per_host(),
+            config.idle_timeout(),
+            config.max_connections_per_host(),
+        );
+        Client {
+            inner: Arc::new(Inner {
+                config,
+                pool: Arc::new(pool),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &HttpClientConfig {
+        &self.inner.config
+    }
+
+    /// start a request with `method` to `url`, which must be an `http://` url
+    pub fn request(&self, method: &str, url: &str) -> RequestBuilder {
+        let error = if valid_token(method) {
+            None
+        } else {
+            Some(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid method",
+            ))
+        };
+        RequestBuilder {
+            client: self.clone(),
+            method: method.to_owned(),
+            url: url.to_owned(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            timeout: self.inner.config.timeout(),
+            error,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.request("GET", url)
+    }
+
+    pub fn head(&self, url: &str) -> RequestBuilder {
+        self.request("HEAD", url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.request("POST", url)
+    }
+
+    pub fn put(&self, url: &str) -> RequestBuilder {
+        self.request("PUT", url)
+    }
+
+    pub fn patch(&self, url: &str) -> RequestBuilder {
+        self.request("PATCH", url)
+    }
+
+    pub fn delete(&self, url: &str) -> RequestBuilder {
+        self.request("DELETE", url)
+    }
+
+    fn pool_key(&self, url: &Url) -> String {
+        #[cfg(unix)]
+        {
+            if let Some(path) = self.inner.config.unix_socket() {
+                return format!("unix:{}", path.display());
+            }
+        }
+        format!("{}:{}", url.host, url.port)
+    }
+
+    fn connect(&self, url: &Url, timeouts: &Timeouts) -> io::Result<Transport> {
+        #[cfg(unix)]
+        {
+            if let Some(path) = self.inner.config.unix_socket() {
+                return UnixStream::connect(path).map(Transport::Unix);
+            }
+        }
+        let timeout = match (self.inner.config.connect_timeout(), timeouts.left()?) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let stream = TcpStream::connect_happy_eyeballs(
+            &url.host,
+            url.port,
+            HappyEyeballs::new().timeout(timeout),
+        )?;
+        stream.set_nodelay(true).ok();
+        Ok(Transport::Tcp(stream))
+    }
+
+    // send one request and read the response head
+    fn execute(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(String, String)],
+        body: &[u8],
+        timeouts: Timeouts,
+    ) -> io::Result<Response> {
+        let mut req = Vec::with_capacity(256 + body.len());
+        encode_request(&mut req, method, url, headers, body);
+        let key = self.pool_key(url);
+        loop {
+            let mut conn = self
+                .inner
+                .pool
+                .get(&key, timeouts.left()?, || self.connect(url, &timeouts))?;
+            let mut raw = BytesMut::new();
+            let ret = write_request(&mut conn, &req, &timeouts).and_then(|_| {
+                response::read_head(
+                    &mut conn,
+                    &mut raw,
+                    &timeouts,
+                    self.inner.config.max_header_bytes(),
+                )
+            });
+            match ret {
+                Ok(Some(head)) => return Response::new(head, conn, raw, method, url, timeouts),
+                // the server has closed the idle connection, try another one,
+                // unless the request may have been applied
+                Ok(None) if conn.reused && idempotent(method) => continue,
+                Ok(None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed without a response",
+                    ))
+                }
+                Err(ref e)
+                    if conn.reused
+                        && raw.is_empty()
+                        && idempotent(method)
+                        && matches!(
+                            e.kind(),
+                            io::ErrorKind::ConnectionReset
+                                | io::ErrorKind::ConnectionAborted
+                                | io::ErrorKind::BrokenPipe
+                        ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn encode_request(
+    buf: &mut Vec<u8>,
+    method: &str,
+    url: &Url,
+    headers: &[(String, String)],
+    body: &[u8],
+) {
+    write!(buf, "{} {} HTTP/1.1\r\n", method, url.path).unwrap();
+    if !headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("Host")) {
+        write!(buf, "Host: {}\r\n", url.authority()).unwrap();
+    }
+    for (name, value) in headers {
+        // the body is always framed by the client
+        if name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Transfer-Encoding")
+        {
+            continue;
+        }
+        write!(buf, "{}: {}\r\n", name, value).unwrap();
+    }
+    if !body.is_empty() || matches!(method, "POST" | "PUT" | "PATCH") {
+        write!(buf, "Content-Length: {}\r\n", body.len()).unwrap();
+    }
+    buf.extend_from_slice(b"\r\n");
+    buf.extend_from_slice(body);
+}
+
+fn write_request(conn: &mut PooledConn, req: &[u8], timeouts: &Timeouts) -> io::Result<()> {
+    conn.transport.set_write_timeout(timeouts.next_io()?)?;
+    conn.transport.write_all(req).map_err(|e| {
+        if e.kind() == io::ErrorKind::WouldBlock {
+            response::timed_out()
+        } else {
+            e
+        }
+    })
+}
+
+/// the builder of a client request, created by `Client::request`
+pub struct RequestBuilder {
+    client: Client,
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    timeout: Option<Duration>,
+    // the first invalid input, returned by `send`
+    error: Option<io::Error>,
+}
+
+impl RequestBuilder {
+    /// add a header
+    ///
+    /// `send` fails with `InvalidInput` if the name is not a valid token or
+    /// the value contains a line break. `Content-Length` is always set by
+    /// the client
+    pub fn header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        let (name, value) = (name.into(), value.into());
+        if !valid_token(&name) || value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+            self.error.get_or_insert_with(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "invalid header")
+            });
+        }
+        self.headers.push((name, value));
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// set the time allowed for the whole request, including reading the body
+    ///
+    /// it overrides `HttpClientConfig::timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// send the request and read the response head, the redirects are followed
+    ///
+    /// the coroutine is parked while waiting for the server
+    pub fn send(self) -> io::Result<Response> {
+        let RequestBuilder {
+            client,
+            mut method,
+            url,
+            mut headers,
+            mut body,
+            timeout,
+            error,
+        } = self;
+        if let Some(e) = error {
+            return Err(e);
+        }
+        let config = client.config();
+        let timeouts = Timeouts {
+            read: config.read_timeout(),
+            deadline: timeout.map(|t| Instant::now() + t),
+        };
+        let mut url = Url::parse(&url)?;
+        let mut redirects = 0;
+        loop {
+            let rsp = client.execute(&method, &url, &headers, &body, timeouts)?;
+            let status = rsp.status();
+            let location = match status {
+                301 | 302 | 303 | 307 | 308 if redirects < config.max_redirects() => rsp
+                    .header("Location")
+                    .and_then(|l| std::str::from_utf8(l).ok()),
+                _ => None,
+            };
+            let next = match location {
+                Some(location) => url.join(location)?,
+                None => return Ok(rsp),
+            };
+            rsp.discard();
+
+            // only 307 and 308 repeat the request as it is
+            if (status == 303 && method != "HEAD")
+                || (matches!(status, 301 | 302) && method == "POST")
+            {
+                method = "GET".to_owned();
+                body.clear();
+                headers.retain(|(n, _)| !n.eq_ignore_ascii_case("Content-Type"));
+            }
+            // don't leak the credentials to another host
+            if next.host != url.host || next.port != url.port {
+                headers.retain(|(n, _)| {
+                    !n.eq_ignore_ascii_case("Authorization") && !n.eq_ignore_ascii_case("Cookie")
+                });
+            }
+            url = next;
+            redirects += 1;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::coroutine;
+    use crate::std::http::server::{HttpServer, HttpServerConfig, Router, StatusCode};
+    use std::io::Read;
+    use std::net::SocketAddr;
+
+    fn serve(config: HttpServerConfig) -> SocketAddr {
+        let router = Router::new()
+            .get("/hello", |_, rsp| {
+                rsp.body("hello");
+                Ok(())
+            })
+            .any("/echo", |req, rsp| {
+                rsp.add_header("X-Method", req.method().to_owned())?;
+                if let Some(v) = req.header("x-token") {
+                    rsp.add_header("X-Token", String::from_utf8_lossy(v).into_owned())?;
+                }
+                rsp.body_vec(req.body().to_vec());
+                Ok(())
+            })
+            .get("/chunked", |_, rsp| {
+                rsp.body_iter((0..100).map(|i| Ok(format!("{},", i))));
+                Ok(())
+            })
+            .get("/slow", |_, rsp| {
+                coroutine::sleep(Duration::from_millis(300));
+                rsp.body("slow");
+                Ok(())
+            })
+            .get("/redirect/:n", |req, rsp| {
+                match req.param_as::<u32>("n")? {
+                    0 => rsp.body("done"),
+                    n => {
+                        rsp.status(StatusCode::Found)
+                            .add_header("Location", format!("/redirect/{}", n - 1))?;
+                    }
+                }
+                Ok(())
+            })
+            .post("/see-other", |_, rsp| {
+                rsp.status(StatusCode::SeeOther).header("Location: /echo");
+                Ok(())
+            })
+            .post("/temporary", |_, rsp| {
+                rsp.status(StatusCode::TemporaryRedirect)
+                    .header("Location: echo");
+                Ok(())
+            });
+        HttpServer(router)
+            .serve("127.0.0.1:0", config)
+            .unwrap()
+            .local_addr()
+    }
+
+    #[test]
+    fn keep_alive() {
+        let addr = serve(HttpServerConfig::new());
+        let client = Client::new();
+        let key = addr.to_string();
+        for _ in 0..3 {
+            let rsp = client
+                .get(&format!("http://{}/hello", addr))
+                .send()
+                .unwrap();
+            assert_eq!((rsp.status(), rsp.reason()), (200, "Ok"));
+            assert_eq!(rsp.text().unwrap(), "hello");
+            // the same connection is reused
+            assert_eq!(client.inner.pool.idle_count(&key), 1);
+        }
+        let rsp = client
+            .get(&format!("http://{}/chunked", addr))
+            .send()
+            .unwrap();
+        assert_eq!(rsp.header("Transfer-Encoding"), Some(&b"chunked"[..]));
+        let expect: String = (0..100).map(|i| format!("{},", i)).collect();
+        assert_eq!(rsp.text().unwrap(), expect);
+        assert_eq!(client.inner.pool.idle_count(&key), 1);
+    }
+
+    #[test]
+    fn request_body() {
+        let addr = serve(HttpServerConfig::new());
+        let client = Client::new();
+        let rsp = client
+            .put(&format!("http://{}/echo", addr))
+            .header("X-Token", "abc")
+            .body("data")
+            .send()
+            .unwrap();
+        assert_eq!(rsp.header("x-method"), Some(&b"PUT"[..]));
+        assert_eq!(rsp.header("x-token"), Some(&b"abc"[..]));
+        assert_eq!(rsp.bytes().unwrap(), b"data");
+
+        let ret = client.get("http://a/").header("X\r\n", "v").send();
+        assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let ret = client.get("https://a/").send();
+        assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn redirect() {
+        let addr = serve(HttpServerConfig::new());
+        let client = Client::new();
+        let rsp = client
+            .get(&format!("http://{}/redirect/3", addr))
+            .send()
+            .unwrap();
+        assert_eq!(rsp.url(), format!("http://{}/redirect/0", addr));
+        assert_eq!(rsp.text().unwrap(), "done");
+
+        // a 303 is followed with a GET
+        let rsp = client
+            .post(&format!("http://{}/see-other", addr))
+            .body("data")
+            .send()
+            .unwrap();
+        assert_eq!(rsp.header("x-method"), Some(&b"GET"[..]));
+        assert_eq!(rsp.text().unwrap(), "");
+        // a 307 repeats the request
+        let rsp = client
+            .post(&format!("http://{}/temporary", addr))
+            .body("data")
+            .send()
+            .unwrap();
+        assert_eq!(rsp.header("x-method"), Some(&b"POST"[..]));
+        assert_eq!(rsp.text().unwrap(), "data");
+
+        let mut config = HttpClientConfig::new();
+        config.set_max_redirects(2);
+        let client = Client::with_config(config);
+        let rsp = client
+            .get(&format!("http://{}/redirect/3", addr))
+            .send()
+            .unwrap();
+        assert_eq!(rsp.status(), 302);
+        assert_eq!(rsp.header("location"), Some(&b"/redirect/0"[..]));
+    }
+
+    #[test]
+    fn timeout() {
+        let addr = serve(HttpServerConfig::new());
+        let mut config = HttpClientConfig::new();
+        config.set_read_timeout(Some(Duration::from_millis(100)));
+        let client = Client::with_config(config);
+        let ret = client.get(&format!("http://{}/slow", addr)).send();
+        assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        let ret = Client::new()
+            .get(&format!("http://{}/slow", addr))
+            .timeout(Duration::from_millis(100))
+            .send();
+        assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn max_connections_per_host() {
+        let addr = serve(HttpServerConfig::new());
+        let mut config = HttpClientConfig::new();
+        config.set_max_connections_per_host(Some(1));
+        let client = Client::with_config(config);
+        let url = format!("http://{}/chunked", addr);
+        // the unread body holds the connection
+        let mut rsp = client.get(&url).send().unwrap();
+        let ret = client.get(&url).timeout(Duration::from_millis(100)).send();
+        assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        rsp.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(client.get(&url).send().unwrap().status(), 200);
+    }
+
+    #[test]
+    fn stale_connection() {
+        let mut config = HttpServerConfig::new();
+        config.set_keep_alive_timeout(Some(Duration::from_millis(50)));
+        let addr = serve(config);
+        let client = Client::new();
+        let url = format!("http://{}/hello", addr);
+        assert_eq!(client.get(&url).send().unwrap().text().unwrap(), "hello");
+        // the server closes the idle connection, the request is sent on a new one
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(client.get(&url).send().unwrap().text().unwrap(), "hello");
+    }
+
+    #[test]
+    fn post_not_retried() {
+        use crate::net::TcpListener;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let posts = Arc::new(AtomicUsize::new(0));
+        let counter = posts.clone();
+        go!(move || {
+            for s in listener.incoming() {
+                let (mut s, counter) = (s.unwrap(), counter.clone());
+                go!(move || {
+                    let mut req = Vec::new();
+                    let mut buf = [0; 1024];
+                    while let Ok(n @ 1..=1024) = s.read(&mut buf) {
+                        req.extend_from_slice(&buf[..n]);
+                        if !req.ends_with(b"\r\n\r\n") {
+                            continue;
+                        }
+                        if req.starts_with(b"POST") {
+                            // the request is applied, but no response is sent
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            return;
+                        }
+                        req.clear();
+                        s.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let client = Client::new();
+        let url = format!("http://{}/x", addr);
+        assert_eq!(client.get(&url).send().unwrap().text().unwrap(), "ok");
+        // the post on the reused connection is not sent again
+        let e = client.post(&url).send().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(posts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn unix_socket() {
+        use crate::os::unix::net::UnixListener;
+
+        let path =
+            std::env::temp_dir().join(format!("cogo-http-client-{}.sock", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let listener = UnixListener::bind(&path).unwrap();
+        go!(move || {
+            // both reqs come on the same connection
+            let (mut s, _) = listener.accept().unwrap();
+            let mut req = Vec::new();
+            for expect in &["GET /x HTTP/1.1\r\nHost: local\r\n", "HEAD /x HTTP/1.1\r\n"] {
+                while !req.ends_with(b"\r\n\r\n") {
+                    let mut buf = [0; 1024];
+                    let n = s.read(&mut buf).unwrap();
+                    req.extend_from_slice(&buf[..n]);
+                }
+                assert!(req.starts_with(expect.as_bytes()));
+                req.clear();
+                s.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n")
+                    .unwrap();
+                if expect.starts_with("GET") {
+                    s.write_all(b"ok").unwrap();
+                }
+            }
+        });
+
+        let mut config = HttpClientConfig::new();
+        config.set_unix_socket(Some(&path));
+        let client = Client::with_config(config);
+        let rsp = client.get("http://local/x").send().unwrap();
+        assert_eq!(rsp.text().unwrap(), "ok");
+        // no body follows the head
+        let rsp = client.head("http://local/x").send().unwrap();
+        assert_eq!(rsp.header("content-length"), Some(&b"2"[..]));
+        assert_eq!(rsp.text().unwrap(), "");
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,9 @@
+mod config;
+mod http_client;
+mod pool;
+mod response;
+mod url;
+
+pub use config::*;
+pub use http_client::*;
+pub use response::Response;
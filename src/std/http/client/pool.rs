@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::net::TcpStream;
+#[cfg(unix)]
+use crate::os::unix::net::UnixStream;
+use crate::std::sync::Semphore;
+
+// the connection to a server
+pub(crate) enum Transport {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Transport {
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        match self {
+            Transport::Tcp(s) => s.set_read_timeout(dur),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.set_read_timeout(dur),
+        }
+    }
+
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        match self {
+            Transport::Tcp(s) => s.set_write_timeout(dur),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.set_write_timeout(dur),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.flush(),
+        }
+    }
+}
+
+// allows one more request to a host, released on drop
+struct Permit(Option<Arc<Semphore>>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(ref sem) = self.0 {
+            sem.post();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Host {
+    // the idle connections and since when they are idle
+    idle: Vec<(Transport, Instant)>,
+    limit: Option<Arc<Semphore>>,
+}
+
+// the keep-alive connections of each host
+pub(crate) struct Pool {
+    hosts: Mutex<HashMap<String, Host>>,
+    max_idle: usize,
+    idle_timeout: Option<Duration>,
+    max_per_host: Option<usize>,
+}
+
+// a connection that is returned to the pool by `release`
+pub(crate) struct PooledConn {
+    pub transport: Transport,
+    // the connection is taken from the pool
+    pub reused: bool,
+    key: String,
+    pool: Arc<Pool>,
+    _permit: Permit,
+}
+
+impl PooledConn {
+    // keep the connection for the next request to the same host
+    pub fn release(self) {
+        let PooledConn {
+            transport,
+            key,
+            pool,
+            _permit,
+            ..
+        } = self;
+        pool.put(key, transport);
+    }
+}
+
+impl Pool {
+    pub fn new(
+        max_idle: usize,
+        idle_timeout: Option<Duration>,
+        max_per_host: Option<usize>,
+    ) -> Self {
+        Pool {
+            hosts: Mutex::new(HashMap::new()),
+            max_idle,
+            idle_timeout,
+            max_per_host,
+        }
+    }
+
+    // get an idle connection to the host of `key`, or create one by `connect`
+    // wait no longer than `timeout` when the host has too many connections
+    pub fn get<F>(
+        self: &Arc<Self>,
+        key: &str,
+        timeout: Option<Duration>,
+        connect: F,
+    ) -> io::Result<PooledConn>
+    where
+        F: FnOnce() -> io::Result<Transport>,
+    {
+        let limit = self.max_per_host.map(|n| {
+            let mut hosts = self.hosts.lock().unwrap();
+            let host = hosts.entry(key.to_owned()).or_default();
+            host.limit
+                .get_or_insert_with(|| Arc::new(Semphore::new(n)))
+                .clone()
+        });
+        if let Some(ref sem) = limit {
+            let acquired = match timeout {
+                Some(dur) => sem.wait_timeout(dur),
+                None => {
+                    sem.wait();
+                    true
+                }
+            };
+            if !acquired {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for a connection",
+                ));
+            }
+        }
+        let permit = Permit(limit);
+
+        let (transport, reused) = match self.take_idle(key) {
+            Some(transport) => (transport, true),
+            None => (connect()?, false),
+        };
+        Ok(PooledConn {
+            transport,
+            reused,
+            key: key.to_owned(),
+            pool: self.clone(),
+            _permit: permit,
+        })
+    }
+
+    fn take_idle(&self, key: &str) -> Option<Transport> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.get_mut(key)?;
+        // the most recently used one is the least likely to be closed by the server
+        while let Some((transport, since)) = host.idle.pop() {
+            if self.idle_timeout.map_or(true, |t| since.elapsed() < t) {
+                return Some(transport);
+            }
+        }
+        None
+    }
+
+    fn put(&self, key: String, transport: Transport) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.entry(key).or_default();
+        if let Some(timeout) = self.idle_timeout {
+            host.idle.retain(|(_, since)| since.elapsed() < timeout);
+        }
+        if host.idle.len() < self.max_idle {
+            host.idle.push((transport, Instant::now()));
+        }
+    }
+
+    #[cfg(test)]
+    pub fn idle_count(&self, key: &str) -> usize {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.get(key).map_or(0, |h| h.idle.len())
+    }
+}
@@ -0,0 +1,371 @@
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+use std::{fmt, str};
+
+use bytes::{Buf, BytesMut};
+
+use super::pool::PooledConn;
+use super::url::Url;
+use crate::std::http::server::ChunkedDecoder;
+
+// the max unread body that is skipped to reuse the connection
+const MAX_DRAIN: u64 = 64 * 1024;
+const MAX_HEADERS: usize = 100;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn closed(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, msg)
+}
+
+pub(crate) fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "http request timed out")
+}
+
+// the limits of the io on a connection
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timeouts {
+    pub read: Option<Duration>,
+    // when the whole request must be done
+    pub deadline: Option<Instant>,
+}
+
+impl Timeouts {
+    // the time left before the deadline
+    pub fn left(&self) -> io::Result<Option<Duration>> {
+        match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if left > Duration::from_millis(0) => Ok(Some(left)),
+                _ => Err(timed_out()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // the timeout of the next read or write
+    pub fn next_io(&self) -> io::Result<Option<Duration>> {
+        Ok(match (self.read, self.left()?) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        })
+    }
+}
+
+// read from the connection with the timeouts applied
+fn read_conn(conn: &mut PooledConn, timeouts: &Timeouts, buf: &mut [u8]) -> io::Result<usize> {
+    conn.transport.set_read_timeout(timeouts.next_io()?)?;
+    match conn.transport.read(buf) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Err(timed_out()),
+        ret => ret,
+    }
+}
+
+fn read_more(conn: &mut PooledConn, timeouts: &Timeouts, raw: &mut BytesMut) -> io::Result<usize> {
+    let mut buf = [0; 4096];
+    let n = read_conn(conn, timeouts, &mut buf)?;
+    raw.extend_from_slice(&buf[..n]);
+    Ok(n)
+}
+
+// the status line and headers of a response
+pub(crate) struct Head {
+    status: u16,
+    reason: String,
+    version: u8,
+    headers: Vec<(String, Vec<u8>)>,
+}
+
+// read the final response head, the interim `1xx` ones are skipped
+// return `None` if the connection is closed before any byte is received,
+// which happens to a keep-alive connection that the server has closed
+pub(crate) fn read_head(
+    conn: &mut PooledConn,
+    raw: &mut BytesMut,
+    timeouts: &Timeouts,
+    max_header_bytes: usize,
+) -> io::Result<Option<Head>> {
+    loop {
+        if !raw.is_empty() {
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut rsp = httparse::Response::new(&mut headers);
+            let parsed = match rsp.parse(raw) {
+                Ok(httparse::Status::Complete(n)) => Some((n, rsp.code.unwrap_or_default())),
+                Ok(httparse::Status::Partial) => None,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            };
+            match parsed {
+                Some((n, code)) if (100..200).contains(&code) && code != 101 => {
+                    raw.advance(n);
+                    continue;
+                }
+                Some((n, status)) => {
+                    let head = Head {
+                        status,
+                        reason: rsp.reason.unwrap_or_default().to_owned(),
+                        version: rsp.version.unwrap_or_default(),
+                        headers: rsp
+                            .headers
+                            .iter()
+                            .map(|h| (h.name.to_owned(), h.value.to_vec()))
+                            .collect(),
+                    };
+                    raw.advance(n);
+                    return Ok(Some(head));
+                }
+                None if raw.len() > max_header_bytes => {
+                    return Err(invalid("response head is too large"))
+                }
+                None => {}
+            }
+        }
+        let empty = raw.is_empty();
+        if read_more(conn, timeouts, raw)? == 0 {
+            if empty {
+                return Ok(None);
+            }
+            return Err(closed("connection closed in the response head"));
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Kind {
+    // the body bytes left on the connection
+    Length(u64),
+    Chunked(ChunkedDecoder),
+    // the body ends when the connection is closed
+    Close,
+    Done,
+}
+
+/// the response of the http client
+///
+/// the body is streamed from the connection by `Read`, the connection is
+/// put back to the pool once the body is read to the end
+pub struct Response {
+    head: Head,
+    url: String,
+    conn: Option<PooledConn>,
+    // the bytes read from the connection but not decoded yet
+    raw: BytesMut,
+    // the decoded chunked body that is not read yet
+    decoded: BytesMut,
+    kind: Kind,
+    // the connection could be reused after the body
+    keep_alive: bool,
+    timeouts: Timeouts,
+}
+
+impl Response {
+    pub(crate) fn new(
+        head: Head,
+        conn: PooledConn,
+        raw: BytesMut,
+        method: &str,
+        url: &Url,
+        timeouts: Timeouts,
+    ) -> io::Result<Self> {
+        let has_token = |name: &str, token: &str| {
+            head.headers
+                .iter()
+                .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+                .filter_map(|(_, v)| str::from_utf8(v).ok())
+                .any(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+        };
+        let mut keep_alive = head.version == 1 && !has_token("Connection", "close");
+        let no_body =
+            method == "HEAD" || head.status == 204 || head.status == 304 || head.status < 200;
+        let kind = if head.status == 101 {
+            // the connection is switched to another protocol
+            keep_alive = false;
+            Kind::Length(0)
+        } else if no_body {
+            Kind::Length(0)
+        } else if has_token("Transfer-Encoding", "chunked") {
+            Kind::Chunked(ChunkedDecoder::new())
+        } else {
+            let len = head
+                .headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case("Content-Length"));
+            match len {
+                Some((_, v)) => str::from_utf8(v)
+                    .ok()
+                    .and_then(|v| v.trim().parse().ok())
+                    .map(Kind::Length)
+                    .ok_or_else(|| invalid("invalid Content-Length"))?,
+                None => {
+                    keep_alive = false;
+                    Kind::Close
+                }
+            }
+        };
+
+        let mut rsp = Response {
+            head,
+            url: format!("http://{}{}", url.authority(), url.path),
+            conn: Some(conn),
+            raw,
+            decoded: BytesMut::new(),
+            kind,
+            keep_alive,
+            timeouts,
+        };
+        if let Kind::Length(0) = rsp.kind {
+            rsp.finish();
+        }
+        Ok(rsp)
+    }
+
+    /// the status code, e.g. `200`
+    pub fn status(&self) -> u16 {
+        self.head.status
+    }
+
+    /// the reason phrase, e.g. `"OK"`
+    pub fn reason(&self) -> &str {
+        &self.head.reason
+    }
+
+    /// the minor http version, `1` for HTTP/1.1
+    pub fn version(&self) -> u8 {
+        self.head.version
+    }
+
+    /// the url of the response, which differs from the requested one after redirects
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// get the value of the first header named `name`, ignoring the case
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.head
+            .headers
+            .iter()
+            .map(|(n, v)| (n.as_str(), v.as_slice()))
+    }
+
+    /// read the whole body
+    pub fn bytes(mut self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self.read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    /// read the whole body as utf-8 text
+    pub fn text(mut self) -> io::Result<String> {
+        let mut body = String::new();
+        self.read_to_string(&mut body)?;
+        Ok(body)
+    }
+
+    // skip a small unread body so that the connection could be reused
+    pub(crate) fn discard(mut self) {
+        io::copy(&mut (&mut self).take(MAX_DRAIN), &mut io::sink()).ok();
+    }
+
+    // the body is read to the end
+    fn finish(&mut self) {
+        self.kind = Kind::Done;
+        if let Some(conn) = self.conn.take() {
+            if self.keep_alive && self.raw.is_empty() {
+                conn.release();
+            }
+        }
+    }
+
+    fn read_chunked(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let done = match self.kind {
+                Kind::Chunked(ref decoder) => decoder.is_done(),
+                _ => true,
+            };
+            if !self.decoded.is_empty() {
+                let n = buf.len().min(self.decoded.len());
+                buf[..n].copy_from_slice(&self.decoded[..n]);
+                self.decoded.advance(n);
+                if done && self.decoded.is_empty() {
+                    self.finish();
+                }
+                return Ok(n);
+            }
+            if done {
+                self.finish();
+                return Ok(0);
+            }
+            if self.raw.is_empty() {
+                let conn = self
+                    .conn
+                    .as_mut()
+                    .ok_or_else(|| closed("connection is closed"))?;
+                if read_more(conn, &self.timeouts, &mut self.raw)? == 0 {
+                    return Err(closed("connection closed in the response body"));
+                }
+            }
+            if let Kind::Chunked(ref mut decoder) = self.kind {
+                let n = decoder.decode(&self.raw, &mut self.decoded)?;
+                self.raw.advance(n);
+            }
+        }
+    }
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let left = match self.kind {
+            Kind::Done => return Ok(0),
+            Kind::Chunked(_) => return self.read_chunked(buf),
+            Kind::Length(left) => Some(left),
+            Kind::Close => None,
+        };
+        let max = left.map_or(buf.len(), |left| buf.len().min(left as usize));
+        let n = if !self.raw.is_empty() {
+            let n = max.min(self.raw.len());
+            buf[..n].copy_from_slice(&self.raw[..n]);
+            self.raw.advance(n);
+            n
+        } else {
+            let conn = self
+                .conn
+                .as_mut()
+                .ok_or_else(|| closed("connection is closed"))?;
+            read_conn(conn, &self.timeouts, &mut buf[..max])?
+        };
+        match left {
+            Some(_) if n == 0 => Err(closed("connection closed in the response body")),
+            Some(left) => {
+                self.kind = Kind::Length(left - n as u64);
+                if left == n as u64 {
+                    self.finish();
+                }
+                Ok(n)
+            }
+            None => {
+                if n == 0 {
+                    self.finish();
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.head.status)
+            .field("url", &self.url)
+            .finish()
+    }
+}
@@ -0,0 +1,141 @@
+use std::io;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+// the parts of an `http://` url that the client needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Url {
+    pub host: String,
+    pub port: u16,
+    // the path and query
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(s: &str) -> io::Result<Url> {
+        let rest = match s.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("http://") => &s[7..],
+            _ => match s.get(..8) {
+                Some(scheme) if scheme.eq_ignore_ascii_case("https://") => {
+                    return Err(invalid("https is not supported"))
+                }
+                _ => return Err(invalid("url must start with http://")),
+            },
+        };
+        // the fragment is never sent
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, path) = match rest.find(|c| c == '/' || c == '?') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let path = if path.starts_with('?') {
+            format!("/{}", path)
+        } else {
+            path.to_owned()
+        };
+        if path.contains(|c: char| c.is_ascii_whitespace() || c.is_ascii_control()) {
+            return Err(invalid("invalid url path"));
+        }
+
+        // the credentials are not supported
+        let authority = authority.rsplit('@').next().unwrap_or_default();
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            let end = rest.find(']').ok_or_else(|| invalid("invalid ipv6 host"))?;
+            (&rest[..end], &rest[end + 1..])
+        } else {
+            match authority.find(':') {
+                Some(i) => (&authority[..i], &authority[i..]),
+                None => (authority, ""),
+            }
+        };
+        let port = match port {
+            "" => 80,
+            port => port
+                .strip_prefix(':')
+                .and_then(|p| p.parse().ok())
+                .ok_or_else(|| invalid("invalid url port"))?,
+        };
+        if host.is_empty() {
+            return Err(invalid("url has no host"));
+        }
+        Ok(Url {
+            host: host.to_owned(),
+            port,
+            path,
+        })
+    }
+
+    // the url that a `Location` header refers to from this one
+    pub fn join(&self, location: &str) -> io::Result<Url> {
+        if location.contains("://") {
+            return Url::parse(location);
+        }
+        if location.starts_with("//") {
+            return Url::parse(&format!("http:{}", location));
+        }
+        let location = location.split('#').next().unwrap_or_default();
+        let path = if location.starts_with('/') {
+            location.to_owned()
+        } else {
+            // relative to the directory of the current path
+            let path = self.path.split('?').next().unwrap_or_default();
+            let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+            format!("{}{}", dir, location)
+        };
+        Ok(Url {
+            host: self.host.clone(),
+            port: self.port,
+            path,
+        })
+    }
+
+    // the value of the `Host` header
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match self.port {
+            80 => host,
+            port => format!("{}:{}", host, port),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let url = Url::parse("http://example.com").unwrap();
+        assert_eq!((&*url.host, url.port, &*url.path), ("example.com", 80, "/"));
+        let url = Url::parse("HTTP://user@127.0.0.1:8080?a=1#top").unwrap();
+        assert_eq!(
+            (&*url.host, url.port, &*url.path),
+            ("127.0.0.1", 8080, "/?a=1")
+        );
+        let url = Url::parse("http://[::1]:81/a/b").unwrap();
+        assert_eq!((&*url.host, url.port, &*url.path), ("::1", 81, "/a/b"));
+        assert_eq!(url.authority(), "[::1]:81");
+
+        assert!(Url::parse("https://example.com").is_err());
+        assert!(Url::parse("ftp://example.com").is_err());
+        assert!(Url::parse("http://:80/").is_err());
+        assert!(Url::parse("http://a:x/").is_err());
+        assert!(Url::parse("http://a/b c").is_err());
+    }
+
+    #[test]
+    fn join() {
+        let url = Url::parse("http://a:81/x/y?q=1").unwrap();
+        assert_eq!(url.join("/z").unwrap().path, "/z");
+        assert_eq!(url.join("z?r").unwrap().path, "/x/z?r");
+        let other = url.join("//b/c").unwrap();
+        assert_eq!((&*other.host, other.port), ("b", 80));
+        assert_eq!(url.join("http://c:82/").unwrap().port, 82);
+    }
+}
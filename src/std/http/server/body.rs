@@ -0,0 +1,418 @@
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use bytes::{Buf, BytesMut};
+
+use crate::net::TcpStream;
+
+// the max unread body that is skipped to reuse the connection
+const MAX_DRAIN: usize = 256 * 1024;
+// the max bytes of a chunk extension or the trailer section
+const MAX_CHUNK_META: usize = 8 * 1024;
+// read size when streaming the body from the connection
+const READ_SIZE: usize = 64 * 1024;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Size,
+    Ext,
+    SizeLf,
+    Data(u64),
+    DataCr,
+    DataLf,
+    Trailer,
+    TrailerLf,
+    Done,
+}
+
+/// incremental decoder of the chunked transfer coding
+#[derive(Debug)]
+pub(crate) struct ChunkedDecoder {
+    state: State,
+    size: u64,
+    digits: usize,
+    // bytes of the current extension or trailer line
+    meta: usize,
+    // the current trailer line is empty
+    empty_line: bool,
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> Self {
+        ChunkedDecoder {
+            state: State::Size,
+            size: 0,
+            digits: 0,
+            meta: 0,
+            empty_line: true,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// decode `src` into `dst`, return the number of bytes consumed
+    ///
+    /// the bytes after the end of the body are not consumed
+    pub fn decode(&mut self, src: &[u8], dst: &mut BytesMut) -> io::Result<usize> {
+        let mut i = 0;
+        while i < src.len() && self.state != State::Done {
+            if let State::Data(n) = self.state {
+                let take = std::cmp::min(n, (src.len() - i) as u64) as usize;
+                dst.extend_from_slice(&src[i..i + take]);
+                i += take;
+                self.state = if take as u64 == n {
+                    State::DataCr
+                } else {
+                    State::Data(n - take as u64)
+                };
+                continue;
+            }
+
+            let b = src[i];
+            i += 1;
+            self.state = match (self.state, b) {
+                (State::Size, b) if b.is_ascii_hexdigit() => {
+                    let d = (b as char).to_digit(16).unwrap() as u64;
+                    self.size = self
+                        .size
+                        .checked_mul(16)
+                        .and_then(|s| s.checked_add(d))
+                        .ok_or_else(|| invalid("chunk size overflow"))?;
+                    self.digits += 1;
+                    State::Size
+                }
+                (State::Size, b';') | (State::Size, b' ') | (State::Size, b'\t')
+                    if self.digits > 0 =>
+                {
+                    self.meta = 0;
+                    State::Ext
+                }
+                (State::Size, b'\r') if self.digits > 0 => State::SizeLf,
+                (State::Ext, b'\r') => State::SizeLf,
+                (State::Ext, _) => {
+                    self.meta += 1;
+                    if self.meta > MAX_CHUNK_META {
+                        return Err(invalid("chunk extension too long"));
+                    }
+                    State::Ext
+                }
+                (State::SizeLf, b'\n') => {
+                    let size = self.size;
+                    self.size = 0;
+                    self.digits = 0;
+                    if size == 0 {
+                        self.meta = 0;
+                        self.empty_line = true;
+                        State::Trailer
+                    } else {
+                        State::Data(size)
+                    }
+                }
+                (State::DataCr, b'\r') => State::DataLf,
+                (State::DataLf, b'\n') => State::Size,
+                (State::Trailer, b'\r') => State::TrailerLf,
+                (State::Trailer, _) => {
+                    self.meta += 1;
+                    self.empty_line = false;
+                    if self.meta > MAX_CHUNK_META {
+                        return Err(invalid("chunk trailer too long"));
+                    }
+                    State::Trailer
+                }
+                (State::TrailerLf, b'\n') => {
+                    if self.empty_line {
+                        State::Done
+                    } else {
+                        self.empty_line = true;
+                        State::Trailer
+                    }
+                }
+                _ => return Err(invalid("invalid chunked body")),
+            };
+        }
+        Ok(i)
+    }
+}
+
+#[derive(Debug)]
+enum Kind {
+    // the body bytes left on the connection
+    Length(u64),
+    Chunked(ChunkedDecoder),
+    Done,
+}
+
+// the connection state handed back by a streamed body reader
+#[derive(Debug, Default)]
+pub(crate) struct Leftover {
+    // the bytes read after the end of the body
+    pub data: BytesMut,
+    // the body is consumed so that the connection could be reused
+    pub complete: bool,
+    // the body is over the size limit, the rsp should be `413`
+    pub too_large: bool,
+}
+
+/// the reader of the request body
+///
+/// small bodies are buffered before the service is called, larger ones
+/// are streamed from the connection while reading
+#[derive(Debug)]
+pub struct BodyReader {
+    // the decoded body
+    buf: BytesMut,
+    // the read position in `buf`
+    pos: usize,
+    // the raw bytes that are not decoded yet
+    raw: BytesMut,
+    kind: Kind,
+    // the decoded body size and its limit
+    total: u64,
+    limit: u64,
+    stream: Option<TcpStream>,
+    // send `100 Continue` before reading from the connection
+    expect_continue: bool,
+    leftover: Option<Arc<Mutex<Leftover>>>,
+}
+
+impl BodyReader {
+    pub(crate) fn buffered(body: BytesMut) -> Self {
+        BodyReader {
+            total: body.len() as u64,
+            limit: body.len() as u64,
+            buf: body,
+            pos: 0,
+            raw: BytesMut::new(),
+            kind: Kind::Done,
+            stream: None,
+            expect_continue: false,
+            leftover: None,
+        }
+    }
+
+    // body with a known length, `remaining` bytes are still on the connection
+    pub(crate) fn with_length(
+        prefix: BytesMut,
+        remaining: u64,
+        stream: TcpStream,
+        expect_continue: bool,
+    ) -> Self {
+        let total = prefix.len() as u64;
+        BodyReader {
+            buf: prefix,
+            pos: 0,
+            raw: BytesMut::new(),
+            kind: Kind::Length(remaining),
+            total,
+            limit: total + remaining,
+            stream: Some(stream),
+            expect_continue: expect_continue && remaining > 0,
+            leftover: Some(Arc::default()),
+        }
+    }
+
+    // chunked body, `raw` are the bytes read after the decoded `prefix`
+    pub(crate) fn chunked(
+        prefix: BytesMut,
+        decoder: ChunkedDecoder,
+        raw: BytesMut,
+        limit: u64,
+        stream: TcpStream,
+        expect_continue: bool,
+    ) -> Self {
+        BodyReader {
+            total: prefix.len() as u64,
+            buf: prefix,
+            pos: 0,
+            raw,
+            kind: Kind::Chunked(decoder),
+            limit,
+            stream: Some(stream),
+            expect_continue,
+            leftover: Some(Arc::default()),
+        }
+    }
+
+    /// whether the body is streamed from the connection
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // the whole body if it's buffered
+    pub(crate) fn buffered_body(&self) -> &[u8] {
+        if self.stream.is_some() {
+            return &[];
+        }
+        &self.buf
+    }
+
+    pub(crate) fn leftover(&self) -> Option<Arc<Mutex<Leftover>>> {
+        self.leftover.clone()
+    }
+
+    // decode more body into `buf`, return false at the end of the body
+    fn fill(&mut self) -> io::Result<bool> {
+        if let Kind::Length(0) = self.kind {
+            self.kind = Kind::Done;
+        }
+        if let Kind::Chunked(ref dec) = self.kind {
+            if dec.is_done() {
+                self.kind = Kind::Done;
+            }
+        }
+        let stream = match (&self.kind, self.stream.as_mut()) {
+            (Kind::Done, _) | (_, None) => return Ok(false),
+            (_, Some(s)) => s,
+        };
+
+        if self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+        }
+
+        match self.kind {
+            Kind::Length(remaining) => {
+                if self.expect_continue {
+                    self.expect_continue = false;
+                    stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+                }
+                let n = std::cmp::min(remaining, READ_SIZE as u64) as usize;
+                let len = self.buf.len();
+                self.buf.resize(len + n, 0);
+                let ret = stream.read(&mut self.buf[len..]);
+                let n = *ret.as_ref().unwrap_or(&0);
+                self.buf.truncate(len + n);
+                if ret? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                self.total += n as u64;
+                self.kind = Kind::Length(remaining - n as u64);
+            }
+            Kind::Chunked(ref mut dec) => {
+                if self.raw.is_empty() {
+                    if self.expect_continue {
+                        self.expect_continue = false;
+                        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+                    }
+                    self.raw.resize(READ_SIZE, 0);
+                    let ret = stream.read(&mut self.raw);
+                    self.raw.truncate(*ret.as_ref().unwrap_or(&0));
+                    if ret? == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                }
+                let len = self.buf.len();
+                let n = dec.decode(&self.raw, &mut self.buf)?;
+                self.raw.advance(n);
+                self.total += (self.buf.len() - len) as u64;
+                if self.total > self.limit {
+                    if let Some(ref leftover) = self.leftover {
+                        let mut leftover = leftover.lock().unwrap_or_else(|e| e.into_inner());
+                        leftover.too_large = true;
+                    }
+                    return Err(invalid("request body too large"));
+                }
+            }
+            Kind::Done => {}
+        }
+        Ok(true)
+    }
+
+    // skip the unread body, return true if the connection could be reused
+    fn drain(&mut self) -> bool {
+        // the client is still waiting for `100 Continue`
+        if self.expect_continue {
+            return false;
+        }
+        let mut drained = 0;
+        loop {
+            drained += self.buf.len() - self.pos;
+            self.pos = self.buf.len();
+            if drained > MAX_DRAIN {
+                return false;
+            }
+            match self.fill() {
+                Ok(true) => {}
+                Ok(false) => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let n = std::cmp::min(out.len(), self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for BodyReader {
+    fn drop(&mut self) {
+        let leftover = match self.leftover.take() {
+            Some(l) => l,
+            None => return,
+        };
+        // no io is allowed when unwinding, e.g. the coroutine is canceled
+        let complete = !std::thread::panicking() && self.drain();
+        let mut leftover = leftover.lock().unwrap_or_else(|e| e.into_inner());
+        leftover.data = self.raw.split();
+        leftover.complete = complete;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(src: &[u8]) -> io::Result<(usize, BytesMut, bool)> {
+        let mut dec = ChunkedDecoder::new();
+        let mut dst = BytesMut::new();
+        let n = dec.decode(src, &mut dst)?;
+        Ok((n, dst, dec.is_done()))
+    }
+
+    #[test]
+    fn chunked() {
+        let src = b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: a\r\n\r\nGET /";
+        let (n, body, done) = decode_all(src).unwrap();
+        assert!(done);
+        assert_eq!(&body[..], b"hello world");
+        assert_eq!(&src[n..], b"GET /");
+    }
+
+    #[test]
+    fn chunked_partial() {
+        let src = b"a\r\n0123456789\r\n0\r\n\r\n";
+        let mut dec = ChunkedDecoder::new();
+        let mut body = BytesMut::new();
+        // feed one byte at a time
+        for b in src.chunks(1) {
+            assert!(!dec.is_done());
+            assert_eq!(dec.decode(b, &mut body).unwrap(), 1);
+        }
+        assert!(dec.is_done());
+        assert_eq!(&body[..], b"0123456789");
+    }
+
+    #[test]
+    fn chunked_invalid() {
+        assert!(decode_all(b"x\r\n").is_err());
+        assert!(decode_all(b"\r\n").is_err());
+        assert!(decode_all(b"3\r\nabcX").is_err());
+        assert!(decode_all(b"fffffffffffffffff\r\n").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+/// the http server configuration
+#[derive(Debug, Clone)]
+pub struct HttpServerConfig {
+    max_body_size: usize,
+    body_buffer_size: usize,
+    max_headers: usize,
+    max_header_bytes: usize,
+    header_read_timeout: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    body_read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_requests_per_connection: Option<usize>,
+    max_connections: Option<usize>,
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        HttpServerConfig {
+            max_body_size: 8 * 1024 * 1024,
+            body_buffer_size: 64 * 1024,
+            max_headers: 100,
+            max_header_bytes: 64 * 1024,
+            header_read_timeout: Some(Duration::from_secs(30)),
+            keep_alive_timeout: Some(Duration::from_secs(60)),
+            body_read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            max_requests_per_connection: None,
+            max_connections: None,
+        }
+    }
+}
+
+impl HttpServerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// set the max request body size
+    ///
+    /// a request with a larger body is rejected with `413 Payload Too Large`
+    pub fn set_max_body_size(&mut self, size: usize) -> &mut Self {
+        self.max_body_size = size;
+        self
+    }
+
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+
+    /// set the max request body size that is buffered before the service is called
+    ///
+    /// larger bodies are streamed from the connection by `Request::body_reader`
+    pub fn set_body_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.body_buffer_size = size;
+        self
+    }
+
+    pub fn body_buffer_size(&self) -> usize {
+        self.body_buffer_size
+    }
+
+    /// set the max number of request headers
+    ///
+    /// a request with more headers is rejected with
+    /// `431 Request Header Fields Too Large`
+    pub fn set_max_headers(&mut self, n: usize) -> &mut Self {
+        self.max_headers = n;
+        self
+    }
+
+    pub fn max_headers(&self) -> usize {
+        self.max_headers
+    }
+
+    /// set the max bytes of the request line and headers
+    ///
+    /// a request with a larger head is rejected with
+    /// `431 Request Header Fields Too Large`
+    pub fn set_max_header_bytes(&mut self, n: usize) -> &mut Self {
+        self.max_header_bytes = n;
+        self
+    }
+
+    pub fn max_header_bytes(&self) -> usize {
+        self.max_header_bytes
+    }
+
+    /// set the time allowed to receive a request after its first byte
+    ///
+    /// the body is included if it's buffered, the connection is closed with
+    /// `408 Request Timeout` when it expires. `None` means no limit
+    pub fn set_header_read_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.header_read_timeout = timeout;
+        self
+    }
+
+    pub fn header_read_timeout(&self) -> Option<Duration> {
+        self.header_read_timeout
+    }
+
+    /// set how long an idle keep-alive connection is kept open
+    ///
+    /// `None` means no limit
+    pub fn set_keep_alive_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    pub fn keep_alive_timeout(&self) -> Option<Duration> {
+        self.keep_alive_timeout
+    }
+
+    /// set the time allowed for each read of a streamed request body
+    ///
+    /// `Request::body_reader` returns a `TimedOut` error when it expires.
+    /// `None` means no limit
+    pub fn set_body_read_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.body_read_timeout = timeout;
+        self
+    }
+
+    pub fn body_read_timeout(&self) -> Option<Duration> {
+        self.body_read_timeout
+    }
+
+    /// set the time allowed for each write of the responses
+    ///
+    /// the connection is closed when it expires. `None` means no limit
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// set the max number of requests served on one connection
+    ///
+    /// the last response carries `Connection: close`. `None` means no limit
+    pub fn set_max_requests_per_connection(&mut self, n: Option<usize>) -> &mut Self {
+        self.max_requests_per_connection = n;
+        self
+    }
+
+    pub fn max_requests_per_connection(&self) -> Option<usize> {
+        self.max_requests_per_connection
+    }
+
+    /// set the max number of concurrent connections
+    ///
+    /// no more connection is accepted when the limit is reached, the new
+    /// ones wait in the listen backlog. `None` means no limit
+    pub fn set_max_connections(&mut self, n: Option<usize>) -> &mut Self {
+        self.max_connections = n;
+        self
+    }
+
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+}
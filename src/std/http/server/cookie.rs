@@ -0,0 +1,366 @@
+//! cookies and signed cookie values
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use hmac_sha256::HMAC;
+
+use crate::std::time::time::{Time, TimeFormat};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+// a token of rfc 6265, the same as a header name
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !b"\"(),/:;<=>?@[\\]{}".contains(&b))
+}
+
+// the cookie-octets, no quotes, spaces, commas, semicolons or backslashes
+fn valid_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b.is_ascii_graphic() && !b"\",;\\".contains(&b))
+}
+
+// the path and domain attributes must not end the attribute early
+fn valid_attr(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| (0x20..0x7f).contains(&b) && b != b';')
+}
+
+/// parse the value of a `Cookie` header into the name value pairs
+///
+/// the pairs are trimmed, and the quotes around a value are removed
+pub(crate) fn parse(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let mut kv = pair.splitn(2, '=');
+        let name = kv.next().unwrap_or_default().trim();
+        let value = kv.next()?.trim();
+        let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            Some(v) => v,
+            None => value,
+        };
+        if name.is_empty() {
+            None
+        } else {
+            Some((name, value))
+        }
+    })
+}
+
+/// the `SameSite` attribute of a cookie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// the browsers require `Secure` along with it
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+/// a cookie to send by `Response::set_cookie`
+///
+/// it displays as the value of the `Set-Cookie` header
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use cogo::std::http::server::{Cookie, HttpServer, Router, SameSite};
+///
+/// let router = Router::new().get("/", |req, rsp| {
+///     let visits: u32 = req.cookie("visits").and_then(|v| v.parse().ok()).unwrap_or(0);
+///     let cookie = Cookie::new("visits", (visits + 1).to_string())
+///         .path("/")
+///         .max_age(Duration::from_secs(3600))
+///         .http_only(true)
+///         .same_site(SameSite::Lax);
+///     rsp.set_cookie(&cookie)?;
+///     rsp.body_vec(format!("visits: {}", visits).into_bytes());
+///     Ok(())
+/// });
+/// let server = HttpServer(router).start("127.0.0.1:8080").unwrap();
+/// server.join().unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    // the formatted date, `Time` is not `Clone`
+    expires: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Self {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// a cookie that makes the client remove the cookie named `name`
+    ///
+    /// the path and domain must be the same as the ones it was set with
+    pub fn removal<N: Into<String>>(name: N) -> Self {
+        let mut cookie = Cookie::new(name, "").max_age(Duration::from_secs(0));
+        cookie.expires = Some("Thu, 01 Jan 1970 00:00:00 GMT".to_owned());
+        cookie
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    /// how long the cookie lives, it takes precedence over `expires`
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// when the cookie expires, it's formatted in GMT
+    pub fn expires(mut self, time: &Time) -> Self {
+        self.expires = Some(time.utc().format(TimeFormat));
+        self
+    }
+
+    /// only send the cookie over https
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// hide the cookie from the scripts
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// check that the cookie could be sent as is
+    ///
+    /// an `InvalidInput` error is returned if the name is not a token, or
+    /// the value or an attribute contains a char it must not have
+    pub fn validate(&self) -> io::Result<()> {
+        if !valid_name(&self.name) {
+            return Err(invalid("invalid cookie name"));
+        }
+        if !valid_value(&self.value) {
+            return Err(invalid("invalid cookie value"));
+        }
+        let mut attrs = self.path.iter().chain(self.domain.iter());
+        if !attrs.all(|a| valid_attr(a)) {
+            return Err(invalid("invalid cookie attribute"));
+        }
+        Ok(())
+    }
+}
+
+/// the `Set-Cookie` header value, e.g. `id=1; Path=/; HttpOnly`
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(age) = self.max_age {
+            write!(f, "; Max-Age={}", age.as_secs())?;
+        }
+        if let Some(ref expires) = self.expires {
+            write!(f, "; Expires={}", expires)?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// sign the cookie values with HMAC-SHA256, so that the changed ones are found out
+///
+/// the signature covers the cookie name too, so a signed value can't be
+/// moved to another cookie. the value is still readable by the client
+///
+/// # Examples
+///
+/// ```
+/// use cogo::std::http::server::CookieSigner;
+///
+/// let signer = CookieSigner::new(b"a secret key of at least 32 bytes");
+/// let signed = signer.sign("user", "alice");
+/// assert_eq!(signer.verify("user", &signed), Some("alice"));
+/// assert_eq!(signer.verify("admin", &signed), None);
+/// ```
+#[derive(Clone)]
+pub struct CookieSigner {
+    key: Vec<u8>,
+}
+
+impl CookieSigner {
+    /// create a signer with the secret `key`, it should be at least 32 random bytes
+    pub fn new(key: &[u8]) -> Self {
+        CookieSigner { key: key.to_vec() }
+    }
+
+    fn input(name: &str, value: &str) -> String {
+        format!("{}={}", name, value)
+    }
+
+    /// the value followed by a `.` and its url safe base64 signature
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let mac = HMAC::mac(Self::input(name, value), &self.key);
+        format!(
+            "{}.{}",
+            value,
+            base64::encode_config(mac, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// get the original value of a signed one, `None` if the signature doesn't match
+    pub fn verify<'a>(&self, name: &str, signed: &'a str) -> Option<&'a str> {
+        let i = signed.rfind('.')?;
+        let (value, sig) = (&signed[..i], &signed[i + 1..]);
+        let sig = base64::decode_config(sig, base64::URL_SAFE_NO_PAD).ok()?;
+        if sig.len() != 32 {
+            return None;
+        }
+        let mut expected = [0u8; 32];
+        expected.copy_from_slice(&sig);
+        // compared in constant time
+        if HMAC::verify(Self::input(name, value), &self.key, &expected) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+// never show the key
+impl fmt::Debug for CookieSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("CookieSigner")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::OffsetDateTime;
+
+    #[test]
+    fn encoding() {
+        let c = Cookie::new("id", "1");
+        assert_eq!(c.to_string(), "id=1");
+        let epoch = Time {
+            inner: OffsetDateTime::UNIX_EPOCH,
+        };
+        let c = Cookie::new("id", "a1")
+            .path("/")
+            .domain("example.com")
+            .max_age(Duration::from_secs(60))
+            .expires(&epoch)
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            c.to_string(),
+            "id=a1; Path=/; Domain=example.com; Max-Age=60; \
+             Expires=Thu, 01 Jan 1970 00:00:00 GMT; Secure; HttpOnly; SameSite=Strict"
+        );
+        assert_eq!(
+            Cookie::removal("id").path("/").to_string(),
+            "id=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn validate() {
+        assert!(Cookie::new("id", "a-b_c.d!").validate().is_ok());
+        assert!(Cookie::new("", "1").validate().is_err());
+        assert!(Cookie::new("a=b", "1").validate().is_err());
+        assert!(Cookie::new("id", "a b").validate().is_err());
+        assert!(Cookie::new("id", "a;b").validate().is_err());
+        assert!(Cookie::new("id", "\"a\"").validate().is_err());
+        assert!(Cookie::new("id", "1").path("/;x").validate().is_err());
+        assert!(Cookie::new("id", "1").domain("a\r\n").validate().is_err());
+    }
+
+    #[test]
+    fn parse_header() {
+        let pairs: Vec<_> = parse("a=1; b=\"x y\";c=;flag; =bad ; d = 2=3").collect();
+        assert_eq!(
+            pairs,
+            vec![("a", "1"), ("b", "x y"), ("c", ""), ("d", "2=3")]
+        );
+    }
+
+    #[test]
+    fn signer() {
+        let signer = CookieSigner::new(b"0123456789abcdef0123456789abcdef");
+        let signed = signer.sign("sid", "abc.def");
+        assert!(signed.starts_with("abc.def."));
+        assert!(valid_value(&signed));
+        assert_eq!(signer.verify("sid", &signed), Some("abc.def"));
+        assert_eq!(signer.verify("other", &signed), None);
+        assert_eq!(signer.verify("sid", &signed.replace("abc", "abd")), None);
+        assert_eq!(signer.verify("sid", "abc"), None);
+        assert_eq!(signer.verify("sid", "abc.!!"), None);
+        let other = CookieSigner::new(b"another key");
+        assert_eq!(other.verify("sid", &signed), None);
+        assert_eq!(format!("{:?}", signer), "CookieSigner");
+    }
+}
@@ -0,0 +1,838 @@
+//! static file service
+//!
+//! the file system calls block, so they are done by a helper thread while
+//! the coroutine waits for the result, the worker thread keeps running the
+//! other coroutines
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::form::{percent_decode, percent_encode};
+use super::{HttpService, Request, Response, StatusCode};
+use crate::io::blocking::FS;
+
+// the bytes read from the file at a time
+const READ_SIZE: usize = 64 * 1024;
+// a `Range` header with more ranges is ignored
+const MAX_RANGES: usize = 16;
+
+// a file that is read on the helper thread
+struct BlockingFile(Option<File>);
+
+impl BlockingFile {
+    // read up to `len` bytes at `offset`
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut file = self
+            .0
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "file read failed"))?;
+        let (file, ret) = FS.run(move || {
+            let ret = file.seek(SeekFrom::Start(offset)).and_then(|_| {
+                let mut buf = Vec::with_capacity(len);
+                (&mut file).take(len as u64).read_to_end(&mut buf)?;
+                Ok(buf)
+            });
+            (file, ret)
+        });
+        self.0 = Some(file);
+        ret
+    }
+
+    // write `len` bytes at `offset` to `w`
+    fn copy_to(&mut self, w: &mut dyn Write, offset: u64, len: u64) -> io::Result<()> {
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let n = READ_SIZE.min((end - pos) as usize);
+            let data = self.read_at(pos, n)?;
+            if data.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file is truncated",
+                ));
+            }
+            w.write_all(&data)?;
+            pos += data.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+// what a url path refers to
+enum Entry {
+    File(File, Metadata, PathBuf),
+    // the names in a directory, those of the sub directories end with `/`
+    Dir(Vec<String>),
+    NotFound,
+}
+
+// the relative file path of a url path, `None` if it could escape the root
+fn file_path(url_path: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for seg in percent_decode(url_path).split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => return None,
+            seg if seg.contains(|c| c == '\\' || c == ':' || c == '\0') => return None,
+            seg => path.push(seg),
+        }
+    }
+    Some(path)
+}
+
+// find the entry of `rel` under `root`, also tell if it's a directory
+// this runs on the helper thread
+fn lookup(
+    root: &Path,
+    rel: &Path,
+    index: Option<&str>,
+    listing: bool,
+) -> io::Result<(Entry, bool)> {
+    let not_found = |e: io::Error| match e.kind() {
+        io::ErrorKind::PermissionDenied => Err(e),
+        _ => Ok((Entry::NotFound, false)),
+    };
+    let root = root.canonicalize()?;
+    let path = match root.join(rel).canonicalize() {
+        Ok(path) => path,
+        Err(e) => return not_found(e),
+    };
+    // a symlink must not lead out of the root
+    if !path.starts_with(&root) {
+        return Ok((Entry::NotFound, false));
+    }
+    let meta = fs::metadata(&path)?;
+    if meta.is_file() {
+        return Ok((Entry::File(File::open(&path)?, meta, path), false));
+    }
+    // the special files like fifos would block the reads
+    if !meta.is_dir() {
+        return Ok((Entry::NotFound, false));
+    }
+
+    // the index file could be a symlink out of the root too
+    let index = index.and_then(|i| path.join(i).canonicalize().ok());
+    if let Some(index) = index.filter(|i| i.starts_with(&root)) {
+        match fs::metadata(&index) {
+            Ok(meta) if meta.is_file() => {
+                return Ok((Entry::File(File::open(&index)?, meta, index), true))
+            }
+            _ => {}
+        }
+    }
+    if !listing {
+        return Ok((Entry::NotFound, true));
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(&path)? {
+        let entry = entry?;
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.path().is_dir() {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    names.sort();
+    Ok((Entry::Dir(names), true))
+}
+
+/// get the mime type of a file from its extension
+///
+/// `application/octet-stream` is returned for the unknown ones
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match &*ext {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "text/xml; charset=utf-8",
+        "json" | "map" => "application/json",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+fn header_str<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.header(name)
+        .and_then(|v| str::from_utf8(v).ok())
+        .map(str::trim)
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// a validator that changes whenever the file is modified
+fn etag(meta: &Metadata) -> String {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!("\"{:x}-{:x}\"", meta.len(), mtime)
+}
+
+// the cached copy of the client is still fresh
+fn not_modified(req: &Request, etag: &str, mtime: Option<SystemTime>) -> bool {
+    // the weak comparison, `If-Modified-Since` is ignored when it's present
+    if let Some(tags) = header_str(req, "If-None-Match") {
+        return tags
+            .split(',')
+            .map(|t| t.trim())
+            .any(|t| t == "*" || t.strip_prefix("W/").unwrap_or(t) == etag);
+    }
+    let since =
+        header_str(req, "If-Modified-Since").and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since, mtime) {
+        (Some(since), Some(mtime)) => secs(mtime) <= secs(since),
+        _ => false,
+    }
+}
+
+// the `Range` is for the current file, `If-Range` has a strong etag or a date
+fn range_applies(req: &Request, etag: &str, mtime: Option<SystemTime>) -> bool {
+    match header_str(req, "If-Range") {
+        None => true,
+        Some(v) if v.starts_with('"') || v.starts_with("W/") => v == etag,
+        Some(v) => match (httpdate::parse_http_date(v), mtime) {
+            (Ok(date), Some(mtime)) => secs(date) == secs(mtime),
+            _ => false,
+        },
+    }
+}
+
+// parse a `Range` header into the inclusive byte ranges of a `len` bytes file
+// `None` if the header should be ignored, and an empty vec if no range could
+// be satisfied
+fn parse_range(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut it = part.splitn(2, '-');
+        let (start, end) = (it.next()?.trim(), it.next()?.trim());
+        if start.is_empty() {
+            // the last `n` bytes
+            let n: u64 = end.parse().ok()?;
+            if n > 0 && len > 0 {
+                ranges.push((len.saturating_sub(n), len - 1));
+            }
+            continue;
+        }
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => None,
+            end => Some(end.parse::<u64>().ok()?),
+        };
+        if end.map_or(false, |end| end < start) {
+            return None;
+        }
+        if start < len {
+            ranges.push((start, end.map_or(len - 1, |end| end.min(len - 1))));
+        }
+    }
+    if ranges.len() > MAX_RANGES {
+        return None;
+    }
+    Some(ranges)
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// percent encode a file name for a relative link
+fn encode_href(name: &str) -> String {
+    match name.strip_suffix('/') {
+        Some(dir) => format!("{}/", percent_encode(dir)),
+        None => percent_encode(name),
+    }
+}
+
+// the html page that lists the names of a directory
+fn listing_page(url_path: &str, names: &[String], has_parent: bool) -> Vec<u8> {
+    let title = escape_html(&percent_decode(url_path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n\
+         <body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if has_parent {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in names {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            encode_href(name),
+            escape_html(name)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html.into_bytes()
+}
+
+// a boundary of the multipart rsp that won't appear in the file
+fn boundary() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    format!(
+        "cogo-{:x}-{:x}",
+        nanos,
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// the http service that serves the files under a directory
+///
+/// `GET` and `HEAD` requests are supported, the files are sent with `ETag`
+/// and `Last-Modified`, so that the conditional requests get
+/// `304 Not Modified`, and `Range` requests get `206 Partial Content`. the
+/// urls that could lead out of the root directory get `404 Not Found`
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::std::http::server::{FileServer, HttpServer, Router};
+///
+/// let files = FileServer::new("./public").prefix("/static").listing(true);
+/// let router = Router::new().any("/static/*path", move |req, rsp| files.serve(&req, rsp));
+/// let server = HttpServer(router).start("127.0.0.1:8080").unwrap();
+/// server.join().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileServer {
+    root: PathBuf,
+    prefix: String,
+    index: Option<String>,
+    listing: bool,
+}
+
+impl FileServer {
+    /// serve the files under `root`
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        FileServer {
+            root: root.as_ref().to_owned(),
+            prefix: String::new(),
+            index: Some("index.html".to_owned()),
+            listing: false,
+        }
+    }
+
+    /// strip `prefix` from the url path before looking up the file
+    ///
+    /// the urls that don't start with it get `404 Not Found`
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// set the file that is served for a directory, `index.html` by default
+    ///
+    /// `None` disables it
+    pub fn index(mut self, name: Option<&str>) -> Self {
+        self.index = name.map(|n| n.to_owned());
+        self
+    }
+
+    /// list the names in a directory that has no index file
+    ///
+    /// it's disabled by default, such directories get `404 Not Found`
+    pub fn listing(mut self, enable: bool) -> Self {
+        self.listing = enable;
+        self
+    }
+
+    /// serve the file that `req` refers to
+    pub fn serve(&self, req: &Request, rsp: &mut Response) -> io::Result<()> {
+        let method = req.method();
+        if method != "GET" && method != "HEAD" {
+            rsp.status(StatusCode::MethodNotAllowed)
+                .header("Allow: GET, HEAD");
+            return Ok(());
+        }
+        let path = req.path();
+        let (path, query) = match path.find('?') {
+            Some(i) => path.split_at(i),
+            None => (path, ""),
+        };
+        let rel = match path.strip_prefix(&*self.prefix) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => file_path(rest),
+            _ => None,
+        };
+        let rel = match rel {
+            Some(rel) => rel,
+            None => {
+                rsp.status(StatusCode::NotFound);
+                return Ok(());
+            }
+        };
+
+        let (root, index, listing) = (self.root.clone(), self.index.clone(), self.listing);
+        let ret = FS.run(move || lookup(&root, &rel, index.as_deref(), listing));
+        let (entry, is_dir) = match ret {
+            Ok(found) => found,
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                rsp.status(StatusCode::Forbidden);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        match entry {
+            Entry::NotFound => {
+                rsp.status(StatusCode::NotFound);
+            }
+            // the relative links in the page need the trailing `/`
+            _ if is_dir && !path.ends_with('/') => {
+                // `//host/dir` would be a protocol relative url
+                let path = path.trim_start_matches('/');
+                rsp.status(StatusCode::MovedPermanently)
+                    .add_header("Location", format!("/{}/{}", path, query))?;
+            }
+            Entry::Dir(names) => {
+                let has_parent = path.len() > self.prefix.len() + 1;
+                rsp.header("Content-Type: text/html; charset=utf-8")
+                    .body_vec(listing_page(path, &names, has_parent));
+            }
+            Entry::File(file, meta, file_path) => {
+                serve_file(req, rsp, file, &meta, mime_type(&file_path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn serve_file(
+    req: &Request,
+    rsp: &mut Response,
+    file: File,
+    meta: &Metadata,
+    content_type: &'static str,
+) -> io::Result<()> {
+    let len = meta.len();
+    let mtime = meta.modified().ok();
+    let etag = etag(meta);
+    rsp.add_header("ETag", etag.clone())?;
+    if let Some(mtime) = mtime {
+        rsp.add_header("Last-Modified", httpdate::fmt_http_date(mtime))?;
+    }
+    rsp.header("Accept-Ranges: bytes");
+    if not_modified(req, &etag, mtime) {
+        rsp.status(StatusCode::NotModified);
+        return Ok(());
+    }
+
+    let ranges = match header_str(req, "Range") {
+        Some(range) if req.method() == "GET" && range_applies(req, &etag, mtime) => {
+            parse_range(range, len)
+        }
+        _ => None,
+    };
+    let mut file = BlockingFile(Some(file));
+    match ranges {
+        None => {
+            rsp.add_header("Content-Type", content_type)?;
+            rsp.body_stream_len(len, move |w| file.copy_to(w, 0, len));
+        }
+        Some(ranges) if ranges.is_empty() => {
+            rsp.status(StatusCode::RangeNotSatisfiable)
+                .add_header("Content-Range", format!("bytes */{}", len))?;
+        }
+        Some(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            rsp.status(StatusCode::PartialContent)
+                .add_header("Content-Type", content_type)?
+                .add_header("Content-Range", format!("bytes {}-{}/{}", start, end, len))?;
+            rsp.body_stream_len(end - start + 1, move |w| {
+                file.copy_to(w, start, end - start + 1)
+            });
+        }
+        Some(ranges) => {
+            let boundary = boundary();
+            let parts: Vec<_> = ranges
+                .into_iter()
+                .map(|(start, end)| {
+                    let head = format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, content_type, start, end, len
+                    );
+                    (head, start, end)
+                })
+                .collect();
+            let tail = format!("\r\n--{}--\r\n", boundary);
+            let body_len = parts
+                .iter()
+                .map(|(head, start, end)| head.len() as u64 + end - start + 1)
+                .sum::<u64>()
+                + tail.len() as u64;
+            rsp.status(StatusCode::PartialContent).add_header(
+                "Content-Type",
+                format!("multipart/byteranges; boundary={}", boundary),
+            )?;
+            rsp.body_stream_len(body_len, move |w| {
+                for (head, start, end) in parts {
+                    w.write_all(head.as_bytes())?;
+                    file.copy_to(w, start, end - start + 1)?;
+                }
+                w.write_all(tail.as_bytes())
+            });
+        }
+    }
+    Ok(())
+}
+
+impl HttpService for FileServer {
+    fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+        self.serve(&req, rsp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path() {
+        assert_eq!(file_path("/a/./b/"), Some(PathBuf::from("a/b")));
+        assert_eq!(file_path("/a%20b"), Some(PathBuf::from("a b")));
+        assert_eq!(file_path("/"), Some(PathBuf::new()));
+        assert_eq!(file_path("/../etc/passwd"), None);
+        assert_eq!(file_path("/a/%2e%2e/%2e%2e/x"), None);
+        assert_eq!(file_path("/a/..%2f..%2fx"), None);
+        assert_eq!(file_path("/..%5cx"), None);
+        assert_eq!(file_path("/c:/x"), None);
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some(vec![(0, 4)]));
+        assert_eq!(parse_range("bytes=5-", 10), Some(vec![(5, 9)]));
+        assert_eq!(parse_range("bytes=-3", 10), Some(vec![(7, 9)]));
+        assert_eq!(parse_range("bytes=-30", 10), Some(vec![(0, 9)]));
+        assert_eq!(parse_range("bytes=8-20", 10), Some(vec![(8, 9)]));
+        assert_eq!(
+            parse_range("bytes=0-1, 4-5", 10),
+            Some(vec![(0, 1), (4, 5)])
+        );
+        // unsatisfiable
+        assert_eq!(parse_range("bytes=10-", 10), Some(vec![]));
+        assert_eq!(parse_range("bytes=-0", 10), Some(vec![]));
+        assert_eq!(parse_range("bytes=0-", 0), Some(vec![]));
+        // invalid
+        assert_eq!(parse_range("bytes=4-2", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+        assert_eq!(parse_range("bytes=a-1", 10), None);
+        assert_eq!(parse_range("bytes=1", 10), None);
+        let many = vec!["0-0"; MAX_RANGES + 1].join(",");
+        assert_eq!(parse_range(&format!("bytes={}", many), 10), None);
+    }
+
+    #[test]
+    fn mime() {
+        assert_eq!(mime_type(Path::new("a/b.HTML")), "text/html; charset=utf-8");
+        assert_eq!(mime_type(Path::new("x.png")), "image/png");
+        assert_eq!(mime_type(Path::new("x")), "application/octet-stream");
+    }
+
+    #[test]
+    fn listing_html() {
+        let page = listing_page("/a%20b/", &["<x>.txt".to_owned(), "d/".to_owned()], true);
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.contains("<title>Index of /a b/</title>"));
+        assert!(page.contains("<a href=\"../\">"));
+        assert!(page.contains("<a href=\"%3Cx%3E.txt\">&lt;x&gt;.txt</a>"));
+        assert!(page.contains("<a href=\"d/\">d/</a>"));
+    }
+
+    #[cfg(unix)]
+    mod serve {
+        use super::*;
+        use crate::std::http::client::{Client, HttpClientConfig};
+        use crate::std::http::server::{HttpServer, HttpServerConfig};
+        use std::time::Duration;
+
+        // the files of the tests, the dir is removed on drop
+        fn files() -> tempdir::TempDir {
+            let dir = tempdir::TempDir::new("file_server").unwrap();
+            fs::write(dir.path().join("a.txt"), "hello world").unwrap();
+            fs::create_dir(dir.path().join("sub")).unwrap();
+            fs::write(dir.path().join("sub/b.json"), "{}").unwrap();
+            fs::create_dir(dir.path().join("site")).unwrap();
+            fs::write(dir.path().join("site/index.html"), "<p>home</p>").unwrap();
+            dir
+        }
+
+        fn serve(files: FileServer) -> String {
+            let addr = HttpServer(files)
+                .serve("127.0.0.1:0", HttpServerConfig::new())
+                .unwrap()
+                .local_addr();
+            format!("http://{}", addr)
+        }
+
+        fn no_redirect() -> Client {
+            let mut config = HttpClientConfig::new();
+            config.set_max_redirects(0);
+            Client::with_config(config)
+        }
+
+        #[test]
+        fn files_and_dirs() {
+            let dir = files();
+            let url = serve(FileServer::new(dir.path()));
+            let client = no_redirect();
+
+            let rsp = client.get(&format!("{}/a.txt", url)).send().unwrap();
+            assert_eq!(rsp.status(), 200);
+            assert_eq!(
+                rsp.header("Content-Type"),
+                Some(&b"text/plain; charset=utf-8"[..])
+            );
+            assert!(rsp.header("ETag").is_some());
+            assert!(rsp.header("Last-Modified").is_some());
+            assert_eq!(rsp.text().unwrap(), "hello world");
+
+            let rsp = client.head(&format!("{}/a.txt", url)).send().unwrap();
+            assert_eq!(rsp.header("Content-Length"), Some(&b"11"[..]));
+            assert_eq!(rsp.text().unwrap(), "");
+
+            let rsp = client.get(&format!("{}/site/", url)).send().unwrap();
+            assert_eq!(rsp.text().unwrap(), "<p>home</p>");
+            let rsp = client.get(&format!("{}/site?x=1", url)).send().unwrap();
+            assert_eq!(rsp.status(), 301);
+            assert_eq!(rsp.header("Location"), Some(&b"/site/?x=1"[..]));
+            // never redirect to another host
+            let rsp = client.get(&format!("{}//site", url)).send().unwrap();
+            assert_eq!(rsp.status(), 301);
+            assert_eq!(rsp.header("Location"), Some(&b"/site/"[..]));
+
+            // no index file and the listing is disabled
+            let rsp = client.get(&format!("{}/sub/", url)).send().unwrap();
+            assert_eq!(rsp.status(), 404);
+            for path in &["/missing", "/../a.txt", "/sub/%2e%2e/a.txt", "/a.txt/x"] {
+                let rsp = client.get(&format!("{}{}", url, path)).send().unwrap();
+                assert_eq!(rsp.status(), 404, "{}", path);
+            }
+            let rsp = client.post(&format!("{}/a.txt", url)).send().unwrap();
+            assert_eq!(rsp.status(), 405);
+        }
+
+        #[test]
+        fn symlink_out_of_root() {
+            let dir = files();
+            let outside = tempdir::TempDir::new("file_server_outside").unwrap();
+            fs::write(outside.path().join("secret"), "secret").unwrap();
+            std::os::unix::fs::symlink(outside.path().join("secret"), dir.path().join("link"))
+                .unwrap();
+            // an index file that leads out of the root
+            fs::create_dir(dir.path().join("linked")).unwrap();
+            std::os::unix::fs::symlink(
+                outside.path().join("secret"),
+                dir.path().join("linked/index.html"),
+            )
+            .unwrap();
+            let url = serve(FileServer::new(dir.path()));
+            let rsp = Client::new().get(&format!("{}/link", url)).send().unwrap();
+            assert_eq!(rsp.status(), 404);
+            let rsp = Client::new()
+                .get(&format!("{}/linked/", url))
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 404);
+        }
+
+        #[test]
+        fn listing_and_prefix() {
+            let dir = files();
+            let url = serve(FileServer::new(dir.path()).prefix("/static/").listing(true));
+            let client = no_redirect();
+            let rsp = client.get(&format!("{}/static/", url)).send().unwrap();
+            assert_eq!(rsp.status(), 200);
+            let page = rsp.text().unwrap();
+            assert!(page.contains("<a href=\"a.txt\">a.txt</a>"));
+            assert!(page.contains("<a href=\"sub/\">sub/</a>"));
+            assert!(!page.contains("../"));
+            let rsp = client
+                .get(&format!("{}/static/sub/b.json", url))
+                .send()
+                .unwrap();
+            assert_eq!(rsp.header("Content-Type"), Some(&b"application/json"[..]));
+            let rsp = client.get(&format!("{}/static", url)).send().unwrap();
+            assert_eq!(rsp.status(), 301);
+            let rsp = client.get(&format!("{}/a.txt", url)).send().unwrap();
+            assert_eq!(rsp.status(), 404);
+            let rsp = client
+                .get(&format!("{}/staticx/a.txt", url))
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 404);
+        }
+
+        #[test]
+        fn conditional() {
+            let dir = files();
+            let url = format!("{}/a.txt", serve(FileServer::new(dir.path())));
+            let client = Client::new();
+            let rsp = client.get(&url).send().unwrap();
+            let header = |name| String::from_utf8(rsp.header(name).unwrap().to_vec()).unwrap();
+            let (etag, modified) = (header("ETag"), header("Last-Modified"));
+            rsp.discard();
+
+            let rsp = client
+                .get(&url)
+                .header("If-None-Match", &etag)
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 304);
+            assert_eq!(rsp.header("ETag"), Some(etag.as_bytes()));
+            assert_eq!(rsp.header("Content-Length"), None);
+            let tags = format!("\"x\", W/{}", etag);
+            let rsp = client
+                .get(&url)
+                .header("If-None-Match", &tags)
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 304);
+            let rsp = client
+                .get(&url)
+                .header("If-None-Match", "\"x\"")
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 200);
+            assert_eq!(rsp.text().unwrap(), "hello world");
+
+            let rsp = client
+                .get(&url)
+                .header("If-Modified-Since", &modified)
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 304);
+            let earlier = httpdate::parse_http_date(&modified).unwrap() - Duration::from_secs(10);
+            let rsp = client
+                .get(&url)
+                .header("If-Modified-Since", httpdate::fmt_http_date(earlier))
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 200);
+            // the connection is still usable after the 304s
+            assert_eq!(rsp.text().unwrap(), "hello world");
+        }
+
+        #[test]
+        fn ranges() {
+            let dir = files();
+            let url = format!("{}/a.txt", serve(FileServer::new(dir.path())));
+            let client = Client::new();
+            let rsp = client
+                .get(&url)
+                .header("Range", "bytes=0-4")
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 206);
+            assert_eq!(rsp.header("Content-Range"), Some(&b"bytes 0-4/11"[..]));
+            assert_eq!(rsp.text().unwrap(), "hello");
+            let rsp = client.get(&url).header("Range", "bytes=-5").send().unwrap();
+            assert_eq!(rsp.text().unwrap(), "world");
+
+            let rsp = client
+                .get(&url)
+                .header("Range", "bytes=20-")
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 416);
+            assert_eq!(rsp.header("Content-Range"), Some(&b"bytes */11"[..]));
+            rsp.discard();
+
+            let rsp = client
+                .get(&url)
+                .header("Range", "bytes=0-1,6-7")
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 206);
+            let content_type =
+                String::from_utf8(rsp.header("Content-Type").unwrap().to_vec()).unwrap();
+            let boundary = content_type
+                .strip_prefix("multipart/byteranges; boundary=")
+                .unwrap()
+                .to_owned();
+            let expect = format!(
+                "\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/11\r\n\r\nhe\
+                 \r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 6-7/11\r\n\r\nwo\
+                 \r\n--{0}--\r\n",
+                boundary
+            );
+            assert_eq!(rsp.text().unwrap(), expect);
+
+            // the file is changed since the client got the etag
+            let rsp = client
+                .get(&url)
+                .header("Range", "bytes=0-4")
+                .header("If-Range", "\"old\"")
+                .send()
+                .unwrap();
+            assert_eq!(rsp.status(), 200);
+            assert_eq!(rsp.text().unwrap(), "hello world");
+        }
+
+        #[test]
+        fn large_file() {
+            let dir = files();
+            let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+            fs::write(dir.path().join("large.bin"), &data).unwrap();
+            let url = format!("{}/large.bin", serve(FileServer::new(dir.path())));
+            let client = Client::new();
+            let rsp = client.get(&url).send().unwrap();
+            assert_eq!(rsp.header("Content-Length"), Some(&b"300000"[..]));
+            assert!(rsp.bytes().unwrap() == data);
+            let rsp = client
+                .get(&url)
+                .header("Range", "bytes=100000-250000")
+                .send()
+                .unwrap();
+            assert!(rsp.bytes().unwrap() == data[100_000..=250_000]);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! query strings and url encoded forms
+
+use std::fmt;
+use std::io::{self, Read};
+use std::iter::FromIterator;
+use std::str::{self, FromStr};
+
+use super::Request;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// decode the `%xx` escapes, invalid ones are kept as is
+fn decode(s: &str, plus_as_space: bool) -> String {
+    fn hex(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(h), Some(l)) => {
+                    out.push(h << 4 | l);
+                    i += 3;
+                    continue;
+                }
+                _ => out.push(b'%'),
+            },
+            b'+' if plus_as_space => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+fn encode(s: &str, space_as_plus: bool) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            b' ' if space_as_plus => out.push('+'),
+            b => {
+                out.push('%');
+                out.push(HEX[(b >> 4) as usize] as char);
+                out.push(HEX[(b & 15) as usize] as char);
+            }
+        }
+    }
+    out
+}
+
+/// decode the `%xx` escapes of a url path or query component
+///
+/// the invalid escapes are kept as is, and the invalid utf-8 is replaced
+/// with `U+FFFD`
+pub fn percent_decode(s: &str) -> String {
+    decode(s, false)
+}
+
+/// escape all the bytes of `s` but the unreserved ones, e.g. for a path segment
+pub fn percent_encode(s: &str) -> String {
+    encode(s, false)
+}
+
+/// decode an `application/x-www-form-urlencoded` name or value, `+` is a space
+pub fn form_decode(s: &str) -> String {
+    decode(s, true)
+}
+
+/// encode an `application/x-www-form-urlencoded` name or value
+pub fn form_encode(s: &str) -> String {
+    encode(s, true)
+}
+
+/// the limits of parsing the request forms
+#[derive(Debug, Clone)]
+pub struct FormConfig {
+    max_fields: usize,
+    max_form_size: usize,
+    max_part_size: u64,
+    max_part_header_bytes: usize,
+}
+
+impl Default for FormConfig {
+    fn default() -> Self {
+        FormConfig {
+            max_fields: 1000,
+            max_form_size: 2 * 1024 * 1024,
+            max_part_size: 32 * 1024 * 1024,
+            max_part_header_bytes: 8 * 1024,
+        }
+    }
+}
+
+impl FormConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// set the max number of the fields of a form, or the parts of a multipart body
+    pub fn set_max_fields(&mut self, n: usize) -> &mut Self {
+        self.max_fields = n;
+        self
+    }
+
+    pub fn max_fields(&self) -> usize {
+        self.max_fields
+    }
+
+    /// set the max size of an url encoded form body
+    pub fn set_max_form_size(&mut self, size: usize) -> &mut Self {
+        self.max_form_size = size;
+        self
+    }
+
+    pub fn max_form_size(&self) -> usize {
+        self.max_form_size
+    }
+
+    /// set the max content size of a multipart part
+    pub fn set_max_part_size(&mut self, size: u64) -> &mut Self {
+        self.max_part_size = size;
+        self
+    }
+
+    pub fn max_part_size(&self) -> u64 {
+        self.max_part_size
+    }
+
+    /// set the max bytes of the headers of a multipart part
+    pub fn set_max_part_header_bytes(&mut self, n: usize) -> &mut Self {
+        self.max_part_header_bytes = n;
+        self
+    }
+
+    pub fn max_part_header_bytes(&self) -> usize {
+        self.max_part_header_bytes
+    }
+}
+
+/// the parsed query string or url encoded form
+///
+/// it's a multi-map that keeps the order of the pairs
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Query(Vec<(String, String)>);
+
+/// the parsed `application/x-www-form-urlencoded` request body
+pub type Form = Query;
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// parse an `application/x-www-form-urlencoded` string, e.g. `a=1&b=x+y`
+    pub fn parse(s: &str) -> Self {
+        let pairs = s
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let mut kv = p.splitn(2, '=');
+                let k = kv.next().unwrap_or("");
+                let v = kv.next().unwrap_or("");
+                (form_decode(k), form_decode(v))
+            })
+            .collect();
+        Query(pairs)
+    }
+
+    /// read and parse the url encoded body of `req`
+    ///
+    /// an `InvalidData` error is returned if the `Content-Type` is not
+    /// `application/x-www-form-urlencoded` or a limit of `config` is exceeded
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::std::http::server::{Form, FormConfig, HttpServer, Router};
+    ///
+    /// let router = Router::new().post("/login", |mut req, rsp| {
+    ///     let form = Form::from_request(&mut req, &FormConfig::new())?;
+    ///     let user = form.get("user").unwrap_or("");
+    ///     rsp.body_vec(format!("hello {}", user).into_bytes());
+    ///     Ok(())
+    /// });
+    /// let server = HttpServer(router).start("127.0.0.1:8080").unwrap();
+    /// server.join().unwrap();
+    /// ```
+    pub fn from_request(req: &mut Request, config: &FormConfig) -> io::Result<Self> {
+        let content_type = req
+            .header("Content-Type")
+            .and_then(|v| str::from_utf8(v).ok())
+            .unwrap_or_default();
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        if !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return Err(invalid("not an url encoded form"));
+        }
+
+        let max = config.max_form_size;
+        let mut body = Vec::new();
+        Read::take(req.body_reader(), max as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > max {
+            return Err(invalid("form body is too large"));
+        }
+        let body = String::from_utf8_lossy(&body);
+        if body.split('&').filter(|p| !p.is_empty()).count() > config.max_fields {
+            return Err(invalid("too many form fields"));
+        }
+        Ok(Query::parse(&body))
+    }
+
+    /// get the first value of `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// get the first value of `name` parsed as `T`
+    pub fn get_as<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name).and_then(|v| v.parse().ok())
+    }
+
+    /// get all the values of `name`
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter().filter(move |(k, _)| *k == name).map(|(_, v)| v)
+    }
+
+    /// add a pair, the existing values of `name` are kept
+    pub fn append<K: Into<String>, V: Into<String>>(&mut self, name: K, value: V) -> &mut Self {
+        self.0.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Query {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Query(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        )
+    }
+}
+
+/// the `application/x-www-form-urlencoded` encoding, e.g. `a=1&b=x+y`
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (k, v)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("&")?;
+            }
+            write!(f, "{}={}", form_encode(k), form_encode(v))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::http::server::request::decode;
+    use bytes::BytesMut;
+
+    #[test]
+    fn query() {
+        let q = Query::parse("a=1&b=x+y%21&a=2&flag&bad=%zz");
+        assert_eq!(q.get("a"), Some("1"));
+        assert_eq!(q.get_all("a").collect::<Vec<_>>(), vec!["1", "2"]);
+        assert_eq!(q.get_as::<u32>("a"), Some(1));
+        assert_eq!(q.get("b"), Some("x y!"));
+        assert_eq!(q.get("flag"), Some(""));
+        assert_eq!(q.get("bad"), Some("%zz"));
+        assert_eq!(q.get("c"), None);
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(percent_decode("a+b%20c%E4%B8%AD"), "a+b c中");
+        assert_eq!(percent_encode("a b/中~"), "a%20b%2F%E4%B8%AD~");
+        assert_eq!(form_decode("a+b%2B"), "a b+");
+        assert_eq!(form_encode("a b+&="), "a+b%2B%26%3D");
+
+        let mut q: Query = vec![("name", "a b"), ("x", "1&2")].into_iter().collect();
+        q.append("x", "中");
+        assert_eq!(q.len(), 3);
+        let s = q.to_string();
+        assert_eq!(s, "name=a+b&x=1%262&x=%E4%B8%AD");
+        assert_eq!(Query::parse(&s), q);
+    }
+
+    #[test]
+    fn from_request() {
+        let req = |content_type: &str, body: &str| {
+            let raw = format!(
+                "POST / HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            );
+            decode(&mut BytesMut::from(raw.as_str())).unwrap().unwrap()
+        };
+        let config = FormConfig::new();
+        let mut r = req(
+            "application/x-www-form-urlencoded; charset=utf-8",
+            "a=1&b=%21",
+        );
+        let form = Form::from_request(&mut r, &config).unwrap();
+        assert_eq!((form.get("a"), form.get("b")), (Some("1"), Some("!")));
+
+        let mut r = req("text/plain", "a=1");
+        assert!(Form::from_request(&mut r, &config).is_err());
+
+        let mut config = FormConfig::new();
+        config.set_max_fields(2);
+        let mut r = req("application/x-www-form-urlencoded", "a=1&b=2&c=3");
+        let e = Form::from_request(&mut r, &config).unwrap_err();
+        assert_eq!(e.to_string(), "too many form fields");
+        config.set_max_form_size(4);
+        let mut r = req("application/x-www-form-urlencoded", "a=123");
+        let e = Form::from_request(&mut r, &config).unwrap_err();
+        assert_eq!(e.to_string(), "form body is too large");
+    }
+}
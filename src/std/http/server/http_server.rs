@@ -1,17 +1,24 @@
 //! http server implementation on top of `MAY`
 
-use std::io::{self, Read, Write};
+use std::io::{self, BufWriter, Write};
+#[cfg(unix)]
+use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::std::http::server::request::{self, Request};
-use crate::std::http::server::response::{self, Response};
-#[cfg(unix)]
-use bytes::Buf;
-use bytes::{BufMut, BytesMut};
 #[cfg(unix)]
 use crate::io::WaitIo;
+#[cfg(unix)]
+use crate::net::graceful::{Conn, Graceful};
 use crate::net::{TcpListener, TcpStream};
+use crate::std::http::server::request::{self, Decoded, Request};
+use crate::std::http::server::response::{self, Response, Tail};
+use crate::std::http::server::stream::{ChunkedWriter, LengthWriter, CHUNK_SIZE};
+use crate::std::http::server::{BodyFn, HttpServerConfig, StatusCode};
+use crate::std::sync::Semphore;
 use crate::{coroutine, go};
+use bytes::BytesMut;
 
 macro_rules! t {
     ($e: expr) => {
@@ -59,25 +66,202 @@ pub trait HttpServiceFactory: Send + Sized + 'static {
     /// Spawns the http service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
+        self.start_with_config(addr, HttpServerConfig::default())
+    }
+
+    /// Spawns the http service with the given config, binding to the given address
+    /// return a coroutine that you can cancel it when need to stop the service
+    fn start_with_config<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        config: HttpServerConfig,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
         let listener = TcpListener::bind(addr)?;
-        go!(
-            coroutine::Builder::new(),
-            move || {
-                for stream in listener.incoming() {
-                    let stream = t_c!(stream);
-                    let service = self.new_service();
-                    go!(move ||  each_connection_loop(stream, service));
-                }
-            }
-        )
+        let limit = conn_limit(&config);
+        spawn_acceptor(listener, Arc::new(config), limit, move || {
+            self.new_service()
+        })
+    }
+
+    /// Spawns the http service on `n` `SO_REUSEPORT` listeners, binding to the given address
+    /// each listener has its own accept coroutine that is run on a different worker
+    /// return the accept coroutines that you can cancel them when need to stop the service
+    #[cfg(unix)]
+    fn start_sharded<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        n: usize,
+    ) -> io::Result<Vec<coroutine::JoinHandle<()>>>
+    where
+        Self: Sync,
+    {
+        let factory = Arc::new(self);
+        let config = Arc::new(HttpServerConfig::default());
+        TcpListener::bind_sharded(addr, n)?
+            .into_iter()
+            .map(|listener| {
+                let factory = factory.clone();
+                spawn_acceptor(listener, config.clone(), None, move || {
+                    factory.new_service()
+                })
+            })
+            .collect()
+    }
+
+    /// Spawns the http service on an existing listener, e.g. one inherited from the old process
+    /// the accept coroutine is stopped by `graceful.shutdown()` and the connections are
+    /// tracked by `graceful` so that they could be drained by `graceful.wait()`
+    #[cfg(unix)]
+    fn start_graceful(
+        self,
+        listener: TcpListener,
+        graceful: &Graceful,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
+        let config = HttpServerConfig::default();
+        spawn_graceful(listener, graceful, config, move || self.new_service())
+    }
+
+    /// Spawns the http service with the given config, binding to the given address
+    /// return a handle that could shut down the server gracefully
+    #[cfg(unix)]
+    fn serve<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        config: HttpServerConfig,
+    ) -> io::Result<ServerHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let graceful = Graceful::new();
+        spawn_graceful(listener, &graceful, config, move || self.new_service())?;
+        Ok(ServerHandle { graceful, addr })
+    }
+}
+
+/// the handle of a server spawned by `serve`
+#[cfg(unix)]
+#[derive(Clone)]
+pub struct ServerHandle {
+    graceful: Graceful,
+    addr: SocketAddr,
+}
+
+#[cfg(unix)]
+impl ServerHandle {
+    /// the address that the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// stop accepting and wait for the in-flight requests to finish
+    ///
+    /// the idle connections are closed at once and the busy ones after their
+    /// current requests. the connections that are still open at `deadline`
+    /// are shut down forcibly and `false` is returned
+    pub fn shutdown(&self, deadline: Instant) -> bool {
+        self.graceful.shutdown();
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.graceful.wait(timeout)
+    }
+
+    /// the number of the connections that are not closed yet
+    pub fn active(&self) -> usize {
+        self.graceful.active()
+    }
+}
+
+// the accept permit of a connection, it's returned on drop
+struct ConnPermit(Option<Arc<Semphore>>);
+
+impl Drop for ConnPermit {
+    fn drop(&mut self) {
+        if let Some(ref limit) = self.0 {
+            limit.post();
+        }
+    }
+}
+
+// the limit of the concurrent connections that is shared by the accept loops
+fn conn_limit(config: &HttpServerConfig) -> Option<Arc<Semphore>> {
+    config.max_connections().map(|n| Arc::new(Semphore::new(n)))
+}
+
+// accept the connections, `f` should keep the permit until the connection is closed
+fn accept_loop<F>(listener: TcpListener, limit: Option<Arc<Semphore>>, mut f: F)
+where
+    F: FnMut(TcpStream, ConnPermit),
+{
+    loop {
+        if let Some(ref limit) = limit {
+            limit.wait();
+        }
+        let permit = ConnPermit(limit.clone());
+        let (stream, _) = t_c!(listener.accept());
+        f(stream, permit);
     }
 }
 
+// spawn the accept coroutine that serves each connection by a new service
+fn spawn_acceptor<S, F>(
+    listener: TcpListener,
+    config: Arc<HttpServerConfig>,
+    limit: Option<Arc<Semphore>>,
+    mut new_service: F,
+) -> io::Result<coroutine::JoinHandle<()>>
+where
+    S: HttpService + Send + 'static,
+    F: FnMut() -> S + Send + 'static,
+{
+    go!(coroutine::Builder::new(), move || {
+        accept_loop(listener, limit, |stream, permit| {
+            let service = new_service();
+            let config = config.clone();
+            go!(move || {
+                let _permit = permit;
+                each_connection_loop(stream, service, config)
+            });
+        })
+    })
+}
+
+// spawn the accept coroutine that is stopped by `graceful`
+#[cfg(unix)]
+fn spawn_graceful<S, F>(
+    listener: TcpListener,
+    graceful: &Graceful,
+    config: HttpServerConfig,
+    mut new_service: F,
+) -> io::Result<coroutine::JoinHandle<()>>
+where
+    S: HttpService + Send + 'static,
+    F: FnMut() -> S + Send + 'static,
+{
+    let g = graceful.clone();
+    let limit = conn_limit(&config);
+    let config = Arc::new(config);
+    graceful.spawn_acceptor(move || {
+        accept_loop(listener, limit, |stream, permit| {
+            let conn = match g.track(&stream) {
+                Ok(conn) => conn,
+                Err(err) => {
+                    error!("track connection err = {:?}", err);
+                    return;
+                }
+            };
+            let service = new_service();
+            let config = config.clone();
+            go!(move || {
+                let _permit = permit;
+                connection_loop(stream, service, Some(&conn), &config)
+            });
+        })
+    })
+}
+
 fn internal_error_rsp(e: io::Error, buf: &mut BytesMut) -> Response {
     //error!("error in service: err = {:?}", e);
     buf.clear();
     let mut err_rsp = Response::new(buf);
-    err_rsp.status_code("500", "Internal Server Error");
+    err_rsp.status(StatusCode::InternalServerError);
     err_rsp
         .body_mut()
         .extend_from_slice(e.to_string().as_bytes());
@@ -89,12 +273,230 @@ fn internal_error_rsp(e: io::Error, buf: &mut BytesMut) -> Response {
 ///
 pub struct HttpServer<T>(pub T);
 
+// write all the pending responses
+fn flush(stream: &mut TcpStream, rsp_buf: &mut BytesMut) -> io::Result<()> {
+    #[cfg(unix)]
+    stream.set_nonblocking(false)?;
+    let ret = stream.write_all(rsp_buf);
+    rsp_buf.clear();
+    #[cfg(unix)]
+    stream.set_nonblocking(true)?;
+    ret
+}
+
+// write the rsps in `rsp_buf` and then the streaming body, the socket is
+// blocking so that the body writer is held back by a slow client
+fn write_body_stream(
+    stream: &mut TcpStream,
+    rsp_buf: &mut BytesMut,
+    body: BodyFn,
+    len: Option<u64>,
+    chunked: bool,
+) -> io::Result<()> {
+    #[cfg(unix)]
+    stream.set_nonblocking(false)?;
+    let ret = (|| {
+        stream.write_all(rsp_buf)?;
+        if let Some(len) = len {
+            let buf = BufWriter::with_capacity(CHUNK_SIZE, &mut *stream);
+            let mut w = LengthWriter::new(buf, len);
+            body(&mut w)?;
+            w.finish()
+        } else if chunked {
+            let mut w = ChunkedWriter(&mut *stream);
+            let mut buf = BufWriter::with_capacity(CHUNK_SIZE, &mut w);
+            body(&mut buf)?;
+            buf.flush()?;
+            drop(buf);
+            w.finish()
+        } else {
+            let mut buf = BufWriter::with_capacity(CHUNK_SIZE, &mut *stream);
+            body(&mut buf)?;
+            buf.flush()
+        }
+    })();
+    rsp_buf.clear();
+    #[cfg(unix)]
+    stream.set_nonblocking(true)?;
+    ret
+}
+
+// encode an error rsp that closes the connection
+fn close_rsp(status: StatusCode, body_buf: &mut BytesMut, rsp_buf: &mut BytesMut) {
+    body_buf.clear();
+    let mut rsp = Response::new(body_buf);
+    rsp.status(status).header("Connection: close");
+    response::encode(rsp, rsp_buf);
+}
+
+// the request processing state of a connection
+#[derive(Default)]
+struct ConnState {
+    // `100 Continue` is sent for the pending request
+    continue_sent: bool,
+    // the number of the served requests
+    requests: usize,
+}
+
+// decode the reqs in `req_buf`, call the service and encode the rsps into `rsp_buf`
+// return false if the connection should be closed after the rsps are written
+fn process_reqs<T: HttpService>(
+    stream: &mut TcpStream,
+    service: &mut T,
+    config: &HttpServerConfig,
+    state: &mut ConnState,
+    req_buf: &mut BytesMut,
+    rsp_buf: &mut BytesMut,
+    body_buf: &mut BytesMut,
+) -> io::Result<bool> {
+    loop {
+        let req = match request::decode_with(req_buf, config, Some(&*stream))? {
+            Decoded::Request(req) => req,
+            Decoded::Partial { expect_continue } => {
+                if expect_continue && !state.continue_sent {
+                    state.continue_sent = true;
+                    rsp_buf.extend_from_slice(b"HTTP/1.1 100 Continue\r\n\r\n");
+                }
+                return Ok(true);
+            }
+            Decoded::TooLarge => {
+                close_rsp(StatusCode::PayloadTooLarge, body_buf, rsp_buf);
+                return Ok(false);
+            }
+            Decoded::HeadersTooLarge => {
+                close_rsp(StatusCode::RequestHeaderFieldsTooLarge, body_buf, rsp_buf);
+                return Ok(false);
+            }
+            Decoded::BadRequest => {
+                close_rsp(StatusCode::BadRequest, body_buf, rsp_buf);
+                return Ok(false);
+            }
+        };
+        state.continue_sent = false;
+
+        let leftover = req.body_leftover();
+        if leftover.is_some() {
+            // the service would block on reading the body
+            flush(stream, rsp_buf)?;
+        }
+
+        let version = req.version();
+        let head = req.method() == "HEAD";
+        let mut rsp = Response::new(body_buf);
+        let ret = service.call(req, &mut rsp);
+        // the streamed body is over the limit, whatever the service replied
+        if let Some(ref leftover) = leftover {
+            if leftover.lock().unwrap_or_else(|e| e.into_inner()).too_large {
+                close_rsp(StatusCode::PayloadTooLarge, body_buf, rsp_buf);
+                return Ok(false);
+            }
+        }
+        let mut rsp = match ret {
+            Ok(_) => rsp,
+            Err(e) => internal_error_rsp(e, body_buf),
+        };
+        state.requests += 1;
+        let last = config
+            .max_requests_per_connection()
+            .map_or(false, |n| state.requests >= n);
+        if last && !rsp.is_upgrade() {
+            rsp.header("Connection: close");
+        }
+        // HTTP/1.0 clients don't know the chunked coding
+        let chunked = version == 1;
+        match response::encode_stream(rsp, rsp_buf, chunked, head) {
+            Some(Tail::Stream(body, len)) => {
+                if let Err(e) = write_body_stream(stream, rsp_buf, body, len, chunked) {
+                    error!("write streaming body err = {:?}", e);
+                    return Ok(false);
+                }
+                if len.is_none() && !chunked {
+                    return Ok(false);
+                }
+            }
+            Some(Tail::Upgrade(f)) => {
+                flush(stream, rsp_buf)?;
+                // the bytes after the req already belong to the new protocol
+                let buf = req_buf.split();
+                f(stream.try_clone()?, buf);
+                return Ok(false);
+            }
+            None => {}
+        }
+
+        // take back the bytes that the streamed body reader read after the body
+        if let Some(leftover) = leftover {
+            let leftover = match Arc::try_unwrap(leftover) {
+                Ok(l) => l.into_inner().unwrap_or_else(|e| e.into_inner()),
+                // the body reader is still alive in somewhere else
+                Err(_) => return Ok(false),
+            };
+            if !leftover.complete {
+                return Ok(false);
+            }
+            if !leftover.data.is_empty() {
+                let mut data = leftover.data;
+                data.extend_from_slice(req_buf);
+                *req_buf = data;
+            }
+        }
+
+        if last {
+            return Ok(false);
+        }
+    }
+}
+
+// the client is too slow, reply `408 Request Timeout` if a req is pending
+fn request_timeout(
+    stream: &mut TcpStream,
+    req_buf: &BytesMut,
+    body_buf: &mut BytesMut,
+    rsp_buf: &mut BytesMut,
+) {
+    if !req_buf.is_empty() {
+        close_rsp(StatusCode::RequestTimeout, body_buf, rsp_buf);
+        flush(stream, rsp_buf).ok();
+    }
+}
+
+// the timeout of waiting for more request data, `since` is when the pending
+// request started to arrive, or when the connection became idle
+fn read_timeout(config: &HttpServerConfig, req_buf: &BytesMut, since: Instant) -> Option<Duration> {
+    let timeout = if req_buf.is_empty() {
+        config.keep_alive_timeout()
+    } else {
+        config.header_read_timeout()
+    };
+    timeout.map(|t| t.checked_sub(since.elapsed()).unwrap_or_default())
+}
+
+#[cfg(unix)]
+fn each_connection_loop<T: HttpService>(
+    stream: TcpStream,
+    service: T,
+    config: Arc<HttpServerConfig>,
+) {
+    connection_loop(stream, service, None, &config)
+}
+
 #[cfg(unix)]
-fn each_connection_loop<T: HttpService>(mut stream: TcpStream, mut service: T) {
+fn connection_loop<T: HttpService>(
+    mut stream: TcpStream,
+    mut service: T,
+    conn: Option<&Conn>,
+    config: &HttpServerConfig,
+) {
     let mut req_buf = BytesMut::with_capacity(4096 * 8);
     let mut rsp_buf = BytesMut::with_capacity(4096 * 32);
     let mut body_buf = BytesMut::with_capacity(4096 * 8);
+    let mut state = ConnState::default();
+    let mut since = Instant::now();
     stream.set_nonblocking(true).unwrap();
+    // the clones of the stream inherit it
+    if stream.set_write_timeout(config.write_timeout()).is_err() {
+        return;
+    }
     loop {
         stream.reset_io();
         loop {
@@ -104,15 +506,18 @@ fn each_connection_loop<T: HttpService>(mut stream: TcpStream, mut service: T) {
                 req_buf.reserve(4096 * 8 - remaining);
             }
 
-            let buf = req_buf.chunk_mut();
-            let read_buf = unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) };
-            match stream.read(read_buf) {
+            let was_empty = req_buf.is_empty();
+            match stream.read_buf(&mut req_buf) {
                 Ok(n) => {
                     if n == 0 {
                         //connection was closed
                         return;
-                    } else {
-                        unsafe { req_buf.advance_mut(n) };
+                    }
+                    if was_empty {
+                        since = Instant::now();
+                    }
+                    if let Some(conn) = conn {
+                        conn.set_idle(false);
                     }
                 }
                 Err(err) => {
@@ -136,58 +541,92 @@ fn each_connection_loop<T: HttpService>(mut stream: TcpStream, mut service: T) {
         }
 
         // prepare the reqs
-        while let Some(req) = t!(request::decode(&mut req_buf)) {
-            let mut rsp = Response::new(&mut body_buf);
-            if let Err(e) = service.call(req, &mut rsp) {
-                let err_rsp = internal_error_rsp(e, &mut body_buf);
-                response::encode(err_rsp, &mut rsp_buf);
-            } else {
-                response::encode(rsp, &mut rsp_buf);
-            }
+        let served = state.requests;
+        let keep_alive = t!(process_reqs(
+            &mut stream,
+            &mut service,
+            config,
+            &mut state,
+            &mut req_buf,
+            &mut rsp_buf,
+            &mut body_buf,
+        ));
+        if state.requests != served {
+            since = Instant::now();
+        }
+        if !keep_alive {
+            t!(flush(&mut stream, &mut rsp_buf));
+            return;
         }
 
-        if !rsp_buf.is_empty() {
-            let len = rsp_buf.len();
-            let mut written = 0;
-            while written < len {
-                match stream.write(&rsp_buf[written..]) {
-                    Ok(n) => {
-                        if n == 0 {
-                            return;
-                        } else {
-                            written += n;
-                        }
+        // the written data is consumed from the rsp_buf
+        while !rsp_buf.is_empty() {
+            match stream.write_buf(&mut rsp_buf) {
+                Ok(n) => {
+                    if n == 0 {
+                        return;
                     }
-                    Err(err) => {
-                        if err.kind() == io::ErrorKind::WouldBlock {
-                            break;
-                        } else if err.kind() == io::ErrorKind::ConnectionReset
-                            || err.kind() == io::ErrorKind::UnexpectedEof
-                        {
-                            error!("http server read req: connection closed");
-                            return;
-                        }
-                        error!("call = {:?}\nerr = {:?}", stringify!($e), err);
+                }
+                Err(err) => {
+                    if err.kind() == io::ErrorKind::WouldBlock {
+                        break;
+                    } else if err.kind() == io::ErrorKind::ConnectionReset
+                        || err.kind() == io::ErrorKind::UnexpectedEof
+                    {
+                        error!("http server read req: connection closed");
                         return;
                     }
+                    error!("call = {:?}\nerr = {:?}", stringify!($e), err);
+                    return;
                 }
             }
-            if written == len {
-                unsafe { rsp_buf.set_len(0) }
-            } else if written > 0 {
-                rsp_buf.advance(written);
+        }
+
+        // no request in progress, exit if the server is shutting down
+        if let Some(conn) = conn {
+            if req_buf.is_empty() && rsp_buf.is_empty() {
+                conn.set_idle(true);
+                if conn.is_shutdown() {
+                    return;
+                }
             }
         }
 
-        stream.wait_io();
+        // wait for the rsps to be written
+        if !rsp_buf.is_empty() {
+            if stream.wait_writable(config.write_timeout()).is_err() {
+                return;
+            }
+            continue;
+        }
+        match read_timeout(config, &req_buf, since) {
+            None => stream.wait_io(),
+            Some(timeout) => match stream.wait_readable(Some(timeout)) {
+                Ok(_) => {}
+                Err(err) => {
+                    if err.kind() == io::ErrorKind::TimedOut {
+                        request_timeout(&mut stream, &req_buf, &mut body_buf, &mut rsp_buf);
+                    }
+                    return;
+                }
+            },
+        }
     }
 }
 
 #[cfg(not(unix))]
-fn each_connection_loop<T: HttpService>(mut stream: TcpStream, mut service: T) {
+fn each_connection_loop<T: HttpService>(
+    mut stream: TcpStream,
+    mut service: T,
+    config: Arc<HttpServerConfig>,
+) {
     let mut req_buf = BytesMut::with_capacity(4096 * 8);
     let mut rsp_buf = BytesMut::with_capacity(4096 * 32);
     let mut body_buf = BytesMut::with_capacity(4096 * 8);
+    let mut state = ConnState::default();
+    let mut since = Instant::now();
+    // the clones of the stream inherit it
+    t!(stream.set_write_timeout(config.write_timeout()));
     loop {
         // read the socket for reqs
         let remaining = req_buf.capacity() - req_buf.len();
@@ -195,31 +634,51 @@ fn each_connection_loop<T: HttpService>(mut stream: TcpStream, mut service: T) {
             req_buf.reserve(4096 * 8 - remaining);
         }
 
-        let n = {
-            let buf = req_buf.chunk_mut();
-            let read_buf = unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) };
-            t!(stream.read(read_buf))
+        // zero is not a valid read timeout
+        let timeout = read_timeout(&config, &req_buf, since)
+            .map(|t| std::cmp::max(t, Duration::from_millis(1)));
+        t!(stream.set_read_timeout(timeout));
+        let was_empty = req_buf.is_empty();
+        let n = match stream.read_buf(&mut req_buf) {
+            Ok(n) => n,
+            Err(err)
+                if err.kind() == io::ErrorKind::TimedOut
+                    || err.kind() == io::ErrorKind::WouldBlock =>
+            {
+                request_timeout(&mut stream, &req_buf, &mut body_buf, &mut rsp_buf);
+                return;
+            }
+            Err(err) => t!(Err::<usize, _>(err)),
         };
         //connection was closed
         if n == 0 {
             return;
         }
-        unsafe { req_buf.advance_mut(n) };
+        if was_empty {
+            since = Instant::now();
+        }
 
         // prepare the reqs
-        while let Some(req) = t!(request::decode(&mut req_buf)) {
-            let mut rsp = Response::new(&mut body_buf);
-            if let Err(e) = service.call(req, &mut rsp) {
-                let err_rsp = internal_error_rsp(e, &mut body_buf);
-                response::encode(err_rsp, &mut rsp_buf);
-            } else {
-                response::encode(rsp, &mut rsp_buf);
-            }
+        let served = state.requests;
+        let keep_alive = t!(process_reqs(
+            &mut stream,
+            &mut service,
+            &config,
+            &mut state,
+            &mut req_buf,
+            &mut rsp_buf,
+            &mut body_buf,
+        ));
+        if state.requests != served {
+            since = Instant::now();
         }
 
         // send the result back to client
         t!(stream.write_all(rsp_buf.as_ref()));
         rsp_buf.clear();
+        if !keep_alive {
+            return;
+        }
     }
 }
 
@@ -227,17 +686,656 @@ impl<T: HttpService + Clone + Send + Sync + 'static> HttpServer<T> {
     /// Spawns the http service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
+        self.start_with_config(addr, HttpServerConfig::default())
+    }
+
+    /// Spawns the http service with the given config, binding to the given address
+    /// return a coroutine that you can cancel it when need to stop the service
+    pub fn start_with_config<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        config: HttpServerConfig,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
         let listener = TcpListener::bind(addr)?;
         let service = self.0;
-        go!(
-            coroutine::Builder::new(),
-            move || {
-                for stream in listener.incoming() {
-                    let stream = t_c!(stream);
-                    let service = service.clone();
-                    go!(move || each_connection_loop(stream, service));
+        let limit = conn_limit(&config);
+        spawn_acceptor(listener, Arc::new(config), limit, move || service.clone())
+    }
+
+    /// Spawns the http service on `n` `SO_REUSEPORT` listeners, binding to the given address
+    /// return the accept coroutines that you can cancel them when need to stop the service
+    #[cfg(unix)]
+    pub fn start_sharded<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        n: usize,
+    ) -> io::Result<Vec<coroutine::JoinHandle<()>>> {
+        let config = Arc::new(HttpServerConfig::default());
+        TcpListener::bind_sharded(addr, n)?
+            .into_iter()
+            .map(|listener| {
+                let service = self.0.clone();
+                spawn_acceptor(listener, config.clone(), None, move || service.clone())
+            })
+            .collect()
+    }
+
+    /// Spawns the http service on an existing listener, e.g. one inherited from the old process
+    /// the accept coroutine is stopped by `graceful.shutdown()` and the connections are
+    /// tracked by `graceful` so that they could be drained by `graceful.wait()`
+    #[cfg(unix)]
+    pub fn start_graceful(
+        self,
+        listener: TcpListener,
+        graceful: &Graceful,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
+        let service = self.0;
+        let config = HttpServerConfig::default();
+        spawn_graceful(listener, graceful, config, move || service.clone())
+    }
+
+    /// Spawns the http service with the given config, binding to the given address
+    /// return a handle that could shut down the server gracefully
+    #[cfg(unix)]
+    pub fn serve<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        config: HttpServerConfig,
+    ) -> io::Result<ServerHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let graceful = Graceful::new();
+        let service = self.0;
+        spawn_graceful(listener, &graceful, config, move || service.clone())?;
+        Ok(ServerHandle { graceful, addr })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::http::server::{Message, SseEvent, WebSocket};
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::SocketAddr;
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl HttpService for Echo {
+        fn call(&mut self, mut req: Request, rsp: &mut Response) -> io::Result<()> {
+            let mut body = req.body().to_vec();
+            if req.body_reader().is_streamed() {
+                req.body_reader().read_to_end(&mut body)?;
+            }
+            if let Some(v) = req.header("x-echo") {
+                let v = String::from_utf8_lossy(v).into_owned();
+                rsp.add_header("X-Echo", v)?;
+            }
+            rsp.body_vec(body);
+            Ok(())
+        }
+    }
+
+    // serve one connection with the `Echo` service
+    fn serve(config: HttpServerConfig) -> SocketAddr {
+        serve_with(Echo, config)
+    }
+
+    // serve one connection with `service`
+    fn serve_with<T: HttpService + Send + 'static>(
+        service: T,
+        config: HttpServerConfig,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        go!(move || {
+            let (stream, _) = listener.accept().unwrap();
+            each_connection_loop(stream, service, Arc::new(config));
+        });
+        addr
+    }
+
+    // decode a chunked body
+    fn read_chunked<R: BufRead>(r: &mut R) -> Vec<u8> {
+        let mut body = Vec::new();
+        loop {
+            let mut line = String::new();
+            r.read_line(&mut line).unwrap();
+            let len = usize::from_str_radix(line.trim_end(), 16).unwrap();
+            let mut chunk = vec![0; len + 2];
+            r.read_exact(&mut chunk).unwrap();
+            assert_eq!(&chunk[len..], b"\r\n");
+            if len == 0 {
+                return body;
+            }
+            body.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    struct Streams;
+
+    impl HttpService for Streams {
+        fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+            match req.path() {
+                "/iter" => rsp.body_iter(vec!["hello", " ", "world"].into_iter().map(Ok)),
+                "/large" => rsp.body_stream(|w| {
+                    for i in 0..1000 {
+                        writeln!(w, "line {}", i)?;
+                    }
+                    Ok(())
+                }),
+                "/sized" => rsp.body_stream_len(5, |w| w.write_all(b"hello")),
+                "/huge" => rsp.body_stream(|w| {
+                    let buf = vec![b'x'; 64 * 1024];
+                    for _ in 0..1024 {
+                        w.write_all(&buf)?;
+                    }
+                    Ok(())
+                }),
+                "/short" => rsp.body_stream_len(10, |w| w.write_all(b"hello")),
+                "/events" => {
+                    let (tx, rx) = crate::std::sync::channel();
+                    go!(move || {
+                        tx.send(SseEvent::new("1")).unwrap();
+                        coroutine::sleep(Duration::from_millis(100));
+                        tx.send(SseEvent::new("2").event("x")).unwrap();
+                    });
+                    rsp.sse(rx, Some(Duration::from_millis(30)));
+                }
+                "/ws" => {
+                    WebSocket::upgrade(&req, rsp, |mut ws| {
+                        while let Ok(msg) = ws.recv() {
+                            match msg {
+                                Message::Close(_) => break,
+                                msg => ws.send(msg).unwrap(),
+                            }
+                        }
+                    });
+                }
+                _ => rsp.body("done"),
+            }
+            Ok(())
+        }
+    }
+
+    // read a response, return the status line and the body
+    fn read_rsp<R: BufRead>(r: &mut R) -> (String, Vec<u8>) {
+        read_rsp_headers(r, &mut Vec::new())
+    }
+
+    // read a response, the header lines are pushed to `headers`
+    fn read_rsp_headers<R: BufRead>(r: &mut R, headers: &mut Vec<String>) -> (String, Vec<u8>) {
+        let mut status = String::new();
+        r.read_line(&mut status).unwrap();
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            r.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(v) = line.strip_prefix("Content-Length: ") {
+                len = v.trim().parse().unwrap();
+            }
+            headers.push(line.trim_end().to_owned());
+        }
+        let mut body = vec![0; len];
+        r.read_exact(&mut body).unwrap();
+        (status.trim_end().to_owned(), body)
+    }
+
+    #[test]
+    fn request_body() {
+        let addr = serve(HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        // pipelined reqs with both framings
+        s.write_all(
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+              POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n\
+              GET / HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        let mut r = BufReader::new(s);
+        assert_eq!(
+            read_rsp(&mut r),
+            ("HTTP/1.1 200 Ok".to_owned(), b"hello".to_vec())
+        );
+        assert_eq!(read_rsp(&mut r).1, b"abcde");
+        assert_eq!(read_rsp(&mut r).1, b"");
+    }
+
+    #[test]
+    fn expect_continue() {
+        let addr = serve(HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"POST / HTTP/1.1\r\nContent-Length: 4\r\nExpect: 100-continue\r\n\r\n")
+            .unwrap();
+        let mut r = BufReader::new(s.try_clone().unwrap());
+        let mut line = String::new();
+        r.read_line(&mut line).unwrap();
+        assert_eq!(line, "HTTP/1.1 100 Continue\r\n");
+        r.read_line(&mut line).unwrap();
+        s.write_all(b"data").unwrap();
+        assert_eq!(read_rsp(&mut r).1, b"data");
+    }
+
+    #[test]
+    fn body_too_large() {
+        let mut config = HttpServerConfig::new();
+        config.set_max_body_size(16);
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n")
+            .unwrap();
+        let mut r = BufReader::new(s);
+        let (status, _) = read_rsp(&mut r);
+        assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+        // the connection is closed
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn streamed_body_too_large() {
+        let mut config = HttpServerConfig::new();
+        config.set_body_buffer_size(1024);
+        config.set_max_body_size(4096);
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        let chunk = format!("3e8\r\n{}\r\n", "x".repeat(1000));
+        // over the buffer size, the rest is read by the streamed body reader
+        s.write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .unwrap();
+        s.write_all(chunk.repeat(2).as_bytes()).unwrap();
+        coroutine::sleep(Duration::from_millis(50));
+        s.write_all(format!("{}0\r\n\r\n", chunk.repeat(3)).as_bytes())
+            .unwrap();
+        let mut r = BufReader::new(s);
+        let (status, _) = read_rsp(&mut r);
+        assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn chunked_with_content_length() {
+        let addr = serve(HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n\
+              0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        let mut r = BufReader::new(s);
+        let mut headers = Vec::new();
+        let (status, _) = read_rsp_headers(&mut r, &mut headers);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(headers.contains(&"Connection: close".to_owned()));
+        // the connection is closed, nothing else is served
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn streamed_body() {
+        let mut config = HttpServerConfig::new();
+        config.set_body_buffer_size(1024);
+        let addr = serve(config);
+        let s = std::net::TcpStream::connect(addr).unwrap();
+        let body: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let writer = {
+            let mut s = s.try_clone().unwrap();
+            let body = body.clone();
+            std::thread::spawn(move || {
+                let head = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", body.len());
+                s.write_all(head.as_bytes()).unwrap();
+                s.write_all(&body).unwrap();
+                // the chunked one follows on the same connection
+                s.write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")
+                    .unwrap();
+                for c in body.chunks(3000) {
+                    s.write_all(format!("{:x}\r\n", c.len()).as_bytes())
+                        .unwrap();
+                    s.write_all(c).unwrap();
+                    s.write_all(b"\r\n").unwrap();
                 }
+                s.write_all(b"0\r\n\r\nGET / HTTP/1.1\r\n\r\n").unwrap();
+            })
+        };
+        let mut r = BufReader::new(s.try_clone().unwrap());
+        assert!(read_rsp(&mut r).1 == body);
+        assert!(read_rsp(&mut r).1 == body);
+        assert_eq!(read_rsp(&mut r).1, b"");
+        writer.join().unwrap();
+        s.shutdown(std::net::Shutdown::Both).unwrap();
+    }
+
+    #[test]
+    fn many_headers() {
+        let mut config = HttpServerConfig::new();
+        config.set_max_headers(30);
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        let mut req = String::from("GET / HTTP/1.1\r\n");
+        for i in 0..25 {
+            req.push_str(&format!("X-Header-{}: {}\r\n", i, i));
+        }
+        req.push_str("x-ECHO: hello\r\n\r\n");
+        s.write_all(req.as_bytes()).unwrap();
+        let mut r = BufReader::new(s.try_clone().unwrap());
+        let mut headers = Vec::new();
+        read_rsp_headers(&mut r, &mut headers);
+        assert!(headers.contains(&"X-Echo: hello".to_owned()));
+
+        // over the limit
+        let mut req = String::from("GET / HTTP/1.1\r\n");
+        for i in 0..31 {
+            req.push_str(&format!("X-Header-{}: {}\r\n", i, i));
+        }
+        req.push_str("\r\n");
+        s.write_all(req.as_bytes()).unwrap();
+        let (status, _) = read_rsp(&mut r);
+        assert_eq!(status, "HTTP/1.1 431 Request Header Fields Too Large");
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn few_headers() {
+        let mut config = HttpServerConfig::new();
+        config.set_max_headers(5);
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        let mut req = String::from("GET / HTTP/1.1\r\n");
+        for i in 0..4 {
+            req.push_str(&format!("X-Header-{}: {}\r\n", i, i));
+        }
+        req.push_str("x-ECHO: hello\r\n\r\n");
+        s.write_all(req.as_bytes()).unwrap();
+        let mut r = BufReader::new(s.try_clone().unwrap());
+        let mut headers = Vec::new();
+        read_rsp_headers(&mut r, &mut headers);
+        assert!(headers.contains(&"X-Echo: hello".to_owned()));
+
+        // under the fixed 16 headers, but over the limit
+        let mut req = String::from("GET / HTTP/1.1\r\n");
+        for i in 0..10 {
+            req.push_str(&format!("X-Header-{}: {}\r\n", i, i));
+        }
+        req.push_str("\r\n");
+        s.write_all(req.as_bytes()).unwrap();
+        let (status, _) = read_rsp(&mut r);
+        assert_eq!(status, "HTTP/1.1 431 Request Header Fields Too Large");
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut buf = BytesMut::new();
+        let mut rsp = Response::new(&mut buf);
+        assert!(rsp.add_header("X-Ok", "a\r\nSet-Cookie: x").is_err());
+        assert!(rsp.add_header("Bad Name", "a").is_err());
+        assert!(rsp.add_header("", "a").is_err());
+        rsp.add_header("X-Ok", format!("{}", 1)).unwrap();
+    }
+
+    #[test]
+    fn keep_alive_timeout() {
+        let mut config = HttpServerConfig::new();
+        config.set_keep_alive_timeout(Some(Duration::from_millis(100)));
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut r = BufReader::new(s);
+        read_rsp(&mut r);
+        let start = Instant::now();
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn header_read_timeout() {
+        let mut config = HttpServerConfig::new();
+        config.set_header_read_timeout(Some(Duration::from_millis(100)));
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n").unwrap();
+        let mut r = BufReader::new(s);
+        let (status, _) = read_rsp(&mut r);
+        assert_eq!(status, "HTTP/1.1 408 Request Timeout");
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn body_read_timeout() {
+        let mut config = HttpServerConfig::new();
+        config.set_body_buffer_size(16);
+        config.set_body_read_timeout(Some(Duration::from_millis(100)));
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        // the rest of the streamed body never comes
+        s.write_all(b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n0123456789abcdefg")
+            .unwrap();
+        s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut r = BufReader::new(s);
+        let start = Instant::now();
+        let (status, _) = read_rsp(&mut r);
+        assert_eq!(status, "HTTP/1.1 500 Internal Server Error");
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn write_timeout() {
+        let mut config = HttpServerConfig::new();
+        config.set_write_timeout(Some(Duration::from_millis(100)));
+        let addr = serve_with(Streams, config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"GET /huge HTTP/1.1\r\n\r\n").unwrap();
+        // the client doesn't read, the server gives up the rsp
+        std::thread::sleep(Duration::from_millis(500));
+        s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut data = Vec::new();
+        s.read_to_end(&mut data).unwrap();
+        assert!(data.len() < 64 * 1024 * 1024);
+    }
+
+    #[test]
+    fn max_header_bytes() {
+        let mut config = HttpServerConfig::new();
+        config.set_max_header_bytes(1024);
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        let req = format!("GET / HTTP/1.1\r\nX-Big: {}", "a".repeat(2000));
+        s.write_all(req.as_bytes()).unwrap();
+        let mut r = BufReader::new(s);
+        let (status, _) = read_rsp(&mut r);
+        assert_eq!(status, "HTTP/1.1 431 Request Header Fields Too Large");
+    }
+
+    #[test]
+    fn max_requests_per_connection() {
+        let mut config = HttpServerConfig::new();
+        config.set_max_requests_per_connection(Some(2));
+        let addr = serve(config);
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"GET / HTTP/1.1\r\n\r\n".repeat(3).as_slice())
+            .unwrap();
+        let mut r = BufReader::new(s);
+        let mut headers = Vec::new();
+        read_rsp_headers(&mut r, &mut headers);
+        assert!(!headers.contains(&"Connection: close".to_owned()));
+        headers.clear();
+        read_rsp_headers(&mut r, &mut headers);
+        assert!(headers.contains(&"Connection: close".to_owned()));
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn max_connections() {
+        let mut config = HttpServerConfig::new();
+        config.set_max_connections(Some(1));
+        let server = HttpServer(Echo).serve("127.0.0.1:0", config).unwrap();
+        let addr = server.local_addr();
+
+        let mut a = std::net::TcpStream::connect(addr).unwrap();
+        a.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        read_rsp(&mut BufReader::new(&a));
+
+        // not accepted until the first one is closed
+        let mut b = std::net::TcpStream::connect(addr).unwrap();
+        b.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        b.set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        assert!(b.read(&mut [0; 1]).is_err());
+        drop(a);
+        b.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (status, _) = read_rsp(&mut BufReader::new(&b));
+        assert_eq!(status, "HTTP/1.1 200 Ok");
+        assert!(server.shutdown(Instant::now() + Duration::from_secs(5)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shutdown() {
+        let server = HttpServer(Echo)
+            .serve("127.0.0.1:0", HttpServerConfig::new())
+            .unwrap();
+        let mut s = std::net::TcpStream::connect(server.local_addr()).unwrap();
+        s.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut r = BufReader::new(s);
+        read_rsp(&mut r);
+        assert_eq!(server.active(), 1);
+
+        // the idle connection is closed
+        assert!(server.shutdown(Instant::now() + Duration::from_secs(5)));
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+        assert_eq!(server.active(), 0);
+    }
+
+    #[test]
+    fn streaming_body() {
+        let addr = serve_with(Streams, HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"GET /iter HTTP/1.1\r\n\r\nGET /large HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut r = BufReader::new(s);
+        let mut headers = Vec::new();
+        let (status, _) = read_rsp_headers(&mut r, &mut headers);
+        assert_eq!(status, "HTTP/1.1 200 Ok");
+        assert!(headers.contains(&"Transfer-Encoding: chunked".to_owned()));
+        assert_eq!(read_chunked(&mut r), b"hello world");
+        read_rsp(&mut r);
+        let expect: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+        assert!(read_chunked(&mut r) == expect.as_bytes());
+        // the connection is kept alive
+        assert_eq!(read_rsp(&mut r).1, b"done");
+    }
+
+    #[test]
+    fn streaming_body_http10() {
+        let addr = serve_with(Streams, HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"GET /iter HTTP/1.0\r\n\r\n").unwrap();
+        let mut r = BufReader::new(s);
+        let mut headers = Vec::new();
+        read_rsp_headers(&mut r, &mut headers);
+        assert!(headers.contains(&"Connection: close".to_owned()));
+        // the body ends with the connection
+        let mut body = Vec::new();
+        r.read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"hello world");
+    }
+
+    #[test]
+    fn sized_streaming_body() {
+        let addr = serve_with(Streams, HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(
+            b"GET /sized HTTP/1.1\r\n\r\nHEAD /sized HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        let mut r = BufReader::new(s);
+        let mut headers = Vec::new();
+        assert_eq!(read_rsp_headers(&mut r, &mut headers).1, b"hello");
+        assert!(headers.contains(&"Content-Length: 5".to_owned()));
+        // the head of a `HEAD` rsp tells the length without the body
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            r.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
             }
+            head.push(line.trim_end().to_owned());
+        }
+        assert!(head.contains(&"Content-Length: 5".to_owned()));
+        assert_eq!(read_rsp(&mut r).1, b"done");
+    }
+
+    #[test]
+    fn sized_streaming_body_too_short() {
+        let addr = serve_with(Streams, HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"GET /short HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+            .unwrap();
+        // the connection is closed instead of sending the next rsp
+        let mut rsp = String::new();
+        s.read_to_string(&mut rsp).unwrap();
+        assert!(rsp.contains("Content-Length: 10\r\n"));
+        assert!(rsp.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn server_sent_events() {
+        let addr = serve_with(Streams, HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut r = BufReader::new(s);
+        let mut headers = Vec::new();
+        read_rsp_headers(&mut r, &mut headers);
+        assert!(headers.contains(&"Content-Type: text/event-stream".to_owned()));
+        let body = String::from_utf8(read_chunked(&mut r)).unwrap();
+        assert!(body.starts_with("data: 1\n\n"));
+        assert!(body.contains(": keep-alive\n\n"));
+        assert!(body.ends_with("event: x\ndata: 2\n\n"));
+    }
+
+    #[test]
+    fn websocket() {
+        let addr = serve_with(Streams, HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        // a masked "Hello" and close frame follow the handshake req
+        s.write_all(
+            b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n\
+              \x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58\
+              \x88\x80\x01\x02\x03\x04",
+        )
+        .unwrap();
+        let mut r = BufReader::new(s);
+        let mut headers = Vec::new();
+        let (status, _) = read_rsp_headers(&mut r, &mut headers);
+        assert_eq!(status, "HTTP/1.1 101 Switching Protocols");
+        assert!(headers.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_owned()));
+        let mut frames = Vec::new();
+        r.read_to_end(&mut frames).unwrap();
+        assert_eq!(frames, b"\x81\x05Hello\x88\x00");
+    }
+
+    #[test]
+    fn websocket_bad_handshake() {
+        let addr = serve_with(Streams, HttpServerConfig::new());
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        s.write_all(
+            b"GET /ws HTTP/1.1\r\n\r\n\
+              GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n",
         )
+        .unwrap();
+        let mut r = BufReader::new(s);
+        assert_eq!(read_rsp(&mut r).0, "HTTP/1.1 400 Bad Request");
+        let mut headers = Vec::new();
+        let (status, _) = read_rsp_headers(&mut r, &mut headers);
+        assert_eq!(status, "HTTP/1.1 426 Upgrade Required");
+        assert!(headers.contains(&"Sec-WebSocket-Version: 13".to_owned()));
     }
 }
@@ -0,0 +1,409 @@
+//! the common middlewares for `Router`
+
+use std::any::Any;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{Middleware, Next, Response, RouteRequest, StatusCode};
+use crate::coroutine;
+use crate::std::sync::SyncFlag;
+
+// the coroutine is canceled, the panic must go on
+fn is_cancel(e: &(dyn Any + Send)) -> bool {
+    matches!(
+        e.downcast_ref::<generator::Error>(),
+        Some(generator::Error::Cancel)
+    )
+}
+
+fn panic_msg(e: &(dyn Any + Send)) -> &str {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// log the method, path, status and elapsed time of each request
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn call(&self, req: RouteRequest, rsp: &mut Response, next: Next) -> io::Result<()> {
+        let start = Instant::now();
+        let method = req.method().to_owned();
+        let path = req.path().to_owned();
+        let ret = next.run(req, rsp);
+        let status = match ret {
+            Ok(_) => rsp.get_status_code(),
+            Err(_) => StatusCode::InternalServerError.as_str(),
+        };
+        info!("{} {} {} {:?}", method, path, status, start.elapsed());
+        ret
+    }
+}
+
+/// turn a panic of the inner handler into `500 Internal Server Error`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Recover;
+
+impl Middleware for Recover {
+    fn call(&self, req: RouteRequest, rsp: &mut Response, next: Next) -> io::Result<()> {
+        match panic::catch_unwind(AssertUnwindSafe(|| next.run(req, rsp))) {
+            Ok(ret) => ret,
+            Err(e) => {
+                if is_cancel(&*e) {
+                    panic::resume_unwind(e);
+                }
+                error!("http handler panicked: {}", panic_msg(&*e));
+                rsp.reset();
+                rsp.status(StatusCode::InternalServerError);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// reply `503 Service Unavailable` if the inner handler doesn't finish in time
+///
+/// the handler runs in a scoped coroutine that is canceled on timeout, the
+/// cancel takes effect at its next blocking point, so a handler that keeps
+/// the cpu busy can't be stopped
+#[derive(Debug, Clone, Copy)]
+pub struct Timeout(Duration);
+
+impl Timeout {
+    pub fn new(dur: Duration) -> Self {
+        Timeout(dur)
+    }
+}
+
+impl Middleware for Timeout {
+    fn call(&self, req: RouteRequest, rsp: &mut Response, next: Next) -> io::Result<()> {
+        let done = SyncFlag::new();
+        let (timed_out, ret) = coroutine::scope(|s| {
+            let done = &done;
+            let rsp = &mut *rsp;
+            // the panic is caught so that the scope doesn't propagate it
+            let h = unsafe {
+                s.spawn(move || {
+                    let ret = panic::catch_unwind(AssertUnwindSafe(|| next.run(req, rsp)));
+                    done.fire();
+                    ret
+                })
+            };
+            let timed_out = !done.wait_timeout(self.0);
+            if timed_out {
+                unsafe { h.coroutine().cancel() };
+            }
+            (timed_out, h.join())
+        });
+
+        match ret {
+            Ok(ret) => ret,
+            Err(e) if timed_out && is_cancel(&*e) => {
+                rsp.reset();
+                rsp.status(StatusCode::ServiceUnavailable);
+                Ok(())
+            }
+            Err(e) => panic::resume_unwind(e),
+        }
+    }
+}
+
+/// tag each request with an id
+///
+/// the `X-Request-Id` header of the request is used if present, otherwise a
+/// new id is generated, the id is set to `RouteRequest::request_id` and
+/// echoed in the response header
+#[derive(Debug)]
+pub struct RequestId {
+    prefix: String,
+    next_id: AtomicUsize,
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestId {
+    pub fn new() -> Self {
+        // different per process to avoid collisions across restarts
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        RequestId {
+            prefix: format!("{:x}{:x}", std::process::id(), nanos & 0xffff_ffff),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+}
+
+// don't trust an arbitrary client id
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+impl Middleware for RequestId {
+    fn call(&self, mut req: RouteRequest, rsp: &mut Response, next: Next) -> io::Result<()> {
+        let id = req
+            .header("x-request-id")
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .filter(|v| valid_id(v))
+            .map(str::to_owned)
+            .unwrap_or_else(|| {
+                let n = self.next_id.fetch_add(1, Ordering::Relaxed);
+                format!("{}-{}", self.prefix, n)
+            });
+        req.set_request_id(id.clone());
+        let ret = next.run(req, rsp);
+        rsp.add_header("X-Request-Id", id)?;
+        ret
+    }
+}
+
+/// cross origin resource sharing
+///
+/// preflight requests are answered directly with `204 No Content`, other
+/// requests from the allowed origins get the `Access-Control-Allow-Origin`
+/// header
+#[derive(Debug, Clone)]
+pub struct Cors {
+    // empty for any origin
+    origins: Vec<String>,
+    methods: String,
+    headers: String,
+    max_age: Option<Duration>,
+    credentials: bool,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cors {
+    /// allow any origin with the common methods
+    pub fn new() -> Self {
+        Cors {
+            origins: Vec::new(),
+            methods: "GET, HEAD, POST, PUT, PATCH, DELETE".to_owned(),
+            headers: String::new(),
+            max_age: None,
+            credentials: false,
+        }
+    }
+
+    /// only allow `origin`, call it multiple times to allow more
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.to_owned());
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = methods.join(", ");
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.join(", ");
+        self
+    }
+
+    /// how long the preflight result could be cached
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// allow the requests with credentials, only from the origins added by
+    /// `allow_origin`. no origin is allowed if there is none, since any
+    /// site could read the credentialed responses otherwise
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.credentials = allow;
+        self
+    }
+
+    // the allowed origin to reply for the request
+    fn allowed_origin(&self, origin: &str) -> Option<String> {
+        if self.origins.is_empty() {
+            // credentials are not allowed with `*`, nor with any origin
+            if self.credentials {
+                None
+            } else {
+                Some("*".to_owned())
+            }
+        } else if self.origins.iter().any(|o| o == origin) {
+            Some(origin.to_owned())
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn call(&self, req: RouteRequest, rsp: &mut Response, next: Next) -> io::Result<()> {
+        let origin = match req
+            .header("origin")
+            .and_then(|v| std::str::from_utf8(v).ok())
+        {
+            Some(o) => o.to_owned(),
+            None => return next.run(req, rsp),
+        };
+        let allowed = match self.allowed_origin(&origin) {
+            Some(o) => o,
+            None => return next.run(req, rsp),
+        };
+
+        let preflight =
+            req.method() == "OPTIONS" && req.header("access-control-request-method").is_some();
+        if preflight {
+            rsp.status(StatusCode::NoContent)
+                .add_header("Access-Control-Allow-Methods", self.methods.clone())?;
+            let headers = if self.headers.is_empty() {
+                // allow what is asked for
+                req.header("access-control-request-headers")
+                    .and_then(|v| std::str::from_utf8(v).ok())
+                    .unwrap_or("")
+                    .to_owned()
+            } else {
+                self.headers.clone()
+            };
+            if !headers.is_empty() {
+                rsp.add_header("Access-Control-Allow-Headers", headers)?;
+            }
+            if let Some(age) = self.max_age {
+                rsp.add_header("Access-Control-Max-Age", age.as_secs().to_string())?;
+            }
+        } else {
+            next.run(req, rsp)?;
+        }
+
+        if allowed != "*" {
+            rsp.header("Vary: Origin");
+        }
+        rsp.add_header("Access-Control-Allow-Origin", allowed)?;
+        if self.credentials {
+            rsp.header("Access-Control-Allow-Credentials: true");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::http::server::{call, Router};
+
+    fn routes() -> Router {
+        Router::new()
+            .get("/panic", |_, _| panic!("boom"))
+            .get("/slow", |_, rsp| {
+                coroutine::sleep(Duration::from_secs(10));
+                rsp.body("too late");
+                Ok(())
+            })
+            .get("/id", |req, rsp| {
+                rsp.body_vec(req.request_id().unwrap_or("none").as_bytes().to_vec());
+                Ok(())
+            })
+    }
+
+    #[test]
+    fn recover() {
+        let mut router = routes().with(Recover);
+        let rsp = call(&mut router, "GET /panic HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(!rsp.contains("boom"));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut router = routes().with(Timeout::new(Duration::from_millis(50)));
+        let h = go!(move || {
+            let start = Instant::now();
+            let rsp = call(&mut router, "GET /slow HTTP/1.1\r\n\r\n");
+            assert!(rsp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+            assert!(!rsp.contains("too late"));
+            assert!(start.elapsed() < Duration::from_secs(5));
+            // the fast ones are not affected
+            let rsp = call(&mut router, "GET /id HTTP/1.1\r\n\r\n");
+            assert!(rsp.ends_with("\r\n\r\nnone"));
+        });
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn request_id() {
+        let mut router = routes().with(RequestId::new());
+        let rsp = call(&mut router, "GET /id HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n");
+        assert!(rsp.contains("\r\nX-Request-Id: abc\r\n"));
+        assert!(rsp.ends_with("\r\n\r\nabc"));
+        let a = call(&mut router, "GET /id HTTP/1.1\r\n\r\n");
+        let b = call(&mut router, "GET /id HTTP/1.1\r\n\r\n");
+        assert_ne!(a.rsplit("\r\n").next(), b.rsplit("\r\n").next());
+    }
+
+    #[test]
+    fn cors() {
+        let mut router = routes().with(
+            Cors::new()
+                .allow_origin("https://a.com")
+                .max_age(Duration::from_secs(60)),
+        );
+        let rsp = call(
+            &mut router,
+            "OPTIONS /id HTTP/1.1\r\nOrigin: https://a.com\r\n\
+             Access-Control-Request-Method: POST\r\n\
+             Access-Control-Request-Headers: content-type\r\n\r\n",
+        );
+        assert!(rsp.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Origin: https://a.com\r\n"));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Headers: content-type\r\n"));
+        assert!(rsp.contains("\r\nAccess-Control-Max-Age: 60\r\n"));
+
+        let rsp = call(
+            &mut router,
+            "GET /id HTTP/1.1\r\nOrigin: https://b.com\r\n\r\n",
+        );
+        assert!(!rsp.contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn cors_credentials() {
+        // any origin is never allowed with credentials
+        let mut router = routes().with(Cors::new().allow_credentials(true));
+        let rsp = call(
+            &mut router,
+            "GET /id HTTP/1.1\r\nOrigin: https://evil.com\r\n\r\n",
+        );
+        assert!(rsp.ends_with("\r\n\r\nnone"));
+        assert!(!rsp.contains("Access-Control-Allow-"));
+
+        let mut router = routes().with(
+            Cors::new()
+                .allow_origin("https://a.com")
+                .allow_credentials(true),
+        );
+        let rsp = call(
+            &mut router,
+            "GET /id HTTP/1.1\r\nOrigin: https://a.com\r\n\r\n",
+        );
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Origin: https://a.com\r\n"));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Credentials: true\r\n"));
+        assert!(rsp.contains("\r\nVary: Origin\r\n"));
+        let rsp = call(
+            &mut router,
+            "GET /id HTTP/1.1\r\nOrigin: https://evil.com\r\n\r\n",
+        );
+        assert!(!rsp.contains("Access-Control-Allow-"));
+    }
+}
@@ -1,8 +1,46 @@
+mod body;
+mod config;
+mod cookie;
+mod date;
+mod file_server;
+mod form;
 mod http_server;
+mod middleware;
+mod multipart;
 mod request;
 mod response;
-mod date;
+mod router;
+mod session;
+mod status;
+mod stream;
+mod websocket;
 
+pub use body::BodyReader;
+pub(crate) use body::ChunkedDecoder;
+pub use config::*;
+pub use cookie::{Cookie, CookieSigner, SameSite};
+pub use file_server::{mime_type, FileServer};
+pub use form::*;
 pub use http_server::*;
+pub use middleware::*;
+pub use multipart::{Multipart, Part};
 pub use request::*;
 pub use response::*;
+pub use router::*;
+pub use session::*;
+pub use status::StatusCode;
+pub use stream::{BodyFn, SseEvent};
+pub use websocket::{Message, WebSocket};
+
+// run the raw request through `service`, return the encoded response
+#[cfg(test)]
+pub(crate) fn call<S: HttpService>(service: &mut S, req: &str) -> String {
+    use bytes::BytesMut;
+
+    let req = decode(&mut BytesMut::from(req)).unwrap().unwrap();
+    let (mut body, mut buf) = (BytesMut::new(), BytesMut::new());
+    let mut rsp = Response::new(&mut body);
+    service.call(req, &mut rsp).unwrap();
+    encode(rsp, &mut buf);
+    String::from_utf8(buf.to_vec()).unwrap()
+}
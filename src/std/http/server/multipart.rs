@@ -0,0 +1,606 @@
+//! streaming `multipart/form-data` parser
+//!
+//! the parts are read from the body one after another, so a large file
+//! could be saved to disk without holding the whole of it in memory
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str;
+
+use bytes::{Buf, BytesMut};
+
+use super::form::{percent_decode, FormConfig};
+use super::{BodyReader, Request};
+use crate::io::blocking::FS;
+
+const MAX_PART_HEADERS: usize = 32;
+// the bytes read from the body at a time
+const READ_SIZE: usize = 16 * 1024;
+// the bytes written to a file at a time
+const WRITE_SIZE: usize = 64 * 1024;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "multipart body is truncated")
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// split a header value like `form-data; name="a"; filename="b"` into the
+// value and its parameters, the parameter names are lowercased
+pub(crate) fn header_params(value: &str) -> (&str, Vec<(String, String)>) {
+    let (main, mut rest) = match value.find(';') {
+        Some(i) => (&value[..i], &value[i..]),
+        None => (value, ""),
+    };
+    let mut params = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ';' || c.is_ascii_whitespace());
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(|c| c == '=' || c == ';').unwrap_or(rest.len());
+        let name = rest[..end].trim().to_ascii_lowercase();
+        if !rest[end..].starts_with('=') {
+            params.push((name, String::new()));
+            rest = &rest[end..];
+            continue;
+        }
+        rest = rest[end + 1..].trim_start();
+
+        let mut value = String::new();
+        if let Some(quoted) = rest.strip_prefix('"') {
+            // only `\"` and `\\` are escapes, the clients send the windows
+            // paths with a single backslash
+            let mut chars = quoted.char_indices().peekable();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    '\\' => match chars.peek() {
+                        Some(&(_, c)) if c == '"' || c == '\\' => {
+                            value.push(c);
+                            chars.next();
+                        }
+                        _ => value.push('\\'),
+                    },
+                    c => value.push(c),
+                }
+            }
+            rest = &quoted[end..];
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            value.push_str(rest[..end].trim());
+            rest = &rest[end..];
+        }
+        params.push((name, value));
+    }
+    (main.trim(), params)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // before the first delimiter
+    Preamble,
+    // in the content of a part
+    Body,
+    // right after a delimiter
+    Delimiter,
+    Done,
+}
+
+/// the streaming parser of a `multipart/form-data` body
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::std::http::server::{FormConfig, HttpServer, Multipart, Router};
+///
+/// let router = Router::new().post("/upload", |mut req, rsp| {
+///     let mut multipart = Multipart::from_request(&mut req, FormConfig::new())?;
+///     while let Some(mut part) = multipart.next_part()? {
+///         if part.filename().is_some() {
+///             part.save_to(std::env::temp_dir().join("upload"))?;
+///         } else {
+///             let name = part.name().unwrap_or_default().to_owned();
+///             println!("{} = {}", name, part.text()?);
+///         }
+///     }
+///     rsp.body("uploaded");
+///     Ok(())
+/// });
+/// let server = HttpServer(router).start("127.0.0.1:8080").unwrap();
+/// server.join().unwrap();
+/// ```
+pub struct Multipart<R> {
+    reader: R,
+    // `\r\n--` and the boundary
+    delimiter: Vec<u8>,
+    // the bytes read but not parsed yet
+    buf: BytesMut,
+    state: State,
+    config: FormConfig,
+    parts: usize,
+}
+
+impl<'a> Multipart<&'a mut BodyReader> {
+    /// parse the `multipart/form-data` body of `req`
+    ///
+    /// an `InvalidData` error is returned if the `Content-Type` is not
+    /// `multipart/form-data` or it has no boundary
+    pub fn from_request(req: &'a mut Request, config: FormConfig) -> io::Result<Self> {
+        let content_type = req
+            .header("Content-Type")
+            .and_then(|v| str::from_utf8(v).ok())
+            .unwrap_or_default();
+        let (mime, params) = header_params(content_type);
+        if !mime.eq_ignore_ascii_case("multipart/form-data") {
+            return Err(invalid("not a multipart form"));
+        }
+        let boundary = params
+            .into_iter()
+            .find(|(k, _)| k == "boundary")
+            .map(|(_, v)| v)
+            .filter(|b| !b.is_empty() && b.len() <= 70)
+            .ok_or_else(|| invalid("invalid multipart boundary"))?;
+        Ok(Multipart::new(req.body_reader(), &boundary, config))
+    }
+}
+
+impl<R: Read> Multipart<R> {
+    /// parse the multipart body from `reader` with `boundary`
+    pub fn new(reader: R, boundary: &str, config: FormConfig) -> Self {
+        // the first delimiter is found like the others
+        let mut buf = BytesMut::with_capacity(READ_SIZE);
+        buf.extend_from_slice(b"\r\n");
+        Multipart {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            buf,
+            state: State::Preamble,
+            config,
+            parts: 0,
+        }
+    }
+
+    // read more body into `buf`, return false at the end of the body
+    fn fill(&mut self) -> io::Result<bool> {
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+        let ret = self.reader.read(&mut self.buf[len..]);
+        self.buf.truncate(len + *ret.as_ref().unwrap_or(&0));
+        Ok(ret? > 0)
+    }
+
+    /// get the next part, `None` after the last one
+    ///
+    /// the unread content of the previous part is skipped
+    pub fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Preamble | State::Body => match find(&self.buf, &self.delimiter) {
+                    Some(i) => {
+                        self.buf.advance(i + self.delimiter.len());
+                        self.state = State::Delimiter;
+                    }
+                    None => {
+                        // keep the tail that could be the start of a delimiter
+                        let keep = self.buf.len().min(self.delimiter.len() - 1);
+                        let skip = self.buf.len() - keep;
+                        self.buf.advance(skip);
+                        if !self.fill()? {
+                            return Err(truncated());
+                        }
+                    }
+                },
+                State::Delimiter => {
+                    if self.buf.len() < 2 {
+                        if !self.fill()? {
+                            return Err(truncated());
+                        }
+                        continue;
+                    }
+                    // the last delimiter is followed by `--`
+                    if self.buf.starts_with(b"--") {
+                        self.state = State::Done;
+                        return Ok(None);
+                    }
+                    let end = match find(&self.buf, b"\r\n\r\n") {
+                        Some(end) if end <= self.config.max_part_header_bytes() => end,
+                        Some(_) => return Err(invalid("multipart part headers are too large")),
+                        None if self.buf.len() > self.config.max_part_header_bytes() => {
+                            return Err(invalid("multipart part headers are too large"))
+                        }
+                        None => {
+                            if !self.fill()? {
+                                return Err(truncated());
+                            }
+                            continue;
+                        }
+                    };
+                    if self.parts >= self.config.max_fields() {
+                        return Err(invalid("too many form fields"));
+                    }
+
+                    // the rest of the delimiter line could only be whitespaces
+                    let line = find(&self.buf, b"\r\n").unwrap_or(end);
+                    if !self.buf[..line].iter().all(|&b| b == b' ' || b == b'\t') {
+                        return Err(invalid("invalid multipart delimiter"));
+                    }
+                    let mut headers = [httparse::EMPTY_HEADER; MAX_PART_HEADERS];
+                    let headers =
+                        match httparse::parse_headers(&self.buf[line + 2..end + 4], &mut headers) {
+                            Ok(httparse::Status::Complete((_, headers))) => headers
+                                .iter()
+                                .map(|h| {
+                                    let value = String::from_utf8_lossy(h.value).into_owned();
+                                    (h.name.to_owned(), value)
+                                })
+                                .collect(),
+                            _ => return Err(invalid("invalid multipart part headers")),
+                        };
+                    self.buf.advance(end + 4);
+                    self.state = State::Body;
+                    self.parts += 1;
+                    return Ok(Some(Part::new(self, headers)));
+                }
+            }
+        }
+    }
+}
+
+/// a part of a multipart body, its content is read by `Read`
+///
+/// reading fails with `InvalidData` once the content is larger than
+/// `FormConfig::max_part_size`
+pub struct Part<'m, R> {
+    multipart: &'m mut Multipart<R>,
+    headers: Vec<(String, String)>,
+    name: Option<String>,
+    filename: Option<String>,
+    // the content size read so far
+    read: u64,
+}
+
+impl<'m, R: Read> Part<'m, R> {
+    fn new(multipart: &'m mut Multipart<R>, headers: Vec<(String, String)>) -> Self {
+        let disposition = headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case("Content-Disposition"))
+            .map(|(_, v)| header_params(v).1)
+            .unwrap_or_default();
+        let param = |name: &str| {
+            disposition
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+        };
+        // `filename*` is like `UTF-8''%E4%B8%AD.txt`
+        let filename = param("filename*")
+            .and_then(|v| v.splitn(3, '\'').nth(2).map(percent_decode))
+            .or_else(|| param("filename"))
+            .map(|f| {
+                // only the base name is kept from the paths some clients send
+                let base = f
+                    .rsplit(|c| c == '/' || c == '\\')
+                    .next()
+                    .unwrap_or_default();
+                base.to_owned()
+            });
+        Part {
+            name: param("name"),
+            filename,
+            multipart,
+            headers,
+            read: 0,
+        }
+    }
+
+    /// get the value of the first header named `name`, ignoring the case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// the field name from the `Content-Disposition` header
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// the file name from the `Content-Disposition` header
+    ///
+    /// the directories are removed, but it's still sent by the client and
+    /// should be checked before being used as a path
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// the `Content-Type` header, `text/plain` if it's not present
+    pub fn content_type(&self) -> &str {
+        self.header("Content-Type").unwrap_or("text/plain")
+    }
+
+    /// read the whole content
+    pub fn bytes(mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// read the whole content as utf-8 text
+    pub fn text(mut self) -> io::Result<String> {
+        let mut text = String::new();
+        self.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    /// stream the content to the file at `path`, return the content size
+    ///
+    /// the file is written on a helper thread, so the worker thread is not
+    /// blocked. the file is removed if the content could not be read
+    pub fn save_to<P: AsRef<Path>>(&mut self, path: P) -> io::Result<u64> {
+        let path = path.as_ref().to_owned();
+        let file = {
+            let path = path.clone();
+            FS.run(move || File::create(path))?
+        };
+        let ret = self.copy_to_file(file);
+        if ret.is_err() {
+            FS.run(move || fs::remove_file(path)).ok();
+        }
+        ret
+    }
+
+    fn copy_to_file(&mut self, file: File) -> io::Result<u64> {
+        let mut file = Some(file);
+        let mut buf = vec![0; WRITE_SIZE];
+        let mut total = 0;
+        loop {
+            // fill the buffer before handing it to the helper thread
+            let mut n = 0;
+            while n < buf.len() {
+                match self.read(&mut buf[n..])? {
+                    0 => break,
+                    m => n += m,
+                }
+            }
+            if n == 0 {
+                return Ok(total);
+            }
+            let mut f = file.take().expect("no file");
+            let (f, data, ret) = FS.run(move || {
+                let ret = f.write_all(&buf[..n]);
+                (f, buf, ret)
+            });
+            ret?;
+            file = Some(f);
+            buf = data;
+            total += n as u64;
+        }
+    }
+
+    fn consume(&mut self, out: &mut [u8], n: usize) -> io::Result<usize> {
+        let n = n.min(out.len());
+        if self.read + n as u64 > self.multipart.config.max_part_size() {
+            return Err(invalid("multipart part is too large"));
+        }
+        out[..n].copy_from_slice(&self.multipart.buf[..n]);
+        self.multipart.buf.advance(n);
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'m, R: Read> Read for Part<'m, R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let m = &mut *self.multipart;
+            if m.state != State::Body {
+                return Ok(0);
+            }
+            match find(&m.buf, &m.delimiter) {
+                Some(0) => {
+                    let len = m.delimiter.len();
+                    m.buf.advance(len);
+                    m.state = State::Delimiter;
+                    return Ok(0);
+                }
+                Some(i) => return self.consume(out, i),
+                None => {
+                    // the tail could be the start of the delimiter
+                    let safe = m.buf.len().saturating_sub(m.delimiter.len() - 1);
+                    if safe > 0 {
+                        return self.consume(out, safe);
+                    }
+                    if !m.fill()? {
+                        return Err(truncated());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::http::server::request::decode;
+
+    const BODY: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        hello\r\n--Xy\r\n\
+        --XyZ  \r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"C:\\\\dir\\\\a \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line 1\r\nline 2\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"doc\"; filename*=UTF-8''%E4%B8%AD.txt\r\n\
+        \r\n\
+        \r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    // return at most one byte from each read
+    struct OneByte<'a>(&'a [u8]);
+
+    impl<'a> Read for OneByte<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((b, rest)) if !buf.is_empty() => {
+                    buf[0] = *b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn parse<R: Read>(reader: R) -> Vec<(Option<String>, Option<String>, String)> {
+        let mut multipart = Multipart::new(reader, "XyZ", FormConfig::new());
+        let mut parts = Vec::new();
+        while let Some(part) = multipart.next_part().unwrap() {
+            let name = part.name().map(|n| n.to_owned());
+            let filename = part.filename().map(|n| n.to_owned());
+            parts.push((name, filename, part.text().unwrap()));
+        }
+        parts
+    }
+
+    #[test]
+    fn params() {
+        let (value, params) = header_params("form-data; name=\"a;b\"; filename=x.txt ;flag");
+        assert_eq!(value, "form-data");
+        let p = |k: &str, v: &str| (k.to_owned(), v.to_owned());
+        assert_eq!(
+            params,
+            vec![p("name", "a;b"), p("filename", "x.txt"), p("flag", "")]
+        );
+        let (_, params) = header_params("multipart/form-data; BOUNDARY=\"a\\\"b\\c\"");
+        assert_eq!(params, vec![p("boundary", "a\"b\\c")]);
+    }
+
+    #[test]
+    fn parts() {
+        let s = |s: &str| Some(s.to_owned());
+        let expect = vec![
+            (s("title"), None, "hello\r\n--Xy".to_owned()),
+            (s("file"), s("a \"b\".txt"), "line 1\r\nline 2".to_owned()),
+            (s("doc"), s("中.txt"), String::new()),
+        ];
+        assert_eq!(parse(BODY.as_bytes()), expect);
+        assert_eq!(parse(OneByte(BODY.as_bytes())), expect);
+    }
+
+    #[test]
+    fn skip_unread() {
+        let mut multipart = Multipart::new(BODY.as_bytes(), "XyZ", FormConfig::new());
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.read(&mut [0; 2]).unwrap(), 2);
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.content_type(), "text/plain");
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(
+            part.header("content-disposition"),
+            Some("form-data; name=\"doc\"; filename*=UTF-8''%E4%B8%AD.txt")
+        );
+        assert_eq!(part.text().unwrap(), "");
+        assert!(multipart.next_part().unwrap().is_none());
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn limits() {
+        let mut config = FormConfig::new();
+        config.set_max_fields(2);
+        let mut multipart = Multipart::new(BODY.as_bytes(), "XyZ", config);
+        multipart.next_part().unwrap();
+        multipart.next_part().unwrap();
+        let e = multipart.next_part().err().unwrap();
+        assert_eq!(e.to_string(), "too many form fields");
+
+        let mut config = FormConfig::new();
+        config.set_max_part_size(10);
+        let mut multipart = Multipart::new(BODY.as_bytes(), "XyZ", config);
+        let e = multipart.next_part().unwrap().unwrap().bytes().unwrap_err();
+        assert_eq!(e.to_string(), "multipart part is too large");
+
+        let mut config = FormConfig::new();
+        config.set_max_part_header_bytes(20);
+        let mut multipart = Multipart::new(BODY.as_bytes(), "XyZ", config);
+        let e = multipart.next_part().err().unwrap();
+        assert_eq!(e.to_string(), "multipart part headers are too large");
+
+        let body = &BODY[..BODY.find("line 2").unwrap()];
+        let mut multipart = Multipart::new(body.as_bytes(), "XyZ", FormConfig::new());
+        multipart.next_part().unwrap();
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(
+            part.text().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn from_request() {
+        let raw = format!(
+            "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n{}",
+            BODY.len(),
+            BODY
+        );
+        let mut req = decode(&mut BytesMut::from(raw.as_str())).unwrap().unwrap();
+        let mut multipart = Multipart::from_request(&mut req, FormConfig::new()).unwrap();
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.text().unwrap(), "hello\r\n--Xy");
+
+        let raw = "POST / HTTP/1.1\r\nContent-Type: multipart/form-data\r\n\r\n";
+        let mut req = decode(&mut BytesMut::from(raw)).unwrap().unwrap();
+        assert!(Multipart::from_request(&mut req, FormConfig::new()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_to() {
+        let dir = tempdir::TempDir::new("multipart").unwrap();
+        let data: String = (0..20_000).map(|i| format!("{}\r\n", i)).collect();
+        let body = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"f\"; filename=\"big.txt\"\r\n\r\n{}\r\n--XyZ--\r\n",
+            data
+        );
+        let mut multipart = Multipart::new(body.as_bytes(), "XyZ", FormConfig::new());
+        let mut part = multipart.next_part().unwrap().unwrap();
+        let path = dir.path().join(part.filename().unwrap());
+        assert_eq!(part.save_to(&path).unwrap(), data.len() as u64);
+        assert_eq!(fs::read_to_string(&path).unwrap(), data);
+
+        // the partial file is removed
+        let mut config = FormConfig::new();
+        config.set_max_part_size(1000);
+        let mut multipart = Multipart::new(body.as_bytes(), "XyZ", config);
+        let mut part = multipart.next_part().unwrap().unwrap();
+        let path = dir.path().join("partial.txt");
+        assert!(part.save_to(&path).is_err());
+        assert!(!path.exists());
+    }
+}
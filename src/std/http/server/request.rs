@@ -1,14 +1,21 @@
+use std::sync::{Arc, Mutex};
 use std::{fmt, io, slice, str};
 
-use bytes::BytesMut;
+use bytes::{Buf, BytesMut};
+use smallvec::SmallVec;
+
+use super::body::{BodyReader, ChunkedDecoder, Leftover};
+use super::cookie;
+use super::HttpServerConfig;
+use crate::net::TcpStream;
 
 pub struct Request {
     method: Slice,
     path: Slice,
     version: u8,
-    headers: [(Slice, Slice); 16],
-    headers_len: usize,
+    headers: SmallVec<[(Slice, Slice); 16]>,
     data: BytesMut,
+    body: BodyReader,
 }
 
 type Slice = (usize, usize);
@@ -35,13 +42,46 @@ impl Request {
 
     pub fn headers(&self) -> RequestHeaders {
         RequestHeaders {
-            headers: self.headers[..self.headers_len].iter(),
+            headers: self.headers.iter(),
             req: self,
         }
     }
 
+    /// get the value of the first header named `name`, ignoring the case
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    /// get the value of the cookie named `name`
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    /// the name value pairs of all the `Cookie` headers
+    pub fn cookies(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers()
+            .filter(|(n, _)| n.eq_ignore_ascii_case("cookie"))
+            .filter_map(|(_, v)| str::from_utf8(v).ok())
+            .flat_map(cookie::parse)
+    }
+
+    /// the request body
+    ///
+    /// it's empty if the body is larger than `HttpServerConfig::body_buffer_size`,
+    /// read such a body with `body_reader`
     pub fn body(&self) -> &[u8] {
-        unimplemented!()
+        self.body.buffered_body()
+    }
+
+    /// the streaming reader of the request body
+    pub fn body_reader(&mut self) -> &mut BodyReader {
+        &mut self.body
+    }
+
+    pub(crate) fn body_leftover(&self) -> Option<Arc<Mutex<Leftover>>> {
+        self.body.leftover()
     }
 
     fn slice(&self, slice: &Slice) -> &[u8] {
@@ -55,13 +95,66 @@ impl fmt::Debug for Request {
     }
 }
 
+// the result of decoding the request buffer
+pub(crate) enum Decoded {
+    Request(Request),
+    // need more data, `expect_continue` is set if the client
+    // waits for `100 Continue` before sending the body
+    Partial { expect_continue: bool },
+    // the body is larger than `HttpServerConfig::max_body_size`
+    TooLarge,
+    // more headers than `HttpServerConfig::max_headers`, or the request
+    // head is larger than `HttpServerConfig::max_header_bytes`
+    HeadersTooLarge,
+    // the body framing is ambiguous, both `Transfer-Encoding` and
+    // `Content-Length` are set
+    BadRequest,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// the connection that the streamed body is read from
+fn body_stream(stream: &TcpStream, config: &HttpServerConfig) -> io::Result<TcpStream> {
+    let s = stream.try_clone()?;
+    s.set_read_timeout(config.body_read_timeout())?;
+    Ok(s)
+}
+
+/// decode a request from `buf`, the whole body is buffered
 pub fn decode(buf: &mut BytesMut) -> io::Result<Option<Request>> {
-    let mut headers: [httparse::Header; 16] =
-        unsafe { std::mem::MaybeUninit::uninit().assume_init() };
+    match decode_with(buf, &HttpServerConfig::default(), None)? {
+        Decoded::Request(req) => Ok(Some(req)),
+        Decoded::Partial { .. } => Ok(None),
+        Decoded::TooLarge => Err(invalid("request body too large")),
+        Decoded::HeadersTooLarge => Err(invalid("request headers too large")),
+        Decoded::BadRequest => Err(invalid("ambiguous request body length")),
+    }
+}
+
+// decode a request from `buf`, large bodies are streamed from `stream`
+pub(crate) fn decode_with(
+    buf: &mut BytesMut,
+    config: &HttpServerConfig,
+    stream: Option<&TcpStream>,
+) -> io::Result<Decoded> {
+    let mut headers = [httparse::EMPTY_HEADER; 16];
+    let mut more_headers;
     let mut r = httparse::Request::new(&mut headers);
 
-    let status = match r.parse(buf) {
+    let ret = match r.parse(buf) {
+        // only allocate for the rare requests with many headers
+        Err(httparse::Error::TooManyHeaders) if config.max_headers() > 16 => {
+            more_headers = vec![httparse::EMPTY_HEADER; config.max_headers()];
+            r = httparse::Request::new(&mut more_headers);
+            r.parse(buf)
+        }
+        ret => ret,
+    };
+    let status = match ret {
         Ok(s) => s,
+        Err(httparse::Error::TooManyHeaders) => return Ok(Decoded::HeadersTooLarge),
         Err(e) => {
             let msg = format!("failed to parse http request: {:?}", e);
             return Err(io::Error::new(io::ErrorKind::Other, msg));
@@ -69,9 +162,65 @@ pub fn decode(buf: &mut BytesMut) -> io::Result<Option<Request>> {
     };
 
     let amt = match status {
+        httparse::Status::Complete(amt) if amt > config.max_header_bytes() => {
+            return Ok(Decoded::HeadersTooLarge)
+        }
+        // the fixed array may hold more than a limit under 16
+        httparse::Status::Complete(_) if r.headers.len() > config.max_headers() => {
+            return Ok(Decoded::HeadersTooLarge)
+        }
         httparse::Status::Complete(amt) => amt,
-        httparse::Status::Partial => return Ok(None),
+        httparse::Status::Partial if buf.len() > config.max_header_bytes() => {
+            return Ok(Decoded::HeadersTooLarge)
+        }
+        httparse::Status::Partial => {
+            return Ok(Decoded::Partial {
+                expect_continue: false,
+            })
+        }
+    };
+
+    // find out the body framing
+    let mut chunked = false;
+    let mut content_length = None;
+    let mut expect_continue = false;
+    for h in r.headers.iter() {
+        if h.name.eq_ignore_ascii_case("transfer-encoding") {
+            let value =
+                str::from_utf8(h.value).map_err(|_| invalid("invalid transfer-encoding"))?;
+            // chunked must be the last coding
+            match value.rsplit(',').next().map(str::trim) {
+                Some(v) if v.eq_ignore_ascii_case("chunked") => chunked = true,
+                _ => return Err(invalid("unsupported transfer-encoding")),
+            }
+        } else if h.name.eq_ignore_ascii_case("content-length") {
+            let len = str::from_utf8(h.value)
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .ok_or_else(|| invalid("invalid content-length"))?;
+            if content_length.map_or(false, |l| l != len) {
+                return Err(invalid("conflicting content-length"));
+            }
+            content_length = Some(len);
+        } else if h.name.eq_ignore_ascii_case("expect") {
+            expect_continue = h.value.eq_ignore_ascii_case(b"100-continue");
+        }
+    }
+    // the two framings may be read differently by a proxy in front,
+    // which lets a request be smuggled in the body
+    if chunked && content_length.is_some() {
+        return Ok(Decoded::BadRequest);
+    }
+    // only HTTP/1.1 clients wait for `100 Continue`
+    let expect_continue = expect_continue && r.version == Some(1);
+
+    let limit = config.max_body_size() as u64;
+    // the body can't be streamed without the connection
+    let threshold = match stream {
+        Some(_) => std::cmp::min(config.body_buffer_size() as u64, limit),
+        None => limit,
     };
+    let received = (buf.len() - amt) as u64;
 
     let toslice = |a: &[u8]| {
         let start = a.as_ptr() as usize - buf.as_ptr() as usize;
@@ -79,23 +228,75 @@ pub fn decode(buf: &mut BytesMut) -> io::Result<Option<Request>> {
         (start, start + a.len())
     };
 
-    let mut headers: [(Slice, Slice); 16] =
-        unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-    let mut headers_len = 0;
-    for h in r.headers.iter() {
-        debug_assert!(headers_len < 16);
-        *unsafe { headers.get_unchecked_mut(headers_len) } =
-            (toslice(h.name.as_bytes()), toslice(h.value));
-        headers_len += 1;
-    }
+    let headers = r
+        .headers
+        .iter()
+        .map(|h| (toslice(h.name.as_bytes()), toslice(h.value)))
+        .collect();
+    let method = toslice(r.method.unwrap().as_bytes());
+    let path = toslice(r.path.unwrap().as_bytes());
+    let version = r.version.unwrap();
+
+    let (data, body) = if chunked {
+        let mut decoder = ChunkedDecoder::new();
+        let mut body = BytesMut::new();
+        let n = decoder.decode(&buf[amt..], &mut body)?;
+        if body.len() as u64 > limit {
+            return Ok(Decoded::TooLarge);
+        }
+        if decoder.is_done() {
+            let data = buf.split_to(amt);
+            buf.advance(n);
+            (data, BodyReader::buffered(body))
+        } else {
+            let stream = match stream {
+                Some(s) if body.len() as u64 > threshold => body_stream(s, config)?,
+                _ => {
+                    return Ok(Decoded::Partial {
+                        expect_continue: expect_continue && received == 0,
+                    })
+                }
+            };
+            let data = buf.split_to(amt);
+            buf.advance(n);
+            // the bytes after the decoded part are owned by the body reader
+            let raw = buf.split();
+            // the client is already sending the body
+            let body = BodyReader::chunked(body, decoder, raw, limit, stream, false);
+            (data, body)
+        }
+    } else {
+        let len = content_length.unwrap_or(0);
+        if len > limit {
+            return Ok(Decoded::TooLarge);
+        }
+        if len <= threshold {
+            if received < len {
+                return Ok(Decoded::Partial {
+                    expect_continue: expect_continue && received == 0,
+                });
+            }
+            let data = buf.split_to(amt);
+            (data, BodyReader::buffered(buf.split_to(len as usize)))
+        } else {
+            // the threshold is the limit when there is no stream
+            let stream = body_stream(stream.unwrap(), config)?;
+            let data = buf.split_to(amt);
+            let take = std::cmp::min(len, received);
+            let prefix = buf.split_to(take as usize);
+            let expect_continue = expect_continue && received == 0;
+            let body = BodyReader::with_length(prefix, len - take, stream, expect_continue);
+            (data, body)
+        }
+    };
 
-    Ok(Some(Request {
-        method: toslice(r.method.unwrap().as_bytes()),
-        path: toslice(r.path.unwrap().as_bytes()),
-        version: r.version.unwrap(),
+    Ok(Decoded::Request(Request {
+        method,
+        path,
+        version,
         headers,
-        headers_len,
-        data: buf.split_to(amt),
+        data,
+        body,
     }))
 }
 
@@ -1,10 +1,22 @@
+use std::borrow::Cow;
 use std::io;
+use std::time::Duration;
 
 use bytes::BytesMut;
+use smallvec::SmallVec;
+
+use super::stream::{self, BodyFn, SseEvent};
+use super::Cookie;
+use super::StatusCode;
+use crate::net::TcpStream;
+use crate::std::sync::Receiver;
+
+// takes over the connection after a `101 Switching Protocols` rsp, with the
+// bytes that are already read after the req
+pub(crate) type UpgradeFn = Box<dyn FnOnce(TcpStream, BytesMut) + Send>;
 
 pub struct Response<'a> {
-    headers: [&'static str; 16],
-    headers_len: usize,
+    headers: SmallVec<[Header; 16]>,
     status_message: StatusMessage,
     body: Body,
     rsp_buf: &'a mut BytesMut,
@@ -14,6 +26,15 @@ enum Body {
     SMsg(&'static str),
     VMsg(Vec<u8>),
     DMsg,
+    // the body fn and the length of the body if it's known
+    Stream(BodyFn, Option<u64>),
+    Upgrade(UpgradeFn),
+}
+
+enum Header {
+    // the whole header line
+    Line(&'static str),
+    Pair(Cow<'static, str>, Cow<'static, str>),
 }
 
 struct StatusMessage {
@@ -24,8 +45,7 @@ struct StatusMessage {
 impl<'a> Response<'a> {
     pub(crate) fn new(rsp_buf: &'a mut BytesMut) -> Response {
         Response {
-            headers: unsafe { std::mem::MaybeUninit::uninit().assume_init() },
-            headers_len: 0,
+            headers: SmallVec::new(),
             body: Body::DMsg,
             status_message: StatusMessage {
                 code: "200",
@@ -40,13 +60,70 @@ impl<'a> Response<'a> {
         self
     }
 
+    /// the status code that is set, e.g. `"200"`
+    pub fn get_status_code(&self) -> &'static str {
+        self.status_message.code
+    }
+
+    // discard every thing set so far
+    pub(crate) fn reset(&mut self) {
+        self.headers.clear();
+        self.status_message = StatusMessage {
+            code: "200",
+            msg: "Ok",
+        };
+        self.body = Body::DMsg;
+        self.rsp_buf.clear();
+    }
+
+    pub fn status(&mut self, status: StatusCode) -> &mut Self {
+        self.status_code(status.as_str(), status.reason())
+    }
+
+    /// add a whole header line, e.g. `"Content-Type: text/plain"`
     pub fn header(&mut self, header: &'static str) -> &mut Self {
-        debug_assert!(self.headers_len < 16);
-        *unsafe { self.headers.get_unchecked_mut(self.headers_len) } = header;
-        self.headers_len += 1;
+        self.headers.push(Header::Line(header));
         self
     }
 
+    /// add a header from its name and value
+    ///
+    /// an `InvalidInput` error is returned if the name is not a valid token
+    /// or the value contains a line break
+    pub fn add_header<N, V>(&mut self, name: N, value: V) -> io::Result<&mut Self>
+    where
+        N: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        let (name, value) = (name.into(), value.into());
+        let valid_name = !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && !b"\"(),/:;<=>?@[\\]{}".contains(&b));
+        if !valid_name {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid header name",
+            ));
+        }
+        if value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid header value",
+            ));
+        }
+        self.headers.push(Header::Pair(name, value));
+        Ok(self)
+    }
+
+    /// add a `Set-Cookie` header for `cookie`
+    ///
+    /// an `InvalidInput` error is returned if the cookie is invalid, see `Cookie::validate`
+    pub fn set_cookie(&mut self, cookie: &Cookie) -> io::Result<&mut Self> {
+        cookie.validate()?;
+        self.add_header("Set-Cookie", cookie.to_string())
+    }
+
     pub fn body(&mut self, s: &'static str) {
         self.body = Body::SMsg(s);
     }
@@ -55,9 +132,72 @@ impl<'a> Response<'a> {
         self.body = Body::VMsg(v);
     }
 
+    /// stream the body from `f`, which is called after the service returns
+    ///
+    /// the written data is sent with the chunked transfer coding when the
+    /// internal buffer is full or `flush` is called, and writing blocks the
+    /// coroutine when the client can't keep up. the connection is closed if
+    /// `f` returns an error
+    pub fn body_stream<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut dyn io::Write) -> io::Result<()> + Send + 'static,
+    {
+        self.body = Body::Stream(Box::new(f), None);
+    }
+
+    /// stream a body of exactly `len` bytes from `f`
+    ///
+    /// unlike `body_stream` the body is sent with `Content-Length`, so the
+    /// client knows its size in advance. the connection is closed if `f`
+    /// writes more or less than `len` bytes
+    pub fn body_stream_len<F>(&mut self, len: u64, f: F)
+    where
+        F: FnOnce(&mut dyn io::Write) -> io::Result<()> + Send + 'static,
+    {
+        self.body = Body::Stream(Box::new(f), Some(len));
+    }
+
+    /// stream the body from `iter`, each item is sent as a chunk
+    pub fn body_iter<I, B>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = io::Result<B>>,
+        I::IntoIter: Send + 'static,
+        B: AsRef<[u8]>,
+    {
+        let iter = iter.into_iter();
+        self.body_stream(move |w| {
+            for data in iter {
+                w.write_all(data?.as_ref())?;
+                w.flush()?;
+            }
+            Ok(())
+        })
+    }
+
+    /// send the events from `rx` as server-sent events until all the senders are dropped
+    ///
+    /// a comment is sent every `keep_alive` when there is no event, so that the
+    /// proxies keep the stream open and a gone client is found out
+    pub fn sse(&mut self, rx: Receiver<SseEvent>, keep_alive: Option<Duration>) {
+        self.header("Content-Type: text/event-stream")
+            .header("Cache-Control: no-cache");
+        self.body_stream(move |w| stream::write_sse(w, rx, keep_alive))
+    }
+
+    // switch the connection to another protocol after the rsp is sent
+    pub(crate) fn upgrade(&mut self, f: UpgradeFn) {
+        self.status(StatusCode::SwitchingProtocols);
+        self.body = Body::Upgrade(f);
+    }
+
+    pub(crate) fn is_upgrade(&self) -> bool {
+        matches!(self.body, Body::Upgrade(_))
+    }
+
     pub fn body_mut(&mut self) -> &mut BytesMut {
         match self.body {
             Body::DMsg => {}
+            Body::Stream(..) | Body::Upgrade(_) => self.body = Body::DMsg,
             Body::SMsg(s) => {
                 self.rsp_buf.extend_from_slice(s.as_bytes());
                 self.body = Body::DMsg;
@@ -75,6 +215,7 @@ impl<'a> Response<'a> {
             Body::DMsg => self.rsp_buf.len(),
             Body::SMsg(s) => s.len(),
             Body::VMsg(ref v) => v.len(),
+            Body::Stream(..) | Body::Upgrade(_) => 0,
         }
     }
 
@@ -83,6 +224,7 @@ impl<'a> Response<'a> {
             Body::DMsg => self.rsp_buf.as_ref(),
             Body::SMsg(s) => s.as_bytes(),
             Body::VMsg(ref v) => v,
+            Body::Stream(..) | Body::Upgrade(_) => &[],
         }
     }
 
@@ -91,11 +233,93 @@ impl<'a> Response<'a> {
             Body::DMsg => self.rsp_buf.clear(),
             Body::SMsg(_) => {}
             Body::VMsg(_) => {}
+            Body::Stream(..) | Body::Upgrade(_) => {}
         }
     }
 }
 
-pub fn encode(mut msg: Response, mut buf: &mut BytesMut) {
+// how the end of the body is told to the client
+enum Framing {
+    Length(u64),
+    Chunked,
+    // the body ends when the connection is closed
+    Close,
+    // no body follows the head
+    None,
+}
+
+// what is written after the head of a rsp
+pub(crate) enum Tail {
+    // the body fn and the length of the body if it's known
+    Stream(BodyFn, Option<u64>),
+    Upgrade(UpgradeFn),
+}
+
+// the `1xx`, `204` and `304` rsps never have a body
+fn no_body(code: &str) -> bool {
+    code.starts_with('1') || code == "204" || code == "304"
+}
+
+/// encode the rsp into `buf`, a streaming body is dropped
+pub fn encode(msg: Response, buf: &mut BytesMut) {
+    encode_body(msg, buf, false)
+}
+
+// encode a rsp whose body is in memory, the body is left out for a `HEAD` req
+fn encode_body(mut msg: Response, buf: &mut BytesMut, head: bool) {
+    if no_body(msg.status_message.code) {
+        encode_head(&msg, buf, Framing::None);
+    } else {
+        let len = msg.body_len() as u64;
+        encode_head(&msg, buf, Framing::Length(len));
+        if !head {
+            buf.extend_from_slice(msg.get_body());
+        }
+    }
+    msg.clear_body();
+}
+
+// encode a rsp whose body may be streaming or that upgrades the connection,
+// what's left to do after the head is returned. a streaming body without a
+// length is chunked unless the client only speaks HTTP/1.0. no body is sent
+// for a `HEAD` req
+pub(crate) fn encode_stream(
+    mut msg: Response,
+    buf: &mut BytesMut,
+    chunked: bool,
+    head: bool,
+) -> Option<Tail> {
+    match std::mem::replace(&mut msg.body, Body::DMsg) {
+        Body::Stream(body, len) => {
+            let framing = match len {
+                _ if no_body(msg.status_message.code) => Framing::None,
+                Some(len) => Framing::Length(len),
+                None if chunked => Framing::Chunked,
+                None => Framing::Close,
+            };
+            let send = !head && !matches!(framing, Framing::None);
+            encode_head(&msg, buf, framing);
+            msg.rsp_buf.clear();
+            if send {
+                Some(Tail::Stream(body, len))
+            } else {
+                None
+            }
+        }
+        Body::Upgrade(f) => {
+            encode_head(&msg, buf, Framing::None);
+            msg.rsp_buf.clear();
+            Some(Tail::Upgrade(f))
+        }
+        body => {
+            msg.body = body;
+            encode_body(msg, buf, head);
+            None
+        }
+    }
+}
+
+fn encode_head(msg: &Response, mut buf: &mut BytesMut, framing: Framing) {
     if msg.status_message.msg == "Ok" {
         buf.extend_from_slice(b"HTTP/1.1 200 Ok\r\nServer: cogo\r\nDate: ");
     } else {
@@ -106,18 +330,29 @@ pub fn encode(mut msg: Response, mut buf: &mut BytesMut) {
         buf.extend_from_slice(b"\r\nServer: cogo\r\nDate: ");
     }
     crate::std::http::server::date::set_date(buf);
-    buf.extend_from_slice(b"\r\nContent-Length: ");
-    itoa::fmt(&mut buf, msg.body_len()).unwrap();
+    match framing {
+        Framing::Length(len) => {
+            buf.extend_from_slice(b"\r\nContent-Length: ");
+            itoa::fmt(&mut buf, len).unwrap();
+        }
+        Framing::Chunked => buf.extend_from_slice(b"\r\nTransfer-Encoding: chunked"),
+        Framing::Close => buf.extend_from_slice(b"\r\nConnection: close"),
+        Framing::None => {}
+    }
 
-    for i in 0..msg.headers_len {
-        let h = *unsafe { msg.headers.get_unchecked(i) };
+    for h in msg.headers.iter() {
         buf.extend_from_slice(b"\r\n");
-        buf.extend_from_slice(h.as_bytes());
+        match h {
+            Header::Line(line) => buf.extend_from_slice(line.as_bytes()),
+            Header::Pair(name, value) => {
+                buf.extend_from_slice(name.as_bytes());
+                buf.extend_from_slice(b": ");
+                buf.extend_from_slice(value.as_bytes());
+            }
+        }
     }
 
     buf.extend_from_slice(b"\r\n\r\n");
-    buf.extend_from_slice(msg.get_body());
-    msg.clear_body();
 }
 
 // impl io::Write for the response body
@@ -0,0 +1,464 @@
+//! request router on top of `HttpService`
+//!
+//! routes are matched in the order they are added, a pattern is made of
+//! `/` separated segments, `:name` captures one segment and `*name`, which
+//! must be the last one, captures the rest of the path
+
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::form::{percent_decode, Query};
+use super::{HttpService, Request, Response, Session, StatusCode};
+
+/// the request handler type of a route
+pub type Handler = dyn Fn(RouteRequest, &mut Response) -> io::Result<()> + Send + Sync;
+
+/// the middleware that wraps the routing of each request
+///
+/// call `next.run` to pass the request to the inner middlewares and then
+/// the matched route, or write the response directly to stop there
+pub trait Middleware: Send + Sync + 'static {
+    fn call(&self, req: RouteRequest, rsp: &mut Response, next: Next) -> io::Result<()>;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(RouteRequest, &mut Response, Next) -> io::Result<()> + Send + Sync + 'static,
+{
+    fn call(&self, req: RouteRequest, rsp: &mut Response, next: Next) -> io::Result<()> {
+        self(req, rsp, next)
+    }
+}
+
+/// the rest of the middleware chain
+pub struct Next<'r> {
+    router: &'r Router,
+    middlewares: &'r [Arc<dyn Middleware>],
+}
+
+impl<'r> Next<'r> {
+    pub fn run(self, req: RouteRequest, rsp: &mut Response) -> io::Result<()> {
+        match self.middlewares.split_first() {
+            Some((m, rest)) => m.call(
+                req,
+                rsp,
+                Next {
+                    router: self.router,
+                    middlewares: rest,
+                },
+            ),
+            None => self.router.dispatch(req, rsp),
+        }
+    }
+}
+
+/// the request passed to the route handlers and middlewares
+///
+/// it derefs to the underlying `Request`
+pub struct RouteRequest {
+    req: Request,
+    params: Vec<(String, String)>,
+    query: Query,
+    request_id: Option<String>,
+    session: Option<Session>,
+}
+
+impl RouteRequest {
+    pub fn new(req: Request) -> Self {
+        let query = match req.path().find('?') {
+            Some(i) => Query::parse(&req.path()[i + 1..]),
+            None => Query::default(),
+        };
+        RouteRequest {
+            req,
+            params: Vec::new(),
+            query,
+            request_id: None,
+            session: None,
+        }
+    }
+
+    /// the request path without the query string
+    pub fn route_path(&self) -> &str {
+        let path = self.req.path();
+        path.split('?').next().unwrap_or(path)
+    }
+
+    /// get the path param captured by `:name` or `*name`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// get the path param parsed as `T`
+    ///
+    /// an `InvalidInput` error is returned if it's missing or fails to parse,
+    /// so that handlers could simply use `?` on it
+    pub fn param_as<T: FromStr>(&self, name: &str) -> io::Result<T> {
+        self.param(name)
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| {
+                let msg = format!("invalid path param `{}`", name);
+                io::Error::new(io::ErrorKind::InvalidInput, msg)
+            })
+    }
+
+    /// all the path params in pattern order
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// the parsed query string
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// the id set by the `RequestId` middleware
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub fn set_request_id(&mut self, id: String) {
+        self.request_id = Some(id);
+    }
+
+    /// the session set by the `Sessions` middleware
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    pub fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    pub fn into_inner(self) -> Request {
+        self.req
+    }
+}
+
+impl Deref for RouteRequest {
+    type Target = Request;
+
+    fn deref(&self) -> &Request {
+        &self.req
+    }
+}
+
+impl DerefMut for RouteRequest {
+    fn deref_mut(&mut self) -> &mut Request {
+        &mut self.req
+    }
+}
+
+impl fmt::Debug for RouteRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RouteRequest")
+            .field("req", &self.req)
+            .field("params", &self.params)
+            .field("query", &self.query)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Rest(String),
+}
+
+#[derive(Debug, Clone)]
+struct Pattern(Vec<Segment>);
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        assert!(pattern.starts_with('/'), "pattern must start with `/`");
+        let parts: Vec<&str> = pattern[1..].split('/').collect();
+        let n = parts.len();
+        let segments = parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| {
+                if let Some(name) = p.strip_prefix(':') {
+                    assert!(!name.is_empty(), "empty param name in `{}`", pattern);
+                    Segment::Param(name.to_owned())
+                } else if let Some(name) = p.strip_prefix('*') {
+                    assert!(i + 1 == n, "`*{}` must be the last segment", name);
+                    Segment::Rest(name.to_owned())
+                } else {
+                    Segment::Static(p.to_owned())
+                }
+            })
+            .collect();
+        Pattern(segments)
+    }
+
+    // match the path, the captured params are pushed to `params`
+    fn matches(&self, path: &str, params: &mut Vec<(String, String)>) -> bool {
+        let path = match path.strip_prefix('/') {
+            Some(p) => p,
+            None => return false,
+        };
+        let mut parts = path.split('/');
+        let mut rest = path;
+        for seg in self.0.iter() {
+            if let Segment::Rest(name) = seg {
+                params.push((name.clone(), percent_decode(rest)));
+                return true;
+            }
+            let part = match parts.next() {
+                Some(p) => p,
+                None => return false,
+            };
+            rest = rest.get(part.len() + 1..).unwrap_or("");
+            match seg {
+                Segment::Static(s) if s == part => {}
+                Segment::Param(name) if !part.is_empty() => {
+                    params.push((name.clone(), percent_decode(part)));
+                }
+                _ => return false,
+            }
+        }
+        parts.next().is_none()
+    }
+}
+
+#[derive(Clone)]
+struct Route {
+    // `None` matches any method
+    method: Option<String>,
+    pattern: Pattern,
+    handler: Arc<Handler>,
+}
+
+/// the http service that dispatches requests to the matched routes
+///
+/// requests that match no route get `404 Not Found`, and those that only
+/// match the path of some routes get `405 Method Not Allowed`
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::std::http::server::{HttpServer, Router};
+///
+/// let router = Router::new()
+///     .get("/users/:id", |req, rsp| {
+///         let id: u64 = req.param_as("id")?;
+///         rsp.body_vec(format!("user {}", id).into_bytes());
+///         Ok(())
+///     })
+///     .get("/static/*rest", |req, rsp| {
+///         rsp.body_vec(req.param("rest").unwrap_or("").as_bytes().to_vec());
+///         Ok(())
+///     });
+/// let server = HttpServer(router).start("127.0.0.1:8080").unwrap();
+/// server.join().unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Arc<Vec<Route>>,
+    middlewares: Arc<Vec<Arc<dyn Middleware>>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add a route for `method` and `pattern`
+    ///
+    /// # Panics
+    ///
+    /// panics if the pattern is invalid
+    pub fn route<H>(mut self, method: &str, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(RouteRequest, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let route = Route {
+            method: Some(method.to_ascii_uppercase()),
+            pattern: Pattern::parse(pattern),
+            handler: Arc::new(handler),
+        };
+        Arc::make_mut(&mut self.routes).push(route);
+        self
+    }
+
+    /// add a route for any method
+    pub fn any<H>(mut self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(RouteRequest, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let route = Route {
+            method: None,
+            pattern: Pattern::parse(pattern),
+            handler: Arc::new(handler),
+        };
+        Arc::make_mut(&mut self.routes).push(route);
+        self
+    }
+
+    pub fn get<H>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(RouteRequest, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post<H>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(RouteRequest, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn put<H>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(RouteRequest, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route("PUT", pattern, handler)
+    }
+
+    pub fn delete<H>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(RouteRequest, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route("DELETE", pattern, handler)
+    }
+
+    pub fn patch<H>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(RouteRequest, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route("PATCH", pattern, handler)
+    }
+
+    /// add a middleware, the first added one is the outermost
+    pub fn with<M: Middleware>(mut self, middleware: M) -> Self {
+        Arc::make_mut(&mut self.middlewares).push(Arc::new(middleware));
+        self
+    }
+
+    // find the route and call its handler
+    fn dispatch(&self, mut req: RouteRequest, rsp: &mut Response) -> io::Result<()> {
+        let mut allowed: Vec<&str> = Vec::new();
+        let mut params = Vec::new();
+        for route in self.routes.iter() {
+            params.clear();
+            if !route.pattern.matches(req.route_path(), &mut params) {
+                continue;
+            }
+            match route.method {
+                Some(ref m) if m != req.method() => {
+                    if !allowed.contains(&m.as_str()) {
+                        allowed.push(m);
+                    }
+                }
+                _ => {
+                    req.params = params;
+                    return (route.handler)(req, rsp);
+                }
+            }
+        }
+
+        if allowed.is_empty() {
+            rsp.status(StatusCode::NotFound);
+        } else {
+            rsp.status(StatusCode::MethodNotAllowed)
+                .add_header("Allow", allowed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Router {{ routes: {}, middlewares: {} }}",
+            self.routes.len(),
+            self.middlewares.len()
+        )
+    }
+}
+
+impl HttpService for Router {
+    fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+        let next = Next {
+            router: self,
+            middlewares: &self.middlewares,
+        };
+        next.run(RouteRequest::new(req), rsp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::http::server::call;
+
+    fn matches(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let mut params = Vec::new();
+        if Pattern::parse(pattern).matches(path, &mut params) {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    fn p(k: &str, v: &str) -> (String, String) {
+        (k.to_owned(), v.to_owned())
+    }
+
+    #[test]
+    fn pattern() {
+        assert_eq!(matches("/", "/"), Some(vec![]));
+        assert_eq!(matches("/users", "/users"), Some(vec![]));
+        assert_eq!(matches("/users", "/users/1"), None);
+        assert_eq!(
+            matches("/users/:id", "/users/4%202"),
+            Some(vec![p("id", "4 2")])
+        );
+        assert_eq!(matches("/users/:id", "/users/"), None);
+        assert_eq!(matches("/users/:id", "/users"), None);
+        assert_eq!(
+            matches("/static/*rest", "/static/css/a.css"),
+            Some(vec![p("rest", "css/a.css")])
+        );
+        assert_eq!(
+            matches("/static/*rest", "/static"),
+            Some(vec![p("rest", "")])
+        );
+        assert_eq!(matches("/static/*rest", "/assets/a"), None);
+    }
+
+    #[test]
+    fn routing() {
+        let mut router = Router::new()
+            .get("/users/:id", |req, rsp| {
+                let id: u32 = req.param_as("id")?;
+                let q = req.query().get("q").unwrap_or("").to_owned();
+                rsp.body_vec(format!("user {} {}", id, q).into_bytes());
+                Ok(())
+            })
+            .post("/users/:id", |_, _| Ok(()))
+            .get("/static/*rest", |req, rsp| {
+                rsp.body_vec(req.param("rest").unwrap().as_bytes().to_vec());
+                Ok(())
+            });
+
+        let rsp = call(&mut router, "GET /users/7?q=a+b HTTP/1.1\r\n\r\n");
+        assert!(rsp.ends_with("\r\n\r\nuser 7 a b"));
+        let rsp = call(&mut router, "GET /static/js/a.js HTTP/1.1\r\n\r\n");
+        assert!(rsp.ends_with("\r\n\r\njs/a.js"));
+        let rsp = call(&mut router, "DELETE /users/7 HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(rsp.contains("\r\nAllow: GET, POST\r\n"));
+        let rsp = call(&mut router, "GET /nope HTTP/1.1\r\n\r\n");
+        assert!(rsp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,473 @@
+//! server side sessions
+
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::{Cookie, CookieSigner, Middleware, Next, Response, RouteRequest, SameSite};
+use crate::std::map::SyncHashMap;
+
+/// the data of a session
+pub type SessionData = HashMap<String, String>;
+
+/// where the `Sessions` middleware keeps the session data
+pub trait SessionStore: Send + Sync + 'static {
+    /// load the data of session `id`, `None` if it doesn't exist or is expired
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>>;
+
+    /// save the data of session `id`, it expires if not saved again within `ttl`
+    fn save(&self, id: &str, data: SessionData, ttl: Duration) -> io::Result<()>;
+
+    fn remove(&self, id: &str) -> io::Result<()>;
+}
+
+// how often the memory store drops the expired sessions
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// the in memory session store, the default of `Sessions`
+///
+/// the expired sessions are dropped when loaded, and swept out periodically
+pub struct MemoryStore {
+    // the data and when it expires, only the locked accessors are used
+    sessions: SyncHashMap<String, (SessionData, Instant)>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            sessions: SyncHashMap::new(),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// the number of the sessions, including the expired ones not swept out yet
+    pub fn len(&self) -> usize {
+        self.sessions.iter_mut().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn sweep(&self) {
+        let now = Instant::now();
+        {
+            let mut last = self.last_sweep.lock().unwrap_or_else(|e| e.into_inner());
+            if now.duration_since(*last) < SWEEP_INTERVAL {
+                return;
+            }
+            *last = now;
+        }
+        let expired: Vec<String> = self
+            .sessions
+            .iter_mut()
+            .filter(|(_, (_, expires))| *expires <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            self.sessions.remove(&id);
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>> {
+        let expired = match self.sessions.get_mut(id) {
+            Some(entry) if entry.1 > Instant::now() => return Ok(Some(entry.0.clone())),
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            self.sessions.remove(&id.to_owned());
+        }
+        Ok(None)
+    }
+
+    fn save(&self, id: &str, data: SessionData, ttl: Duration) -> io::Result<()> {
+        self.sweep();
+        self.sessions
+            .insert(id.to_owned(), (data, Instant::now() + ttl));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.sessions.remove(&id.to_owned());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    data: SessionData,
+    renew: bool,
+    destroy: bool,
+}
+
+/// the session of a request, got by `RouteRequest::session`
+///
+/// it's a handle, the clones share the same data
+#[derive(Debug, Clone, Default)]
+pub struct Session(Arc<Mutex<State>>);
+
+impl Session {
+    fn new(data: SessionData) -> Self {
+        Session(Arc::new(Mutex::new(State {
+            data,
+            ..State::default()
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state().data.get(key).cloned()
+    }
+
+    /// get the value of `key` parsed as `T`
+    pub fn get_as<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.state().data.get(key).and_then(|v| v.parse().ok())
+    }
+
+    pub fn set<K: Into<String>, V: Into<String>>(&self, key: K, value: V) {
+        self.state().data.insert(key.into(), value.into());
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.state().data.remove(key)
+    }
+
+    pub fn clear(&self) {
+        self.state().data.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state().data.is_empty()
+    }
+
+    /// give the session a new id and keep the data
+    ///
+    /// call it when the user logs in, to prevent the session fixation
+    pub fn renew(&self) {
+        self.state().renew = true;
+    }
+
+    /// remove the session from the store and the client
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.destroy = true;
+        state.data.clear();
+    }
+}
+
+// 128 random bits in url safe base64
+fn new_id() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    Ok(base64::encode_config(bytes, base64::URL_SAFE_NO_PAD))
+}
+
+fn valid_id(id: &str) -> bool {
+    id.len() == 22
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// the session middleware
+///
+/// the session id is kept in a cookie and the data in a `SessionStore`,
+/// the handlers get the session by `RouteRequest::session`. a session is only
+/// created once some data is set, and each request renews its ttl
+///
+/// # Examples
+///
+/// ```no_run
+/// use cogo::std::http::server::{HttpServer, Router, Sessions};
+///
+/// let router = Router::new()
+///     .post("/login", |req, _| {
+///         let session = req.session().unwrap();
+///         session.renew();
+///         session.set("user", "alice");
+///         Ok(())
+///     })
+///     .get("/", |req, rsp| {
+///         let user = req.session().and_then(|s| s.get("user"));
+///         rsp.body_vec(format!("hello {:?}", user).into_bytes());
+///         Ok(())
+///     })
+///     .with(Sessions::new().signed(b"a secret key of at least 32 bytes"));
+/// let server = HttpServer(router).start("127.0.0.1:8080").unwrap();
+/// server.join().unwrap();
+/// ```
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    signer: Option<CookieSigner>,
+    path: String,
+    domain: Option<String>,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sessions {
+    /// keep the sessions in a `MemoryStore` for a day, in the `sid` cookie
+    pub fn new() -> Self {
+        Sessions {
+            store: Arc::new(MemoryStore::new()),
+            cookie_name: "sid".to_owned(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            signer: None,
+            path: "/".to_owned(),
+            domain: None,
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    pub fn store<S: SessionStore>(mut self, store: S) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+    /// how long an idle session lives
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// sign the session cookie with `key`, see `CookieSigner`
+    pub fn signed(mut self, key: &[u8]) -> Self {
+        self.signer = Some(CookieSigner::new(key));
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    /// only send the session cookie over https
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    // the session id sent by the client, if it's well formed and signed
+    fn client_id<'a>(&self, req: &'a RouteRequest) -> Option<&'a str> {
+        let value = req.cookie(&self.cookie_name)?;
+        let id = match self.signer {
+            Some(ref signer) => signer.verify(&self.cookie_name, value)?,
+            None => value,
+        };
+        Some(id).filter(|id| valid_id(id))
+    }
+
+    // add the configured attributes to the session cookie
+    fn cookie(&self, cookie: Cookie) -> Cookie {
+        let cookie = cookie
+            .path(&self.path)
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(self.same_site);
+        match self.domain {
+            Some(ref domain) => cookie.domain(domain),
+            None => cookie,
+        }
+    }
+}
+
+impl Middleware for Sessions {
+    fn call(&self, mut req: RouteRequest, rsp: &mut Response, next: Next) -> io::Result<()> {
+        let id = self.client_id(&req).map(str::to_owned);
+        let data = match id {
+            Some(ref id) => self.store.load(id)?,
+            None => None,
+        };
+        // an unknown id is never adopted
+        let id = if data.is_some() { id } else { None };
+        let session = Session::new(data.unwrap_or_default());
+        req.set_session(session.clone());
+        let ret = next.run(req, rsp);
+
+        let state = std::mem::take(&mut *session.state());
+        if state.destroy {
+            if let Some(id) = id {
+                self.store.remove(&id)?;
+                rsp.set_cookie(&self.cookie(Cookie::removal(self.cookie_name.as_str())))?;
+            }
+            return ret;
+        }
+        if id.is_none() && state.data.is_empty() {
+            return ret;
+        }
+        let id = match id {
+            Some(id) if !state.renew => id,
+            old => {
+                if let Some(old) = old {
+                    self.store.remove(&old)?;
+                }
+                new_id()?
+            }
+        };
+        self.store.save(&id, state.data, self.ttl)?;
+        let value = match self.signer {
+            Some(ref signer) => signer.sign(&self.cookie_name, &id),
+            None => id,
+        };
+        let cookie = Cookie::new(self.cookie_name.as_str(), value).max_age(self.ttl);
+        rsp.set_cookie(&self.cookie(cookie))?;
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::http::server::{call, Router};
+
+    // the `name=value` of the set cookie, if any
+    fn set_cookie(rsp: &str) -> Option<String> {
+        let line = rsp.lines().find(|l| l.starts_with("Set-Cookie: "))?;
+        let value = line["Set-Cookie: ".len()..].split(';').next()?;
+        Some(value.to_owned())
+    }
+
+    fn get(router: &mut Router, path: &str, cookie: Option<&str>) -> String {
+        let cookie = cookie.map_or(String::new(), |c| format!("Cookie: a=1; {}\r\n", c));
+        call(router, &format!("GET {} HTTP/1.1\r\n{}\r\n", path, cookie))
+    }
+
+    fn routes() -> Router {
+        Router::new()
+            .get("/user", |req, rsp| {
+                let user = req.session().unwrap().get("user").unwrap_or_default();
+                rsp.body_vec(user.into_bytes());
+                Ok(())
+            })
+            .get("/login", |req, _| {
+                let session = req.session().unwrap();
+                session.renew();
+                session.set("user", "alice");
+                Ok(())
+            })
+            .get("/visit", |req, rsp| {
+                let session = req.session().unwrap();
+                let n = session.get_as::<u32>("n").unwrap_or(0) + 1;
+                session.set("n", n.to_string());
+                rsp.body_vec(n.to_string().into_bytes());
+                Ok(())
+            })
+            .get("/logout", |req, _| {
+                req.session().unwrap().destroy();
+                Ok(())
+            })
+    }
+
+    #[test]
+    fn lifecycle() {
+        let mut router = routes().with(Sessions::new());
+        // no session is created for nothing
+        let rsp = get(&mut router, "/user", None);
+        assert_eq!(set_cookie(&rsp), None);
+
+        let rsp = get(&mut router, "/login", None);
+        assert!(rsp.contains("; Path=/; Max-Age=86400; HttpOnly; SameSite=Lax\r\n"));
+        let sid = set_cookie(&rsp).unwrap();
+        assert!(sid.starts_with("sid="));
+        let rsp = get(&mut router, "/user", Some(&sid));
+        assert!(rsp.ends_with("\r\n\r\nalice"));
+        // the ttl rolls
+        assert_eq!(set_cookie(&rsp).as_ref(), Some(&sid));
+
+        // the old id is dropped on renew
+        let rsp = get(&mut router, "/login", Some(&sid));
+        let renewed = set_cookie(&rsp).unwrap();
+        assert_ne!(renewed, sid);
+        assert!(get(&mut router, "/user", Some(&sid)).ends_with("\r\n\r\n"));
+        assert!(get(&mut router, "/user", Some(&renewed)).ends_with("\r\n\r\nalice"));
+
+        let rsp = get(&mut router, "/logout", Some(&renewed));
+        assert!(rsp.contains("Set-Cookie: sid=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970"));
+        assert!(get(&mut router, "/user", Some(&renewed)).ends_with("\r\n\r\n"));
+
+        // an unknown id is replaced
+        let forged = "sid=AAAAAAAAAAAAAAAAAAAAAA";
+        let rsp = get(&mut router, "/visit", Some(forged));
+        assert!(rsp.ends_with("\r\n\r\n1"));
+        assert_ne!(set_cookie(&rsp).unwrap(), forged);
+    }
+
+    #[test]
+    fn signed() {
+        let key = b"0123456789abcdef0123456789abcdef";
+        let mut router = routes().with(Sessions::new().cookie_name("s").signed(key));
+        let rsp = get(&mut router, "/visit", None);
+        let sid = set_cookie(&rsp).unwrap();
+        let signer = CookieSigner::new(key);
+        let id = signer.verify("s", &sid[2..]).unwrap();
+        assert!(get(&mut router, "/visit", Some(&sid)).ends_with("\r\n\r\n2"));
+
+        // the unsigned and tampered ids are ignored
+        let unsigned = format!("s={}", id);
+        assert!(get(&mut router, "/visit", Some(&unsigned)).ends_with("\r\n\r\n1"));
+        let tampered = format!("{}x", sid);
+        assert!(get(&mut router, "/visit", Some(&tampered)).ends_with("\r\n\r\n1"));
+        assert!(get(&mut router, "/visit", Some(&sid)).ends_with("\r\n\r\n3"));
+    }
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryStore::new();
+        let mut data = SessionData::new();
+        data.insert("k".to_owned(), "v".to_owned());
+        store
+            .save("a", data.clone(), Duration::from_secs(60))
+            .unwrap();
+        store
+            .save("b", data.clone(), Duration::from_millis(0))
+            .unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.load("a").unwrap(), Some(data));
+        // the expired one is dropped when loaded
+        assert_eq!(store.load("b").unwrap(), None);
+        assert_eq!(store.len(), 1);
+        store.remove("a").unwrap();
+        assert_eq!(store.load("a").unwrap(), None);
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+
+macro_rules! status_codes {
+    ($($name: ident = $code: literal, $reason: expr;)*) => {
+        /// the standard http status codes
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum StatusCode {
+            $($name = $code,)*
+        }
+
+        impl StatusCode {
+            /// get the status code from its number
+            pub fn from_u16(code: u16) -> Option<StatusCode> {
+                match code {
+                    $($code => Some(StatusCode::$name),)*
+                    _ => None,
+                }
+            }
+
+            /// the code number as a string, e.g. `"404"`
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(StatusCode::$name => stringify!($code),)*
+                }
+            }
+
+            /// the canonical reason phrase, e.g. `"Not Found"`
+            pub fn reason(self) -> &'static str {
+                match self {
+                    $(StatusCode::$name => $reason,)*
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    PayloadTooLarge = 413, "Payload Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableEntity = 422, "Unprocessable Entity";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
+}
+
+impl StatusCode {
+    /// the code number
+    pub fn as_u16(self) -> u16 {
+        self as u16
+    }
+
+    pub fn is_informational(self) -> bool {
+        self.as_u16() < 200
+    }
+
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.as_u16())
+    }
+
+    pub fn is_redirection(self) -> bool {
+        (300..400).contains(&self.as_u16())
+    }
+
+    pub fn is_client_error(self) -> bool {
+        (400..500).contains(&self.as_u16())
+    }
+
+    pub fn is_server_error(self) -> bool {
+        self.as_u16() >= 500
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.as_str(), self.reason())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_code() {
+        let s = StatusCode::from_u16(431).unwrap();
+        assert_eq!(s, StatusCode::RequestHeaderFieldsTooLarge);
+        assert_eq!(s.as_str(), "431");
+        assert_eq!(s.as_u16(), 431);
+        assert!(s.is_client_error());
+        assert_eq!(StatusCode::NotFound.to_string(), "404 Not Found");
+        assert_eq!(StatusCode::from_u16(299), None);
+    }
+}
@@ -0,0 +1,198 @@
+//! streaming response bodies
+
+use std::io::{self, Write};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use crate::std::sync::Receiver;
+
+/// the writer passed to a streaming body, see `Response::body_stream`
+pub type BodyFn = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+// the buffer size before the written data is sent as a chunk
+pub(crate) const CHUNK_SIZE: usize = 8 * 1024;
+
+// write each buffer as a chunk of the chunked transfer coding
+pub(crate) struct ChunkedWriter<W: Write>(pub W);
+
+impl<W: Write> ChunkedWriter<W> {
+    // write the last chunk
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.0.write_all(b"0\r\n\r\n")?;
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // an empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.0, "{:x}\r\n", buf.len())?;
+        self.0.write_all(buf)?;
+        self.0.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// write a body of exactly the declared length
+pub(crate) struct LengthWriter<W: Write> {
+    inner: W,
+    // the bytes that are not written yet
+    left: u64,
+}
+
+impl<W: Write> LengthWriter<W> {
+    pub fn new(inner: W, len: u64) -> Self {
+        LengthWriter { inner, left: len }
+    }
+
+    // check that the whole body is written
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.left != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "streamed body is shorter than its length",
+            ));
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for LengthWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.left {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "streamed body is longer than its length",
+            ));
+        }
+        let n = self.inner.write(buf)?;
+        self.left -= n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// an event of the server-sent events stream
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    data: String,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+// the line breaks would split the field
+fn one_line(s: &str) -> String {
+    s.replace(|c| c == '\r' || c == '\n', " ")
+}
+
+impl SseEvent {
+    /// create an event with `data`, which could span multiple lines
+    pub fn new<S: Into<String>>(data: S) -> Self {
+        SseEvent {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// set the event type, the client listens to it by `addEventListener`
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(one_line(event));
+        self
+    }
+
+    /// set the event id, the client sends it back in `Last-Event-ID` on reconnecting
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(one_line(id));
+        self
+    }
+
+    /// set the reconnection time of the client
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        if let Some(ref event) = self.event {
+            buf.extend_from_slice(format!("event: {}\n", event).as_bytes());
+        }
+        if let Some(ref id) = self.id {
+            buf.extend_from_slice(format!("id: {}\n", id).as_bytes());
+        }
+        if let Some(retry) = self.retry {
+            buf.extend_from_slice(format!("retry: {}\n", retry.as_millis()).as_bytes());
+        }
+        for line in self.data.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            buf.extend_from_slice(b"data: ");
+            buf.extend_from_slice(line.as_bytes());
+            buf.push(b'\n');
+        }
+        buf.push(b'\n');
+    }
+}
+
+// send the events from `rx` until all the senders are dropped
+pub(crate) fn write_sse(
+    w: &mut dyn Write,
+    rx: Receiver<SseEvent>,
+    keep_alive: Option<Duration>,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    loop {
+        let ret = match keep_alive {
+            Some(dur) => rx.recv_timeout(dur),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match ret {
+            Ok(event) => {
+                buf.clear();
+                event.encode(&mut buf);
+                w.write_all(&buf)?;
+            }
+            // a comment that keeps the proxies from closing the idle stream
+            Err(RecvTimeoutError::Timeout) => w.write_all(b": keep-alive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        w.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunked_writer() {
+        let mut w = ChunkedWriter(Vec::new());
+        w.write_all(b"hello").unwrap();
+        w.write_all(&[b'x'; 20]).unwrap();
+        w.write_all(b"").unwrap();
+        w.finish().unwrap();
+        let expect = format!("5\r\nhello\r\n14\r\n{}\r\n0\r\n\r\n", "x".repeat(20));
+        assert_eq!(String::from_utf8(w.0).unwrap(), expect);
+    }
+
+    #[test]
+    fn sse_event() {
+        let mut buf = Vec::new();
+        SseEvent::new("a\r\nb")
+            .event("up\ndate")
+            .id("1")
+            .retry(Duration::from_secs(3))
+            .encode(&mut buf);
+        let expect = "event: up date\nid: 1\nretry: 3000\ndata: a\ndata: b\n\n";
+        assert_eq!(String::from_utf8(buf).unwrap(), expect);
+    }
+}
@@ -0,0 +1,612 @@
+//! websocket (RFC 6455) on top of the http server
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+
+use super::{Request, Response, StatusCode};
+use crate::net::TcpStream;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// the frame opcodes
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+// the close codes that are sent on errors
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_PAYLOAD: u16 = 1007;
+const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// a websocket message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// the close code and reason, `None` if the close frame carries no code
+    Close(Option<(u16, String)>),
+}
+
+// a decoded frame
+#[derive(Debug)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[i & 3];
+    }
+}
+
+// decode a frame from `buf`, `masked` tells whether the peer must mask it
+// return the close code on a bad frame
+fn decode_frame(buf: &mut BytesMut, masked: bool, max_size: usize) -> Result<Option<Frame>, u16> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let (b0, b1) = (buf[0], buf[1]);
+    let fin = b0 & 0x80 != 0;
+    let opcode = b0 & 0x0f;
+    // no extension is negotiated, so the rsv bits must be clear
+    if b0 & 0x70 != 0 || (b1 & 0x80 != 0) != masked {
+        return Err(PROTOCOL_ERROR);
+    }
+    match opcode {
+        CONTINUATION | TEXT | BINARY => {}
+        // control frames can't be fragmented
+        CLOSE | PING | PONG if fin && b1 & 0x7f <= 125 => {}
+        _ => return Err(PROTOCOL_ERROR),
+    }
+
+    let (len, mut pos) = match b1 & 0x7f {
+        126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() >= 10 => {
+            let mut len = [0; 8];
+            len.copy_from_slice(&buf[2..10]);
+            (u64::from_be_bytes(len), 10)
+        }
+        126 | 127 => return Ok(None),
+        n => (n as u64, 2),
+    };
+    if len > max_size as u64 {
+        return Err(MESSAGE_TOO_BIG);
+    }
+    let len = len as usize;
+    let mask = if masked {
+        if buf.len() < pos + 4 {
+            return Ok(None);
+        }
+        let mask = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+        pos += 4;
+        Some(mask)
+    } else {
+        None
+    };
+    if buf.len() < pos + len {
+        return Ok(None);
+    }
+
+    buf.advance(pos);
+    let mut payload = buf.split_to(len).to_vec();
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+    Ok(Some(Frame {
+        fin,
+        opcode,
+        payload,
+    }))
+}
+
+// encode a frame into `buf`, only the client frames are masked
+fn encode_frame(buf: &mut Vec<u8>, fin: bool, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) {
+    buf.push(if fin { 0x80 | opcode } else { opcode });
+    let masked = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        n if n < 126 => buf.push(masked | n as u8),
+        n if n <= 0xffff => {
+            buf.push(masked | 126);
+            buf.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            buf.push(masked | 127);
+            buf.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    let start = buf.len();
+    match mask {
+        Some(mask) => {
+            buf.extend_from_slice(&mask);
+            buf.extend_from_slice(payload);
+            apply_mask(&mut buf[start + 4..], mask);
+        }
+        None => buf.extend_from_slice(payload),
+    }
+}
+
+// the `Sec-WebSocket-Accept` value for the `Sec-WebSocket-Key` of the client
+fn accept_key(key: &[u8]) -> String {
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key);
+    sha1.update(GUID.as_bytes());
+    base64::encode(sha1.digest().bytes())
+}
+
+// check whether the comma separated values of the headers named `name` contain `token`
+fn has_token(req: &Request, name: &str, token: &str) -> bool {
+    req.headers()
+        .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+        .filter_map(|(_, v)| std::str::from_utf8(v).ok())
+        .any(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+}
+
+// validate the handshake req, return the accept key
+fn handshake(req: &Request) -> Result<String, StatusCode> {
+    if req.method() != "GET"
+        || req.version() != 1
+        || !has_token(req, "Upgrade", "websocket")
+        || !has_token(req, "Connection", "upgrade")
+    {
+        return Err(StatusCode::BadRequest);
+    }
+    if req.header("Sec-WebSocket-Version") != Some(b"13") {
+        return Err(StatusCode::UpgradeRequired);
+    }
+    match req.header("Sec-WebSocket-Key") {
+        Some(key) if base64::decode(key).map_or(false, |k| k.len() == 16) => Ok(accept_key(key)),
+        _ => Err(StatusCode::BadRequest),
+    }
+}
+
+// the close codes that may be received
+fn valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// a websocket connection
+///
+/// `send` and `recv` park the coroutine until the frames are written or
+/// received, pings of the peer are answered automatically
+pub struct WebSocket {
+    stream: TcpStream,
+    rbuf: BytesMut,
+    wbuf: Vec<u8>,
+    max_message_size: usize,
+    // a ping is sent and nothing is received since
+    ping_sent: bool,
+    // the opcode and data of an unfinished fragmented message
+    fragments: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    /// upgrade the connection of `req` to a websocket, `f` is called with it
+    /// after the handshake rsp is sent
+    ///
+    /// `f` runs in the connection coroutine, and the connection is closed
+    /// when it returns. return false if the handshake is invalid, which is
+    /// answered with `400 Bad Request`, or `426 Upgrade Required` for an
+    /// unsupported version
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use cogo::std::http::server::{HttpServer, Message, Router, WebSocket};
+    ///
+    /// let router = Router::new().get("/echo", |req, rsp| {
+    ///     WebSocket::upgrade(&req, rsp, |mut ws| {
+    ///         while let Ok(msg) = ws.recv() {
+    ///             match msg {
+    ///                 Message::Close(_) => break,
+    ///                 msg => ws.send(msg).unwrap(),
+    ///             }
+    ///         }
+    ///     });
+    ///     Ok(())
+    /// });
+    /// let server = HttpServer(router).start("127.0.0.1:8080").unwrap();
+    /// server.join().unwrap();
+    /// ```
+    pub fn upgrade<F>(req: &Request, rsp: &mut Response, f: F) -> bool
+    where
+        F: FnOnce(WebSocket) + Send + 'static,
+    {
+        match handshake(req) {
+            Ok(accept) => {
+                rsp.upgrade(Box::new(move |stream, buf| f(WebSocket::new(stream, buf))));
+                rsp.header("Upgrade: websocket")
+                    .header("Connection: Upgrade")
+                    .add_header("Sec-WebSocket-Accept", accept)
+                    .is_ok()
+            }
+            Err(status) => {
+                rsp.status(status);
+                if status == StatusCode::UpgradeRequired {
+                    rsp.header("Sec-WebSocket-Version: 13");
+                }
+                false
+            }
+        }
+    }
+
+    fn new(stream: TcpStream, rbuf: BytesMut) -> Self {
+        // the http server may have set a read timeout
+        stream.set_read_timeout(None).ok();
+        WebSocket {
+            stream,
+            rbuf,
+            wbuf: Vec::new(),
+            max_message_size: 16 * 1024 * 1024,
+            ping_sent: false,
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// set the max size of a received message
+    ///
+    /// a larger message fails the connection with the close code 1009
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// ping the peer when nothing is received for `interval`
+    ///
+    /// `recv` fails with `TimedOut` when still nothing is received after
+    /// another `interval`. `None`, the default, disables the keepalive
+    pub fn set_ping_interval(&mut self, interval: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(interval)
+    }
+
+    /// send a message, `Message::Close` starts the close handshake
+    pub fn send(&mut self, msg: Message) -> io::Result<()> {
+        if self.close_sent {
+            return Err(closed());
+        }
+        match msg {
+            Message::Text(s) => self.write_frame(TEXT, s.as_bytes()),
+            Message::Binary(data) => self.write_frame(BINARY, &data),
+            Message::Close(close) => {
+                let close = close
+                    .as_ref()
+                    .map(|(code, reason)| (*code, reason.as_str()));
+                self.send_close(close)
+            }
+        }
+    }
+
+    /// send a ping with at most 125 bytes of `data`
+    pub fn ping(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() > 125 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ping data is too long",
+            ));
+        }
+        if self.close_sent {
+            return Err(closed());
+        }
+        self.write_frame(PING, data)
+    }
+
+    /// receive a message
+    ///
+    /// `Message::Close` is returned once the peer closes the websocket, the
+    /// close handshake is replied if it's started by the peer
+    pub fn recv(&mut self) -> io::Result<Message> {
+        loop {
+            if self.close_received {
+                return Err(closed());
+            }
+            let frame = self.read_frame()?;
+            match frame.opcode {
+                PING => {
+                    if !self.close_sent {
+                        self.write_frame(PONG, &frame.payload)?;
+                    }
+                }
+                PONG => {}
+                CLOSE => {
+                    self.close_received = true;
+                    let close = match *frame.payload {
+                        [] => None,
+                        [a, b, ref reason @ ..] if valid_close_code(u16::from_be_bytes([a, b])) => {
+                            match std::str::from_utf8(reason) {
+                                Ok(reason) => Some((u16::from_be_bytes([a, b]), reason.to_owned())),
+                                Err(_) => return Err(self.fail(INVALID_PAYLOAD)),
+                            }
+                        }
+                        _ => return Err(self.fail(PROTOCOL_ERROR)),
+                    };
+                    if !self.close_sent {
+                        // echo the code to finish the close handshake
+                        self.send_close(close.as_ref().map(|(code, _)| (*code, "")))?;
+                    }
+                    return Ok(Message::Close(close));
+                }
+                opcode => {
+                    if let Some(msg) = self.push_fragment(opcode, frame.fin, frame.payload)? {
+                        return Ok(msg);
+                    }
+                }
+            }
+        }
+    }
+
+    /// close the websocket with `code` and `reason`, and wait for the peer
+    /// to reply the close handshake
+    ///
+    /// the messages received in the meantime are dropped
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if !self.close_sent {
+            self.send_close(Some((code, reason)))?;
+        }
+        while !self.close_received {
+            self.recv()?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        self.wbuf.clear();
+        encode_frame(&mut self.wbuf, true, opcode, payload, None);
+        self.stream.write_all(&self.wbuf)
+    }
+
+    fn send_close(&mut self, close: Option<(u16, &str)>) -> io::Result<()> {
+        self.close_sent = true;
+        let mut payload = Vec::new();
+        if let Some((code, reason)) = close {
+            // the control frame payload is at most 125 bytes
+            let mut end = reason.len().min(123);
+            while !reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.extend_from_slice(&code.to_be_bytes());
+            payload.extend_from_slice(reason[..end].as_bytes());
+        }
+        self.write_frame(CLOSE, &payload)
+    }
+
+    // close the websocket because of a bad frame from the peer
+    fn fail(&mut self, code: u16) -> io::Error {
+        if !self.close_sent {
+            self.send_close(Some((code, ""))).ok();
+        }
+        self.close_received = true;
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("websocket failed with close code {}", code),
+        )
+    }
+
+    // assemble the data frames into a message
+    fn push_fragment(
+        &mut self,
+        opcode: u8,
+        fin: bool,
+        payload: Vec<u8>,
+    ) -> io::Result<Option<Message>> {
+        let (opcode, data) = match (opcode, self.fragments.take()) {
+            (CONTINUATION, Some((opcode, mut data))) => {
+                if data.len() + payload.len() > self.max_message_size {
+                    return Err(self.fail(MESSAGE_TOO_BIG));
+                }
+                data.extend_from_slice(&payload);
+                (opcode, data)
+            }
+            (TEXT, None) | (BINARY, None) => (opcode, payload),
+            // a continuation without a start, or a new message inside a fragmented one
+            _ => return Err(self.fail(PROTOCOL_ERROR)),
+        };
+        if !fin {
+            self.fragments = Some((opcode, data));
+            return Ok(None);
+        }
+        if opcode == BINARY {
+            return Ok(Some(Message::Binary(data)));
+        }
+        match String::from_utf8(data) {
+            Ok(s) => Ok(Some(Message::Text(s))),
+            Err(_) => Err(self.fail(INVALID_PAYLOAD)),
+        }
+    }
+
+    fn read_frame(&mut self) -> io::Result<Frame> {
+        let mut buf = [0; 4096];
+        loop {
+            match decode_frame(&mut self.rbuf, true, self.max_message_size) {
+                Ok(Some(frame)) => return Ok(frame),
+                Ok(None) => {}
+                Err(code) => return Err(self.fail(code)),
+            }
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "websocket closed without a close frame",
+                    ))
+                }
+                Ok(n) => {
+                    self.ping_sent = false;
+                    self.rbuf.extend_from_slice(&buf[..n]);
+                }
+                // nothing is received for the ping interval
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::WouldBlock =>
+                {
+                    if self.ping_sent {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "websocket peer is not responding",
+                        ));
+                    }
+                    self.write_frame(PING, b"")?;
+                    self.ping_sent = true;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "websocket is closed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8], masked: bool) -> Result<Option<Frame>, u16> {
+        decode_frame(&mut BytesMut::from(data), masked, 1 << 20)
+    }
+
+    #[test]
+    fn accept() {
+        // the example of RFC 6455
+        let accept = accept_key(b"dGhlIHNhbXBsZSBub25jZQ==");
+        assert_eq!(accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn frame() {
+        for &len in &[0, 5, 125, 126, 65535, 65536] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut buf = Vec::new();
+            encode_frame(&mut buf, false, BINARY, &payload, Some([1, 2, 3, 4]));
+            // incomplete
+            assert!(decode(&buf[..buf.len() - 1], true).unwrap().is_none());
+            let frame = decode(&buf, true).unwrap().unwrap();
+            assert!(!frame.fin);
+            assert_eq!(frame.opcode, BINARY);
+            assert!(frame.payload == payload);
+        }
+
+        let mut buf = BytesMut::new();
+        let mut data = Vec::new();
+        encode_frame(&mut data, true, TEXT, b"hello", None);
+        encode_frame(&mut data, true, PING, b"", None);
+        buf.extend_from_slice(&data);
+        let frame = decode_frame(&mut buf, false, 10).unwrap().unwrap();
+        assert_eq!(frame.payload, b"hello");
+        assert_eq!(
+            decode_frame(&mut buf, false, 10).unwrap().unwrap().opcode,
+            PING
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn bad_frame() {
+        let mut buf = Vec::new();
+        encode_frame(&mut buf, true, TEXT, b"hello", None);
+        // the client must mask
+        assert_eq!(decode(&buf, true).unwrap_err(), PROTOCOL_ERROR);
+        buf.clear();
+        encode_frame(&mut buf, false, PING, b"", Some([0; 4]));
+        assert_eq!(decode(&buf, true).unwrap_err(), PROTOCOL_ERROR);
+        buf.clear();
+        encode_frame(&mut buf, true, 0x3, b"", Some([0; 4]));
+        assert_eq!(decode(&buf, true).unwrap_err(), PROTOCOL_ERROR);
+        buf.clear();
+        encode_frame(&mut buf, true, BINARY, &[0; 100], Some([0; 4]));
+        let ret = decode_frame(&mut BytesMut::from(&buf[..]), true, 99);
+        assert_eq!(ret.unwrap_err(), MESSAGE_TOO_BIG);
+    }
+
+    // serve one websocket connection that echoes the messages
+    fn serve(ping_interval: Option<Duration>) -> std::net::TcpStream {
+        let listener = crate::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        go!(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = WebSocket::new(stream, BytesMut::new());
+            ws.set_ping_interval(ping_interval).unwrap();
+            while let Ok(msg) = ws.recv() {
+                match msg {
+                    Message::Close(_) => break,
+                    msg => ws.send(msg).unwrap(),
+                }
+            }
+        });
+        std::net::TcpStream::connect(addr).unwrap()
+    }
+
+    fn write(s: &mut std::net::TcpStream, fin: bool, opcode: u8, payload: &[u8]) {
+        let mut buf = Vec::new();
+        encode_frame(&mut buf, fin, opcode, payload, Some([7, 3, 5, 1]));
+        s.write_all(&buf).unwrap();
+    }
+
+    fn read(s: &mut std::net::TcpStream, buf: &mut BytesMut) -> Option<Frame> {
+        loop {
+            if let Some(frame) = decode_frame(buf, false, 1 << 20).unwrap() {
+                return Some(frame);
+            }
+            let mut data = [0; 1024];
+            match s.read(&mut data).unwrap() {
+                0 => return None,
+                n => buf.extend_from_slice(&data[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn session() {
+        let mut s = serve(None);
+        let mut buf = BytesMut::new();
+        // a ping between the fragments
+        write(&mut s, false, TEXT, b"hel");
+        write(&mut s, true, PING, b"p");
+        write(&mut s, true, CONTINUATION, "lo 世界".as_bytes());
+        let frame = read(&mut s, &mut buf).unwrap();
+        assert_eq!((frame.opcode, frame.payload), (PONG, b"p".to_vec()));
+        let frame = read(&mut s, &mut buf).unwrap();
+        assert_eq!(frame.opcode, TEXT);
+        assert_eq!(frame.payload, "hello 世界".as_bytes());
+
+        write(&mut s, true, BINARY, &[0, 1, 2]);
+        assert_eq!(read(&mut s, &mut buf).unwrap().payload, [0, 1, 2]);
+
+        // the close handshake
+        write(&mut s, true, CLOSE, &[0x03, 0xe8, b'b', b'y', b'e']);
+        let frame = read(&mut s, &mut buf).unwrap();
+        assert_eq!((frame.opcode, frame.payload), (CLOSE, vec![0x03, 0xe8]));
+        assert!(read(&mut s, &mut buf).is_none());
+    }
+
+    #[test]
+    fn invalid_text() {
+        let mut s = serve(None);
+        let mut buf = BytesMut::new();
+        write(&mut s, true, TEXT, &[0xff, 0xfe]);
+        let frame = read(&mut s, &mut buf).unwrap();
+        assert_eq!(frame.opcode, CLOSE);
+        assert_eq!(frame.payload, INVALID_PAYLOAD.to_be_bytes());
+        assert!(read(&mut s, &mut buf).is_none());
+    }
+
+    #[test]
+    fn keepalive() {
+        let mut s = serve(Some(Duration::from_millis(50)));
+        let mut buf = BytesMut::new();
+        let frame = read(&mut s, &mut buf).unwrap();
+        assert_eq!(frame.opcode, PING);
+        write(&mut s, true, PONG, b"");
+        assert_eq!(read(&mut s, &mut buf).unwrap().opcode, PING);
+        // no pong, the connection is dropped
+        assert!(read(&mut s, &mut buf).is_none());
+    }
+}